@@ -0,0 +1,17 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use pigi::bench;
+
+fn index_rendering(c: &mut Criterion) {
+    let simple = bench::simple_index(1_000);
+    c.bench_function("simple index, 1k packages", |b| {
+        b.iter(|| bench::render(&simple))
+    });
+    let package = bench::package_page(1_000, 10);
+    c.bench_function("package page, 10k files", |b| {
+        b.iter(|| bench::render(&package))
+    });
+}
+
+criterion_group!(benches, index_rendering);
+criterion_main!(benches);
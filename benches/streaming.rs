@@ -0,0 +1,22 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use pigi::bench;
+
+const SIZE: usize = 64 * 1024 * 1024;
+
+fn streaming_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let (url, sha256) = runtime.block_on(bench::mock_upstream(SIZE));
+    let mut group = c.benchmark_group("streaming");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("64 MiB through verification and buffering", |b| {
+        b.to_async(&runtime)
+            .iter(|| bench::stream(&url, &sha256, 1024 * 1024))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, streaming_throughput);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+use std::process::Command;
+
+/// Embeds the commit pigi is built from as `PIGI_COMMIT`, builds without a
+/// git checkout (e.g. in docker) can pass it in the environment instead.
+fn main() {
+    println!("cargo:rerun-if-env-changed=PIGI_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    let commit = std::env::var("PIGI_COMMIT").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()?;
+        let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        output.status.success().then_some(commit)
+    });
+    println!(
+        "cargo:rustc-env=PIGI_COMMIT={}",
+        commit.unwrap_or("unknown".to_string())
+    );
+}
@@ -0,0 +1,66 @@
+//! Current metadata of single release assets, fetched with a call for just the
+//! asset instead of the whole release list. Downloads of files the sync knows
+//! check them this way, the answers are kept for a minute for the next ones.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Repository;
+use crate::error::ErrorResponse;
+use crate::github::{Asset, GithubClient};
+
+/// How long an answer is used for.
+const TTL: Duration = Duration::from_secs(60);
+/// Answers kept at most.
+const CAPACITY: usize = 1024;
+
+struct Entry {
+    fetched_at: Instant,
+    /// `None` when the asset is gone
+    asset: Option<Asset>,
+}
+
+#[derive(Default)]
+pub struct AssetMetadata {
+    /// by token and asset id
+    entries: Mutex<HashMap<(String, u64), Entry>>,
+}
+
+impl AssetMetadata {
+    /// `asset` as GitHub has it now, `None` when it is gone.
+    pub async fn get(
+        &self,
+        client: &GithubClient,
+        repository: &Repository,
+        asset: &Asset,
+    ) -> Result<Option<Asset>, ErrorResponse> {
+        let key = (client.token_key().to_string(), asset.id);
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.fetched_at.elapsed() < TTL {
+                return Ok(entry.asset.clone());
+            }
+        }
+        let current = repository.asset_metadata(client, asset).await?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= CAPACITY {
+            entries.retain(|_, entry| entry.fetched_at.elapsed() < TTL);
+        }
+        if entries.len() >= CAPACITY {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.fetched_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                fetched_at: Instant::now(),
+                asset: current.clone(),
+            },
+        );
+        return Ok(current);
+    }
+}
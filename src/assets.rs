@@ -0,0 +1,676 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use askama_axum::Response;
+use axum::body::Body;
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Json};
+use futures_util::StreamExt;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::auth::GithubToken;
+use crate::buffer;
+use crate::cache;
+use crate::companions::{self, CompanionKind};
+use crate::compat;
+use crate::config::Repository;
+use crate::error::ErrorResponse;
+use crate::filenames;
+use crate::github::{Asset, AssetStream, ByteStream, GithubClient, Release};
+use crate::integrity;
+use crate::names;
+use crate::namespace::{CurrentNamespace, Namespace};
+use crate::policy::CurrentPolicy;
+use crate::quarantine;
+use crate::redact::log;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+/// Downloads a file of a release. Files are addressed by release version and
+/// filename, resolved to the current GitHub asset on every request, so urls
+/// survive re-uploads. Older urls with a GitHub asset id in place of the
+/// version are redirected.
+pub async fn asset(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((package_name, version, filename)): Path<(String, String, String)>,
+    GithubToken(token): GithubToken,
+    policy: CurrentPolicy,
+    request: Request,
+) -> Result<Response, ErrorResponse> {
+    policy.check(&version, &filename)?;
+    let client = GithubClient::new(&app_state.github, token);
+    let (configured_name, repository) = namespace.find_repository(&package_name)?;
+    let canonical_name = names::normalize(configured_name);
+    if package_name == canonical_name {
+        if let Some(asset) = current_asset(
+            &app_state,
+            &client,
+            &namespace,
+            configured_name,
+            repository,
+            &version,
+            &filename,
+        )
+        .await?
+        {
+            let response = serve(
+                &app_state,
+                &client,
+                &namespace,
+                &package_name,
+                repository,
+                &asset,
+                request,
+            )
+            .await?;
+            return Ok(app_state.content_types.apply(&filename, response));
+        }
+    }
+    let mut releases = repository.releases(&client).await?;
+    quarantine::retain_approved(&app_state, &namespace, configured_name, &mut releases);
+    app_state.hashes.fill(&mut releases);
+    app_state.links.hide_broken(&mut releases);
+    app_state.hashes.record_traffic(&namespace, configured_name);
+    filenames::hide_malformed(configured_name, &mut releases);
+
+    if let Some(release) = release_by_asset_id(&releases, &version) {
+        // the filename part of asset id urls is informative only, unless it names a companion
+        let filename = match companions::subject_of(&filename) {
+            Some(_) => filename,
+            None => release
+                .assets
+                .iter()
+                .find(|asset| asset.id.to_string() == version)
+                .map(|asset| asset.name.clone())
+                .unwrap_or(filename),
+        };
+        return Ok(compat::moved_permanently(format!(
+            "../../{}/{}/{}",
+            canonical_name,
+            release.version(),
+            filename
+        )));
+    }
+    if package_name != canonical_name {
+        return Ok(compat::moved_permanently(format!(
+            "../../{}/{}/{}",
+            canonical_name, version, filename
+        )));
+    }
+
+    let release = releases
+        .iter()
+        .find(|release| release.version() == version || release.tag_name == version)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let response = match release.assets.iter().find(|asset| asset.name == filename) {
+        Some(asset) => {
+            serve(
+                &app_state,
+                &client,
+                &namespace,
+                &package_name,
+                repository,
+                asset,
+                request,
+            )
+            .await?
+        }
+        None => companion(&client, repository, release, &filename).await?,
+    };
+    return Ok(app_state.content_types.apply(&filename, response));
+}
+
+/// The synced asset of the file, checked with a call for just the asset.
+/// `None` when the sync doesn't know it or it changed on GitHub since, a
+/// re-upload is a new asset, then the file is looked up in the release list.
+async fn current_asset(
+    app_state: &AppState,
+    client: &GithubClient,
+    namespace: &Namespace,
+    package_name: &String,
+    repository: &Repository,
+    version: &str,
+    filename: &str,
+) -> Result<Option<Asset>, ErrorResponse> {
+    let Some(metadata) = namespace.metadata.get(package_name) else {
+        return Ok(None);
+    };
+    let Some(mut asset) = metadata
+        .releases
+        .iter()
+        .find(|release| release.version() == version || release.tag_name == version)
+        .and_then(|release| release.assets.iter().find(|asset| asset.name == filename))
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    if app_state.github.cache.exhausted_until(client.token_key()).is_some() {
+        // out of GitHub budget the synced asset will do, cached files can still be served
+        return Ok(Some(asset));
+    }
+    let Some(current) = app_state
+        .asset_metadata
+        .get(client, repository, &asset)
+        .await?
+    else {
+        return Ok(None);
+    };
+    if current.name != asset.name || current.updated_at != asset.updated_at {
+        return Ok(None);
+    }
+    asset.size = current.size;
+    if current.digest.is_some() {
+        asset.digest = current.digest;
+    }
+    return Ok(Some(asset));
+}
+
+/// Downloads a file by the sha256 of its content, from whichever synced
+/// release of the namespace published it.
+pub async fn by_hash(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((digest,)): Path<(String,)>,
+    GithubToken(token): GithubToken,
+    policy: CurrentPolicy,
+    account: CurrentAccount,
+    request: Request,
+) -> Result<Response, ErrorResponse> {
+    let digest = digest.to_lowercase();
+    if !cache::is_sha256(&digest) {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    let (package_name, version, asset) = namespace
+        .metadata
+        .all()
+        .into_iter()
+        .filter(|(package_name, _)| namespace.repos.get(package_name).is_some())
+        .find_map(|(package_name, metadata)| {
+            let (version, asset) = metadata.releases.iter().find_map(|release| {
+                let asset = release
+                    .assets
+                    .iter()
+                    .find(|asset| asset.sha256() == Some(digest.as_str()))?;
+                Some((release.version().to_string(), asset.clone()))
+            })?;
+            Some((package_name, version, asset))
+        })
+        .ok_or(ErrorResponse::PageNotFound)?;
+    account.check_package(&namespace, &package_name)?;
+    policy.check(&version, &asset.name)?;
+    let repository = namespace.get_repository(&package_name)?;
+    let client = GithubClient::new(&app_state.github, token);
+    let response = serve(
+        &app_state,
+        &client,
+        &namespace,
+        &package_name,
+        repository,
+        &asset,
+        request,
+    )
+    .await?;
+    let mut response = app_state.content_types.apply(&asset.name, response);
+    let headers = response.headers_mut();
+    if let Ok(disposition) = format!("attachment; filename=\"{}\"", asset.name).parse() {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    return Ok(response);
+}
+
+/// Downloads any file of a release of a package with `downloads`, like CLIs
+/// and installers, which aren't Python distributions: file names aren't
+/// checked and the files aren't listed on the index.
+pub async fn portal(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((package_name, version, filename)): Path<(String, String, String)>,
+    GithubToken(token): GithubToken,
+    policy: CurrentPolicy,
+    request: Request,
+) -> Result<Response, ErrorResponse> {
+    policy.check(&version, &filename)?;
+    let client = GithubClient::new(&app_state.github, token);
+    let (configured_name, repository) = namespace.find_repository(&package_name)?;
+    if !repository.downloads {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    let asset = match current_asset(
+        &app_state,
+        &client,
+        &namespace,
+        configured_name,
+        repository,
+        &version,
+        &filename,
+    )
+    .await?
+    {
+        Some(asset) => asset,
+        None => {
+            let releases =
+                downloadable(&app_state, &client, &namespace, configured_name, repository).await?;
+            releases
+                .iter()
+                .find(|release| release.version() == version || release.tag_name == version)
+                .and_then(|release| release.assets.iter().find(|asset| asset.name == filename))
+                .cloned()
+                .ok_or(ErrorResponse::PageNotFound)?
+        }
+    };
+    app_state.hashes.record_traffic(&namespace, configured_name);
+    let response = serve(
+        &app_state,
+        &client,
+        &namespace,
+        configured_name,
+        repository,
+        &asset,
+        request,
+    )
+    .await?;
+    let mut response = app_state.content_types.apply(&asset.name, response);
+    let headers = response.headers_mut();
+    if let Ok(disposition) = format!("attachment; filename=\"{}\"", asset.name).parse() {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    return Ok(response);
+}
+
+/// Releases of a package with all their files, malformed wheel and sdist
+/// names included, as `/downloads/` serves them.
+pub async fn downloadable(
+    app_state: &AppState,
+    client: &GithubClient,
+    namespace: &Namespace,
+    package_name: &str,
+    repository: &Repository,
+) -> Result<Vec<Release>, ErrorResponse> {
+    let mut releases = repository.releases(client).await?;
+    quarantine::retain_approved(app_state, namespace, package_name, &mut releases);
+    app_state.hashes.fill(&mut releases);
+    app_state.links.hide_broken(&mut releases);
+    return Ok(releases);
+}
+
+/// Content of `asset`, from the disk cache when it was downloaded before.
+/// Assets with a digest are verified while downloading and cached once complete.
+/// Conditional requests are answered before anything is downloaded.
+async fn serve(
+    app_state: &AppState,
+    client: &GithubClient,
+    namespace: &Arc<Namespace>,
+    package_name: &str,
+    repository: &Repository,
+    asset: &Asset,
+    mut request: Request,
+) -> Result<Response, ErrorResponse> {
+    let etag = asset
+        .sha256()
+        .map(|sha256| HeaderValue::from_str(&format!("\"{}\"", sha256)).unwrap());
+    let last_modified = asset.modified_at();
+    let response = if is_not_modified(&request, etag.as_ref(), last_modified) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        if !is_range_current(&request, etag.as_ref(), last_modified) {
+            // the client has parts of a different file, it gets all of this one
+            request.headers_mut().remove(header::RANGE);
+        }
+        serve_content(
+            app_state,
+            client,
+            namespace,
+            package_name,
+            repository,
+            asset,
+            request,
+        )
+        .await?
+    };
+    let mut response = with_validators(response, etag, last_modified);
+    if app_state.config.stream_no_transform {
+        // files are compressed already, proxies in front shouldn't spend time on it
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("no-transform"),
+        );
+    }
+    return Ok(response);
+}
+
+/// Whether the client's copy is current: its `If-None-Match` names the content
+/// hash, or, without `If-None-Match`, the asset wasn't uploaded after `If-Modified-Since`.
+fn is_not_modified(
+    request: &Request,
+    etag: Option<&HeaderValue>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    let headers = request.headers();
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        let Some(etag) = etag else {
+            return false;
+        };
+        return if_none_match.to_str().is_ok_and(|value| {
+            value.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            })
+        });
+    }
+    let Some(last_modified) = last_modified else {
+        return false;
+    };
+    return headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .is_some_and(|since| last_modified <= since);
+}
+
+/// Whether the `If-Range` of a range request names this asset, when it has one.
+fn is_range_current(
+    request: &Request,
+    etag: Option<&HeaderValue>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    let Some(if_range) = request.headers().get(header::IF_RANGE) else {
+        return true;
+    };
+    if if_range.as_bytes().starts_with(b"\"") {
+        return etag == Some(if_range);
+    }
+    let since = if_range
+        .to_str()
+        .ok()
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    return since.is_some() && since == last_modified;
+}
+
+/// Sets the `ETag` and `Last-Modified` of the asset, not of the cached file.
+fn with_validators(
+    mut response: Response,
+    etag: Option<HeaderValue>,
+    last_modified: Option<SystemTime>,
+) -> Response {
+    let headers = response.headers_mut();
+    match etag {
+        Some(etag) => headers.insert(header::ETAG, etag),
+        None => headers.remove(header::ETAG),
+    };
+    match last_modified {
+        Some(last_modified) => {
+            let value = HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)).unwrap();
+            headers.insert(header::LAST_MODIFIED, value)
+        }
+        None => headers.remove(header::LAST_MODIFIED),
+    };
+    return response;
+}
+
+async fn serve_content(
+    app_state: &AppState,
+    client: &GithubClient,
+    namespace: &Arc<Namespace>,
+    package_name: &str,
+    repository: &Repository,
+    asset: &Asset,
+    request: Request,
+) -> Result<Response, ErrorResponse> {
+    if let Some(sha256) = asset.sha256() {
+        if let Some(path) = app_state.cache.touch(sha256).await {
+            return Ok(serve_cached(app_state, path, request).await);
+        }
+        let is_large = app_state
+            .config
+            .parallel_fetch_threshold
+            .is_some_and(|threshold| asset.size >= threshold);
+        if is_large && app_state.cache.is_enabled() {
+            let connections = app_state.config.parallel_fetch_connections;
+            match app_state
+                .cache
+                .fetch_parallel(client, repository, asset, connections)
+                .await
+            {
+                Ok(path) => return Ok(serve_cached(app_state, path, request).await),
+                // a single streamed download still verifies the file and reports a mismatch
+                Err(_) => log!(
+                    "Parallel download of {} failed, streaming it instead",
+                    asset.name
+                ),
+            }
+        }
+    }
+    let stream = repository.asset(client, asset).await?;
+    let content_length = stream.content_length;
+    let stream = match asset.sha256() {
+        Some(sha256) => {
+            let verified = verify(namespace, package_name, asset, sha256, stream.stream);
+            app_state.cache.store(sha256, verified).await
+        }
+        None => stream.stream,
+    };
+    // reading, verifying and caching happen on the streaming runtime
+    let stream = buffer::bounded(
+        stream,
+        app_state.config.stream_buffer_bytes,
+        app_state.config.stream_chunk_bytes,
+        &app_state.streaming,
+    );
+    return Ok(stream_response(AssetStream {
+        content_length,
+        stream,
+    }));
+}
+
+pub fn verify(
+    namespace: &Arc<Namespace>,
+    package_name: &str,
+    asset: &Asset,
+    sha256: &str,
+    stream: ByteStream<io::Error>,
+) -> ByteStream<io::Error> {
+    let asset_name = asset.name.clone();
+    let namespace = namespace.clone();
+    let package_name = package_name.to_string();
+    let on_mismatch = move || {
+        log!(
+            "Checksum of {} of {} doesn't match, invalidating its metadata",
+            asset_name,
+            namespace.qualified_name(&package_name)
+        );
+        namespace.metadata.invalidate(&package_name);
+    };
+    return integrity::verify_sha256(stream, sha256.to_string(), on_mismatch);
+}
+
+/// Serves a cached file from disk, with range requests.
+async fn serve_cached(app_state: &AppState, path: PathBuf, mut request: Request) -> Response {
+    // already answered for the asset, the file times are those of the cache
+    let headers = request.headers_mut();
+    headers.remove(header::IF_NONE_MATCH);
+    headers.remove(header::IF_MODIFIED_SINCE);
+    headers.remove(header::IF_RANGE);
+    let Ok(response) = ServeFile::new(path)
+        .with_buf_chunk_size(app_state.config.stream_chunk_bytes)
+        .oneshot(request)
+        .await;
+    return response.map(Body::new);
+}
+
+/// Release with an asset of id `asset_id`, unless `asset_id` is also a version.
+fn release_by_asset_id<'a>(releases: &'a [Release], asset_id: &str) -> Option<&'a Release> {
+    let is_version = releases
+        .iter()
+        .any(|release| release.version() == asset_id || release.tag_name == asset_id);
+    if is_version {
+        return None;
+    }
+    return releases.iter().find(|release| {
+        release
+            .assets
+            .iter()
+            .any(|asset| asset.id.to_string() == asset_id)
+    });
+}
+
+fn stream_response(asset: AssetStream) -> Response {
+    let mut response = Body::from_stream(asset.stream).into_response();
+    if let Some(content_length) = asset.content_length {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, content_length.into());
+    }
+    return response;
+}
+
+async fn download(
+    client: &GithubClient,
+    repository: &Repository,
+    asset: &Asset,
+) -> Result<Vec<u8>, ErrorResponse> {
+    let mut stream = repository.asset(client, asset).await?.stream;
+    let mut content = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|error| ErrorResponse::ServerError(Some(error.to_string())))?;
+        content.extend_from_slice(&chunk);
+    }
+    return Ok(content);
+}
+
+/// Serves a companion file that isn't published as an asset of its own: a
+/// checksum taken from the release-wide checksums file, or the provenance
+/// object wrapping a PEP 740 attestation.
+async fn companion(
+    client: &GithubClient,
+    repository: &Repository,
+    release: &Release,
+    filename: &str,
+) -> Result<Response, ErrorResponse> {
+    let (subject, kind) = companions::subject_of(filename).ok_or(ErrorResponse::PageNotFound)?;
+    let subject = release
+        .assets
+        .iter()
+        .find(|asset| asset.name == subject)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    if let Some(companion) = companions::find(&release.assets, &subject.name, kind) {
+        let stream = repository.asset(client, companion).await?;
+        return Ok(stream_response(stream));
+    }
+    if kind == CompanionKind::Provenance {
+        let attestation = companions::pep740_attestation(&release.assets, &subject.name)
+            .ok_or(ErrorResponse::PageNotFound)?;
+        let content = download(client, repository, attestation).await?;
+        let attestation = serde_json::from_slice(&content).map_err(|error| {
+            ErrorResponse::ServerError(Some(format!(
+                "{} isn't valid JSON: {}",
+                attestation.name, error
+            )))
+        })?;
+        let storage = &client.shared().storage;
+        let provenance = companions::provenance(repository, storage, attestation);
+        return Ok(Json(provenance).into_response());
+    }
+    if kind != CompanionKind::Checksum {
+        return Err(ErrorResponse::PageNotFound);
+    }
+
+    let line = match subject.sha256() {
+        Some(sha256) => format!("{}  {}\n", sha256, subject.name),
+        None => {
+            let checksum_file =
+                companions::checksum_file(&release.assets).ok_or(ErrorResponse::PageNotFound)?;
+            let checksums = download(client, repository, checksum_file).await?;
+            companions::checksum_line(&String::from_utf8_lossy(&checksums), &subject.name)
+                .ok_or(ErrorResponse::PageNotFound)?
+        }
+    };
+    return Ok(line.into_response());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const ETAG: &str = "\"sha256-abc\"";
+
+    fn request(headers: &[(header::HeaderName, &str)]) -> Request {
+        let mut builder = Request::builder().uri("/assets/pigi-1.0.tar.gz");
+        for (name, value) in headers {
+            builder = builder.header(name, *value);
+        }
+        return builder.body(Body::empty()).unwrap();
+    }
+
+    fn uploaded() -> SystemTime {
+        return httpdate::parse_http_date("Sun, 06 Nov 2022 08:49:37 GMT").unwrap();
+    }
+
+    #[test]
+    fn if_none_match_compares_the_etag() {
+        let etag = HeaderValue::from_static(ETAG);
+        let matching = request(&[(header::IF_NONE_MATCH, "\"other\", W/\"sha256-abc\"")]);
+        assert!(is_not_modified(&matching, Some(&etag), None));
+        let any = request(&[(header::IF_NONE_MATCH, "*")]);
+        assert!(is_not_modified(&any, Some(&etag), None));
+        let other = request(&[(header::IF_NONE_MATCH, "\"other\"")]);
+        assert!(!is_not_modified(&other, Some(&etag), Some(uploaded())));
+        assert!(!is_not_modified(&matching, None, Some(uploaded())));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let etag = HeaderValue::from_static(ETAG);
+        let request = request(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, "Mon, 07 Nov 2022 08:49:37 GMT"),
+        ]);
+        assert!(!is_not_modified(&request, Some(&etag), Some(uploaded())));
+    }
+
+    #[test]
+    fn if_modified_since_compares_the_upload_time() {
+        let later = request(&[(header::IF_MODIFIED_SINCE, "Mon, 07 Nov 2022 08:49:37 GMT")]);
+        assert!(is_not_modified(&later, None, Some(uploaded())));
+        let same = request(&[(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 2022 08:49:37 GMT")]);
+        assert!(is_not_modified(&same, None, Some(uploaded())));
+        let earlier = request(&[(header::IF_MODIFIED_SINCE, "Sat, 05 Nov 2022 08:49:37 GMT")]);
+        assert!(!is_not_modified(&earlier, None, Some(uploaded())));
+        assert!(!is_not_modified(&later, None, None));
+        let invalid = request(&[(header::IF_MODIFIED_SINCE, "yesterday")]);
+        assert!(!is_not_modified(&invalid, None, Some(uploaded())));
+        assert!(!is_not_modified(&request(&[]), None, Some(uploaded())));
+    }
+
+    #[test]
+    fn ranges_without_if_range_are_current() {
+        assert!(is_range_current(&request(&[]), None, None));
+    }
+
+    #[test]
+    fn if_range_compares_the_etag() {
+        let etag = HeaderValue::from_static(ETAG);
+        let matching = request(&[(header::IF_RANGE, ETAG)]);
+        assert!(is_range_current(&matching, Some(&etag), None));
+        let other = request(&[(header::IF_RANGE, "\"other\"")]);
+        assert!(!is_range_current(&other, Some(&etag), None));
+        assert!(!is_range_current(&matching, None, Some(uploaded())));
+    }
+
+    #[test]
+    fn if_range_compares_the_exact_upload_time() {
+        let same = request(&[(header::IF_RANGE, "Sun, 06 Nov 2022 08:49:37 GMT")]);
+        assert!(is_range_current(&same, None, Some(uploaded())));
+        let later = uploaded() + Duration::from_secs(1);
+        assert!(!is_range_current(&same, None, Some(later)));
+        assert!(!is_range_current(&same, None, None));
+        let invalid = request(&[(header::IF_RANGE, "yesterday")]);
+        assert!(!is_range_current(&invalid, None, Some(uploaded())));
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum_auth::{AuthBasic, AuthBasicCustom};
+
+use crate::error::ErrorResponse;
+use crate::namespace::CurrentNamespace;
+use crate::service_accounts::ServiceAccount;
+use crate::AppState;
+
+/// Token used to talk to GitHub on behalf of the request: the basic auth
+/// password, or the namespace's token when the client sent none. Namespaces
+/// with access tokens always use their own GitHub token, as the password is
+/// a pigi access token there, and so do service accounts.
+pub struct GithubToken(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for GithubToken {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let CurrentNamespace { namespace, .. } =
+            CurrentNamespace::from_request_parts(parts, state).await?;
+        if namespace.access_tokens.is_some() || parts.extensions.get::<ServiceAccount>().is_some() {
+            return Ok(GithubToken(namespace.github_token.get()));
+        }
+        let basic_auth = AuthBasic::decode_request_parts(parts);
+        if let Ok(AuthBasic((_, Some(password)))) = basic_auth {
+            return Ok(GithubToken(Some(password)))
+        }
+        Ok(GithubToken(namespace.github_token.get()))
+    }
+}
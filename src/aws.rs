@@ -0,0 +1,217 @@
+//! AWS credentials and signature version 4, for the AWS Secrets Manager
+//! secrets provider and S3 storage.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::redact;
+use crate::secrets::env;
+
+pub struct AwsCredentials {
+    pub region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Credentials from the env variables the AWS tooling uses.
+    pub fn from_env() -> Self {
+        let secret_access_key = env("AWS_SECRET_ACCESS_KEY");
+        let session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+        redact::register(&secret_access_key);
+        if let Some(session_token) = &session_token {
+            redact::register(session_token);
+        }
+        return AwsCredentials {
+            region: std::env::var("AWS_REGION").unwrap_or_else(|_| env("AWS_DEFAULT_REGION")),
+            access_key_id: env("AWS_ACCESS_KEY_ID"),
+            secret_access_key,
+            session_token,
+        };
+    }
+
+    /// Signs a call to `service` with AWS signature version 4. `path` is
+    /// already URI encoded, `headers` are lowercase and sent with the request,
+    /// and `payload_sha256` is the hex digest of the body or `UNSIGNED-PAYLOAD`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign(
+        &self,
+        request: reqwest::RequestBuilder,
+        service: &str,
+        method: &str,
+        host: &str,
+        path: &str,
+        headers: Vec<(&str, String)>,
+        payload_sha256: &str,
+    ) -> reqwest::RequestBuilder {
+        let (date, time) = utc_now();
+        let amz_date = format!("{}T{}Z", date, time);
+        let mut request = request;
+        for (name, value) in &headers {
+            request = request.header(*name, value);
+        }
+        let authorization = self.authorization(
+            service,
+            method,
+            host,
+            path,
+            headers,
+            payload_sha256,
+            &amz_date,
+        );
+        request = request
+            .header("X-Amz-Date", amz_date)
+            .header("Authorization", authorization);
+        if let Some(session_token) = &self.session_token {
+            request = request.header("X-Amz-Security-Token", session_token);
+        }
+        return request;
+    }
+
+    /// `Authorization` header of a call signed at `amz_date`, `YYYYMMDDTHHMMSSZ`.
+    #[allow(clippy::too_many_arguments)]
+    fn authorization(
+        &self,
+        service: &str,
+        method: &str,
+        host: &str,
+        path: &str,
+        mut headers: Vec<(&str, String)>,
+        payload_sha256: &str,
+        amz_date: &str,
+    ) -> String {
+        let AwsCredentials {
+            region,
+            access_key_id,
+            secret_access_key,
+            session_token,
+        } = self;
+        let date = &amz_date[..8];
+        headers.push(("host", host.to_string()));
+        headers.push(("x-amz-date", amz_date.to_string()));
+        if let Some(session_token) = session_token {
+            headers.push(("x-amz-security-token", session_token.clone()));
+        }
+        headers.sort();
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, payload_sha256
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request))
+        );
+        let key = [date, region, service, "aws4_request"].iter().fold(
+            format!("AWS4{}", secret_access_key).into_bytes(),
+            |key, part| hmac_sha256(&key, part),
+        );
+        let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+        return format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            access_key_id, scope, signed_headers, signature
+        );
+    }
+}
+
+/// `segment` URI encoded the way signature version 4 expects.
+pub fn uri_encode(segment: &str) -> String {
+    return segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data.as_bytes());
+    return mac.finalize().into_bytes().to_vec();
+}
+
+/// Current UTC date as `YYYYMMDD` and time as `HHMMSS`.
+pub fn utc_now() -> (String, String) {
+    return utc_date(SystemTime::now());
+}
+
+/// UTC date of `time` as `YYYYMMDD` and its time of day as `HHMMSS`.
+pub fn utc_date(time: SystemTime) -> (String, String) {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time = seconds % 86400;
+    return (
+        format!("{:04}{:02}{:02}", year, month, day),
+        format!("{:02}{:02}{:02}", time / 3600, time % 3600 / 60, time % 60),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> AwsCredentials {
+        return AwsCredentials {
+            region: "us-east-1".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+    }
+
+    /// `get-vanilla` of the AWS signature version 4 test suite.
+    #[test]
+    fn signs_the_aws_sample_request() {
+        let authorization = credentials().authorization(
+            "service",
+            "GET",
+            "example.amazonaws.com",
+            "/",
+            vec![],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "20150830T123600Z",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn encodes_uri_segments() {
+        assert_eq!(uri_encode("my file+1.whl"), "my%20file%2B1.whl");
+        assert_eq!(uri_encode("a-b_c.d~e"), "a-b_c.d~e");
+    }
+}
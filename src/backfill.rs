@@ -0,0 +1,227 @@
+//! Hashes of release assets GitHub has no digest for, computed in the
+//! background when `HASH_BACKFILL_INTERVAL` is set: one file is downloaded
+//! every interval, files of the most recently requested packages first. Once
+//! hashed, files get `#sha256=` links, so `pip install --require-hashes` works
+//! for them, and downloads are verified and cached like any other file.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use blake2::digest::consts::U32;
+use blake2::Blake2b;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::github::{Asset, GithubClient, Release};
+use crate::namespace::Namespace;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::AppState;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Hashes {
+    sha256: String,
+    blake2b_256: String,
+}
+
+pub struct HashStore {
+    /// where the hashes are kept across restarts, in `CACHE_DIR`
+    path: Option<PathBuf>,
+    /// hashes by asset id, GitHub gives re-uploaded files a new id
+    hashes: RwLock<HashMap<u64, Hashes>>,
+    /// assets that failed to download, not retried until a restart
+    failed: Mutex<HashSet<u64>>,
+    /// when every package was last requested, by qualified name
+    traffic: RwLock<HashMap<String, SystemTime>>,
+}
+
+impl HashStore {
+    pub fn from_config(config: &Config) -> Self {
+        let store = HashStore {
+            path: config
+                .cache_dir
+                .as_ref()
+                .map(|cache_dir| PathBuf::from(cache_dir).join("hashes.json")),
+            hashes: RwLock::default(),
+            failed: Mutex::default(),
+            traffic: RwLock::default(),
+        };
+        store.reload();
+        return store;
+    }
+
+    /// Reads the hashes again, the leader may have computed more.
+    pub fn reload(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let hashes = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content).map_err(io::Error::other),
+            Err(error) => Err(error),
+        };
+        match hashes {
+            Ok(hashes) => *self.hashes.write().unwrap() = hashes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => log!("Failed to load {}: {}", path.display(), error),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = serde_json::to_vec(&*self.hashes.read().unwrap()).unwrap();
+        let temp_path = path.with_extension("json.tmp");
+        return fs::write(&temp_path, content)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|error| format!("Failed to save {}: {}", path.display(), error));
+    }
+
+    /// Sets the digests of the assets the backfill hashed.
+    pub fn fill(&self, releases: &mut [Release]) {
+        let hashes = self.hashes.read().unwrap();
+        if hashes.is_empty() {
+            return;
+        }
+        for asset in releases.iter_mut().flat_map(|release| &mut release.assets) {
+            if asset.digest.is_some() {
+                continue;
+            }
+            if let Some(known) = hashes.get(&asset.id) {
+                asset.digest = Some(format!("sha256:{}", known.sha256));
+                asset.blake2b_256 = Some(known.blake2b_256.clone());
+            }
+        }
+    }
+
+    /// Notes a request for a package, its files are hashed first.
+    pub fn record_traffic(&self, namespace: &Namespace, package_name: &str) {
+        self.traffic
+            .write()
+            .unwrap()
+            .insert(namespace.qualified_name(package_name), SystemTime::now());
+    }
+
+    /// The asset without a digest of the most recently requested package.
+    fn next(&self, app_state: &AppState) -> Option<(Arc<Namespace>, String, Asset)> {
+        let hashes = self.hashes.read().unwrap();
+        let failed = self.failed.lock().unwrap();
+        let traffic = self.traffic.read().unwrap();
+        let mut candidates = vec![];
+        for namespace in app_state.namespaces.iter() {
+            for (package_name, metadata) in namespace.metadata.all() {
+                let asset = metadata
+                    .releases
+                    .iter()
+                    .flat_map(|release| &release.assets)
+                    .find(|asset| {
+                        asset.digest.is_none()
+                            && !hashes.contains_key(&asset.id)
+                            && !failed.contains(&asset.id)
+                    });
+                if let Some(asset) = asset {
+                    let requested_at = traffic.get(&namespace.qualified_name(&package_name));
+                    candidates.push((
+                        requested_at.copied(),
+                        namespace,
+                        package_name,
+                        asset.clone(),
+                    ));
+                }
+            }
+        }
+        let (_, namespace, package_name, asset) = candidates
+            .into_iter()
+            .max_by_key(|(requested_at, ..)| *requested_at)?;
+        return Some((namespace.clone(), package_name, asset));
+    }
+}
+
+async fn hash(
+    app_state: &AppState,
+    namespace: &Namespace,
+    package_name: &String,
+    asset: &Asset,
+) -> Result<Hashes, String> {
+    let repository = namespace
+        .repos
+        .get(package_name)
+        .ok_or("the package is no longer configured")?;
+    let client = GithubClient::new(&app_state.github, namespace.github_token.get()).background();
+    let mut stream = repository
+        .asset(&client, asset)
+        .await
+        .map_err(|error| error.to_string())?
+        .stream;
+    let mut sha256 = Sha256::new();
+    let mut blake2b_256 = Blake2b::<U32>::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|error| error.to_string())?;
+        sha256.update(&chunk);
+        blake2b_256.update(&chunk);
+    }
+    return Ok(Hashes {
+        sha256: hex::encode(sha256.finalize()),
+        blake2b_256: hex::encode(blake2b_256.finalize()),
+    });
+}
+
+/// Hashes one asset every `HASH_BACKFILL_INTERVAL`, on the leader only when
+/// leader election is enabled.
+pub fn spawn_backfill_worker(app_state: Arc<AppState>) {
+    let Some(period) = app_state.config.hash_backfill_interval else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if !app_state.leader.is_leader().await {
+                app_state.hashes.reload();
+                continue;
+            }
+            let Some((namespace, package_name, asset)) = app_state.hashes.next(&app_state) else {
+                continue;
+            };
+            let mut errors = vec![];
+            match hash(&app_state, &namespace, &package_name, &asset).await {
+                Ok(hashes) => {
+                    log!(
+                        "Hashed {} of {}: sha256 {}",
+                        asset.name,
+                        namespace.qualified_name(&package_name),
+                        hashes.sha256
+                    );
+                    app_state
+                        .hashes
+                        .hashes
+                        .write()
+                        .unwrap()
+                        .insert(asset.id, hashes);
+                    errors.extend(app_state.hashes.save().err());
+                }
+                Err(error) => {
+                    app_state.hashes.failed.lock().unwrap().insert(asset.id);
+                    errors.push(format!(
+                        "Failed to hash {} of {}: {}",
+                        asset.name,
+                        namespace.qualified_name(&package_name),
+                        error
+                    ));
+                }
+            }
+            for message in &errors {
+                log!("{}", message);
+                app_state
+                    .reporter
+                    .report(Report::task("hash backfill", message.clone()));
+            }
+            app_state.health.record("hash backfill", errors);
+        }
+    });
+}
@@ -0,0 +1,157 @@
+//! Workloads measured by the criterion benches in `benches/` and by `pigi bench`.
+use std::io;
+use std::time::{Duration, Instant};
+
+use askama::Template;
+use axum::body::Bytes;
+use axum::routing::get;
+use axum::Router;
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+
+use crate::github::{Asset, Release};
+use crate::templates::TemplateContext;
+use crate::theme::Theme;
+use crate::{buffer, integrity, names};
+use crate::{PackageFile, PackageTemplate, Simple, SimpleProject};
+
+/// Simple index listing `repos` packages.
+pub fn simple_index(repos: usize) -> Simple {
+    let repos = (0..repos)
+        .map(|i| {
+            let name = format!("Package_{}", i);
+            SimpleProject {
+                normalized_name: names::normalize(&name),
+                name,
+            }
+        })
+        .collect();
+    return Simple {
+        repos,
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    };
+}
+
+/// Package page with `releases` releases of `assets_per_release` files each.
+pub fn package_page(releases: usize, assets_per_release: usize) -> PackageTemplate {
+    let assets = (0..releases)
+        .map(|i| release(i, assets_per_release))
+        .collect::<Vec<_>>()
+        .iter()
+        .flat_map(PackageFile::from_release)
+        .collect();
+    return PackageTemplate {
+        github_org: "pigi".to_string(),
+        package_name: "package".to_string(),
+        assets,
+        links: vec![],
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    };
+}
+
+fn release(i: usize, assets: usize) -> Release {
+    let version = format!("1.{}.0", i);
+    let assets = (0..assets)
+        .map(|j| Asset {
+            id: (i * assets + j) as u64,
+            name: format!("package-{}-py3-none-any_{}.whl", version, j),
+            size: 1024,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            updated_at: "2024-01-01T00:00:00Z".to_string(),
+            digest: Some(format!("sha256:{:064x}", i * assets + j)),
+            blake2b_256: None,
+            download_url: None,
+        })
+        .collect();
+    return Release {
+        id: i as u64,
+        tag_name: format!("v{}", version),
+        name: None,
+        html_url: String::new(),
+        published_at: None,
+        prerelease: false,
+        draft: false,
+        assets,
+        version: None,
+    };
+}
+
+pub fn render<T: Template>(page: &T) -> String {
+    return page.render().unwrap();
+}
+
+/// Local stand-in for GitHub serving a file of `size` bytes, returns its url
+/// and the sha256 of the file.
+pub async fn mock_upstream(size: usize) -> (String, String) {
+    let content = Bytes::from(vec![7; size]);
+    let sha256 = hex::encode(Sha256::digest(&content));
+    let app = Router::new().route("/asset", get(move || async move { content }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/asset", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    return (url, sha256);
+}
+
+/// Downloads `url` through the same verification and buffering a client
+/// download goes through, returns the number of bytes received.
+pub async fn stream(url: &str, sha256: &str, buffer_bytes: usize) -> u64 {
+    let response = reqwest::get(url).await.unwrap();
+    let upstream = Box::pin(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(io::Error::other)),
+    );
+    let verified = integrity::verify_sha256(upstream, sha256.to_string(), || {
+        panic!("mock upstream content doesn't match its sha256")
+    });
+    let mut stream = buffer::bounded(
+        verified,
+        buffer_bytes,
+        buffer::CHUNK_BYTES,
+        &Handle::current(),
+    );
+    let mut received = 0;
+    while let Some(chunk) = stream.next().await {
+        received += chunk.unwrap().len() as u64;
+    }
+    return received;
+}
+
+fn measure<F: FnMut()>(iterations: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    return start.elapsed() / iterations;
+}
+
+/// Runs every workload once with plain timing, for a quick comparison
+/// without the criterion harness.
+pub async fn report() {
+    let simple = simple_index(1_000);
+    let elapsed = measure(100, || {
+        render(&simple);
+    });
+    println!("simple index, 1k packages: {:?}", elapsed);
+
+    let package = package_page(1_000, 10);
+    let elapsed = measure(20, || {
+        render(&package);
+    });
+    println!("package page, 10k files: {:?}", elapsed);
+
+    let size = 256 * 1024 * 1024;
+    let (url, sha256) = mock_upstream(size).await;
+    let start = Instant::now();
+    let received = stream(&url, &sha256, 1024 * 1024).await;
+    let elapsed = start.elapsed();
+    println!(
+        "streaming, {} MiB: {:?}, {:.0} MiB/s",
+        received / 1024 / 1024,
+        elapsed,
+        received as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64()
+    );
+}
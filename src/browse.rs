@@ -0,0 +1,108 @@
+//! `/packages/`: the packages of a namespace for people, a page at a time with
+//! their description and latest version from the metadata store. `/simple/`
+//! stays complete for installers, but with thousands of packages it is too
+//! large to be browsed.
+use std::sync::Arc;
+
+use askama::Template;
+use askama_axum::Response;
+use axum::extract::{Query, State};
+use serde::{Deserialize, Serialize};
+
+use crate::github::latest_release;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::templates::{Page, TemplateContext};
+use crate::theme::Theme;
+use crate::AppState;
+
+const PER_PAGE: usize = 100;
+const MAX_PER_PAGE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct BrowseQuery {
+    /// starting from 1
+    page: Option<usize>,
+    per_page: Option<usize>,
+    /// part of the package name
+    q: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BrowseEntry {
+    name: String,
+    normalized_name: String,
+    description: Option<String>,
+    latest_version: Option<String>,
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "packages.html")]
+pub struct PackagesTemplate {
+    query: String,
+    page: usize,
+    pages: usize,
+    per_page: usize,
+    /// packages matching the query, on all pages
+    total: usize,
+    packages: Vec<BrowseEntry>,
+    context: TemplateContext,
+    theme: Theme,
+}
+
+impl Page for PackagesTemplate {
+    const NAME: &'static str = "packages.html";
+
+    fn context(&mut self) -> &mut TemplateContext {
+        return &mut self.context;
+    }
+
+    fn theme(&mut self) -> &mut Theme {
+        return &mut self.theme;
+    }
+}
+
+pub async fn packages(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Query(BrowseQuery { page, per_page, q }): Query<BrowseQuery>,
+) -> Response {
+    let query = q.unwrap_or_default().trim().to_string();
+    let needle = names::normalize(&query);
+    let matching: Vec<String> = namespace
+        .repos
+        .all()
+        .into_iter()
+        .filter(|name| names::normalize(name).contains(&needle))
+        .collect();
+    let per_page = per_page.unwrap_or(PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let pages = matching.len().div_ceil(per_page).max(1);
+    let page = page.unwrap_or(1).clamp(1, pages);
+    let total = matching.len();
+    // only the packages shown are looked up in the metadata store
+    let packages = matching
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .map(|name| {
+            let metadata = namespace.metadata.get(&name).unwrap_or_default();
+            BrowseEntry {
+                normalized_name: names::normalize(&name),
+                name,
+                description: metadata.description,
+                latest_version: latest_release(&metadata.releases)
+                    .map(|release| release.version().to_string()),
+            }
+        })
+        .collect();
+    return app_state.templates.render(PackagesTemplate {
+        query,
+        page,
+        pages,
+        per_page,
+        total,
+        packages,
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    });
+}
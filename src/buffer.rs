@@ -0,0 +1,118 @@
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use futures_util::stream::Fuse;
+use futures_util::{FutureExt, StreamExt};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+use crate::github::ByteStream;
+use crate::metrics::STREAM_BUFFERED_BYTES;
+
+/// Default `STREAM_CHUNK_BYTES`.
+pub const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Chunk waiting for the client, holding its share of the buffer.
+struct Buffered {
+    chunk: Result<Bytes, io::Error>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Buffered {
+    fn len(&self) -> usize {
+        return self.chunk.as_ref().map_or(0, |chunk| chunk.len());
+    }
+
+    /// Hands the chunk to the client, releasing its buffer space.
+    fn take(mut self) -> Result<Bytes, io::Error> {
+        STREAM_BUFFERED_BYTES.fetch_sub(self.len() as u64, Ordering::Relaxed);
+        return std::mem::replace(&mut self.chunk, Ok(Bytes::new()));
+    }
+}
+
+impl Drop for Buffered {
+    fn drop(&mut self) {
+        STREAM_BUFFERED_BYTES.fetch_sub(self.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Reads `stream` ahead of the client by at most `limit` bytes. Once the
+/// client falls behind, reading from upstream pauses until it catches up,
+/// so a slow client can't make a download pile up in memory. Reading runs
+/// on `runtime`. Chunks upstream already has ready are sent together, up to
+/// `chunk_bytes`, rather than in the small pieces they arrive in.
+pub fn bounded(
+    stream: ByteStream<io::Error>,
+    limit: usize,
+    chunk_bytes: usize,
+    runtime: &Handle,
+) -> ByteStream<io::Error> {
+    let limit = limit.clamp(1, u32::MAX as usize);
+    let semaphore = Arc::new(Semaphore::new(limit));
+    let (sender, receiver) = mpsc::unbounded_channel();
+    runtime.spawn(async move {
+        let mut stream = stream.fuse();
+        let mut pending = None;
+        loop {
+            let chunk = match pending.take() {
+                Some(chunk) => chunk,
+                None => match stream.next().await {
+                    Some(chunk) => chunk,
+                    None => return,
+                },
+            };
+            let chunk = match chunk {
+                Ok(chunk) => Ok(coalesce(chunk, &mut stream, chunk_bytes, &mut pending)),
+                Err(error) => Err(error),
+            };
+            let size = chunk
+                .as_ref()
+                .map_or(0, |chunk| chunk.len())
+                .clamp(1, limit);
+            let Ok(permit) = semaphore.clone().acquire_many_owned(size as u32).await else {
+                return;
+            };
+            let buffered = Buffered {
+                chunk,
+                _permit: permit,
+            };
+            STREAM_BUFFERED_BYTES.fetch_add(buffered.len() as u64, Ordering::Relaxed);
+            // the client went away
+            if sender.send(buffered).is_err() {
+                return;
+            }
+        }
+    });
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let buffered: Buffered = receiver.recv().await?;
+        return Some((buffered.take(), receiver));
+    });
+    return Box::pin(stream);
+}
+
+/// `chunk` followed by the chunks `stream` has ready without waiting, up to
+/// `chunk_bytes`. An error ends it and is left in `pending`.
+fn coalesce(
+    chunk: Bytes,
+    stream: &mut Fuse<ByteStream<io::Error>>,
+    chunk_bytes: usize,
+    pending: &mut Option<Result<Bytes, io::Error>>,
+) -> Bytes {
+    let mut joined: Option<Vec<u8>> = None;
+    while joined.as_ref().map_or(chunk.len(), Vec::len) < chunk_bytes {
+        match stream.next().now_or_never() {
+            Some(Some(Ok(next))) => joined
+                .get_or_insert_with(|| chunk.to_vec())
+                .extend_from_slice(&next),
+            Some(Some(Err(error))) => {
+                *pending = Some(Err(error));
+                break;
+            }
+            // upstream ended, or has nothing ready yet
+            Some(None) | None => break,
+        }
+    }
+    return joined.map_or(chunk, Bytes::from);
+}
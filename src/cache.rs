@@ -0,0 +1,398 @@
+//! Content addressed disk cache of downloaded artifacts. Files are stored under
+//! their sha256, so a file published in several releases is kept only once.
+use std::collections::HashSet;
+use std::fs::FileTimes;
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::StreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::config::{Config, Repository};
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{Asset, ByteStream, GithubClient};
+use crate::namespace::Namespaces;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::AppState;
+
+pub struct ArtifactCache {
+    dir: Option<PathBuf>,
+}
+
+/// Whether `value` looks like a hex encoded sha256, anything else never names a cached file.
+pub fn is_sha256(value: &str) -> bool {
+    return value.len() == 64
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+}
+
+impl ArtifactCache {
+    pub fn from_config(config: &Config) -> Self {
+        return ArtifactCache {
+            dir: config.cache_dir.as_ref().map(PathBuf::from),
+        };
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return self.dir.is_some();
+    }
+
+    fn path(&self, sha256: &str) -> Option<PathBuf> {
+        if !is_sha256(sha256) {
+            return None;
+        }
+        let dir = self.dir.as_ref()?;
+        return Some(dir.join("sha256").join(&sha256[..2]).join(sha256));
+    }
+
+    /// Whether the content of `sha256` was downloaded before, without touching it.
+    pub fn contains(&self, sha256: &str) -> bool {
+        return self.path(sha256).is_some_and(|path| path.exists());
+    }
+
+    /// Path of the cached content of `sha256`, if it was downloaded before.
+    /// The access time is set explicitly, whatever the mount's atime policy,
+    /// as `gc` keeps files by when they were last downloaded.
+    pub async fn touch(&self, sha256: &str) -> Option<PathBuf> {
+        let path = self.path(sha256)?;
+        let file = File::open(&path).await.ok()?.into_std().await;
+        let _ = file.set_times(FileTimes::new().set_accessed(SystemTime::now()));
+        return Some(path);
+    }
+
+    /// Passes `stream` through while writing it to the cache. The file only
+    /// becomes visible once the stream ended without an error, so `stream`
+    /// has to be verified against `sha256` already.
+    pub async fn store(
+        &self,
+        sha256: &str,
+        stream: ByteStream<io::Error>,
+    ) -> ByteStream<io::Error> {
+        let Some(path) = self.path(sha256) else {
+            return stream;
+        };
+        let temp_path = temp_path(&path);
+        let file = match create(&temp_path).await {
+            Ok(file) => file,
+            Err(error) => {
+                log!("Can't cache {}: {}", path.display(), error);
+                return stream;
+            }
+        };
+        let write = Write {
+            stream,
+            file: Some(file),
+            temp_path,
+            path,
+        };
+        let stream = futures_util::stream::unfold(write, |mut write| async move {
+            let item = write.stream.next().await;
+            match &item {
+                Some(Ok(chunk)) => {
+                    if let Some(file) = write.file.as_mut() {
+                        if file.write_all(chunk).await.is_err() {
+                            write.discard().await;
+                        }
+                    }
+                }
+                Some(Err(_)) => write.discard().await,
+                None => write.commit().await,
+            }
+            return Some((item?, write));
+        });
+        return Box::pin(stream);
+    }
+
+    /// Downloads `asset` into the cache with `connections` ranged requests in
+    /// parallel, then verifies the whole file against the asset's digest.
+    pub async fn fetch_parallel(
+        &self,
+        client: &GithubClient,
+        repository: &Repository,
+        asset: &Asset,
+        connections: u64,
+    ) -> Result<PathBuf, ErrorResponse> {
+        let sha256 = asset.sha256().ok_or(ErrorResponse::PageNotFound)?;
+        let path = self.path(sha256).ok_or(ErrorResponse::PageNotFound)?;
+        let temp_path = temp_path(&path);
+        create(&temp_path).await?.set_len(asset.size).await?;
+
+        let part_size = asset.size.div_ceil(connections.max(1)).max(1);
+        let parts = (0..asset.size)
+            .step_by(part_size as usize)
+            .map(|start| start..(start + part_size).min(asset.size));
+        let temp_path = &temp_path;
+        let downloads = parts.map(|range| async move {
+            let mut stream = repository.asset_range(client, asset, range.clone()).await?;
+            let mut file = OpenOptions::new().write(true).open(temp_path).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+            while let Some(chunk) = stream.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await?;
+            return Ok::<(), ErrorResponse>(());
+        });
+        let downloaded = match futures_util::future::try_join_all(downloads).await {
+            Ok(_) => verify_file(temp_path, sha256).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = downloaded {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            return Err(error);
+        }
+        tokio::fs::rename(temp_path, &path).await?;
+        return Ok(path);
+    }
+
+    /// Removes cached files whose digest isn't in `referenced` and that weren't
+    /// downloaded within `retention`, along with leftovers of interrupted
+    /// downloads. With `dry_run` the files are only reported.
+    pub async fn gc(
+        &self,
+        referenced: &HashSet<String>,
+        retention: Duration,
+        dry_run: bool,
+    ) -> io::Result<GcReport> {
+        let mut report = GcReport::default();
+        let Some(dir) = &self.dir else {
+            return Ok(report);
+        };
+        let cutoff = SystemTime::now() - retention;
+        let mut prefixes = match tokio::fs::read_dir(dir.join("sha256")).await {
+            Ok(prefixes) => prefixes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(report),
+            Err(error) => return Err(error),
+        };
+        while let Some(prefix) = prefixes.next_entry().await? {
+            let mut files = tokio::fs::read_dir(prefix.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                let metadata = file.metadata().await?;
+                let name = file.file_name().to_string_lossy().to_string();
+                let is_referenced = is_sha256(&name) && referenced.contains(&name);
+                if is_referenced || metadata.accessed()? > cutoff {
+                    report.kept += 1;
+                    continue;
+                }
+                if !dry_run {
+                    tokio::fs::remove_file(file.path()).await?;
+                }
+                report.removed.push((name, metadata.len()));
+            }
+        }
+        return Ok(report);
+    }
+
+    /// Number and size of cached files and how long ago they were downloaded.
+    pub async fn stats(&self) -> io::Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        let Some(dir) = &self.dir else {
+            return Ok(stats);
+        };
+        let mut prefixes = match tokio::fs::read_dir(dir.join("sha256")).await {
+            Ok(prefixes) => prefixes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(stats),
+            Err(error) => return Err(error),
+        };
+        let now = SystemTime::now();
+        while let Some(prefix) = prefixes.next_entry().await? {
+            let mut files = tokio::fs::read_dir(prefix.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                let metadata = file.metadata().await?;
+                stats.files += 1;
+                stats.bytes += metadata.len();
+                let age = now
+                    .duration_since(metadata.accessed()?)
+                    .unwrap_or_default()
+                    .as_secs();
+                stats.oldest_access_age = stats.oldest_access_age.max(Some(age));
+                stats.newest_access_age = Some(
+                    stats
+                        .newest_access_age
+                        .map_or(age, |newest| newest.min(age)),
+                );
+            }
+        }
+        return Ok(stats);
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct CacheStats {
+    pub files: u64,
+    pub bytes: u64,
+    /// seconds since the least recently downloaded file was last downloaded
+    pub oldest_access_age: Option<u64>,
+    pub newest_access_age: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct GcReport {
+    /// file names and sizes of removed files
+    pub removed: Vec<(String, u64)>,
+    pub kept: usize,
+}
+
+impl GcReport {
+    pub fn print(&self, dry_run: bool) {
+        let action = if dry_run { "Would remove" } else { "Removed" };
+        for (name, size) in &self.removed {
+            log!("{} {} ({} bytes)", action, name, size);
+        }
+        let freed: u64 = self.removed.iter().map(|(_, size)| size).sum();
+        log!(
+            "{} {} cached files ({} bytes), kept {}",
+            action,
+            self.removed.len(),
+            freed,
+            self.kept
+        );
+    }
+}
+
+/// Digests of every file of every synced release, in all namespaces.
+pub fn referenced(namespaces: &Namespaces) -> HashSet<String> {
+    return namespaces
+        .iter()
+        .flat_map(|namespace| namespace.metadata.all())
+        .flat_map(|(_, metadata)| metadata.releases)
+        .flat_map(|release| release.assets)
+        .filter_map(|asset| asset.sha256().map(|sha256| sha256.to_string()))
+        .collect();
+}
+
+/// Runs `gc` with the configured retention. Nothing is collected while a
+/// package isn't synced, as its files would look unreferenced. Returns why
+/// the collection failed or was skipped.
+pub async fn collect_garbage(app_state: &AppState, dry_run: bool) -> Vec<String> {
+    let unsynced: Vec<String> = app_state
+        .namespaces
+        .iter()
+        .flat_map(|namespace| {
+            namespace
+                .repos
+                .iter()
+                .filter(|(package_name, _)| namespace.metadata.get(package_name).is_none())
+                .map(|(package_name, _)| namespace.qualified_name(package_name))
+        })
+        .collect();
+    if !unsynced.is_empty() {
+        let message = format!(
+            "Skipping cache garbage collection, not synced: {}",
+            unsynced.join(", ")
+        );
+        log!("{}", message);
+        return vec![message];
+    }
+    let referenced = referenced(&app_state.namespaces);
+    let retention = app_state.config.cache_retention;
+    match app_state.cache.gc(&referenced, retention, dry_run).await {
+        Ok(report) => {
+            report.print(dry_run);
+            return vec![];
+        }
+        Err(error) => {
+            let message = format!("Cache garbage collection failed: {}", error);
+            log!("{}", message);
+            app_state
+                .reporter
+                .report(Report::task("cache gc", message.clone()));
+            return vec![message];
+        }
+    }
+}
+
+/// Collects garbage every `CACHE_GC_INTERVAL`. The first run waits a full
+/// interval, so releases are synced by then and their files aren't pruned.
+pub fn spawn_gc_worker(app_state: Arc<AppState>) {
+    if app_state.config.cache_dir.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let period = app_state.config.cache_gc_interval;
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            let errors = collect_garbage(&app_state, false).await;
+            app_state.health.record("cache gc", errors);
+        }
+    });
+}
+
+/// Where a download of `path` is written until it is complete.
+fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    return path.with_extension(format!("{:x}.tmp", nanos));
+}
+
+/// Hex encoded sha256 of the content of `path`.
+pub async fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    return Ok(hex::encode(hasher.finalize()));
+}
+
+pub async fn verify_file(path: &Path, sha256: &str) -> Result<(), ErrorResponse> {
+    let actual = file_sha256(path).await?;
+    if actual != sha256 {
+        return Err(ErrorResponse::Failure(
+            ErrorCode::Cache,
+            format!("sha256 mismatch, expected {} got {}", sha256, actual),
+        ));
+    }
+    return Ok(());
+}
+
+async fn create(path: &PathBuf) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    return File::create(path).await;
+}
+
+struct Write {
+    stream: ByteStream<io::Error>,
+    file: Option<File>,
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl Write {
+    async fn discard(&mut self) {
+        if self.file.take().is_some() {
+            let _ = tokio::fs::remove_file(&self.temp_path).await;
+        }
+    }
+
+    async fn commit(&mut self) {
+        let Some(mut file) = self.file.take() else {
+            return;
+        };
+        let committed = match file.flush().await {
+            Ok(()) => tokio::fs::rename(&self.temp_path, &self.path).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = committed {
+            log!("Can't cache {}: {}", self.path.display(), error);
+            let _ = tokio::fs::remove_file(&self.temp_path).await;
+        }
+    }
+}
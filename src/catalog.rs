@@ -0,0 +1,190 @@
+//! Core metadata (summary, license, classifiers) of the latest version of
+//! every package, read from the `METADATA` of its wheel during the sync and
+//! served on the search page and at `/api/packages`.
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::response::Json;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::Repository;
+use crate::github::{latest_release, Asset, GithubClient, Release};
+use crate::metadata::PackageMetadata;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::storage::StorageSettings;
+use crate::AppState;
+
+/// Wheels larger than this aren't downloaded for their metadata.
+const MAX_WHEEL_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub version: String,
+    pub summary: Option<String>,
+    pub license: Option<String>,
+    pub classifiers: Vec<String>,
+    /// wheel the metadata was read from, it is read again only when this changes
+    pub asset_id: u64,
+}
+
+/// Headers of a `METADATA` file with lowercase names, repeated ones like
+/// `Classifier` as many times as they appear.
+pub fn metadata_headers(content: &str) -> Vec<(String, String)> {
+    // the headers end at the first empty line, the description follows
+    return content
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+}
+
+pub fn header_values<'a>(
+    headers: &'a [(String, String)],
+    name: &'a str,
+) -> impl Iterator<Item = &'a String> {
+    return headers
+        .iter()
+        .filter(move |(key, _)| key == name)
+        .map(|(_, value)| value);
+}
+
+/// `License-Expression`, or the older `License` when it says anything.
+pub fn license(headers: &[(String, String)]) -> Option<String> {
+    return header_values(headers, "license-expression")
+        .chain(header_values(headers, "license"))
+        .find(|license| !license.is_empty() && *license != "UNKNOWN")
+        .cloned();
+}
+
+/// Content of `files` of the wheel's `<name>.dist-info` directory.
+pub fn dist_info(wheel: Vec<u8>, files: &[&str]) -> Result<Vec<String>, String> {
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(wheel)).map_err(|error| error.to_string())?;
+    let dir = archive
+        .file_names()
+        .filter_map(Result::ok)
+        .find_map(|name| {
+            let (dir, file) = name.split_once('/')?;
+            (dir.ends_with(".dist-info") && file == "METADATA").then(|| dir.to_string())
+        })
+        .ok_or("the wheel has no dist-info/METADATA")?;
+    let mut contents = vec![];
+    for file in files {
+        let mut content = String::new();
+        archive
+            .by_name(&format!("{}/{}", dir, file))
+            .map_err(|error| format!("{}: {}", file, error))?
+            .read_to_string(&mut content)
+            .map_err(|error| format!("{}: {}", file, error))?;
+        contents.push(content);
+    }
+    return Ok(contents);
+}
+
+/// Content of a wheel, `None` when it is larger than `MAX_WHEEL_BYTES`.
+pub async fn download_wheel(
+    client: &GithubClient,
+    repository: &Repository,
+    wheel: &Asset,
+) -> Result<Option<Vec<u8>>, String> {
+    if wheel.size as usize > MAX_WHEEL_BYTES {
+        return Ok(None);
+    }
+    let mut stream = repository
+        .asset(client, wheel)
+        .await
+        .map_err(|error| error.to_string())?
+        .stream;
+    let mut content = vec![];
+    while let Some(chunk) = stream.next().await {
+        content.extend_from_slice(&chunk.map_err(|error| error.to_string())?);
+        if content.len() > MAX_WHEEL_BYTES {
+            return Ok(None);
+        }
+    }
+    return Ok(Some(content));
+}
+
+/// Catalog entry of the latest release, reusing `previous` while its wheel is
+/// unchanged. `None` when the release has no wheel small enough to read.
+pub async fn extract(
+    client: &GithubClient,
+    repository: &Repository,
+    releases: &[Release],
+    previous: Option<Catalog>,
+) -> Result<Option<Catalog>, String> {
+    let Some(release) = latest_release(releases) else {
+        return Ok(None);
+    };
+    let Some(wheel) = release
+        .assets
+        .iter()
+        .find(|asset| asset.name.ends_with(".whl") && asset.size as usize <= MAX_WHEEL_BYTES)
+    else {
+        return Ok(None);
+    };
+    if let Some(previous) = previous.filter(|previous| previous.asset_id == wheel.id) {
+        return Ok(Some(previous));
+    }
+    let Some(content) = download_wheel(client, repository, wheel).await? else {
+        return Ok(None);
+    };
+    let metadata = dist_info(content, &["METADATA"])
+        .map_err(|error| format!("{}: {}", wheel.name, error))?
+        .remove(0);
+    let headers = metadata_headers(&metadata);
+    return Ok(Some(Catalog {
+        version: release.version().to_string(),
+        summary: header_values(&headers, "summary").next().cloned(),
+        license: license(&headers),
+        classifiers: header_values(&headers, "classifier").cloned().collect(),
+        asset_id: wheel.id,
+    }));
+}
+
+fn package_json(
+    package_name: &str,
+    repository: &Repository,
+    metadata: Option<PackageMetadata>,
+    storage: &StorageSettings,
+) -> Value {
+    let metadata = metadata.unwrap_or_default();
+    let catalog = metadata.catalog;
+    return json!({
+        "name": package_name,
+        "normalized_name": names::normalize(package_name),
+        "repository": repository.url(storage),
+        "description": metadata.description,
+        "latest_version": latest_release(&metadata.releases).map(|release| release.version()),
+        "summary": catalog.as_ref().and_then(|catalog| catalog.summary.clone()),
+        "license": catalog.as_ref().and_then(|catalog| catalog.license.clone()),
+        "classifiers": catalog.map(|catalog| catalog.classifiers).unwrap_or_default(),
+    });
+}
+
+/// Every package of the namespace with what the catalog knows about it.
+pub async fn packages(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+) -> Json<Vec<Value>> {
+    let mut packages: Vec<(&String, &Repository)> = namespace.repos.iter().collect();
+    packages.sort_by_key(|(package_name, _)| *package_name);
+    return Json(
+        packages
+            .into_iter()
+            .map(|(package_name, repository)| {
+                package_json(
+                    package_name,
+                    repository,
+                    namespace.metadata.get(package_name),
+                    &app_state.github.storage,
+                )
+            })
+            .collect(),
+    );
+}
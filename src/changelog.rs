@@ -0,0 +1,46 @@
+//! `/api/changelog`: the packages that changed after a serial, like PyPI's
+//! changelog, so mirrors sync only what changed since their last run instead
+//! of crawling every package page.
+use axum::extract::Query;
+use axum::response::Json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::github::latest_release;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+
+#[derive(Deserialize)]
+pub struct ChangelogQuery {
+    /// the `last_serial` of the previous call, every package when not given
+    since: Option<u64>,
+}
+
+/// Packages of the namespace given a serial after `since`, oldest change first.
+pub async fn changelog(
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Query(ChangelogQuery { since }): Query<ChangelogQuery>,
+) -> Json<Value> {
+    // read first, so a change made meanwhile shows up in the next call too
+    let last_serial = namespace.metadata.last_serial();
+    let changes: Vec<Value> = namespace
+        .metadata
+        .changed_since(since.unwrap_or(0))
+        .into_iter()
+        // packages no longer configured, or private ones on the public view
+        .filter(|(package_name, _)| namespace.repos.get(package_name).is_some())
+        .map(|(package_name, serial)| {
+            let metadata = namespace.metadata.get(&package_name).unwrap_or_default();
+            json!({
+                "name": package_name,
+                "normalized_name": names::normalize(&package_name),
+                "serial": serial,
+                "latest_version": latest_release(&metadata.releases).map(|release| release.version()),
+            })
+        })
+        .collect();
+    return Json(json!({
+        "last_serial": last_serial,
+        "changes": changes,
+    }));
+}
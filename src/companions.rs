@@ -0,0 +1,102 @@
+//! Signature, attestation and checksum files published next to the
+//! distribution they cover, e.g. `pkg-1.0.tar.gz.asc` for `pkg-1.0.tar.gz`.
+//! Companions are reachable at the url of their subject with the companion
+//! suffix appended, whatever their own asset id is.
+use serde_json::{json, Value};
+
+use crate::config::{Provider, Repository};
+use crate::github::Asset;
+use crate::storage::StorageSettings;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum CompanionKind {
+    /// Detached GPG signature
+    Signature,
+    /// Sigstore bundle or PEP 740 attestation
+    Attestation,
+    /// Sha256 checksum, in `sha256sum` output format
+    Checksum,
+    /// PEP 740 provenance object, built from the `.publish.attestation`
+    Provenance,
+}
+
+/// Suffix of PEP 740 attestations, as `twine upload --attestations` expects them.
+pub const PEP740_SUFFIX: &str = ".publish.attestation";
+
+const SUFFIXES: [(&str, CompanionKind); 6] = [
+    (".asc", CompanionKind::Signature),
+    (".sigstore", CompanionKind::Attestation),
+    (".sigstore.json", CompanionKind::Attestation),
+    (".publish.attestation", CompanionKind::Attestation),
+    (".sha256", CompanionKind::Checksum),
+    (".provenance", CompanionKind::Provenance),
+];
+
+/// Release-wide checksum files listing sha256 of every other asset.
+const CHECKSUM_FILES: [&str; 4] = [
+    "SHA256SUMS",
+    "SHA256SUMS.txt",
+    "sha256sums.txt",
+    "checksums.txt",
+];
+
+/// Name of the file `filename` is a companion of, with the kind of companion.
+pub fn subject_of(filename: &str) -> Option<(&str, CompanionKind)> {
+    return SUFFIXES.iter().find_map(|(suffix, kind)| {
+        let subject = filename.strip_suffix(suffix)?;
+        Some((subject, *kind))
+    });
+}
+
+/// First asset of `kind` covering the file named `subject`.
+pub fn find<'a>(assets: &'a [Asset], subject: &str, kind: CompanionKind) -> Option<&'a Asset> {
+    return assets
+        .iter()
+        .find(|asset| subject_of(&asset.name) == Some((subject, kind)));
+}
+
+/// PEP 740 attestation of the file named `subject`, other attestation
+/// formats can't be part of a provenance object.
+pub fn pep740_attestation<'a>(assets: &'a [Asset], subject: &str) -> Option<&'a Asset> {
+    let name = format!("{}{}", subject, PEP740_SUFFIX);
+    return assets.iter().find(|asset| asset.name == name);
+}
+
+/// PEP 740 provenance object of a file with its `attestation`. The publisher
+/// is the repository the file was published to, verifiers check it against
+/// the identity in the attestation's certificate.
+pub fn provenance(repository: &Repository, storage: &StorageSettings, attestation: Value) -> Value {
+    let publisher = match repository.provider {
+        Provider::Github => json!({
+            "kind": "GitHub",
+            "repository": format!("{}/{}", repository.owner, repository.name),
+        }),
+        Provider::Pypi => json!({"kind": "PyPI", "project": repository.name}),
+        Provider::Local | Provider::S3 => {
+            json!({"kind": "pigi", "storage": repository.url(storage)})
+        }
+        Provider::Proxy => json!({"kind": "index", "url": repository.url(storage)}),
+    };
+    return json!({
+        "version": 1,
+        "attestation_bundles": [{"publisher": publisher, "attestations": [attestation]}],
+    });
+}
+
+pub fn checksum_file(assets: &[Asset]) -> Option<&Asset> {
+    return assets
+        .iter()
+        .find(|asset| CHECKSUM_FILES.contains(&asset.name.as_str()));
+}
+
+/// Line of a `sha256sum` style listing describing `filename`.
+pub fn checksum_line(checksums: &str, filename: &str) -> Option<String> {
+    return checksums.lines().find_map(|line| {
+        let (hash, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start().trim_start_matches('*');
+        if name != filename || hash.len() != 64 {
+            return None;
+        }
+        Some(format!("{}  {}\n", hash.to_lowercase(), filename))
+    });
+}
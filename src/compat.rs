@@ -0,0 +1,13 @@
+//! Redirects keeping urls of older pigi versions working: non-normalized
+//! package names and download urls addressing files by GitHub asset id.
+use askama_axum::Response;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+
+pub fn moved_permanently(location: String) -> Response {
+    return (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, location)],
+    )
+        .into_response();
+}
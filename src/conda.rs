@@ -0,0 +1,175 @@
+//! Conda channels served from the same releases: packages with a
+//! `conda_channel` in the repos config have the `.conda` and `.tar.bz2` files
+//! of their synced releases listed at `/conda/<channel>/noarch/repodata.json`.
+//! Name, version and build are read from the file name, conda's
+//! `<name>-<version>-<build>`, as the `info/index.json` inside the packages
+//! isn't read: every file is listed in `noarch` and without dependencies.
+//! Other subdirs answer an empty repodata, conda asks for the platform's too.
+use std::collections::BTreeMap;
+
+use askama_axum::Response;
+use axum::extract::Path;
+use axum::response::{IntoResponse, Json, Redirect};
+use serde_json::{json, Value};
+
+use crate::error::ErrorResponse;
+use crate::github::Asset;
+use crate::names;
+use crate::namespace::{CurrentNamespace, Namespace};
+use crate::service_accounts::CurrentAccount;
+
+const SUBDIRS: [&str; 10] = [
+    "noarch",
+    "linux-64",
+    "linux-aarch64",
+    "linux-ppc64le",
+    "linux-s390x",
+    "osx-64",
+    "osx-arm64",
+    "win-32",
+    "win-64",
+    "win-arm64",
+];
+
+/// Conda package file name: `<name>-<version>-<build>` and its extension.
+struct CondaFile<'a> {
+    name: &'a str,
+    version: &'a str,
+    build: &'a str,
+    is_conda: bool,
+}
+
+fn parse(filename: &str) -> Option<CondaFile<'_>> {
+    let (stem, is_conda) = match filename.strip_suffix(".conda") {
+        Some(stem) => (stem, true),
+        None => (filename.strip_suffix(".tar.bz2")?, false),
+    };
+    let (rest, build) = stem.rsplit_once('-')?;
+    let (name, version) = rest.rsplit_once('-')?;
+    if name.is_empty() || version.is_empty() || build.is_empty() {
+        return None;
+    }
+    return Some(CondaFile {
+        name,
+        version,
+        build,
+        is_conda,
+    });
+}
+
+/// Number ending the build string, like the `2` of `py310h1234_2`.
+fn build_number(build: &str) -> u64 {
+    let digits = build.rsplit('_').next().unwrap_or(build);
+    return digits.parse().unwrap_or(0);
+}
+
+/// Conda files of the packages of `channel` the account may read, by file
+/// name, with the package and release version they are downloaded from.
+fn channel_files(
+    namespace: &Namespace,
+    account: &CurrentAccount,
+    channel: &str,
+) -> Result<BTreeMap<String, (String, String, Asset)>, ErrorResponse> {
+    let package_names: Vec<String> = namespace
+        .repos
+        .all()
+        .into_iter()
+        .filter(|package_name| {
+            namespace
+                .repos
+                .get(package_name)
+                .is_some_and(|repository| repository.conda_channel.as_deref() == Some(channel))
+        })
+        .collect();
+    if package_names.is_empty() {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    let mut files = BTreeMap::new();
+    for package_name in package_names {
+        if account.check_package(namespace, &package_name).is_err() {
+            continue;
+        }
+        let Some(metadata) = namespace.metadata.get(&package_name) else {
+            continue;
+        };
+        for release in &metadata.releases {
+            for asset in release
+                .assets
+                .iter()
+                .filter(|asset| parse(&asset.name).is_some())
+            {
+                files.insert(
+                    asset.name.clone(),
+                    (
+                        package_name.clone(),
+                        release.version().to_string(),
+                        asset.clone(),
+                    ),
+                );
+            }
+        }
+    }
+    return Ok(files);
+}
+
+fn repodata(subdir: &str, files: &BTreeMap<String, (String, String, Asset)>) -> Value {
+    let mut packages = serde_json::Map::new();
+    let mut conda_packages = serde_json::Map::new();
+    if subdir == "noarch" {
+        for (filename, (_, _, asset)) in files {
+            let Some(file) = parse(filename) else {
+                continue;
+            };
+            let mut record = json!({
+                "name": file.name.to_lowercase(),
+                "version": file.version,
+                "build": file.build,
+                "build_number": build_number(file.build),
+                "depends": [],
+                "subdir": subdir,
+                "size": asset.size,
+            });
+            if let Some(sha256) = asset.sha256() {
+                record["sha256"] = json!(sha256);
+            }
+            match file.is_conda {
+                true => conda_packages.insert(filename.clone(), record),
+                false => packages.insert(filename.clone(), record),
+            };
+        }
+    }
+    return json!({
+        "info": {"subdir": subdir},
+        "packages": packages,
+        "packages.conda": conda_packages,
+        "removed": [],
+        "repodata_version": 1,
+    });
+}
+
+/// `GET /conda/<channel>/<subdir>/<file>`: the subdir's `repodata.json`, or a
+/// redirect to the download of one of its files.
+pub async fn channel(
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((channel, subdir, filename)): Path<(String, String, String)>,
+    account: CurrentAccount,
+) -> Result<Response, ErrorResponse> {
+    if !SUBDIRS.contains(&subdir.as_str()) {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    let files = channel_files(&namespace, &account, &channel)?;
+    if filename == "repodata.json" {
+        return Ok(Json(repodata(&subdir, &files)).into_response());
+    }
+    let (package_name, version, asset) = files
+        .get(&filename)
+        .filter(|_| subdir == "noarch")
+        .ok_or(ErrorResponse::PageNotFound)?;
+    return Ok(Redirect::temporary(&format!(
+        "../../../simple/{}/{}/{}",
+        names::normalize(package_name),
+        version,
+        asset.name
+    ))
+    .into_response());
+}
@@ -0,0 +1,87 @@
+//! Dependency confusion alerts. pip run with `--extra-index-url` installs a
+//! package pigi doesn't serve from PyPI instead, so a request for a name that
+//! looks internal but isn't configured is worth knowing about: it's either a
+//! typo in a requirement or someone probing for names to squat on PyPI. pigi
+//! has no PyPI fallback, such requests are never forwarded upstream.
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::namespace::Namespace;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::{glob, names, AppState};
+
+/// Names alerted about are remembered to alert once, up to this many.
+const MAX_ALERTED: usize = 10_000;
+
+pub struct ConfusionGuard {
+    /// `PROTECTED_PREFIXES`, normalized like package names
+    patterns: Vec<String>,
+    alerted: Mutex<HashSet<String>>,
+}
+
+impl ConfusionGuard {
+    pub fn from_config(config: &Config) -> Self {
+        let patterns = config
+            .protected_prefixes
+            .iter()
+            .map(|pattern| names::normalize(pattern))
+            .collect();
+        return ConfusionGuard {
+            patterns,
+            alerted: Mutex::default(),
+        };
+    }
+
+    /// Why `package_name`, not configured in `namespace`, looks internal.
+    fn internal_looking(
+        &self,
+        app_state: &AppState,
+        namespace: &Namespace,
+        package_name: &str,
+    ) -> Option<String> {
+        let normalized = names::normalize(package_name);
+        if let Some(pattern) = self
+            .patterns
+            .iter()
+            .find(|pattern| glob::matches(pattern, &normalized))
+        {
+            return Some(format!("it matches {}", pattern));
+        }
+        let other = app_state
+            .namespaces
+            .iter()
+            // the public view isn't listed, its private packages count as configured elsewhere
+            .filter(|other| !std::ptr::eq(other.as_ref(), namespace))
+            .find(|other| other.find_repository(package_name).is_ok())?;
+        return Some(format!(
+            "it is configured as {}",
+            other.qualified_name(package_name)
+        ));
+    }
+
+    /// Reports the first request for an internal looking package the
+    /// namespace doesn't serve.
+    pub fn check(&self, app_state: &AppState, namespace: &Namespace, package_name: &str) {
+        let Some(reason) = self.internal_looking(app_state, namespace, package_name) else {
+            return;
+        };
+        let qualified_name = namespace.qualified_name(&names::normalize(package_name));
+        {
+            let mut alerted = self.alerted.lock().unwrap();
+            if alerted.len() >= MAX_ALERTED || !alerted.insert(qualified_name.clone()) {
+                return;
+            }
+        }
+        let message = format!(
+            "Request for {} which isn't served, but looks internal as {}; \
+             pip with --extra-index-url would install it from PyPI",
+            qualified_name, reason
+        );
+        log!("{}", message);
+        app_state
+            .reporter
+            .report(Report::task("dependency confusion", message));
+    }
+}
@@ -0,0 +1,70 @@
+//! Content types downloads are served with, picked by the end of the file name.
+//! Some corporate proxies block `application/octet-stream`, so distributions
+//! and their companion files get the registered type of their format and only
+//! unknown files fall back to it. `CONTENT_TYPES` adds or overrides mappings.
+use askama_axum::Response;
+use axum::http::{header, HeaderValue};
+
+use crate::config::Config;
+
+const FALLBACK: &str = "application/octet-stream";
+
+const DEFAULTS: [(&str, &str); 14] = [
+    // wheels are zip archives, there is no registered type of their own
+    (".whl", "application/zip"),
+    (".zip", "application/zip"),
+    (".egg", "application/zip"),
+    (".tar.gz", "application/gzip"),
+    (".tgz", "application/gzip"),
+    (".tar.bz2", "application/x-bzip2"),
+    (".metadata", "text/plain; charset=utf-8"),
+    (".sha256", "text/plain; charset=utf-8"),
+    (".txt", "text/plain; charset=utf-8"),
+    (".asc", "application/pgp-signature"),
+    (".sigstore", "application/json"),
+    (".json", "application/json"),
+    (".publish.attestation", "application/json"),
+    (".provenance", "application/json"),
+];
+
+pub struct ContentTypes {
+    /// suffixes with their type, longest suffix first
+    mappings: Vec<(String, HeaderValue)>,
+}
+
+impl ContentTypes {
+    pub fn from_config(config: &Config) -> Self {
+        let mut mappings: Vec<(String, HeaderValue)> = vec![];
+        let defaults = DEFAULTS
+            .iter()
+            .map(|(suffix, content_type)| (suffix.to_string(), content_type.to_string()));
+        for (suffix, content_type) in defaults.chain(config.content_types.iter().cloned()) {
+            let value = HeaderValue::from_str(&content_type).unwrap_or_else(|_| {
+                panic!("{} is not a valid content type of {}", content_type, suffix)
+            });
+            let suffix = suffix.to_lowercase();
+            mappings.retain(|(known, _)| *known != suffix);
+            mappings.push((suffix, value));
+        }
+        mappings.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        return ContentTypes { mappings };
+    }
+
+    pub fn of(&self, filename: &str) -> HeaderValue {
+        let filename = filename.to_lowercase();
+        return self
+            .mappings
+            .iter()
+            .find(|(suffix, _)| filename.ends_with(suffix.as_str()))
+            .map(|(_, content_type)| content_type.clone())
+            .unwrap_or(HeaderValue::from_static(FALLBACK));
+    }
+
+    /// Sets the content type of a download of `filename`.
+    pub fn apply(&self, filename: &str, mut response: Response) -> Response {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, self.of(filename));
+        return response;
+    }
+}
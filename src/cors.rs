@@ -0,0 +1,120 @@
+//! CORS of the JSON APIs, for dashboards and docs sites querying pigi from
+//! the browser. Requests to `/api/`, `/simple/` (the JSON Simple API is
+//! negotiated on the same urls) and `/pypi/` from `CORS_ALLOWED_ORIGINS` are
+//! answered with the `Access-Control-*` headers, and their preflight requests
+//! before they reach the access token check, since browsers send them without
+//! credentials.
+use std::sync::Arc;
+
+use askama_axum::Response;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+
+use crate::config::Config;
+use crate::AppState;
+
+const ALLOWED_HEADERS: &str = "Authorization, Accept, Content-Type, If-None-Match";
+const EXPOSED_HEADERS: &str = "ETag, Retry-After, X-Pigi-Error";
+/// Seconds browsers may cache the answer to a preflight request.
+const MAX_AGE: &str = "600";
+
+/// Whether `path` is one of the JSON APIs, in any namespace.
+fn in_scope(app_state: &AppState, path: &str) -> bool {
+    let path = match path[1..].split_once('/') {
+        Some((first, _)) if app_state.namespaces.by_name(first).is_some() => {
+            &path[first.len() + 1..]
+        }
+        _ => path,
+    };
+    let path = path.strip_prefix("/public").unwrap_or(path);
+    return ["/api/", "/simple/", "/pypi/"]
+        .iter()
+        .any(|prefix| path.starts_with(prefix));
+}
+
+/// The `Access-Control-Allow-Origin` of requests from `origin`, when it's allowed.
+fn allowed_origin(config: &Config, origin: &str) -> Option<HeaderValue> {
+    if config
+        .cors_allowed_origins
+        .iter()
+        .any(|allowed| allowed == origin)
+    {
+        return HeaderValue::from_str(origin).ok();
+    }
+    if config
+        .cors_allowed_origins
+        .iter()
+        .any(|allowed| allowed == "*")
+    {
+        return Some(HeaderValue::from_static("*"));
+    }
+    return None;
+}
+
+fn add_headers(headers: &mut HeaderMap, allow_origin: HeaderValue) {
+    // credentials can't be sent to the wildcard origin, only to listed ones
+    if allow_origin != "*" {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+}
+
+pub async fn cors(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = &app_state.config;
+    if config.cors_allowed_origins.is_empty() || !in_scope(&app_state, request.uri().path()) {
+        return next.run(request).await;
+    }
+    let allow_origin = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .and_then(|origin| allowed_origin(config, origin));
+    let Some(allow_origin) = allow_origin else {
+        return next.run(request).await;
+    };
+    let is_preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if is_preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        add_headers(headers, allow_origin);
+        if let Ok(methods) = HeaderValue::from_str(&config.cors_allowed_methods.join(", ")) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static(ALLOWED_HEADERS),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static(MAX_AGE),
+        );
+        return response;
+    }
+    let allowed_method = config
+        .cors_allowed_methods
+        .iter()
+        .any(|method| method.eq_ignore_ascii_case(request.method().as_str()));
+    let mut response = next.run(request).await;
+    if allowed_method {
+        let headers = response.headers_mut();
+        add_headers(headers, allow_origin);
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSED_HEADERS),
+        );
+    }
+    return response;
+}
@@ -0,0 +1,458 @@
+//! `/debug/status`: what the running instance knows, for answering "why is
+//! package X missing?" without shell access. Only served when `DEBUG_TOKEN`
+//! is set, to clients sending it as the basic auth password.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::extract::State;
+use axum::response::Json;
+use axum_auth::AuthBasic;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::error::ErrorResponse;
+use crate::github::{GithubClient, Release};
+use crate::metadata;
+use crate::metrics;
+use crate::notify::NewRelease;
+use crate::preflight::TOKEN_CHECK_TIMEOUT;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::service_accounts::{CurrentAccount, Operation};
+use crate::AppState;
+
+fn age(time: Option<SystemTime>) -> Option<u64> {
+    return Some(SystemTime::now().duration_since(time?).ok()?.as_secs());
+}
+
+fn redacted(value: &Option<String>) -> Option<&'static str> {
+    return value.as_ref().map(|_| "[redacted]");
+}
+
+fn config_summary(config: &Config) -> Value {
+    return json!({
+        "port": config.port,
+        "external_url": config.external_url,
+        "repos_config_path": config.repos_config_path,
+        "repos_config_overlays": config.repos_config_overlays,
+        "github_token": redacted(&config.github_token),
+        "sync_interval": config.sync_interval.as_secs(),
+        "templates_dir": config.templates_dir,
+        "template_context_path": config.template_context_path,
+        "static_dir": config.static_dir,
+        "brand_name": config.brand_name,
+        "brand_logo_url": config.brand_logo_url,
+        "notifications_config_path": config.notifications_config_path,
+        "snapshots_dir": config.snapshots_dir,
+        "cache_dir": config.cache_dir,
+        "cache_retention": config.cache_retention.as_secs(),
+        "cache_gc_interval": config.cache_gc_interval.as_secs(),
+        "stream_buffer_bytes": config.stream_buffer_bytes,
+        "stream_chunk_bytes": config.stream_chunk_bytes,
+        "stream_no_transform": config.stream_no_transform,
+        "parallel_fetch_threshold": config.parallel_fetch_threshold,
+        "parallel_fetch_connections": config.parallel_fetch_connections,
+        "worker_threads": config.worker_threads,
+        "streaming_worker_threads": config.streaming_worker_threads,
+        "max_concurrent_requests": config.max_concurrent_requests,
+        "github_latency_threshold": config.github_latency_threshold.map(|threshold| threshold.as_millis() as u64),
+        "github_max_concurrent": config.github_max_concurrent,
+        "github_points_per_minute": config.github_points_per_minute,
+        "github_hedge_after": config.github_hedge_after.map(|after| after.as_millis() as u64),
+        "github_api_url": config.github_api_url,
+        "github_api_fallback_url": config.github_api_fallback_url,
+        "github_api_failures": config.github_api_failures,
+        "github_api_probe_interval": config.github_api_probe_interval.as_secs(),
+        "reuse_port": config.reuse_port,
+        "tcp_nodelay": config.tcp_nodelay,
+        "drain_timeout": config.drain_timeout.as_secs(),
+        "max_upload_size": config.max_upload_size,
+        "secrets_provider": config.secrets_provider,
+        "leader_election": config.leader_election,
+        "sentry_dsn": redacted(&config.sentry_dsn),
+        "error_webhook_url": redacted(&config.error_webhook_url),
+        "protected_prefixes": config.protected_prefixes,
+        "osv_url": config.osv_url,
+        "quarantine_path": config.quarantine_path,
+        "index_signing_key_path": config.index_signing_key_path,
+        "privacy_mode": config.privacy_mode,
+        "privacy_hash_key": redacted(&config.privacy_hash_key),
+        "hash_backfill_interval": config.hash_backfill_interval.map(|interval| interval.as_secs()),
+        "link_check_interval": config.link_check_interval.map(|interval| interval.as_secs()),
+        "link_check_sample": config.link_check_sample,
+        "simple_html_deprecated_at": config.simple_html_deprecated_at,
+        "simple_html_sunset_at": config.simple_html_sunset_at,
+        "content_types": config
+            .content_types
+            .iter()
+            .map(|(suffix, content_type)| format!("{}={}", suffix, content_type))
+            .collect::<Vec<_>>(),
+        "follow_renames": config.follow_renames,
+        "signed_url_key": redacted(&config.signed_url_key),
+        "signed_url_ttl": config.signed_url_ttl.as_secs(),
+        "service_accounts_path": config.service_accounts_path,
+        "auth_failure_limit": config.auth_failure_limit,
+        "auth_failure_window": config.auth_failure_window.as_secs(),
+        "auth_lockout": config.auth_lockout.as_secs(),
+        "client_ip_header": config.client_ip_header,
+        "security_headers": config.security_headers,
+        "content_security_policy": config.content_security_policy,
+        "referrer_policy": config.referrer_policy,
+        "cors_allowed_origins": config.cors_allowed_origins,
+        "cors_allowed_methods": config.cors_allowed_methods,
+        "trusted_publishing_issuer": config.trusted_publishing_issuer,
+        "trusted_publishing_audience": config.trusted_publishing_audience,
+        "storage_dir": config.storage_dir,
+        "s3_bucket": config.s3_bucket,
+        "s3_endpoint": config.s3_endpoint,
+        "upstream_url": config.upstream_url,
+        "upstream_token": redacted(&config.upstream_token),
+        "upstream_artifacts": config.upstream_artifacts,
+    });
+}
+
+fn versions(releases: &[Release]) -> Vec<String> {
+    return releases
+        .iter()
+        .map(|release| release.version().to_string())
+        .collect();
+}
+
+fn namespaces(app_state: &AppState) -> Vec<Value> {
+    return app_state
+        .namespaces
+        .iter()
+        .map(|namespace| {
+            let packages: BTreeMap<&String, Value> = namespace
+                .repos
+                .iter()
+                .map(|(package_name, repository)| {
+                    let metadata = namespace.metadata.get(package_name);
+                    let package = json!({
+                        "repository": format!("{}/{}", repository.owner, repository.name),
+                        "visibility": repository.visibility,
+                        "synced": metadata.is_some(),
+                        "synced_age": age(namespace.metadata.synced_at(package_name)),
+                        "releases": metadata.as_ref().map(|metadata| metadata.releases.len()),
+                        "quarantined": metadata.map(|metadata| versions(&metadata.quarantined)),
+                    });
+                    (package_name, package)
+                })
+                .collect();
+            json!({
+                "name": namespace.name,
+                "access_tokens": namespace.access_tokens.is_some(),
+                "packages": packages,
+            })
+        })
+        .collect();
+}
+
+/// Rate limit budget of every distinct GitHub token, with the namespaces using it.
+async fn rate_limits(app_state: &AppState) -> Vec<Value> {
+    let mut tokens: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+    for namespace in app_state.namespaces.iter() {
+        tokens
+            .entry(namespace.github_token.get())
+            .or_default()
+            .push(namespace.name.clone());
+    }
+    let checks = tokens.into_iter().map(|(token, namespaces)| async move {
+        let authenticated = token.is_some();
+        let client = GithubClient::new(&app_state.github, token);
+        let budget = match tokio::time::timeout(TOKEN_CHECK_TIMEOUT, client.rate_limit()).await {
+            Ok(Ok(rate_limit)) => json!(rate_limit),
+            Ok(Err(error)) => json!({"error": error.to_string()}),
+            Err(_) => json!({"error": "GitHub didn't respond in time"}),
+        };
+        json!({
+            "namespaces": namespaces,
+            "authenticated": authenticated,
+            "rate_limit": budget,
+        })
+    });
+    return futures_util::future::join_all(checks).await;
+}
+
+/// Lets through requests with `DEBUG_TOKEN`, or the token of a service
+/// account allowed to `admin`, as the password. The endpoints don't exist
+/// without `DEBUG_TOKEN`.
+pub fn authorize(app_state: &AppState, auth: Option<AuthBasic>) -> Result<(), ErrorResponse> {
+    let Some(debug_token) = &app_state.config.debug_token else {
+        return Err(ErrorResponse::PageNotFound);
+    };
+    let password = auth.and_then(|AuthBasic((_, password))| password);
+    if password.as_ref() == Some(debug_token) {
+        return Ok(());
+    }
+    let is_admin = password
+        .zip(app_state.service_accounts.as_ref())
+        .and_then(|(password, service_accounts)| service_accounts.authenticate(&password))
+        .is_some_and(|account| account.allows(Operation::Admin));
+    if !is_admin {
+        return Err(ErrorResponse::Unauthorized);
+    }
+    return Ok(());
+}
+
+pub async fn status(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let artifact_cache = match app_state.cache.stats().await {
+        Ok(stats) => json!(stats),
+        Err(error) => json!({"error": error.to_string()}),
+    };
+    return Ok(Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": env!("PIGI_COMMIT"),
+        "config": config_summary(&app_state.config),
+        "namespaces": namespaces(&app_state),
+        "artifact_cache": artifact_cache,
+        "rate_limits": rate_limits(&app_state).await,
+        "github_api": app_state.github.failover.status(),
+        "github_cache": app_state.github.cache.status(),
+        "github_scheduler": app_state.github.scheduler.status(),
+        "tasks": app_state.health.tasks(),
+        "errors": metrics::errors()
+            .into_iter()
+            .map(|(code, count)| (code.as_str(), count))
+            .collect::<BTreeMap<_, _>>(),
+    })));
+}
+
+/// Every synced version with known vulnerabilities, in all namespaces.
+pub async fn vulnerabilities(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let mut affected = vec![];
+    for namespace in app_state.namespaces.iter() {
+        for (package_name, metadata) in namespace.metadata.all() {
+            for (version, vulnerabilities) in metadata.vulnerabilities {
+                affected.push(json!({
+                    "package": namespace.qualified_name(&package_name),
+                    "version": version,
+                    "vulnerabilities": vulnerabilities,
+                }));
+            }
+        }
+    }
+    affected.sort_by(|a, b| a["package"].as_str().cmp(&b["package"].as_str()));
+    return Ok(Json(json!({
+        "enabled": app_state.osv.is_some(),
+        "affected": affected,
+    })));
+}
+
+/// Distribution files with malformed or non-compliant names, by repository.
+pub async fn filenames(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let mut repositories = BTreeMap::new();
+    for namespace in app_state.namespaces.iter() {
+        for (package_name, metadata) in namespace.metadata.all() {
+            let Some(repository) = namespace.repos.get(&package_name) else {
+                continue;
+            };
+            if metadata.filename_issues.is_empty() {
+                continue;
+            }
+            repositories.insert(
+                format!("{}/{}", repository.owner, repository.name),
+                json!({
+                    "package": namespace.qualified_name(&package_name),
+                    "hidden": metadata.filename_issues.iter().filter(|issue| issue.hidden).count(),
+                    "issues": metadata.filename_issues,
+                }),
+            );
+        }
+    }
+    return Ok(Json(json!(repositories)));
+}
+
+/// Files found gone by the link check, left out of the index.
+pub async fn links(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    return Ok(Json(json!({
+        "enabled": app_state.config.link_check_interval.is_some(),
+        "broken": app_state.links.broken(),
+    })));
+}
+
+/// Repositories renamed, transferred or archived since they were configured.
+pub async fn repositories(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let mut repositories = vec![];
+    for namespace in app_state.namespaces.iter() {
+        for (package_name, metadata) in namespace.metadata.all() {
+            let Some(repository) = namespace.repos.get(&package_name) else {
+                continue;
+            };
+            if metadata.moved_to.is_none() && !metadata.archived {
+                continue;
+            }
+            repositories.push(json!({
+                "package": namespace.qualified_name(&package_name),
+                "repository": format!("{}/{}", repository.owner, repository.name),
+                "moved_to": metadata.moved_to,
+                "archived": metadata.archived,
+            }));
+        }
+    }
+    repositories.sort_by(|a, b| a["package"].as_str().cmp(&b["package"].as_str()));
+    return Ok(Json(json!(repositories)));
+}
+
+/// Every release held for review, in all namespaces.
+pub async fn quarantined(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let mut quarantined = vec![];
+    for namespace in app_state.namespaces.iter() {
+        for (package_name, metadata) in namespace.metadata.all() {
+            for release in metadata.quarantined {
+                let files: Vec<&String> = release.assets.iter().map(|asset| &asset.name).collect();
+                quarantined.push(json!({
+                    "package": namespace.qualified_name(&package_name),
+                    "version": release.version(),
+                    "tag": release.tag_name,
+                    "published_at": release.published_at,
+                    "files": files,
+                }));
+            }
+        }
+    }
+    quarantined.sort_by(|a, b| a["package"].as_str().cmp(&b["package"].as_str()));
+    return Ok(Json(json!({
+        "enabled": app_state.quarantine.is_some(),
+        "quarantined": quarantined,
+    })));
+}
+
+/// Release named by the body of approvals and promotions.
+#[derive(Deserialize)]
+pub struct PackageVersion {
+    /// qualified name, `<namespace>/<package>` outside of the default namespace
+    package: String,
+    version: String,
+}
+
+/// Serves a quarantined release and announces it like a new one.
+pub async fn approve(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+    account: CurrentAccount,
+    Json(approval): Json<PackageVersion>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let quarantine = app_state
+        .quarantine
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let (namespace_name, package_name) = approval
+        .package
+        .split_once('/')
+        .unwrap_or(("", &approval.package));
+    let namespace = app_state
+        .namespaces
+        .by_name(namespace_name)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let (configured_name, _) = namespace.find_repository(package_name)?;
+    account.check_package(namespace, configured_name)?;
+    let package = namespace.qualified_name(configured_name);
+    let is_quarantined = namespace
+        .metadata
+        .get(configured_name)
+        .is_some_and(|metadata| versions(&metadata.quarantined).contains(&approval.version));
+    if !is_quarantined {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    quarantine
+        .approve(&package, &approval.version)
+        .map_err(|error| {
+            ErrorResponse::ServerError(Some(format!("Can't save the approval: {}", error)))
+        })?;
+    let Some(release) = namespace
+        .metadata
+        .approve(configured_name, &approval.version)
+    else {
+        // approved meanwhile by a concurrent request
+        return Err(ErrorResponse::PageNotFound);
+    };
+    log!("Approved {} {}", package, approval.version);
+    let new_release = NewRelease {
+        package_name: package.clone(),
+        release,
+    };
+    for message in app_state.notifier.notify(&new_release).await {
+        app_state
+            .reporter
+            .report(Report::task("notifications", message));
+    }
+    return Ok(Json(json!({
+        "package": package,
+        "version": approval.version,
+    })));
+}
+
+/// Publishes a draft release staged with `pigi publish --draft`. GitHub
+/// publishes it with all of its files at once, and the package is synced
+/// right away so they are served from the next request on.
+pub async fn promote(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+    account: CurrentAccount,
+    Json(promotion): Json<PackageVersion>,
+) -> Result<Json<Value>, ErrorResponse> {
+    authorize(&app_state, auth)?;
+    let (namespace_name, package_name) = promotion
+        .package
+        .split_once('/')
+        .unwrap_or(("", &promotion.package));
+    let namespace = app_state
+        .namespaces
+        .by_name(namespace_name)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let (configured_name, repository) = namespace.find_repository(package_name)?;
+    account.check_package(namespace, configured_name)?;
+    let package = namespace.qualified_name(configured_name);
+    let client = GithubClient::new(&app_state.github, namespace.github_token.get());
+    let draft = repository
+        .draft(&client, &promotion.version)
+        .await?
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let release = client
+        .publish_release(&repository.owner, &repository.name, draft.id)
+        .await?;
+    log!("Promoted {} {}", package, promotion.version);
+    let notify = app_state.leader.is_leader().await;
+    metadata::sync_package(
+        &app_state,
+        namespace,
+        &client,
+        configured_name,
+        repository,
+        notify,
+    )
+    .await;
+    return Ok(Json(json!({
+        "package": package,
+        "version": promotion.version,
+        "url": release.html_url,
+    })));
+}
@@ -0,0 +1,214 @@
+//! `pigi download`: fetches the files of a release from a running pigi, for
+//! scripts that need an artifact without going through pip. Downloads are
+//! written next to their destination and resumed when interrupted.
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use crate::cache::verify_file;
+use crate::{fail, glob};
+
+/// How many times an interrupted download is resumed before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+/// How long the server may send nothing before the download counts as interrupted.
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct ReleaseJson {
+    urls: Vec<FileJson>,
+}
+
+#[derive(Deserialize)]
+struct FileJson {
+    filename: String,
+    url: String,
+    digests: Digests,
+}
+
+#[derive(Deserialize)]
+struct Digests {
+    sha256: Option<String>,
+}
+
+pub struct Download {
+    /// `<package>==<version>`
+    pub requirement: String,
+    /// address of pigi, with or without the trailing `/simple/`
+    pub index_url: String,
+    pub token: Option<String>,
+    pub dest: PathBuf,
+    /// only files matching this `*` pattern
+    pub pattern: Option<String>,
+}
+
+/// Downloads every file of the release matching the pattern into `dest`.
+pub async fn run(download: Download) {
+    let Some((package, version)) = download.requirement.split_once("==") else {
+        fail(format!(
+            "{} isn't <package>==<version>",
+            download.requirement
+        ));
+    };
+    let (package, version) = (package.trim(), version.trim());
+    let index_url = download.index_url.trim_end_matches('/');
+    let index_url = index_url.strip_suffix("/simple").unwrap_or(index_url);
+    let client = reqwest::Client::new();
+    let authorized = |request: reqwest::RequestBuilder| match &download.token {
+        Some(token) => request.basic_auth("__token__", Some(token)),
+        None => request,
+    };
+
+    let url = format!("{}/pypi/{}/{}/json", index_url, package, version);
+    let response = authorized(client.get(&url))
+        .send()
+        .await
+        .unwrap_or_else(|error| fail(format!("Can't reach {}: {}", url, error)));
+    let release: ReleaseJson = match response.status() {
+        StatusCode::NOT_FOUND => fail(format!(
+            "{} {} isn't served by {}",
+            package, version, index_url
+        )),
+        StatusCode::UNAUTHORIZED => fail(format!("{} rejected the token", index_url)),
+        status if !status.is_success() => fail(format!("{} responded {}", url, status)),
+        _ => response
+            .json()
+            .await
+            .unwrap_or_else(|error| fail(format!("Can't parse {}: {}", url, error))),
+    };
+    let files: Vec<FileJson> = release
+        .urls
+        .into_iter()
+        .filter(|file| match &download.pattern {
+            Some(pattern) => glob::matches(pattern, &file.filename),
+            None => true,
+        })
+        .collect();
+    if files.is_empty() {
+        fail(format!("No file of {} {} matches", package, version));
+    }
+
+    tokio::fs::create_dir_all(&download.dest)
+        .await
+        .unwrap_or_else(|error| {
+            fail(format!(
+                "Can't create {}: {}",
+                download.dest.display(),
+                error
+            ))
+        });
+    for file in files {
+        let path = download.dest.join(&file.filename);
+        let Some(sha256) = &file.digests.sha256 else {
+            fail(format!("{} has no sha256 to verify it with", file.filename));
+        };
+        if verify_file(&path, sha256).await.is_ok() {
+            println!("{} is already downloaded", path.display());
+            continue;
+        }
+        let request = || authorized(client.get(&file.url));
+        match fetch(request, &path, sha256).await {
+            Ok(size) => println!(
+                "Downloaded {} ({} bytes, sha256 verified)",
+                path.display(),
+                size
+            ),
+            Err(error) => fail(format!("Can't download {}: {}", file.filename, error)),
+        }
+    }
+}
+
+/// Downloads into `<path>.part`, continuing what an earlier attempt left there
+/// with a `Range` request, and moves it to `path` once the sha256 matches.
+async fn fetch<F>(request: F, path: &Path, sha256: &str) -> Result<u64, String>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    let mut last_error = String::new();
+    let mut complete = false;
+    for _ in 0..MAX_ATTEMPTS {
+        let received = tokio::fs::metadata(&part_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let mut request = request();
+        if received > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", received));
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(error) => {
+                last_error = error.to_string();
+                continue;
+            }
+        };
+        let resumed = match response.status() {
+            StatusCode::PARTIAL_CONTENT => true,
+            // the part already holds the whole file
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                complete = true;
+                break;
+            }
+            status if status.is_success() => false,
+            status => return Err(format!("server responded {}", status)),
+        };
+        let mut part = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part_path)
+            .await
+            .map_err(|error| error.to_string())?;
+        let mut stream = response.bytes_stream();
+        let mut interrupted = false;
+        loop {
+            match tokio::time::timeout(STALL_TIMEOUT, stream.next()).await {
+                Ok(None) => break,
+                Ok(Some(Ok(chunk))) => part
+                    .write_all(&chunk)
+                    .await
+                    .map_err(|error| error.to_string())?,
+                Ok(Some(Err(error))) => {
+                    last_error = error.to_string();
+                    interrupted = true;
+                    break;
+                }
+                Err(_) => {
+                    last_error = "the server stopped sending".to_string();
+                    interrupted = true;
+                    break;
+                }
+            }
+        }
+        part.flush().await.map_err(|error| error.to_string())?;
+        if !interrupted {
+            complete = true;
+            break;
+        }
+        println!("Download of {} interrupted, resuming", path.display());
+    }
+    if !complete {
+        // the part is kept, so running the command again continues the download
+        return Err(last_error);
+    }
+    if let Err(error) = verify_file(&part_path, sha256).await {
+        let _ = tokio::fs::remove_file(&part_path).await;
+        return Err(error.to_string());
+    }
+    tokio::fs::rename(&part_path, path)
+        .await
+        .map_err(|error| error.to_string())?;
+    let size = tokio::fs::metadata(path)
+        .await
+        .map_err(|error| error.to_string())?
+        .len();
+    return Ok(size);
+}
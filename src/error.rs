@@ -0,0 +1,274 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use askama_axum::Response;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::json;
+
+use crate::metrics;
+use crate::redact::{log, redact};
+
+/// Header naming the `ErrorCode` of a failed response.
+pub const ERROR_CODE_HEADER: &str = "x-pigi-error";
+
+/// Stable classes of failures, named the same in responses, logs, metrics and
+/// `/debug/status`, so "GitHub is down" can be told from "bad token".
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// pigi's configuration or templates
+    Config,
+    /// a token is missing or rejected
+    Auth,
+    /// GitHub or PyPI answered with another 4xx
+    Upstream4xx,
+    /// GitHub or PyPI failed, or couldn't be reached
+    Upstream5xx,
+    Timeout,
+    /// the disk cache or snapshots
+    Cache,
+    RateLimited,
+    Overloaded,
+    Internal,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 9] = [
+        ErrorCode::Config,
+        ErrorCode::Auth,
+        ErrorCode::Upstream4xx,
+        ErrorCode::Upstream5xx,
+        ErrorCode::Timeout,
+        ErrorCode::Cache,
+        ErrorCode::RateLimited,
+        ErrorCode::Overloaded,
+        ErrorCode::Internal,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        return match self {
+            ErrorCode::Config => "config",
+            ErrorCode::Auth => "auth",
+            ErrorCode::Upstream4xx => "upstream_4xx",
+            ErrorCode::Upstream5xx => "upstream_5xx",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Cache => "cache",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Overloaded => "overloaded",
+            ErrorCode::Internal => "internal",
+        };
+    }
+
+    /// Class of an unexpected status of GitHub or PyPI.
+    pub fn from_status(status: reqwest::StatusCode) -> Self {
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return ErrorCode::Auth;
+        }
+        if status.is_client_error() {
+            return ErrorCode::Upstream4xx;
+        }
+        if status.is_server_error() {
+            return ErrorCode::Upstream5xx;
+        }
+        return ErrorCode::Internal;
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.as_str());
+    }
+}
+
+pub enum ErrorResponse {
+    /// unclassified, `internal`
+    ServerError(Option<String>),
+    /// a server error of a known class
+    Failure(ErrorCode, String),
+    PageNotFound,
+    Unauthorized,
+    /// the client's access policy doesn't allow it, with why
+    Forbidden(String),
+    /// what the client sent is refused, with why
+    BadRequest(String),
+    /// what the client sent clashes with what is already there, with why
+    Conflict(String),
+    /// what the client sent is over a size limit, with why
+    TooLarge(String),
+    /// overloaded, the client should retry later
+    ServiceUnavailable,
+    /// GitHub's rate limit is used up until `reset`, a unix timestamp, and
+    /// there is nothing cached to answer with
+    RateLimited {
+        reset: u64,
+    },
+    /// too many failed logins, the client may try again in `retry_after` seconds
+    LockedOut {
+        retry_after: u64,
+    },
+}
+
+impl From<reqwest::Error> for ErrorResponse {
+    fn from(value: reqwest::Error) -> Self {
+        let code = if value.is_timeout() {
+            ErrorCode::Timeout
+        } else {
+            ErrorCode::Upstream5xx
+        };
+        return ErrorResponse::Failure(code, "Error during http request".to_string());
+    }
+}
+
+impl From<std::io::Error> for ErrorResponse {
+    fn from(value: std::io::Error) -> Self {
+        return ErrorResponse::Failure(ErrorCode::Cache, value.to_string());
+    }
+}
+
+impl ErrorResponse {
+    /// Class of the failure, `None` for a page that doesn't exist.
+    pub fn code(&self) -> Option<ErrorCode> {
+        return match self {
+            ErrorResponse::ServerError(_) => Some(ErrorCode::Internal),
+            ErrorResponse::Failure(code, _) => Some(*code),
+            ErrorResponse::PageNotFound => None,
+            ErrorResponse::Unauthorized => Some(ErrorCode::Auth),
+            ErrorResponse::Forbidden(_) => Some(ErrorCode::Auth),
+            ErrorResponse::BadRequest(_) => None,
+            ErrorResponse::Conflict(_) => None,
+            ErrorResponse::TooLarge(_) => None,
+            ErrorResponse::ServiceUnavailable => Some(ErrorCode::Overloaded),
+            ErrorResponse::RateLimited { .. } => Some(ErrorCode::RateLimited),
+            ErrorResponse::LockedOut { .. } => Some(ErrorCode::Auth),
+        };
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ErrorResponse::ServerError(message) => {
+                write!(
+                    f,
+                    "[internal] {}",
+                    message.as_deref().unwrap_or("Internal server error")
+                )
+            }
+            ErrorResponse::Failure(code, message) => write!(f, "[{}] {}", code, message),
+            ErrorResponse::PageNotFound => write!(f, "Page not found"),
+            ErrorResponse::Unauthorized => write!(f, "Unauthorized"),
+            ErrorResponse::Forbidden(message) => write!(f, "{}", message),
+            ErrorResponse::BadRequest(message) => write!(f, "{}", message),
+            ErrorResponse::Conflict(message) => write!(f, "{}", message),
+            ErrorResponse::TooLarge(message) => write!(f, "{}", message),
+            ErrorResponse::ServiceUnavailable => write!(f, "Service overloaded"),
+            ErrorResponse::RateLimited { reset } => {
+                write!(
+                    f,
+                    "[rate_limited] GitHub rate limit used up until {}",
+                    reset
+                )
+            }
+            ErrorResponse::LockedOut { retry_after } => {
+                write!(
+                    f,
+                    "Too many failed logins, retry in {} seconds",
+                    retry_after
+                )
+            }
+        };
+    }
+}
+
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        let code = self.code();
+        if let Some(code) = code {
+            metrics::record_error(code);
+        }
+        if matches!(
+            self,
+            ErrorResponse::ServerError(_) | ErrorResponse::Failure(..)
+        ) {
+            log!("Request failed: {}", self);
+        }
+        let mut response = self.render();
+        if let Some(code) = code {
+            response.headers_mut().insert(
+                ERROR_CODE_HEADER,
+                header::HeaderValue::from_static(code.as_str()),
+            );
+        }
+        return response;
+    }
+}
+
+impl ErrorResponse {
+    fn render(self) -> Response {
+        match self {
+            ErrorResponse::ServerError(message) => {
+                let message = message.unwrap_or("Internal server error".to_string());
+                (StatusCode::INTERNAL_SERVER_ERROR, redact(&message)).into_response()
+            }
+            ErrorResponse::Failure(_, message) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, redact(&message)).into_response()
+            }
+            ErrorResponse::PageNotFound => (StatusCode::NOT_FOUND, "Page not found").into_response(),
+            ErrorResponse::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"pigi\"")],
+                "Unauthorized",
+            )
+                .into_response(),
+            ErrorResponse::Forbidden(message) => (StatusCode::FORBIDDEN, message).into_response(),
+            ErrorResponse::BadRequest(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            ErrorResponse::Conflict(message) => (StatusCode::CONFLICT, message).into_response(),
+            ErrorResponse::TooLarge(message) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, message).into_response()
+            }
+            ErrorResponse::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, "5")],
+                "Service overloaded, retry later",
+            )
+                .into_response(),
+            ErrorResponse::RateLimited { reset } => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let retry_after = reset.saturating_sub(now).max(1);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(json!({
+                        "error": "github_rate_limited",
+                        "message": "GitHub's rate limit is used up, retry later",
+                        "retry_after": retry_after,
+                        "reset": reset,
+                    })),
+                )
+                    .into_response()
+            }
+            ErrorResponse::LockedOut { retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                format!("Too many failed logins, retry in {} seconds", retry_after),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The message of the `Forbidden` error `result` holds, for tests of checks.
+#[cfg(test)]
+pub fn refusal<T>(result: Result<T, ErrorResponse>) -> String {
+    return match result {
+        Err(ErrorResponse::Forbidden(message)) => message,
+        Err(error) => panic!("refused with {}", error),
+        Ok(_) => panic!("allowed"),
+    };
+}
@@ -0,0 +1,54 @@
+//! `ETag`s of the index pages and JSON API documents, the ones that are also
+//! signed. Pages render the same for the same metadata, so the digest of the
+//! body only changes when the page does and clients sending `If-None-Match`
+//! get `304 Not Modified` until then.
+use askama_axum::Response;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use sha2::{Digest, Sha256};
+
+use crate::error::ErrorResponse;
+use crate::redact::log;
+use crate::signing;
+
+/// Adds the `ETag` of the body to successful responses of index pages, and
+/// answers with 304 when the client has it.
+pub async fn etag(request: Request, next: Next) -> Response {
+    if !signing::is_signed(request.uri().path()) {
+        return next.run(request).await;
+    }
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(error) => {
+            log!("Failed to read the response to tag: {}", error);
+            return ErrorResponse::ServerError(None).into_response();
+        }
+    };
+    let etag = format!("\"{}\"", hex::encode(Sha256::digest(&body)));
+    parts
+        .headers
+        .insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+    let is_current = if_none_match.is_some_and(|value| {
+        value.to_str().is_ok_and(|value| {
+            value.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            })
+        })
+    });
+    if is_current {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+    return Response::from_parts(parts, Body::from(body));
+}
@@ -0,0 +1,359 @@
+//! `pigi export`: writes the simple index of a namespace with every file as a
+//! static tree, to be served by any web server or carried into an air-gapped
+//! site. Exports into the same directory are differential: files that didn't
+//! change aren't rewritten, so rsync and `aws s3 sync` only transfer what did,
+//! `manifest.json` lists the sha256 of every file and each run appends what it
+//! added, changed and removed to `changes.jsonl`.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Repository;
+use crate::github::{Asset, GithubClient};
+use crate::namespace::Namespace;
+use crate::render::render;
+use crate::{fail, inspect, metadata, names, AppState, PackageTemplate, Simple};
+
+const MANIFEST: &str = "manifest.json";
+const CHANGES: &str = "changes.jsonl";
+
+pub struct Export {
+    pub dest: PathBuf,
+    pub namespace: String,
+    /// the metadata the leader shared in `CACHE_DIR` instead of syncing it from GitHub
+    pub cached: bool,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    sha256: String,
+    size: u64,
+}
+
+/// Files of an export by their path in it.
+type Manifest = BTreeMap<String, Entry>;
+
+#[derive(Default)]
+struct Changes {
+    added: Vec<String>,
+    changed: Vec<String>,
+    removed: Vec<String>,
+}
+
+struct Exporter<'a> {
+    dest: &'a Path,
+    previous: Manifest,
+    manifest: Manifest,
+    changes: Changes,
+    errors: Vec<String>,
+}
+
+impl Exporter<'_> {
+    /// Whether `path` is on disk as the previous export left it.
+    async fn is_current(&self, path: &str, entry: &Entry) -> bool {
+        if self.previous.get(path) != Some(entry) {
+            return false;
+        }
+        return fs::metadata(self.dest.join(path))
+            .await
+            .is_ok_and(|metadata| metadata.len() == entry.size);
+    }
+
+    fn record(&mut self, path: String, entry: Entry) {
+        match self.previous.get(&path) {
+            None => self.changes.added.push(path.clone()),
+            Some(previous) if *previous != entry => self.changes.changed.push(path.clone()),
+            Some(_) => {}
+        }
+        self.manifest.insert(path, entry);
+    }
+
+    /// Keeps what the previous export has under `prefix`, for packages that failed to sync.
+    fn keep(&mut self, prefix: &str) {
+        for (path, entry) in self.previous.range(prefix.to_string()..) {
+            if !path.starts_with(prefix) {
+                break;
+            }
+            self.manifest.insert(path.clone(), entry.clone());
+        }
+    }
+
+    async fn write_page(&mut self, path: String, content: String) {
+        let entry = Entry {
+            sha256: hex::encode(Sha256::digest(content.as_bytes())),
+            size: content.len() as u64,
+        };
+        if !self.is_current(&path, &entry).await {
+            if let Err(error) = write_atomically(&self.dest.join(&path), content.as_bytes()).await {
+                self.errors.push(format!("Can't write {}: {}", path, error));
+                return;
+            }
+        }
+        self.record(path, entry);
+    }
+
+    async fn write_asset(
+        &mut self,
+        app_state: &AppState,
+        client: &GithubClient,
+        repository: &Repository,
+        path: String,
+        asset: &Asset,
+    ) {
+        if let Some(sha256) = asset.sha256() {
+            let entry = Entry {
+                sha256: sha256.to_string(),
+                size: asset.size,
+            };
+            if self.is_current(&path, &entry).await {
+                self.record(path, entry);
+                return;
+            }
+        } else if let Some(previous) = self.previous.get(&path).cloned() {
+            // without a digest the file is taken to be unchanged while its size is
+            if previous.size == asset.size && self.is_current(&path, &previous).await {
+                self.record(path, previous);
+                return;
+            }
+        }
+        match download(app_state, client, repository, asset, &self.dest.join(&path)).await {
+            Ok(entry) => self.record(path, entry),
+            Err(error) => {
+                self.errors
+                    .push(format!("Can't export {}: {}", path, error));
+                if let Some(previous) = self.previous.get(&path).cloned() {
+                    self.manifest.insert(path, previous);
+                }
+            }
+        }
+    }
+}
+
+/// Where `path` is written before it is renamed into place, so a synced tree never has partial files.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".part");
+    return path.with_file_name(name);
+}
+
+async fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap()).await?;
+    let temp_path = temp_path(path);
+    fs::write(&temp_path, content).await?;
+    return fs::rename(&temp_path, path).await;
+}
+
+/// Writes `asset` to `path`, copied from the artifact cache when it is there.
+async fn download(
+    app_state: &AppState,
+    client: &GithubClient,
+    repository: &Repository,
+    asset: &Asset,
+    path: &Path,
+) -> Result<Entry, String> {
+    fs::create_dir_all(path.parent().unwrap())
+        .await
+        .map_err(|error| error.to_string())?;
+    let temp_path = temp_path(path);
+    let cached = match asset.sha256() {
+        Some(sha256) => app_state.cache.touch(sha256).await,
+        None => None,
+    };
+    if let Some(cached) = cached {
+        // the cache only holds verified files
+        let size = fs::copy(&cached, &temp_path)
+            .await
+            .map_err(|error| error.to_string())?;
+        fs::rename(&temp_path, path)
+            .await
+            .map_err(|error| error.to_string())?;
+        let sha256 = asset.sha256().unwrap().to_string();
+        return Ok(Entry { sha256, size });
+    }
+    let mut stream = repository
+        .asset(client, asset)
+        .await
+        .map_err(|error| error.to_string())?
+        .stream;
+    let mut file = fs::File::create(&temp_path)
+        .await
+        .map_err(|error| error.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|error| error.to_string())?;
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+        file.write_all(&chunk)
+            .await
+            .map_err(|error| error.to_string())?;
+    }
+    file.flush().await.map_err(|error| error.to_string())?;
+    let sha256 = hex::encode(hasher.finalize());
+    if asset.sha256().is_some_and(|expected| expected != sha256) {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(format!(
+            "sha256 mismatch, expected {} got {}",
+            asset.sha256().unwrap(),
+            sha256
+        ));
+    }
+    fs::rename(&temp_path, path)
+        .await
+        .map_err(|error| error.to_string())?;
+    return Ok(Entry { sha256, size });
+}
+
+async fn load_manifest(dest: &Path) -> Manifest {
+    let content = match fs::read(dest.join(MANIFEST)).await {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Manifest::new(),
+        Err(error) => fail(format!("Can't read the manifest of the export: {}", error)),
+    };
+    return serde_json::from_slice(&content)
+        .unwrap_or_else(|error| fail(format!("The manifest of the export is invalid: {}", error)));
+}
+
+async fn export_package(
+    app_state: &AppState,
+    exporter: &mut Exporter<'_>,
+    namespace: &Arc<Namespace>,
+    (configured_name, repository): (&String, &Repository),
+) {
+    let normalized_name = names::normalize(configured_name);
+    let prefix = format!("simple/{}/", normalized_name);
+    let Some(metadata) = namespace.metadata.get(configured_name) else {
+        exporter.errors.push(format!(
+            "{} isn't synced, its previous export is kept",
+            namespace.qualified_name(configured_name)
+        ));
+        exporter.keep(&prefix);
+        return;
+    };
+    let page = PackageTemplate::new(
+        repository,
+        normalized_name.clone(),
+        &metadata.releases,
+        &metadata.vulnerabilities,
+    );
+    let page = render(app_state, page);
+    exporter
+        .write_page(format!("{}index.html", prefix), page)
+        .await;
+    let client = GithubClient::new(&app_state.github, namespace.github_token.get());
+    for release in &metadata.releases {
+        for asset in &release.assets {
+            let path = format!("{}{}/{}", prefix, release.version(), asset.name);
+            exporter
+                .write_asset(app_state, &client, repository, path, asset)
+                .await;
+        }
+    }
+}
+
+/// Exports the namespace into `dest`, updating a previous export there.
+pub async fn run(app_state: &AppState, export: Export) {
+    let namespace = inspect::namespace(app_state, &export.namespace);
+    if export.cached {
+        if !metadata::load_shared(app_state) {
+            fail("--cached reads the metadata shared in CACHE_DIR, set it".to_string());
+        }
+    } else {
+        metadata::sync_all(app_state, false).await;
+    }
+    let mut exporter = Exporter {
+        dest: &export.dest,
+        previous: load_manifest(&export.dest).await,
+        manifest: Manifest::new(),
+        changes: Changes::default(),
+        errors: vec![],
+    };
+
+    let index = render(app_state, Simple::from_namespace(namespace));
+    exporter
+        .write_page("simple/index.html".to_string(), index)
+        .await;
+    let mut repos: Vec<(&String, &Repository)> = namespace.repos.iter().collect();
+    repos.sort_by_key(|(package_name, _)| *package_name);
+    for package in repos {
+        export_package(app_state, &mut exporter, namespace, package).await;
+    }
+
+    let removed: Vec<String> = exporter
+        .previous
+        .keys()
+        .filter(|path| !exporter.manifest.contains_key(*path))
+        .cloned()
+        .collect();
+    for path in removed {
+        let full_path = export.dest.join(&path);
+        match fs::remove_file(&full_path).await {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => {
+                exporter
+                    .errors
+                    .push(format!("Can't remove {}: {}", path, error));
+                continue;
+            }
+        }
+        // directories of removed versions and packages go too, others stay as they aren't empty
+        for directory in full_path.ancestors().skip(1) {
+            if directory == export.dest || fs::remove_dir(directory).await.is_err() {
+                break;
+            }
+        }
+        exporter.changes.removed.push(path);
+    }
+
+    let manifest = serde_json::to_vec_pretty(&exporter.manifest).unwrap();
+    if let Err(error) = write_atomically(&export.dest.join(MANIFEST), &manifest).await {
+        fail(format!("Can't write the manifest of the export: {}", error));
+    }
+    let changes = &exporter.changes;
+    let changed = changes.added.len() + changes.changed.len() + changes.removed.len();
+    if changed > 0 {
+        let exported_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let line = json!({
+            "exported_at": exported_at.as_secs(),
+            "namespace": namespace.name,
+            "added": changes.added,
+            "changed": changes.changed,
+            "removed": changes.removed,
+        });
+        let appended = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(export.dest.join(CHANGES))
+            .await;
+        let appended = match appended {
+            Ok(mut file) => match file.write_all(format!("{}\n", line).as_bytes()).await {
+                Ok(()) => file.flush().await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+        if let Err(error) = appended {
+            fail(format!("Can't append to {}: {}", CHANGES, error));
+        }
+    }
+    println!(
+        "Exported {} files to {}: {} added, {} changed, {} removed",
+        exporter.manifest.len(),
+        export.dest.display(),
+        changes.added.len(),
+        changes.changed.len(),
+        changes.removed.len()
+    );
+    if !exporter.errors.is_empty() {
+        fail(exporter.errors.join("\n"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Switches GitHub API calls to `GITHUB_API_FALLBACK_URL`, like a caching
+//! proxy of the API, after `GITHUB_API_FAILURES` calls in a row to
+//! `GITHUB_API_URL` failed to connect or got a server error. While calls go to
+//! the fallback the primary is probed every `GITHUB_API_PROBE_INTERVAL`, calls
+//! go back to it once it answers again. Uploads always go to GitHub.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::AppState;
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// How long the primary gets to answer a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Failover {
+    primary: String,
+    fallback: Option<String>,
+    /// failed calls in a row to the primary before switching to the fallback
+    failures: u64,
+    /// calls in a row to the primary that failed
+    primary_failures: AtomicU64,
+    /// calls go to the fallback
+    on_fallback: AtomicBool,
+}
+
+impl Failover {
+    pub fn from_config(config: &Config) -> Self {
+        return Failover {
+            primary: config.github_api_url.clone(),
+            fallback: config.github_api_fallback_url.clone(),
+            failures: config.github_api_failures,
+            primary_failures: AtomicU64::new(0),
+            on_fallback: AtomicBool::new(false),
+        };
+    }
+
+    /// Base url API calls go to now.
+    pub fn api_url(&self) -> &str {
+        if self.on_fallback.load(Ordering::Relaxed) {
+            if let Some(fallback) = &self.fallback {
+                return fallback;
+            }
+        }
+        return &self.primary;
+    }
+
+    /// Counts the outcome of a call to `url`, only calls to the primary count.
+    pub fn record(&self, url: &str, succeeded: bool) {
+        let Some(fallback) = &self.fallback else {
+            return;
+        };
+        if !url.starts_with(&self.primary) || self.on_fallback.load(Ordering::Relaxed) {
+            return;
+        }
+        if succeeded {
+            self.primary_failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = self.primary_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.failures && !self.on_fallback.swap(true, Ordering::Relaxed) {
+            log!(
+                "GitHub API at {} failed {} times in a row, switching to {}",
+                self.primary,
+                failures,
+                fallback
+            );
+        }
+    }
+
+    /// Where API calls go, for `/debug/status`.
+    pub fn status(&self) -> Value {
+        return json!({
+            "primary": self.primary,
+            "fallback": self.fallback,
+            "active": self.api_url(),
+            "primary_failures": self.primary_failures.load(Ordering::Relaxed),
+        });
+    }
+}
+
+/// Whether the primary at `primary` answers, any response but a server error will do.
+async fn probe(primary: &str) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .user_agent("pigi")
+        .build()
+        .map_err(|error| error.to_string())?;
+    let response = client
+        .get(format!("{}/rate_limit", primary))
+        .send()
+        .await
+        .map_err(|error| error.to_string())?;
+    if response.status().is_server_error() {
+        return Err(format!("it responded {}", response.status()));
+    }
+    return Ok(());
+}
+
+/// Probes the primary while calls go to the fallback, switching back once it answers.
+pub fn spawn_probe_worker(app_state: Arc<AppState>) {
+    if app_state.github.failover.fallback.is_none() {
+        return;
+    }
+    let period = app_state.config.github_api_probe_interval;
+    tokio::spawn(async move {
+        let failover = &app_state.github.failover;
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if !failover.on_fallback.load(Ordering::Relaxed) {
+                app_state.health.record("github failover", vec![]);
+                continue;
+            }
+            let mut errors = vec![];
+            match probe(&failover.primary).await {
+                Ok(()) => {
+                    failover.primary_failures.store(0, Ordering::Relaxed);
+                    failover.on_fallback.store(false, Ordering::Relaxed);
+                    log!(
+                        "GitHub API at {} answers again, switching back",
+                        failover.primary
+                    );
+                }
+                Err(error) => {
+                    let message = format!(
+                        "GitHub API at {} is failing, calls go to {}: {}",
+                        failover.primary,
+                        failover.api_url(),
+                        error
+                    );
+                    log!("{}", message);
+                    app_state
+                        .reporter
+                        .report(Report::task("github failover", message.clone()));
+                    errors.push(message);
+                }
+            }
+            app_state.health.record("github failover", errors);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failover(fallback: Option<&str>) -> Failover {
+        return Failover {
+            primary: GITHUB_API_URL.to_string(),
+            fallback: fallback.map(str::to_string),
+            failures: 2,
+            primary_failures: AtomicU64::new(0),
+            on_fallback: AtomicBool::new(false),
+        };
+    }
+
+    #[test]
+    fn switches_after_failures_in_a_row() {
+        let failover = failover(Some("https://github-proxy.internal"));
+        let url = format!("{}/repos/acme/pigi/releases", GITHUB_API_URL);
+        failover.record(&url, false);
+        failover.record(&url, true);
+        failover.record(&url, false);
+        assert_eq!(failover.api_url(), GITHUB_API_URL);
+        failover.record("https://uploads.github.com/repos/acme/pigi", false);
+        assert_eq!(failover.api_url(), GITHUB_API_URL);
+        failover.record(&url, false);
+        assert_eq!(failover.api_url(), "https://github-proxy.internal");
+    }
+
+    #[test]
+    fn stays_on_the_primary_without_fallback() {
+        let failover = failover(None);
+        for _ in 0..3 {
+            failover.record(GITHUB_API_URL, false);
+        }
+        assert_eq!(failover.api_url(), GITHUB_API_URL);
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::extract::State;
+
+use crate::namespace::CurrentNamespace;
+use crate::AppState;
+
+const FEED_SIZE: usize = 50;
+
+pub struct FeedEntry {
+    package_name: String,
+    tag_name: String,
+    title: String,
+    html_url: String,
+    published_at: String,
+}
+
+#[derive(Template)]
+#[template(path = "feed.xml")]
+pub struct Feed {
+    external_url: String,
+    updated: String,
+    entries: Vec<FeedEntry>,
+}
+
+/// Atom feed of the most recently published releases across all configured repositories.
+pub async fn feed(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+) -> Feed {
+    let mut entries: Vec<FeedEntry> = namespace
+        .metadata
+        .all()
+        .into_iter()
+        // the metadata is shared with the public view, which serves only some of the packages
+        .filter(|(package_name, _)| namespace.repos.get(package_name).is_some())
+        .flat_map(|(package_name, metadata)| {
+            metadata.releases.into_iter().filter_map(move |release| {
+                let published_at = release.published_at?;
+                Some(FeedEntry {
+                    package_name: package_name.clone(),
+                    title: release.name.unwrap_or(release.tag_name.clone()),
+                    tag_name: release.tag_name,
+                    html_url: release.html_url,
+                    published_at,
+                })
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.published_at.cmp(&a.published_at));
+    entries.truncate(FEED_SIZE);
+    let updated = entries
+        .first()
+        .map(|entry| entry.published_at.clone())
+        .unwrap_or("1970-01-01T00:00:00Z".to_string());
+    return Feed {
+        external_url: format!("{}{}", app_state.config.external_url, prefix),
+        updated,
+        entries,
+    };
+}
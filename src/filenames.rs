@@ -0,0 +1,177 @@
+//! Checks the names of distribution files against the wheel (PEP 427) and
+//! sdist (PEP 625) naming rules. Files pip can't use, because their name
+//! doesn't parse or names another project, are hidden from the index; names
+//! that parse but don't follow the current rules are only reported, at
+//! `/debug/filenames` and in the log, so release pipelines can be fixed.
+use std::str::FromStr;
+
+use pep440_rs::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::github::Release;
+use crate::names;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilenameIssue {
+    pub version: String,
+    pub file: String,
+    pub problem: String,
+    /// malformed files are left out of the index
+    pub hidden: bool,
+}
+
+/// Name of the project as written in file names, like `my_package`.
+fn escaped(project: &str) -> String {
+    return names::normalize(project).replace('-', "_");
+}
+
+fn check_name(project: &str, name: &str) -> Result<Option<String>, String> {
+    if names::normalize(name) != names::normalize(project) {
+        return Err(format!("is a file of {}, not of {}", name, project));
+    }
+    if name != escaped(project) {
+        return Ok(Some(format!(
+            "the project name should be written {}",
+            escaped(project)
+        )));
+    }
+    return Ok(None);
+}
+
+fn check_version(version: &str) -> Result<(), String> {
+    return Version::from_str(version)
+        .map(|_| ())
+        .map_err(|_| format!("{} is not a PEP 440 version", version));
+}
+
+/// `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`
+fn check_wheel(project: &str, stem: &str) -> Result<Option<String>, String> {
+    let parts: Vec<&str> = stem.split('-').collect();
+    if parts.len() != 5 && parts.len() != 6 {
+        return Err("wheel names have 5 or 6 parts separated by dashes".to_string());
+    }
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err("has an empty part".to_string());
+    }
+    check_version(parts[1])?;
+    if parts.len() == 6 && !parts[2].starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("build tag {} doesn't start with a digit", parts[2]));
+    }
+    return check_name(project, parts[0]);
+}
+
+/// `{name}-{version}.tar.gz`, or a legacy `.zip`
+fn check_sdist(project: &str, stem: &str, is_zip: bool) -> Result<Option<String>, String> {
+    let (name, version) = stem
+        .rsplit_once('-')
+        .ok_or("sdist names are the project name and version separated by a dash")?;
+    check_version(version)?;
+    let warning = check_name(project, name)?;
+    if is_zip {
+        return Ok(Some(
+            "zip sdists are deprecated, publish a .tar.gz".to_string(),
+        ));
+    }
+    return Ok(warning);
+}
+
+/// Why the name of a distribution of `project` is malformed (`Err`) or not
+/// compliant (`Ok(Some)`). Files other than wheels and sdists aren't checked.
+pub fn check(project: &str, filename: &str) -> Result<Option<String>, String> {
+    if let Some(stem) = filename.strip_suffix(".whl") {
+        return check_wheel(project, stem);
+    }
+    if let Some(stem) = filename.strip_suffix(".tar.gz") {
+        return check_sdist(project, stem, false);
+    }
+    if let Some(stem) = filename.strip_suffix(".zip") {
+        return check_sdist(project, stem, true);
+    }
+    return Ok(None);
+}
+
+/// Removes the malformed files of `project` from `releases`, returns every issue found.
+pub fn hide_malformed(project: &str, releases: &mut [Release]) -> Vec<FilenameIssue> {
+    let mut issues = vec![];
+    for release in releases {
+        let version = release.version().to_string();
+        release.assets.retain(|asset| {
+            let (problem, hidden) = match check(project, &asset.name) {
+                Ok(None) => return true,
+                Ok(Some(warning)) => (warning, false),
+                Err(error) => (error, true),
+            };
+            issues.push(FilenameIssue {
+                version: version.clone(),
+                file: asset.name.clone(),
+                problem,
+                hidden,
+            });
+            return !hidden;
+        });
+    }
+    return issues;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+
+    #[test]
+    fn compliant_names_pass() {
+        assert_eq!(check("my-package", "my_package-1.0-py3-none-any.whl"), Ok(None));
+        assert_eq!(check("my-package", "my_package-1.0-1-py3-none-any.whl"), Ok(None));
+        assert_eq!(check("my-package", "my_package-1.0.tar.gz"), Ok(None));
+        assert_eq!(check("My.Package", "my_package-1.0.tar.gz"), Ok(None));
+    }
+
+    #[test]
+    fn other_files_are_not_checked() {
+        assert_eq!(check("my-package", "checksums.txt"), Ok(None));
+        assert_eq!(check("my-package", "my-package-1.0.exe"), Ok(None));
+    }
+
+    #[test]
+    fn unescaped_names_are_reported() {
+        let warning = check("my-package", "my-package-1.0.tar.gz").unwrap();
+        assert_eq!(
+            warning,
+            Some("the project name should be written my_package".to_string())
+        );
+        let warning = check("my-package", "My_Package-1.0-py3-none-any.whl").unwrap();
+        assert!(warning.is_some());
+    }
+
+    #[test]
+    fn zip_sdists_are_reported() {
+        let warning = check("my-package", "my_package-1.0.zip").unwrap();
+        assert_eq!(
+            warning,
+            Some("zip sdists are deprecated, publish a .tar.gz".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_wheels_are_rejected() {
+        assert!(check("my-package", "my_package-1.0-py3-any.whl").is_err());
+        assert!(check("my-package", "my_package-1.0-x-y-py3-none-any.whl").is_err());
+        assert!(check("my-package", "my_package--py3-none-any.whl").is_err());
+        assert!(check("my-package", "my_package-one-py3-none-any.whl").is_err());
+        assert!(check("my-package", "my_package-1.0-b1-py3-none-any.whl").is_err());
+    }
+
+    #[test]
+    fn malformed_sdists_are_rejected() {
+        assert!(check("my-package", "my_package.tar.gz").is_err());
+        assert!(check("my-package", "my_package-latest.tar.gz").is_err());
+    }
+
+    #[test]
+    fn files_of_other_projects_are_rejected() {
+        assert_eq!(
+            check("my-package", "other-1.0.tar.gz"),
+            Err("is a file of other, not of my-package".to_string())
+        );
+        assert!(check("my-package", "other-1.0-py3-none-any.whl").is_err());
+    }
+}
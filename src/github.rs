@@ -0,0 +1,746 @@
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use futures_core::Stream;
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use pep440_rs::Version;
+use reqwest::header::HeaderMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::failover::Failover;
+use crate::github_cache::{self, GithubCache};
+use crate::metrics;
+use crate::proxy::ProxyState;
+use crate::redact::log;
+use crate::replication::Replication;
+use crate::scheduler::{self, Priority, Scheduler};
+use crate::storage::StorageSettings;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Release {
+    #[serde(default)]
+    pub id: u64,
+    pub tag_name: String,
+    pub name: Option<String>,
+    pub html_url: String,
+    pub published_at: Option<String>,
+    #[serde(default)]
+    pub prerelease: bool,
+    /// staged with `pigi publish --draft`, drafts are never served so never stored
+    #[serde(default, skip_serializing)]
+    pub draft: bool,
+    pub assets: Vec<Asset>,
+    /// extracted from the tag by the repository's `version_from_tag`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Release {
+    /// The version set by `version_from_tag`, otherwise the tag without a leading `v`.
+    pub fn version(&self) -> &str {
+        if let Some(version) = &self.version {
+            return version;
+        }
+        return self.tag_name.strip_prefix('v').unwrap_or(&self.tag_name);
+    }
+}
+
+/// Orders versions as PEP 440 does, the ones that aren't PEP 440 after them by
+/// name, so pages list files the same way on every render whatever order
+/// GitHub returned them in.
+pub fn version_key(version: &str) -> (bool, Option<Version>, String) {
+    let parsed = Version::from_str(version).ok();
+    return (parsed.is_none(), parsed, version.to_string());
+}
+
+/// Newest release that isn't a prerelease, or the newest one when all are.
+pub fn latest_release(releases: &[Release]) -> Option<&Release> {
+    return releases
+        .iter()
+        .find(|release| !release.prerelease)
+        .or(releases.first());
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// unix timestamp of when the budget is restored
+    pub reset: u64,
+}
+
+#[derive(Deserialize)]
+struct RateLimits {
+    rate: RateLimit,
+}
+
+#[derive(Deserialize)]
+pub struct RepositoryInfo {
+    pub description: Option<String>,
+    /// `owner/name` the repository has now, requests for a renamed or
+    /// transferred one are redirected to it
+    #[serde(default)]
+    pub full_name: String,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Asset {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub created_at: String,
+    /// changes when the file is re-uploaded or renamed
+    #[serde(default)]
+    pub updated_at: String,
+    pub digest: Option<String>,
+    /// computed by the hash backfill, GitHub publishes only sha256 digests
+    #[serde(default)]
+    pub blake2b_256: Option<String>,
+    /// where files of mirrored PyPI projects are downloaded from
+    #[serde(default)]
+    pub download_url: Option<String>,
+}
+
+impl Asset {
+    pub fn sha256(&self) -> Option<&str> {
+        return self.digest.as_ref()?.strip_prefix("sha256:");
+    }
+
+    /// When the content was last uploaded, metadata synced by older versions has no `updated_at`.
+    pub fn modified_at(&self) -> Option<SystemTime> {
+        return parse_timestamp(&self.updated_at).or_else(|| parse_timestamp(&self.created_at));
+    }
+}
+
+/// Parses the UTC timestamps of the GitHub API, like `2024-01-02T03:04:05Z`.
+pub fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let date: Vec<i64> = date
+        .split('-')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let time: Vec<u64> = time
+        .split(':')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let (&[year, month, day], &[hours, minutes, seconds]) = (date.as_slice(), time.as_slice())
+    else {
+        return None;
+    };
+    // days since the epoch of a proleptic Gregorian date, years starting in March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+    let seconds = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    return Some(UNIX_EPOCH + Duration::from_secs(seconds));
+}
+
+/// When GitHub allows calls again, if `response` says its rate limit is used
+/// up, or that calls are coming too fast.
+fn rate_limited_until(response: &reqwest::Response) -> Option<u64> {
+    let status = response.status();
+    if status != reqwest::StatusCode::FORBIDDEN && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+    {
+        return None;
+    }
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)?
+            .to_str()
+            .ok()?
+            .parse::<u64>()
+            .ok()
+    };
+    if header("x-ratelimit-remaining") == Some(0) {
+        if let Some(reset) = header("x-ratelimit-reset") {
+            return Some(reset);
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    return scheduler::secondary_limit(response).map(|pause| now + pause.as_secs());
+}
+
+/// GitHub responded with something that isn't the metadata asked for.
+fn unexpected_body(error: serde_json::Error) -> ErrorResponse {
+    return ErrorResponse::Failure(
+        ErrorCode::Upstream5xx,
+        format!("GitHub responded unexpectedly: {}", error),
+    );
+}
+
+/// What every `GithubClient` of the process shares.
+pub struct GithubShared {
+    /// where API calls go
+    pub failover: Failover,
+    /// `GITHUB_HEDGE_AFTER`, metadata calls aren't hedged without it
+    hedge_after: Option<Duration>,
+    /// metadata responses and rate limit budgets
+    pub cache: GithubCache,
+    /// paces the calls
+    pub scheduler: Scheduler,
+    /// packages of local and S3 storage are read and published through the client too
+    pub storage: StorageSettings,
+    /// replicas read releases and files from the upstream pigi instead
+    pub replication: Replication,
+    /// client and page cache of proxied indexes
+    pub proxy: ProxyState,
+}
+
+impl GithubShared {
+    pub fn from_config(config: &Config) -> Arc<Self> {
+        return Arc::new(GithubShared {
+            failover: Failover::from_config(config),
+            hedge_after: config.github_hedge_after,
+            cache: GithubCache::default(),
+            scheduler: Scheduler::from_config(config),
+            storage: StorageSettings::from_config(config),
+            replication: Replication::from_config(config),
+            proxy: ProxyState::default(),
+        });
+    }
+}
+
+pub struct GithubClient {
+    shared: Arc<GithubShared>,
+    client: reqwest::Client,
+    /// names the token in `github_cache`
+    token_key: String,
+    priority: Priority,
+}
+
+impl GithubClient {
+    pub fn new(shared: &Arc<GithubShared>, token: Option<String>) -> Self {
+        let token_key = github_cache::token_key(token.as_deref());
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(reqwest::header::USER_AGENT, "pigi".parse().unwrap());
+        if let Some(token) = token {
+            default_headers.insert(
+                reqwest::header::AUTHORIZATION,
+                format!("token {}", token).parse().unwrap(),
+            );
+        }
+        default_headers.insert("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
+        default_headers.insert(reqwest::header::ACCEPT,"application/vnd.github+json".parse().unwrap());
+
+        let client = reqwest::Client::builder()
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+        return GithubClient {
+            shared: shared.clone(),
+            client,
+            token_key,
+            priority: Priority::Interactive,
+        };
+    }
+
+    pub fn shared(&self) -> &GithubShared {
+        return &self.shared;
+    }
+
+    /// Base url API calls go to now, see `failover`.
+    fn api_url(&self) -> &str {
+        return self.shared.failover.api_url();
+    }
+
+    /// Names the token the client calls GitHub with, without revealing it.
+    pub fn token_key(&self) -> &str {
+        return &self.token_key;
+    }
+
+    /// For background jobs, their calls give way to the ones of clients.
+    pub fn background(mut self) -> Self {
+        self.priority = Priority::Background;
+        return self;
+    }
+
+    /// Sends `request` when the scheduler allows it. Reads that hit a
+    /// secondary rate limit are sent again once GitHub allows it.
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let request = request.build()?;
+        let is_read = matches!(
+            *request.method(),
+            reqwest::Method::GET | reqwest::Method::HEAD
+        );
+        let retry = if is_read { request.try_clone() } else { None };
+        let points = if is_read { 1 } else { 5 };
+        let response = self.execute(request, points).await;
+        let Some(pause) = response.as_ref().ok().and_then(scheduler::secondary_limit) else {
+            return response;
+        };
+        log!(
+            "GitHub secondary rate limit hit, holding calls back for {}s",
+            pause.as_secs()
+        );
+        self.shared.scheduler.pause(pause);
+        let Some(retry) = retry else {
+            return response;
+        };
+        return self.execute(retry, points).await;
+    }
+
+    /// Sends `request`, recording how long GitHub took to respond and the
+    /// budget it reported.
+    async fn execute(
+        &self,
+        request: reqwest::Request,
+        points: u64,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let url = request.url().to_string();
+        let permit = self.shared.scheduler.acquire(self.priority, points).await;
+        let start = Instant::now();
+        let response = self.client.execute(request).await;
+        drop(permit);
+        metrics::record_github_latency(start.elapsed().as_millis() as u64);
+        let succeeded = response
+            .as_ref()
+            .is_ok_and(|response| !response.status().is_server_error());
+        self.shared.failover.record(&url, succeeded);
+        if let Ok(response) = &response {
+            self.shared.cache.record_budget(&self.token_key, response.headers());
+        }
+        return response;
+    }
+
+    /// GETs `url`, sending it again when GitHub hasn't responded within
+    /// `GITHUB_HEDGE_AFTER` and taking whichever response comes first. Only
+    /// for metadata calls, which can be sent twice.
+    async fn get_hedged(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let request = || {
+            let request = self.client.get(url);
+            return match etag {
+                Some(etag) => request.header(reqwest::header::IF_NONE_MATCH, etag),
+                None => request,
+            };
+        };
+        let Some(after) = self.shared.hedge_after else {
+            return self.send(request()).await;
+        };
+        let first = Box::pin(self.send(request()));
+        let first = match future::select(first, Box::pin(tokio::time::sleep(after))).await {
+            Either::Left((response, _)) => return response,
+            Either::Right((_, first)) => first,
+        };
+        metrics::GITHUB_HEDGED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        let second = Box::pin(self.send(request()));
+        return match future::select(first, second).await {
+            Either::Left((response, _)) | Either::Right((response, _)) => response,
+        };
+    }
+
+    /// Body of the metadata at `url`, `None` when GitHub responded 404. Sent
+    /// conditionally, an unchanged response comes from `github_cache`, as does
+    /// every response while the token's budget is used up.
+    async fn get_metadata(&self, url: String) -> Result<Option<String>, ErrorResponse> {
+        let cached = self.shared.cache.body(&self.token_key, &url);
+        if let Some(reset) = self.shared.cache.exhausted_until(&self.token_key) {
+            return match cached {
+                Some(body) => Ok(Some(body)),
+                None => Err(ErrorResponse::RateLimited { reset }),
+            };
+        }
+        let etag = self.shared.cache.etag(&self.token_key, &url);
+        let response = self.get_hedged(&url, etag.as_deref()).await?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(body) = cached {
+                return Ok(Some(body));
+            }
+        }
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = Self::check(response).await?;
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let body = response.text().await?;
+        if let Some(etag) = etag {
+            self.shared.cache.store(&self.token_key, &url, etag, body.clone());
+        }
+        return Ok(Some(body));
+    }
+
+    /// Parses the metadata at `url`, an error when GitHub responded 404.
+    async fn get_json<T: DeserializeOwned>(&self, url: String) -> Result<T, ErrorResponse> {
+        let body = self.get_metadata(url).await?.ok_or(ErrorResponse::Failure(
+            ErrorCode::Upstream4xx,
+            "GitHub responded 404 Not Found".to_string(),
+        ))?;
+        return serde_json::from_str(&body).map_err(unexpected_body);
+    }
+
+    /// Remaining API budget of the token, `Unauthorized` when GitHub rejects the token.
+    pub async fn rate_limit(&self) -> Result<RateLimit, ErrorResponse> {
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/rate_limit", self.api_url())),
+            )
+            .await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(ErrorResponse::Unauthorized);
+        }
+        return Ok(response.json::<RateLimits>().await?.rate);
+    }
+
+    /// `response` when successful, otherwise an error with the start of what GitHub said.
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response, ErrorResponse> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        if let Some(reset) = rate_limited_until(&response) {
+            return Err(ErrorResponse::RateLimited { reset });
+        }
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let snippet: String = body.chars().take(MAX_SNIPPET).collect();
+        return Err(ErrorResponse::Failure(
+            ErrorCode::from_status(status),
+            format!("GitHub responded {}: {}", status, snippet),
+        ));
+    }
+
+    pub async fn releases(
+        &self,
+        org: &String,
+        repo: &String,
+    ) -> Result<Vec<Release>, ErrorResponse> {
+        let url = format!("{}/repos/{}/{}/releases", self.api_url(), org, repo);
+        return self.get_json(url).await;
+    }
+
+    pub async fn repository(
+        &self,
+        org: &String,
+        repo: &String,
+    ) -> Result<RepositoryInfo, ErrorResponse> {
+        let url = format!("{}/repos/{}/{}", self.api_url(), org, repo);
+        return self.get_json(url).await;
+    }
+
+    /// Release tagged `tag`, `None` when there is none.
+    pub async fn release_by_tag(
+        &self,
+        org: &String,
+        repo: &String,
+        tag: &str,
+    ) -> Result<Option<Release>, ErrorResponse> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/tags/{}",
+            self.api_url(),
+            org,
+            repo,
+            tag
+        );
+        let Some(body) = self.get_metadata(url).await? else {
+            return Ok(None);
+        };
+        return Ok(Some(serde_json::from_str(&body).map_err(unexpected_body)?));
+    }
+
+    /// Creates a release, along with its tag on the default branch unless it
+    /// is a `draft`, whose tag is created when it is published.
+    pub async fn create_release(
+        &self,
+        org: &String,
+        repo: &String,
+        tag: &str,
+        prerelease: bool,
+        draft: bool,
+    ) -> Result<Release, ErrorResponse> {
+        let url = format!("{}/repos/{}/{}/releases", self.api_url(), org, repo);
+        let body = serde_json::json!({
+            "tag_name": tag,
+            "name": tag,
+            "prerelease": prerelease,
+            "draft": draft,
+        });
+        let response = Self::check(self.send(self.client.post(url).json(&body)).await?).await?;
+        return Ok(response.json::<Release>().await?);
+    }
+
+    /// Publishes a draft release, with all of its assets at once.
+    pub async fn publish_release(
+        &self,
+        org: &String,
+        repo: &String,
+        release_id: u64,
+    ) -> Result<Release, ErrorResponse> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/{}",
+            self.api_url(),
+            org,
+            repo,
+            release_id
+        );
+        let body = serde_json::json!({"draft": false});
+        let response = Self::check(self.send(self.client.patch(url).json(&body)).await?).await?;
+        return Ok(response.json::<Release>().await?);
+    }
+
+    /// Uploads `length` bytes of `body` as an asset called `name`.
+    pub async fn upload_asset(
+        &self,
+        org: &String,
+        repo: &String,
+        release_id: u64,
+        name: &str,
+        body: reqwest::Body,
+        length: u64,
+    ) -> Result<Asset, ErrorResponse> {
+        let url = format!(
+            "https://uploads.github.com/repos/{}/{}/releases/{}/assets",
+            org, repo, release_id
+        );
+        let request = self
+            .client
+            .post(url)
+            .query(&[("name", name)])
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .header(reqwest::header::CONTENT_LENGTH, length)
+            .body(body);
+        let response = Self::check(self.send(request).await?).await?;
+        return Ok(response.json::<Asset>().await?);
+    }
+
+    /// Metadata of a single release asset, `None` when it is gone.
+    pub async fn asset_metadata(
+        &self,
+        org: &String,
+        repo: &String,
+        asset_id: u64,
+    ) -> Result<Option<Asset>, ErrorResponse> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/assets/{}",
+            self.api_url(),
+            org,
+            repo,
+            asset_id
+        );
+        let response = self.get_hedged(&url, None).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        return Ok(Some(Self::check(response).await?.json::<Asset>().await?));
+    }
+
+    /// Whether a release asset still exists, without downloading it.
+    pub async fn asset_exists(
+        &self,
+        org: &String,
+        repo: &String,
+        asset_id: u64,
+    ) -> Result<bool, ErrorResponse> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/assets/{}",
+            self.api_url(),
+            org,
+            repo,
+            asset_id
+        );
+        let response = self.send(self.client.get(url)).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        Self::check(response).await?;
+        return Ok(true);
+    }
+
+    pub async fn asset(
+        &self,
+        org: &String,
+        repo: &String,
+        asset_id: &String,
+    ) -> Result<AssetStream, ErrorResponse> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/assets/{}",
+            self.api_url(),
+            org,
+            repo,
+            asset_id
+        );
+
+        let request = self
+            .client
+            .get(&url)
+            .header("Accept", "application/octet-stream");
+        let response = self.send(request).await?;
+        if let Some(reset) = rate_limited_until(&response) {
+            return Err(ErrorResponse::RateLimited { reset });
+        }
+        let content_length = response.content_length();
+
+        let transfer = Transfer {
+            client: self.client.clone(),
+            url,
+            stream: Some(Box::pin(response.bytes_stream())),
+            received: 0,
+            content_length,
+            resumes: 0,
+        };
+        let stream = futures_util::stream::unfold(transfer, |mut transfer| async move {
+            let item = transfer.next_chunk().await?;
+            return Some((item, transfer));
+        });
+        return Ok(AssetStream {
+            content_length,
+            stream: Box::pin(stream),
+        });
+    }
+
+    /// Bytes `start..end` of an asset.
+    pub async fn asset_range(
+        &self,
+        org: &String,
+        repo: &String,
+        asset_id: &String,
+        range: Range<u64>,
+    ) -> Result<ByteStream<io::Error>, ErrorResponse> {
+        let url = format!(
+            "{}/repos/{}/{}/releases/assets/{}",
+            self.api_url(),
+            org,
+            repo,
+            asset_id
+        );
+        let request = self
+            .client
+            .get(&url)
+            .header("Accept", "application/octet-stream")
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            );
+        let response = self.send(request).await?;
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(ErrorResponse::Failure(
+                ErrorCode::from_status(response.status()),
+                format!(
+                    "upstream doesn't support range requests, status {}",
+                    response.status()
+                ),
+            ));
+        }
+        return Ok(Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(io::Error::other)),
+        ));
+    }
+}
+
+/// Characters of a GitHub error response kept in the error.
+const MAX_SNIPPET: usize = 500;
+
+/// How many times a broken transfer is resumed before giving up.
+const MAX_RESUMES: u32 = 3;
+
+pub type ByteStream<E> = Pin<Box<dyn Stream<Item = Result<Bytes, E>> + Send>>;
+
+pub struct AssetStream {
+    pub content_length: Option<u64>,
+    pub stream: ByteStream<io::Error>,
+}
+
+/// Upstream download of an asset, resumed with a `Range` request when GitHub
+/// closes the connection before `Content-Length` bytes were received.
+struct Transfer {
+    client: reqwest::Client,
+    url: String,
+    stream: Option<ByteStream<reqwest::Error>>,
+    received: u64,
+    content_length: Option<u64>,
+    resumes: u32,
+}
+
+impl Transfer {
+    async fn next_chunk(&mut self) -> Option<Result<Bytes, io::Error>> {
+        loop {
+            let error = match self.stream.as_mut()?.next().await {
+                Some(Ok(chunk)) => {
+                    self.received += chunk.len() as u64;
+                    return Some(Ok(chunk));
+                }
+                Some(Err(error)) => io::Error::other(error),
+                None => match self.content_length {
+                    Some(content_length) if self.received < content_length => io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "upstream closed after {} of {} bytes",
+                            self.received, content_length
+                        ),
+                    ),
+                    _ => return None,
+                },
+            };
+            self.stream = None;
+            if self.content_length.is_none() || self.resumes >= MAX_RESUMES {
+                return Some(Err(error));
+            }
+            self.resumes += 1;
+            log!(
+                "Resuming download of {} from byte {}: {}",
+                self.url,
+                self.received,
+                error
+            );
+            match resume(&self.client, &self.url, self.received).await {
+                Ok(stream) => self.stream = Some(stream),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+async fn resume(
+    client: &reqwest::Client,
+    url: &str,
+    received: u64,
+) -> Result<ByteStream<reqwest::Error>, io::Error> {
+    let response = client
+        .get(url)
+        .header("Accept", "application/octet-stream")
+        .header(reqwest::header::RANGE, format!("bytes={}-", received))
+        .send()
+        .await
+        .map_err(io::Error::other)?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(io::Error::other(format!(
+            "upstream can't resume download, status {}",
+            response.status()
+        )));
+    }
+    return Ok(Box::pin(response.bytes_stream()));
+}
@@ -0,0 +1,207 @@
+//! What pigi knows about GitHub between calls: the metadata responses with
+//! their `ETag`s, and the rate limit budget of every token as GitHub last
+//! reported it. Metadata calls are sent with `If-None-Match` and a `304`, which
+//! doesn't count against the rate limit, is answered from the cache; while a
+//! token's budget is used up they aren't sent at all. With `CACHE_DIR` both are
+//! kept in `github.json`, so after a restart the first sync is conditional
+//! too instead of fetching every repository again.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::AppState;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedResponse {
+    etag: String,
+    body: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Budget {
+    remaining: u64,
+    /// unix timestamp of when the budget is restored
+    reset: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct State {
+    /// by token and url
+    responses: HashMap<String, CachedResponse>,
+    /// by token
+    budgets: HashMap<String, Budget>,
+}
+
+/// Names `token` in the cache without keeping it.
+pub fn token_key(token: Option<&str>) -> String {
+    let Some(token) = token else {
+        return "anonymous".to_string();
+    };
+    return hex::encode(&Sha256::digest(token)[..8]);
+}
+
+fn response_key(token_key: &str, url: &str) -> String {
+    return format!("{} {}", token_key, url);
+}
+
+fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+}
+
+#[derive(Default)]
+pub struct GithubCache {
+    state: Mutex<State>,
+}
+
+impl GithubCache {
+    /// `ETag` of the cached response to `url`.
+    pub fn etag(&self, token_key: &str, url: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let cached = state.responses.get(&response_key(token_key, url))?;
+        return Some(cached.etag.clone());
+    }
+
+    /// Cached body of the response to `url`.
+    pub fn body(&self, token_key: &str, url: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let cached = state.responses.get(&response_key(token_key, url))?;
+        return Some(cached.body.clone());
+    }
+
+    pub fn store(&self, token_key: &str, url: &str, etag: String, body: String) {
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .insert(response_key(token_key, url), CachedResponse { etag, body });
+    }
+
+    /// Keeps the budget GitHub reported in the headers of a response.
+    pub fn record_budget(&self, token_key: &str, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+        let (Some(remaining), Some(reset)) =
+            (header("x-ratelimit-remaining"), header("x-ratelimit-reset"))
+        else {
+            return;
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .budgets
+            .insert(token_key.to_string(), Budget { remaining, reset });
+    }
+
+    /// When the budget of the token is used up, the time it is restored.
+    pub fn exhausted_until(&self, token_key: &str) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        let budget = state.budgets.get(token_key)?;
+        if budget.remaining > 0 || budget.reset <= now() {
+            return None;
+        }
+        return Some(budget.reset);
+    }
+
+    /// Budgets of the tokens, for `/debug/status`.
+    pub fn status(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        return json!({
+            "cached_responses": state.responses.len(),
+            "budgets": state.budgets,
+        });
+    }
+
+    fn save_to(&self, path: &PathBuf) -> io::Result<()> {
+        let content = serde_json::to_vec(&*self.state.lock().unwrap()).map_err(io::Error::other)?;
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, content)?;
+        return fs::rename(&temp_path, path);
+    }
+}
+
+fn path(app_state: &AppState) -> Option<PathBuf> {
+    return Some(PathBuf::from(app_state.config.cache_dir.as_ref()?).join("github.json"));
+}
+
+/// Writes the cache to `CACHE_DIR`, returns why it couldn't.
+pub fn save(app_state: &AppState) -> Result<(), String> {
+    let Some(path) = path(app_state) else {
+        return Ok(());
+    };
+    if let Err(error) = app_state.github.cache.save_to(&path) {
+        let message = format!("Failed to save the GitHub cache: {}", error);
+        log!("{}", message);
+        app_state
+            .reporter
+            .report(Report::task("metadata sync", message.clone()));
+        return Err(message);
+    }
+    return Ok(());
+}
+
+/// Restores the cache saved before a restart.
+pub fn load(app_state: &AppState) {
+    let Some(path) = path(app_state) else {
+        return;
+    };
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return,
+        Err(error) => {
+            log!("Failed to load {}: {}", path.display(), error);
+            return;
+        }
+    };
+    match serde_json::from_slice(&content) {
+        Ok(state) => *app_state.github.cache.state.lock().unwrap() = state,
+        Err(error) => log!("Failed to load {}: {}", path.display(), error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(remaining: u64, reset: u64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", remaining.into());
+        headers.insert("x-ratelimit-reset", reset.into());
+        return headers;
+    }
+
+    #[test]
+    fn tells_used_up_budgets() {
+        let cache = GithubCache::default();
+        let token_key = token_key(Some("ghp_test"));
+        assert_eq!(cache.exhausted_until(&token_key), None);
+        cache.record_budget(&token_key, &budget(10, now() + 60));
+        assert_eq!(cache.exhausted_until(&token_key), None);
+        cache.record_budget(&token_key, &budget(0, now() + 60));
+        assert_eq!(cache.exhausted_until(&token_key), Some(now() + 60));
+        cache.record_budget(&token_key, &budget(0, now() - 1));
+        assert_eq!(cache.exhausted_until(&token_key), None);
+        assert_eq!(cache.exhausted_until("anonymous"), None);
+    }
+
+    #[test]
+    fn keeps_responses_by_token() {
+        let cache = GithubCache::default();
+        let url = "https://api.github.com/repos/acme/pigi/releases";
+        cache.store("a", url, "\"v1\"".to_string(), "[]".to_string());
+        assert_eq!(cache.etag("a", url).as_deref(), Some("\"v1\""));
+        assert_eq!(cache.body("a", url).as_deref(), Some("[]"));
+        assert_eq!(cache.etag("b", url), None);
+    }
+}
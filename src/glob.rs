@@ -0,0 +1,60 @@
+/// Matches `value` against a shell-like pattern where `*` stands for any
+/// (possibly empty) sequence of characters and `?` for exactly one.
+pub fn matches(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    return pattern[p..].iter().all(|c| *c == '*');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn literal_patterns_match_exactly() {
+        assert!(matches("pigi", "pigi"));
+        assert!(!matches("pigi", "pigi-cli"));
+        assert!(!matches("pigi-cli", "pigi"));
+        assert!(matches("", ""));
+        assert!(!matches("", "pigi"));
+    }
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("pigi-*", "pigi-"));
+        assert!(matches("pigi-*", "pigi-cli"));
+        assert!(matches("*-cli", "pigi-cli"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(matches("a*b", "abab"));
+        assert!(!matches("a*b", "abac"));
+        assert!(matches("**", "x"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("v?", "v1"));
+        assert!(!matches("v?", "v"));
+        assert!(!matches("v?", "v10"));
+        assert!(matches("?*", "x"));
+        assert!(!matches("?*", ""));
+    }
+}
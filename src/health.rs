@@ -0,0 +1,37 @@
+//! Outcome of the latest run of every background task, for `/debug/status`.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+#[derive(Serialize, Clone)]
+pub struct TaskRun {
+    /// unix timestamp of when the run finished
+    pub finished_at: u64,
+    /// problems of the run, empty when it succeeded
+    pub errors: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Health {
+    tasks: Mutex<BTreeMap<&'static str, TaskRun>>,
+}
+
+impl Health {
+    pub fn record(&self, task: &'static str, errors: Vec<String>) {
+        let finished_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let run = TaskRun {
+            finished_at,
+            errors,
+        };
+        self.tasks.lock().unwrap().insert(task, run);
+    }
+
+    pub fn tasks(&self) -> BTreeMap<&'static str, TaskRun> {
+        return self.tasks.lock().unwrap().clone();
+    }
+}
@@ -0,0 +1,136 @@
+//! `pigi import`: uploads a directory of existing distributions, like an old
+//! file share, to the GitHub releases of their versions, so historical
+//! artifacts are served by the index too.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use pep440_rs::Version;
+
+use crate::publish::{self, parse_filename};
+use crate::quotas;
+use crate::{fail, names, AppState};
+
+pub struct Import {
+    pub dir: PathBuf,
+    pub package: String,
+    pub namespace: String,
+    /// prepended to the version to name the release tag
+    pub tag_prefix: String,
+    /// only list what would be uploaded
+    pub dry_run: bool,
+}
+
+/// Every file under `dir`, subdirectories included.
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    return Ok(files);
+}
+
+/// Uploads the wheels and sdists of the package found in the directory, one
+/// release per version, oldest version first. A version that fails doesn't
+/// stop the others, running the command again skips what was uploaded.
+pub async fn run(app_state: &AppState, import: Import) {
+    let (namespace, configured_name, repository, client) =
+        publish::target(app_state, &import.namespace, &import.package);
+    let normalized_name = names::normalize(configured_name);
+    let files = files(&import.dir)
+        .unwrap_or_else(|error| fail(format!("Can't read {}: {}", import.dir.display(), error)));
+
+    let mut versions: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in files {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        match parse_filename(&filename) {
+            Some((name, version)) if names::normalize(name) == normalized_name => {
+                let version = version.to_string();
+                versions.entry(version).or_default().push(path);
+            }
+            Some(_) => println!("Skipping {}, it is of another package", path.display()),
+            None => println!("Skipping {}, it isn't a wheel or sdist", path.display()),
+        }
+    }
+    if versions.is_empty() {
+        fail(format!(
+            "No distributions of {} in {}",
+            configured_name,
+            import.dir.display()
+        ));
+    }
+    let mut versions: Vec<(String, Vec<PathBuf>)> = versions.into_iter().collect();
+    // versions that aren't PEP 440 go last
+    versions.sort_by_cached_key(|(version, _)| {
+        (
+            Version::from_str(version).is_err(),
+            Version::from_str(version).ok(),
+        )
+    });
+
+    let mut errors = vec![];
+    for (version, files) in &versions {
+        let tag = format!("{}{}", import.tag_prefix, version);
+        if import.dry_run {
+            for path in files {
+                println!("Would upload {} to {}", path.display(), tag);
+            }
+            continue;
+        }
+        let checked = quotas::check_upload(
+            app_state,
+            &client,
+            namespace,
+            configured_name,
+            repository,
+            &tag,
+            files,
+        )
+        .await;
+        if let Err(error) = checked {
+            println!("Failed to import {}: {}", version, error);
+            errors.push(version.clone());
+            continue;
+        }
+        match publish::upload(&client, repository, &tag, version, files, true, false).await {
+            Ok(_) => println!(
+                "Imported {} {}",
+                namespace.qualified_name(configured_name),
+                version
+            ),
+            Err(error) => {
+                println!("Failed to import {}: {}", version, error);
+                errors.push(version.clone());
+            }
+        }
+    }
+    if !errors.is_empty() {
+        fail(format!(
+            "{} of {} versions failed to import: {}",
+            errors.len(),
+            versions.len(),
+            errors.join(", ")
+        ));
+    }
+    println!(
+        "{} {} versions of {} from {}",
+        if import.dry_run {
+            "Would import"
+        } else {
+            "Imported"
+        },
+        versions.len(),
+        namespace.qualified_name(configured_name),
+        import.dir.display()
+    );
+}
@@ -0,0 +1,345 @@
+//! `pigi list`, `pigi show` and `pigi resolve`: what the index would serve
+//! with the current configuration, fetched from GitHub without a running server.
+use std::str::FromStr;
+use std::sync::Arc;
+
+use pep440_rs::{Version as Pep440Version, VersionSpecifiers};
+
+use serde::Serialize;
+
+use crate::config::Repository;
+use crate::github::{latest_release, GithubClient, Release};
+use crate::metadata::{self, PackageMetadata};
+use crate::namespace::Namespace;
+use crate::{fail, AppState, Output};
+
+#[derive(Serialize)]
+struct PackageSummary {
+    name: String,
+    repository: String,
+    description: Option<String>,
+    latest_version: Option<String>,
+    versions: Vec<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: String,
+    prerelease: bool,
+    published_at: Option<String>,
+    files: Vec<File>,
+}
+
+#[derive(Serialize)]
+struct File {
+    name: String,
+    size: u64,
+    sha256: Option<String>,
+}
+
+fn files(release: &Release) -> Vec<File> {
+    return release
+        .assets
+        .iter()
+        .map(|asset| File {
+            name: asset.name.clone(),
+            size: asset.size,
+            sha256: asset.sha256().map(|sha256| sha256.to_string()),
+        })
+        .collect();
+}
+
+fn print_files(files: &[File]) {
+    for file in files {
+        println!(
+            "  {:<60} {:>12} {}",
+            file.name,
+            file.size,
+            file.sha256.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/// The namespace called `name`, exits when there is none.
+pub fn namespace<'a>(app_state: &'a AppState, name: &str) -> &'a Arc<Namespace> {
+    return app_state
+        .namespaces
+        .by_name(name)
+        .unwrap_or_else(|| fail(format!("No namespace {} is configured", name)));
+}
+
+pub async fn fetch(
+    app_state: &AppState,
+    namespace: &Namespace,
+    repository: &Repository,
+) -> Result<PackageMetadata, String> {
+    let client = GithubClient::new(&app_state.github, namespace.github_token.get());
+    return metadata::fetch_metadata(&client, repository)
+        .await
+        .map_err(|error| error.to_string());
+}
+
+/// Prints every package of the namespace, or of all namespaces, with the versions it would serve.
+pub async fn list(app_state: &AppState, namespace_name: Option<&str>, output: Output) {
+    let mut namespaces: Vec<&Arc<Namespace>> = match namespace_name {
+        Some(name) => vec![namespace(app_state, name)],
+        None => app_state.namespaces.iter().collect(),
+    };
+    namespaces.sort_by_key(|namespace| &namespace.name);
+    let mut packages = vec![];
+    for namespace in namespaces {
+        let mut repos: Vec<(&String, &Repository)> = namespace.repos.iter().collect();
+        repos.sort_by_key(|(package_name, _)| *package_name);
+        let fetches = repos
+            .iter()
+            .map(|(_, repository)| fetch(app_state, namespace, repository));
+        let fetched = futures_util::future::join_all(fetches).await;
+        for ((package_name, repository), metadata) in repos.into_iter().zip(fetched) {
+            let name = namespace.qualified_name(package_name);
+            let repository = format!("{}/{}", repository.owner, repository.name);
+            let summary = match metadata {
+                Ok(metadata) => PackageSummary {
+                    name,
+                    repository,
+                    latest_version: latest_release(&metadata.releases)
+                        .map(|release| release.version().to_string()),
+                    versions: metadata
+                        .releases
+                        .iter()
+                        .map(|release| release.version().to_string())
+                        .collect(),
+                    description: metadata.description,
+                    error: None,
+                },
+                Err(error) => PackageSummary {
+                    name,
+                    repository,
+                    description: None,
+                    latest_version: None,
+                    versions: vec![],
+                    error: Some(error),
+                },
+            };
+            packages.push(summary);
+        }
+    }
+
+    if output == Output::Json {
+        println!("{}", serde_json::to_string_pretty(&packages).unwrap());
+        return;
+    }
+    for package in &packages {
+        let latest = match (&package.error, &package.latest_version) {
+            (Some(error), _) => format!("error: {}", error),
+            (None, Some(version)) => format!("{} ({} versions)", version, package.versions.len()),
+            (None, None) => "no releases".to_string(),
+        };
+        println!("{:<30} {:<40} {}", package.name, package.repository, latest);
+    }
+}
+
+/// Prints every version of a package with its files.
+pub async fn show(app_state: &AppState, namespace_name: &str, package_name: &str, output: Output) {
+    let namespace = namespace(app_state, namespace_name);
+    let Ok((configured_name, repository)) = namespace.find_repository(package_name) else {
+        fail(format!(
+            "{} is not configured",
+            namespace.qualified_name(package_name)
+        ))
+    };
+    let repository_name = format!("{}/{}", repository.owner, repository.name);
+    let metadata = fetch(app_state, namespace, repository).await.unwrap_or_else(|error| {
+        fail(format!(
+            "Can't fetch {} from GitHub: {}",
+            repository_name, error
+        ))
+    });
+    let versions: Vec<Version> = metadata
+        .releases
+        .iter()
+        .map(|release| Version {
+            version: release.version().to_string(),
+            prerelease: release.prerelease,
+            published_at: release.published_at.clone(),
+            files: files(release),
+        })
+        .collect();
+
+    if output == Output::Json {
+        println!("{}", serde_json::to_string_pretty(&versions).unwrap());
+        return;
+    }
+    println!(
+        "{} ({})",
+        namespace.qualified_name(configured_name),
+        repository_name
+    );
+    if let Some(description) = &metadata.description {
+        println!("{}", description);
+    }
+    for version in &versions {
+        let prerelease = if version.prerelease {
+            " prerelease"
+        } else {
+            ""
+        };
+        println!(
+            "\n{}{} {}",
+            version.version,
+            prerelease,
+            version.published_at.as_deref().unwrap_or_default()
+        );
+        print_files(&version.files);
+    }
+}
+
+#[derive(Serialize)]
+struct Candidate {
+    version: String,
+    /// why pip skips the version, `None` for versions it may install
+    excluded: Option<String>,
+    files: Vec<File>,
+}
+
+#[derive(Serialize)]
+struct Resolution {
+    package: String,
+    specifiers: String,
+    /// version pip installs, the newest candidate
+    selected: Option<String>,
+    versions: Vec<Candidate>,
+}
+
+/// Project name and version specifiers of a requirement like `mypkg[extra]>=1.2,<2`,
+/// extras and environment markers don't affect the version and are ignored.
+fn parse_requirement(requirement: &str) -> Result<(&str, VersionSpecifiers), String> {
+    let requirement = requirement.split(';').next().unwrap_or_default().trim();
+    let name_end = requirement
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "._-".contains(c)))
+        .unwrap_or(requirement.len());
+    let (name, mut rest) = requirement.split_at(name_end);
+    if name.is_empty() {
+        return Err(format!("{} doesn't start with a package name", requirement));
+    }
+    rest = rest.trim();
+    if rest.starts_with('[') {
+        let extras_end = rest.find(']').ok_or("extras are missing the closing ]")?;
+        rest = rest[extras_end + 1..].trim();
+    }
+    let rest = rest.trim_start_matches('(').trim_end_matches(')');
+    if rest.trim().is_empty() {
+        return Ok((name, VersionSpecifiers::empty()));
+    }
+    let specifiers = VersionSpecifiers::from_str(rest).map_err(|error| error.to_string())?;
+    return Ok((name, specifiers));
+}
+
+/// Prints the versions of a package matching a requirement the way pip
+/// selects them: prereleases only with `pre`, when the specifiers name one,
+/// or when nothing else matches.
+pub async fn resolve(
+    app_state: &AppState,
+    namespace_name: &str,
+    requirement: &str,
+    pre: bool,
+    output: Output,
+) {
+    let (package_name, specifiers) = parse_requirement(requirement).unwrap_or_else(|error| {
+        fail(format!(
+            "Can't parse requirement {}: {}",
+            requirement, error
+        ))
+    });
+    let namespace = namespace(app_state, namespace_name);
+    let Ok((configured_name, repository)) = namespace.find_repository(package_name) else {
+        fail(format!(
+            "{} is not configured",
+            namespace.qualified_name(package_name)
+        ))
+    };
+    let metadata = fetch(app_state, namespace, repository).await.unwrap_or_else(|error| {
+        fail(format!(
+            "Can't fetch {}/{} from GitHub: {}",
+            repository.owner, repository.name, error
+        ))
+    });
+
+    let mut releases: Vec<(Option<Pep440Version>, &Release)> = metadata
+        .releases
+        .iter()
+        .map(|release| (Pep440Version::from_str(release.version()).ok(), release))
+        .collect();
+    releases.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let matching = |version: &Pep440Version| specifiers.contains(version);
+    let allows_prereleases = pre
+        || specifiers
+            .iter()
+            .any(|specifier| specifier.any_prerelease())
+        || !releases.iter().any(|(version, _)| {
+            version
+                .as_ref()
+                .is_some_and(|version| matching(version) && !version.any_prerelease())
+        });
+    let versions: Vec<Candidate> = releases
+        .iter()
+        .map(|(version, release)| {
+            let excluded = match version {
+                None => Some("not a PEP 440 version".to_string()),
+                Some(version) if !matching(version) => {
+                    Some(format!("doesn't match {}", specifiers))
+                }
+                Some(version) if version.any_prerelease() && !allows_prereleases => {
+                    Some("prerelease, allowed with --pre".to_string())
+                }
+                Some(_) => None,
+            };
+            Candidate {
+                version: release.version().to_string(),
+                excluded,
+                files: files(release),
+            }
+        })
+        .collect();
+    let resolution = Resolution {
+        package: namespace.qualified_name(configured_name),
+        specifiers: specifiers.to_string(),
+        selected: versions
+            .iter()
+            .find(|candidate| candidate.excluded.is_none())
+            .map(|candidate| candidate.version.clone()),
+        versions,
+    };
+
+    if output == Output::Json {
+        println!("{}", serde_json::to_string_pretty(&resolution).unwrap());
+        return;
+    }
+    let Some(selected) = &resolution.selected else {
+        println!(
+            "No version of {} matches {}",
+            resolution.package, requirement
+        );
+        for candidate in &resolution.versions {
+            println!(
+                "  {:<20} {}",
+                candidate.version,
+                candidate.excluded.as_deref().unwrap_or_default()
+            );
+        }
+        std::process::exit(1);
+    };
+    println!(
+        "{} resolves to {} {}",
+        requirement, resolution.package, selected
+    );
+    for candidate in &resolution.versions {
+        match &candidate.excluded {
+            None => {
+                println!("\n{}", candidate.version);
+                print_files(&candidate.files);
+            }
+            Some(reason) => println!("\n{} skipped: {}", candidate.version, reason),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Installing CLIs released by packages with `downloads`: `/install/<package>.sh`
+//! is a script picking the file of the latest release for the machine's OS
+//! and architecture, `/install/<package>.rb` a Homebrew formula and
+//! `/install/<package>.json` the manifest both are generated from. Platforms
+//! are read from the file names, like `my-cli-1.2.0-linux-amd64.tar.gz`;
+//! files download from `/downloads/`.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use askama::Template;
+use askama_axum::Response;
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Json};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::assets;
+use crate::auth::GithubToken;
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{latest_release, Asset, GithubClient};
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
+enum Archive {
+    Tar,
+    Zip,
+    Binary,
+}
+
+/// A file of the release for one platform, `<os>-<arch>` as `uname` names
+/// them once lowercased: `linux-x86_64`, `darwin-aarch64`.
+#[derive(Serialize, Clone)]
+pub struct PlatformFile {
+    platform: String,
+    name: String,
+    url: String,
+    /// empty when unknown
+    sha256: String,
+    /// CPU as Homebrew's `on_arm` and `on_intel` name it
+    #[serde(skip)]
+    cpu: &'static str,
+}
+
+fn archive(filename: &str) -> Option<Archive> {
+    if [".tar.gz", ".tgz", ".tar.xz"]
+        .iter()
+        .any(|extension| filename.ends_with(extension))
+    {
+        return Some(Archive::Tar);
+    }
+    if filename.ends_with(".zip") {
+        return Some(Archive::Zip);
+    }
+    // executables have no extension, the dots are those of the version
+    let extension = filename.rsplit_once('.').map(|(_, extension)| extension);
+    if extension.is_none_or(|extension| extension.contains(['-', '_'])) {
+        return Some(Archive::Binary);
+    }
+    return None;
+}
+
+/// Operating systems and architectures `filename` is a build for, as
+/// `uname` names them once lowercased.
+pub fn platforms(filename: &str) -> Vec<(&'static str, &'static str)> {
+    let name = filename.to_lowercase();
+    let os = if name.contains("linux") {
+        "linux"
+    } else if ["darwin", "macos", "osx", "apple"]
+        .iter()
+        .any(|os| name.contains(os))
+    {
+        "darwin"
+    } else if ["windows", "win32", "win64", "win_"]
+        .iter()
+        .any(|os| name.contains(os))
+    {
+        "windows"
+    } else {
+        return vec![];
+    };
+    let mut archs = vec![];
+    if ["x86_64", "amd64", "x64"]
+        .iter()
+        .any(|arch| name.contains(arch))
+    {
+        archs.push("x86_64");
+    }
+    if ["aarch64", "arm64"].iter().any(|arch| name.contains(arch)) {
+        archs.push("aarch64");
+    }
+    if ["i686", "i386", "win32"]
+        .iter()
+        .any(|arch| name.contains(arch))
+    {
+        archs.push("x86");
+    }
+    if os == "darwin" && archs.is_empty() && name.contains("universal") {
+        archs = vec!["x86_64", "aarch64"];
+    }
+    return archs.into_iter().map(|arch| (os, arch)).collect();
+}
+
+/// The file to install on every platform: archives over bare executables,
+/// static musl builds over glibc ones.
+fn platform_files(base_url: &str, assets: &[Asset]) -> Vec<PlatformFile> {
+    let mut chosen: BTreeMap<String, (Archive, bool, &Asset)> = BTreeMap::new();
+    for asset in assets {
+        // the script quotes names and urls with single quotes
+        if asset.name.contains('\'') {
+            continue;
+        }
+        let Some(archive) = archive(&asset.name) else {
+            continue;
+        };
+        let is_glibc = !asset.name.contains("musl");
+        for (os, arch) in platforms(&asset.name) {
+            // the script runs on 64-bit Linux and macOS
+            if os == "windows" || arch == "x86" {
+                continue;
+            }
+            let platform = format!("{}-{}", os, arch);
+            let candidate = (archive, is_glibc, asset);
+            match chosen.get(&platform) {
+                Some((known_archive, known_glibc, _))
+                    if (*known_archive, *known_glibc) <= (archive, is_glibc) => {}
+                _ => {
+                    chosen.insert(platform, candidate);
+                }
+            }
+        }
+    }
+    return chosen
+        .into_iter()
+        .map(|(platform, (_, _, asset))| PlatformFile {
+            cpu: match platform.ends_with("aarch64") {
+                true => "arm",
+                false => "intel",
+            },
+            platform,
+            name: asset.name.clone(),
+            url: format!("{}/{}", base_url, asset.name),
+            sha256: asset.sha256().unwrap_or_default().to_string(),
+        })
+        .collect();
+}
+
+#[derive(Template)]
+#[template(path = "install.sh", escape = "none")]
+struct InstallScript {
+    package_name: String,
+    version: String,
+    base_url: String,
+    binary: String,
+    files: Vec<PlatformFile>,
+}
+
+#[derive(Template)]
+#[template(path = "formula.rb", escape = "none")]
+struct Formula {
+    class_name: String,
+    description: String,
+    homepage: String,
+    version: String,
+    binary: String,
+    /// Homebrew's `on_macos` and `on_linux`
+    systems: Vec<(&'static str, Vec<PlatformFile>)>,
+}
+
+/// Formula class name of a package, `my-cli` is `MyCli`.
+fn class_name(package_name: &str) -> String {
+    return package_name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            format!("{}{}", first, chars.as_str())
+        })
+        .collect();
+}
+
+/// Double quoted Ruby string content.
+fn ruby_escape(value: &str) -> String {
+    return value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('#', "\\#");
+}
+
+fn render(template: impl Template, content_type: &'static str) -> Result<Response, ErrorResponse> {
+    let body = template
+        .render()
+        .map_err(|error| ErrorResponse::Failure(ErrorCode::Internal, error.to_string()))?;
+    return Ok(([(header::CONTENT_TYPE, content_type)], body).into_response());
+}
+
+/// `GET /install/<package>.sh`, `.rb` or `.json`, of the latest release.
+pub async fn install(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+    Path(script): Path<String>,
+    GithubToken(token): GithubToken,
+    account: CurrentAccount,
+) -> Result<Response, ErrorResponse> {
+    let (package_name, format) = script.rsplit_once('.').ok_or(ErrorResponse::PageNotFound)?;
+    let (configured_name, repository) = namespace.find_repository(package_name)?;
+    if !repository.downloads {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    account.check_package(&namespace, configured_name)?;
+    let client = GithubClient::new(&app_state.github, token);
+    let releases =
+        assets::downloadable(&app_state, &client, &namespace, configured_name, repository).await?;
+    let release = latest_release(&releases).ok_or(ErrorResponse::PageNotFound)?;
+    let version = release.version().to_string();
+    let base_url = format!("{}{}", app_state.config.external_url, prefix);
+    let files_url = format!(
+        "{}/downloads/{}/{}",
+        base_url,
+        names::normalize(configured_name),
+        version
+    );
+    let files = platform_files(&files_url, &release.assets);
+    if files.is_empty() {
+        // no builds for Linux or macOS
+        return Err(ErrorResponse::PageNotFound);
+    }
+    return match format {
+        "json" => Ok(Json(json!({
+            "package": configured_name,
+            "version": version,
+            "files": files,
+        }))
+        .into_response()),
+        "sh" => render(
+            InstallScript {
+                package_name: configured_name.clone(),
+                version,
+                base_url,
+                binary: configured_name.clone(),
+                files,
+            },
+            "text/x-shellscript; charset=utf-8",
+        ),
+        "rb" => {
+            let description = namespace
+                .metadata
+                .get(configured_name)
+                .and_then(|metadata| metadata.description)
+                .unwrap_or_default();
+            let systems = [("macos", "darwin-"), ("linux", "linux-")]
+                .into_iter()
+                .map(|(system, platform)| {
+                    let files = files
+                        .iter()
+                        .filter(|file| file.platform.starts_with(platform))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    (system, files)
+                })
+                .filter(|(_, files)| !files.is_empty())
+                .collect();
+            render(
+                Formula {
+                    class_name: class_name(configured_name),
+                    description: ruby_escape(&description),
+                    homepage: ruby_escape(&repository.url(&app_state.github.storage)),
+                    version,
+                    binary: configured_name.clone(),
+                    systems,
+                },
+                "text/x-ruby; charset=utf-8",
+            )
+        }
+        _ => Err(ErrorResponse::PageNotFound),
+    };
+}
@@ -0,0 +1,63 @@
+use std::io;
+
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::github::ByteStream;
+
+struct Verification<F: FnOnce()> {
+    stream: ByteStream<io::Error>,
+    hasher: Option<Sha256>,
+    expected_sha256: String,
+    on_mismatch: Option<F>,
+}
+
+/// Passes `stream` through while hashing it, the last chunk is replaced by an
+/// error when the content doesn't match `expected_sha256`, so the client never
+/// receives a complete but corrupted file.
+pub fn verify_sha256<F>(
+    stream: ByteStream<io::Error>,
+    expected_sha256: String,
+    on_mismatch: F,
+) -> ByteStream<io::Error>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let verification = Verification {
+        stream,
+        hasher: Some(Sha256::new()),
+        expected_sha256: expected_sha256.to_lowercase(),
+        on_mismatch: Some(on_mismatch),
+    };
+    let stream = futures_util::stream::unfold(verification, |mut verification| async move {
+        let hasher = verification.hasher.as_mut()?;
+        match verification.stream.next().await {
+            Some(Ok(chunk)) => {
+                hasher.update(&chunk);
+                return Some((Ok(chunk), verification));
+            }
+            Some(Err(error)) => {
+                verification.hasher = None;
+                return Some((Err(error), verification));
+            }
+            None => {
+                let actual = hex::encode(verification.hasher.take()?.finalize());
+                if actual == verification.expected_sha256 {
+                    return None;
+                }
+                if let Some(on_mismatch) = verification.on_mismatch.take() {
+                    on_mismatch();
+                }
+                let error = io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "sha256 mismatch, expected {} got {}",
+                        verification.expected_sha256, actual
+                    ),
+                );
+                return Some((Err(error), verification));
+            }
+        }
+    });
+    return Box::pin(stream);
+}
@@ -0,0 +1,64 @@
+//! `/api/repos`: what the index serves, as JSON for internal tooling: every
+//! package of the namespace with the repository it comes from and how fresh
+//! its synced metadata is. Like the pages, it needs an access token only
+//! where the namespace has them, and lists public packages under `/public/`.
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::State;
+use axum::response::Json;
+use serde_json::{json, Value};
+
+use crate::github::latest_release;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::AppState;
+
+pub async fn repos(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+) -> Json<Value> {
+    // a package missed more than one sync is stale
+    let stale_after = app_state.config.sync_interval.as_secs() * 2;
+    let packages: Vec<Value> = namespace
+        .repos
+        .all()
+        .into_iter()
+        .map(|package_name| {
+            let repository = namespace.repos.get(&package_name).unwrap();
+            let normalized_name = names::normalize(&package_name);
+            let metadata = namespace.metadata.get(&package_name);
+            let synced_at = namespace.metadata.synced_at(&package_name);
+            let synced_age = synced_at
+                .and_then(|synced_at| SystemTime::now().duration_since(synced_at).ok())
+                .map(|age| age.as_secs());
+            json!({
+                "name": package_name,
+                "normalized_name": normalized_name,
+                "owner": repository.owner,
+                "repository": repository.name,
+                "provider": repository.provider,
+                "visibility": repository.visibility,
+                "url": repository.url(&app_state.github.storage),
+                "index_url": format!(
+                    "{}{}/simple/{}/",
+                    app_state.config.external_url, prefix, normalized_name
+                ),
+                "latest_version": metadata
+                    .as_ref()
+                    .and_then(|metadata| latest_release(&metadata.releases))
+                    .map(|release| release.version()),
+                "serial": metadata.as_ref().map(|metadata| metadata.serial),
+                "synced_at": synced_at
+                    .and_then(|synced_at| synced_at.duration_since(UNIX_EPOCH).ok())
+                    .map(|synced_at| synced_at.as_secs()),
+                "synced_age": synced_age,
+                "stale": synced_age.is_none_or(|age| age > stale_after),
+            })
+        })
+        .collect();
+    return Json(json!({
+        "namespace": namespace.name,
+        "packages": packages,
+    }));
+}
@@ -0,0 +1,347 @@
+//! Leader election between replicas, so only one of them syncs releases from
+//! GitHub and sends notifications. Selected with `LEADER_ELECTION`:
+//! `kubernetes` (a coordination.k8s.io Lease) or `redis` (a key with a ttl).
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::aws;
+use crate::config::Config;
+use crate::github;
+use crate::redact::log;
+
+const SERVICE_ACCOUNT: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Takes the lock when it is free, extends it when already held by `ARGV[1]`.
+const REDIS_ACQUIRE: &str = "local holder = redis.call('get', KEYS[1]) \
+    if holder == false or holder == ARGV[1] then \
+    redis.call('set', KEYS[1], ARGV[1], 'PX', ARGV[2]) return 1 end \
+    return 0";
+
+/// How long redis may take to accept the connection or to answer a command.
+const REDIS_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum Election {
+    /// a single instance, always the leader
+    None,
+    Kubernetes(KubernetesLease),
+    Redis(RedisLock),
+}
+
+pub struct KubernetesLease {
+    identity: String,
+    leases_url: String,
+    lease_name: String,
+    lease_duration: Duration,
+    client: reqwest::Client,
+    token: String,
+}
+
+pub struct RedisLock {
+    identity: String,
+    address: String,
+    password: Option<String>,
+    /// selected after connecting, from the path of `REDIS_URL`
+    database: Option<String>,
+    key: String,
+    lease_duration: Duration,
+}
+
+impl Election {
+    pub fn from_config(config: &Config) -> Self {
+        let identity =
+            std::env::var("HOSTNAME").unwrap_or_else(|_| format!("pigi-{}", std::process::id()));
+        // the leader renews on every sync, a few missed syncs hand over leadership
+        let lease_duration = config.sync_interval * 3;
+        let name = &config.leader_election_lease;
+        return match config.leader_election.as_deref() {
+            None => Election::None,
+            Some("kubernetes") => {
+                let read = |file: &str| {
+                    std::fs::read(format!("{}/{}", SERVICE_ACCOUNT, file)).unwrap_or_else(|error| {
+                        panic!("cannot read service account {}: {}", file, error)
+                    })
+                };
+                let certificate = reqwest::Certificate::from_pem(&read("ca.crt"))
+                    .expect("cannot parse the service account ca.crt");
+                let namespace = String::from_utf8_lossy(&read("namespace"))
+                    .trim()
+                    .to_string();
+                let host = std::env::var("KUBERNETES_SERVICE_HOST")
+                    .expect("KUBERNETES_SERVICE_HOST env variable is not set");
+                let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or("443".to_string());
+                Election::Kubernetes(KubernetesLease {
+                    identity,
+                    leases_url: format!(
+                        "https://{}:{}/apis/coordination.k8s.io/v1/namespaces/{}/leases",
+                        host, port, namespace
+                    ),
+                    lease_name: name.clone(),
+                    lease_duration,
+                    client: reqwest::Client::builder()
+                        .add_root_certificate(certificate)
+                        .build()
+                        .unwrap(),
+                    token: String::from_utf8_lossy(&read("token")).trim().to_string(),
+                })
+            }
+            Some("redis") => {
+                let url = std::env::var("REDIS_URL").expect("REDIS_URL env variable is not set");
+                let address = url.strip_prefix("redis://").unwrap_or(&url);
+                let (password, address) = match address.rsplit_once('@') {
+                    Some((credentials, address)) => {
+                        let password = credentials.rsplit(':').next().unwrap_or(credentials);
+                        (Some(password.to_string()), address)
+                    }
+                    None => (None, address),
+                };
+                let (address, database) = address.split_once('/').unwrap_or((address, ""));
+                let database = match database {
+                    "" => None,
+                    database if database.parse::<u32>().is_ok() => Some(database.to_string()),
+                    database => panic!("REDIS_URL names database {}, not a number", database),
+                };
+                Election::Redis(RedisLock {
+                    identity,
+                    address: address.to_string(),
+                    password,
+                    database,
+                    key: format!("pigi:leader:{}", name),
+                    lease_duration,
+                })
+            }
+            Some(other) => panic!("unknown LEADER_ELECTION {}", other),
+        };
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return !matches!(self, Election::None);
+    }
+
+    /// Takes or renews the leadership, an instance that can't reach the lock
+    /// assumes it isn't the leader.
+    pub async fn is_leader(&self) -> bool {
+        let result = match self {
+            Election::None => return true,
+            Election::Kubernetes(lease) => lease.acquire().await,
+            Election::Redis(lock) => lock.acquire().await,
+        };
+        return result.unwrap_or_else(|error| {
+            log!("Leader election failed: {}", error);
+            false
+        });
+    }
+}
+
+impl KubernetesLease {
+    async fn acquire(&self) -> Result<bool, String> {
+        let KubernetesLease {
+            identity,
+            leases_url,
+            lease_name,
+            lease_duration,
+            client,
+            token,
+        } = self;
+        let lease_url = format!("{}/{}", leases_url, lease_name);
+        let response = client
+            .get(&lease_url)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        let now = micro_time(SystemTime::now());
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            let spec = json!({
+                "holderIdentity": identity,
+                "leaseDurationSeconds": lease_duration.as_secs(),
+                "acquireTime": now,
+                "renewTime": now,
+                "leaseTransitions": 0,
+            });
+            let lease = json!({
+                "apiVersion": "coordination.k8s.io/v1",
+                "kind": "Lease",
+                "metadata": {"name": lease_name},
+                "spec": spec,
+            });
+            let created = client
+                .post(leases_url)
+                .bearer_auth(token)
+                .json(&lease)
+                .send()
+                .await
+                .map_err(|error| error.to_string())?;
+            return Ok(created.status().is_success());
+        }
+        let mut lease: Value = response
+            .error_for_status()
+            .map_err(|error| error.to_string())?
+            .json()
+            .await
+            .map_err(|error| error.to_string())?;
+        let holder = lease["spec"]["holderIdentity"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        if holder != *identity && !holder.is_empty() && !is_expired(&lease["spec"]) {
+            return Ok(false);
+        }
+        let spec = &mut lease["spec"];
+        if holder != *identity {
+            spec["acquireTime"] = json!(now);
+            let transitions = spec["leaseTransitions"].as_u64().unwrap_or(0);
+            spec["leaseTransitions"] = json!(transitions + 1);
+        }
+        spec["holderIdentity"] = json!(identity);
+        spec["leaseDurationSeconds"] = json!(lease_duration.as_secs());
+        spec["renewTime"] = json!(now);
+        // the resource version makes the update fail when another replica was faster
+        let updated = client
+            .put(&lease_url)
+            .bearer_auth(token)
+            .json(&lease)
+            .send()
+            .await
+            .map_err(|error| error.to_string())?;
+        return Ok(updated.status().is_success());
+    }
+}
+
+/// Whether the lease wasn't renewed within its duration, going by its
+/// `renewTime`; a lease without one was never held.
+fn is_expired(spec: &Value) -> bool {
+    let Some(renewed_at) = spec["renewTime"].as_str().and_then(parse_micro_time) else {
+        return true;
+    };
+    let duration = Duration::from_secs(spec["leaseDurationSeconds"].as_u64().unwrap_or(0));
+    return renewed_at + duration < SystemTime::now();
+}
+
+/// `time` as a Kubernetes MicroTime, like `2024-01-02T03:04:05.000000Z`.
+fn micro_time(time: SystemTime) -> String {
+    let (date, clock) = aws::utc_date(time);
+    return format!(
+        "{}-{}-{}T{}:{}:{}.000000Z",
+        &date[..4],
+        &date[4..6],
+        &date[6..],
+        &clock[..2],
+        &clock[2..4],
+        &clock[4..]
+    );
+}
+
+/// Parses a MicroTime, the fraction of the second is dropped.
+fn parse_micro_time(time: &str) -> Option<SystemTime> {
+    let seconds = time.strip_suffix('Z')?;
+    let seconds = seconds.split_once('.').map_or(seconds, |(seconds, _)| seconds);
+    return github::parse_timestamp(&format!("{}Z", seconds));
+}
+
+impl RedisLock {
+    async fn acquire(&self) -> Result<bool, String> {
+        let RedisLock {
+            identity,
+            address,
+            password,
+            database,
+            key,
+            lease_duration,
+        } = self;
+        let mut connection = tokio::time::timeout(REDIS_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| format!("connecting to redis at {} timed out", address))?
+            .map_err(|error| error.to_string())?;
+        if let Some(password) = password {
+            let reply = redis_command(&mut connection, &["AUTH", password]).await?;
+            if !reply.starts_with('+') {
+                return Err(format!("redis rejected the password: {}", reply));
+            }
+        }
+        if let Some(database) = database {
+            let reply = redis_command(&mut connection, &["SELECT", database]).await?;
+            if !reply.starts_with('+') {
+                return Err(format!("redis can't select database {}: {}", database, reply));
+            }
+        }
+        let ttl = lease_duration.as_millis().to_string();
+        let reply = redis_command(
+            &mut connection,
+            &["EVAL", REDIS_ACQUIRE, "1", key, identity, &ttl],
+        )
+        .await?;
+        return match reply.as_str() {
+            ":1" => Ok(true),
+            ":0" => Ok(false),
+            _ => Err(format!("unexpected redis reply {}", reply)),
+        };
+    }
+}
+
+/// Sends a command in the RESP protocol, returns the first line of the reply.
+async fn redis_command(connection: &mut TcpStream, arguments: &[&str]) -> Result<String, String> {
+    return tokio::time::timeout(REDIS_TIMEOUT, send_command(connection, arguments))
+        .await
+        .map_err(|_| format!("redis didn't answer {} in time", arguments[0]))?;
+}
+
+async fn send_command(connection: &mut TcpStream, arguments: &[&str]) -> Result<String, String> {
+    let mut command = format!("*{}\r\n", arguments.len());
+    for argument in arguments {
+        command += &format!("${}\r\n{}\r\n", argument.len(), argument);
+    }
+    connection
+        .write_all(command.as_bytes())
+        .await
+        .map_err(|error| error.to_string())?;
+    let mut reply = Vec::new();
+    let mut byte = [0; 1];
+    while !reply.ends_with(b"\r\n") {
+        let read = connection
+            .read(&mut byte)
+            .await
+            .map_err(|error| error.to_string())?;
+        if read == 0 {
+            return Err("redis closed the connection".to_string());
+        }
+        reply.push(byte[0]);
+    }
+    return Ok(String::from_utf8_lossy(&reply).trim_end().to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn micro_times_round_trip() {
+        let time = github::parse_timestamp("2024-01-02T03:04:05Z").unwrap();
+        assert_eq!(micro_time(time), "2024-01-02T03:04:05.000000Z");
+        assert_eq!(parse_micro_time("2024-01-02T03:04:05.123456Z"), Some(time));
+        assert_eq!(parse_micro_time("2024-01-02T03:04:05Z"), Some(time));
+        assert_eq!(parse_micro_time("yesterday"), None);
+    }
+
+    #[test]
+    fn leases_expire_their_duration_after_the_renewal() {
+        let renewed = |ago: u64| {
+            let spec = json!({
+                "renewTime": micro_time(SystemTime::now() - Duration::from_secs(ago)),
+                "leaseDurationSeconds": 60,
+            });
+            return is_expired(&spec);
+        };
+        assert!(!renewed(0));
+        assert!(!renewed(50));
+        assert!(renewed(70));
+    }
+
+    #[test]
+    fn leases_never_renewed_are_expired() {
+        assert!(is_expired(&json!({"leaseDurationSeconds": 60})));
+        assert!(is_expired(&json!({"renewTime": "", "leaseDurationSeconds": 60})));
+    }
+}
@@ -0,0 +1,897 @@
+#![allow(clippy::needless_return)]
+// the OpenAPI document is a single json! literal
+#![recursion_limit = "256"]
+
+use askama::Template;
+use askama_axum::Response;
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::response::Redirect;
+use axum::routing::{delete, get, post};
+use axum::{Router, ServiceExt};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use dotenv::dotenv;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tower::Layer;
+
+use asset_metadata::AssetMetadata;
+use auth::GithubToken;
+use backfill::HashStore;
+use cache::ArtifactCache;
+use companions::CompanionKind;
+use config::{Config, PackageLink, Repository};
+use confusion::ConfusionGuard;
+use content_types::ContentTypes;
+use error::ErrorResponse;
+use github::{version_key, GithubClient, GithubShared, Release};
+use health::Health;
+use leader::Election;
+use links::LinkChecker;
+use lockout::Lockout;
+use namespace::{CurrentNamespace, Namespace, Namespaces};
+use notify::Notifier;
+use osv::{Osv, Vulnerability};
+use prefetch::Prefetcher;
+use quarantine::Quarantine;
+use quotas::StorageUsage;
+use redact::log;
+use reporting::Reporter;
+use sbom::Sboms;
+use secrets::SecretProvider;
+use service_accounts::ServiceAccounts;
+use signing::IndexSigner;
+use templates::{Page, TemplateContext, Templates};
+use theme::Theme;
+use tower_http::services::ServeDir;
+use trusted_publishing::TrustedPublishing;
+
+mod asset_metadata;
+mod assets;
+mod auth;
+mod aws;
+mod backfill;
+pub mod bench;
+mod browse;
+mod buffer;
+mod cache;
+mod catalog;
+mod changelog;
+mod companions;
+mod compat;
+mod conda;
+mod config;
+mod confusion;
+mod content_types;
+mod cors;
+mod debug;
+mod download;
+mod error;
+mod etag;
+mod export;
+mod failover;
+mod feed;
+mod filenames;
+mod github;
+mod github_cache;
+mod glob;
+mod health;
+mod import;
+mod inspect;
+mod install;
+mod integrity;
+mod inventory;
+mod leader;
+mod links;
+mod lockout;
+mod metadata;
+mod metrics;
+mod mirror;
+mod names;
+mod namespace;
+mod notify;
+mod openapi;
+mod osv;
+mod overload;
+mod policy;
+mod prefetch;
+mod preflight;
+mod propagation;
+mod proxy;
+mod publish;
+mod pypi;
+mod quarantine;
+mod quotas;
+mod redact;
+mod render;
+mod replication;
+mod reporting;
+mod restart;
+mod runtime;
+mod sbom;
+mod scheduler;
+mod schema;
+mod search;
+mod secrets;
+mod security_headers;
+mod service_accounts;
+mod signed_urls;
+mod signing;
+mod simple_api;
+mod snapshots;
+mod storage;
+mod templates;
+mod theme;
+mod toml;
+mod trusted_publishing;
+mod upload;
+mod versions;
+
+#[derive(Template, Serialize)]
+#[template(path = "index.html")]
+pub struct Index {
+    external_url: String,
+    authenticated_url: String,
+    context: TemplateContext,
+    theme: Theme,
+}
+
+impl Page for Index {
+    const NAME: &'static str = "index.html";
+
+    fn context(&mut self) -> &mut TemplateContext {
+        return &mut self.context;
+    }
+
+    fn theme(&mut self) -> &mut Theme {
+        return &mut self.theme;
+    }
+}
+
+async fn index(State(app_state): State<Arc<AppState>>, current: CurrentNamespace) -> Response {
+    let external_url = format!("{}{}", app_state.config.external_url, current.prefix);
+    let authenticated_url = match external_url.split_once("://") {
+        Some((scheme, host)) => format!("{}://__token__:$GITHUB_PERSONAL_TOKEN@{}", scheme, host),
+        None => external_url.clone(),
+    };
+    return app_state.templates.render(Index {
+        external_url,
+        authenticated_url,
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    });
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "simple.html")]
+pub struct Simple {
+    repos: Vec<SimpleProject>,
+    context: TemplateContext,
+    theme: Theme,
+}
+
+#[derive(Serialize)]
+pub struct SimpleProject {
+    name: String,
+    normalized_name: String,
+}
+
+impl Page for Simple {
+    const NAME: &'static str = "simple.html";
+
+    fn context(&mut self) -> &mut TemplateContext {
+        return &mut self.context;
+    }
+
+    fn theme(&mut self) -> &mut Theme {
+        return &mut self.theme;
+    }
+}
+
+impl Simple {
+    fn from_namespace(namespace: &Namespace) -> Self {
+        let repos = namespace
+            .repos
+            .all()
+            .into_iter()
+            .map(|name| SimpleProject {
+                normalized_name: names::normalize(&name),
+                name,
+            })
+            .collect();
+        return Simple {
+            repos,
+            context: TemplateContext::new(),
+            theme: Theme::default(),
+        };
+    }
+}
+
+async fn simple(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    headers: HeaderMap,
+) -> Response {
+    let negotiated = simple_api::negotiate(&headers);
+    let response = match negotiated.format {
+        simple_api::Format::JsonV1 => simple_api::index(namespace.repos.all()),
+        simple_api::Format::Html => app_state
+            .templates
+            .render(Simple::from_namespace(&namespace)),
+    };
+    let response = simple_api::with_serial(response, namespace.metadata.last_serial());
+    return simple_api::finish(&app_state.config, negotiated, response);
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "package.html")]
+pub struct PackageTemplate {
+    github_org: String,
+    package_name: String,
+    assets: Vec<PackageFile>,
+    links: Vec<PackageLink>,
+    context: TemplateContext,
+    theme: Theme,
+}
+
+#[derive(Serialize)]
+pub struct PackageFile {
+    id: u64,
+    version: String,
+    name: String,
+    sha256: Option<String>,
+    /// a detached `.asc` signature is published next to the file
+    has_sig: bool,
+    /// a PEP 740 attestation is published next to the file
+    has_provenance: bool,
+    /// ids of the known vulnerabilities of the version
+    vulnerabilities: Vec<String>,
+}
+
+impl PackageFile {
+    fn from_release(release: &Release) -> impl Iterator<Item = PackageFile> + '_ {
+        return release.assets.iter().map(|asset| PackageFile {
+            id: asset.id,
+            version: release.version().to_string(),
+            name: asset.name.clone(),
+            sha256: asset.sha256().map(|sha256| sha256.to_string()),
+            has_sig: companions::find(&release.assets, &asset.name, CompanionKind::Signature)
+                .is_some(),
+            has_provenance: companions::pep740_attestation(&release.assets, &asset.name).is_some(),
+            vulnerabilities: vec![],
+        });
+    }
+}
+
+impl Page for PackageTemplate {
+    const NAME: &'static str = "package.html";
+
+    fn context(&mut self) -> &mut TemplateContext {
+        return &mut self.context;
+    }
+
+    fn theme(&mut self) -> &mut Theme {
+        return &mut self.theme;
+    }
+}
+
+impl PackageTemplate {
+    fn new(
+        repository: &Repository,
+        package_name: String,
+        releases: &[Release],
+        vulnerabilities: &BTreeMap<String, Vec<Vulnerability>>,
+    ) -> Self {
+        let mut assets: Vec<PackageFile> = releases
+            .iter()
+            .flat_map(PackageFile::from_release)
+            .map(|mut file| {
+                if let Some(known) = vulnerabilities.get(&file.version) {
+                    file.vulnerabilities = known.iter().map(|known| known.id.clone()).collect();
+                }
+                file
+            })
+            .collect();
+        assets.sort_by_cached_key(|file| (version_key(&file.version), file.name.clone()));
+        return PackageTemplate {
+            github_org: repository.owner.clone(),
+            package_name,
+            assets,
+            links: repository.links.clone(),
+            context: TemplateContext::new(),
+            theme: Theme::default(),
+        };
+    }
+}
+
+async fn package(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((package_name,)): Path<(String,)>,
+    GithubToken(token): GithubToken,
+    headers: HeaderMap,
+) -> Result<Response, ErrorResponse> {
+    let client = GithubClient::new(&app_state.github, token.clone());
+    let (configured_name, package) = match namespace.find_repository(&package_name) {
+        Ok(found) => found,
+        Err(error) => {
+            app_state
+                .confusion
+                .check(&app_state, &namespace, &package_name);
+            return Err(error);
+        }
+    };
+    let canonical_name = names::normalize(configured_name);
+    if package_name != canonical_name {
+        return Ok(compat::moved_permanently(format!("../{}/", canonical_name)));
+    }
+    let mut releases = package.releases(&client).await?;
+    quarantine::retain_approved(&app_state, &namespace, configured_name, &mut releases);
+    app_state.hashes.fill(&mut releases);
+    app_state.links.hide_broken(&mut releases);
+    app_state.hashes.record_traffic(&namespace, configured_name);
+    filenames::hide_malformed(configured_name, &mut releases);
+    let metadata = namespace.metadata.get(configured_name);
+    let negotiated = simple_api::negotiate(&headers);
+    let response = if negotiated.format == simple_api::Format::JsonV1 {
+        simple_api::project(configured_name, &releases)
+    } else {
+        let vulnerabilities = metadata
+            .as_ref()
+            .map(|metadata| metadata.vulnerabilities.clone())
+            .unwrap_or_default();
+        app_state.templates.render(PackageTemplate::new(
+            package,
+            package_name,
+            &releases,
+            &vulnerabilities,
+        ))
+    };
+    // packages that weren't synced yet have no serial
+    let response = match metadata {
+        Some(metadata) => simple_api::with_serial(response, metadata.serial),
+        None => response,
+    };
+    return Ok(simple_api::finish(&app_state.config, negotiated, response));
+}
+
+struct AppState {
+    config: Config,
+    namespaces: Namespaces,
+    templates: Templates,
+    notifier: Notifier,
+    cache: ArtifactCache,
+    /// runtime downloads from GitHub are streamed on
+    streaming: Handle,
+    secrets: SecretProvider,
+    leader: Election,
+    reporter: Reporter,
+    /// outcome of the latest run of every background task
+    health: Health,
+    confusion: ConfusionGuard,
+    /// looks up vulnerabilities of synced versions, when `OSV_LOOKUP` is set
+    osv: Option<Osv>,
+    /// CycloneDX documents of the wheels asked for so far
+    sboms: Sboms,
+    /// holds new versions back until approved, when `QUARANTINE_PATH` is set
+    quarantine: Option<Quarantine>,
+    service_accounts: Option<ServiceAccounts>,
+    /// verifies the OIDC tokens of namespaces with `trusted_publishing`
+    trusted_publishing: TrustedPublishing,
+    lockout: Option<Lockout>,
+    /// signs index pages and JSON API responses, when `INDEX_SIGNING_KEY_PATH` is set
+    signer: Option<IndexSigner>,
+    /// hashes of the files GitHub has no digest for
+    hashes: HashStore,
+    content_types: ContentTypes,
+    /// downloads queued with `POST /api/prefetch`
+    prefetcher: Prefetcher,
+    /// files found gone, when `LINK_CHECK_INTERVAL` is set
+    links: LinkChecker,
+    /// single assets as GitHub has them now, for downloads
+    asset_metadata: AssetMetadata,
+    /// storage of packages counted for the `max_storage` of namespaces
+    storage_usage: StorageUsage,
+    /// state every GitHub client shares
+    github: Arc<GithubShared>,
+    /// hashes of the templates' inline scripts, allowed by the `Content-Security-Policy`
+    script_hashes: Vec<String>,
+    /// tells apart the directories of concurrent uploads
+    uploads: AtomicU64,
+}
+
+#[derive(Parser)]
+#[command(version, about = "Python package index serving GitHub release assets")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// format of what list, show, resolve and render print
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    output: Output,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the index, the default
+    Serve,
+    /// Manage the artifact cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Time index rendering and download streaming
+    Bench,
+    /// List the configured packages with the versions GitHub has for them
+    List {
+        /// only packages of this namespace, the default namespace is ""
+        #[arg(long)]
+        namespace: Option<String>,
+    },
+    /// Show every version of a package with its files
+    Show {
+        package: String,
+        #[arg(long, default_value = "")]
+        namespace: String,
+    },
+    /// Show the versions of a package pip would choose from for a requirement like "mypkg>=1.2,<2"
+    Resolve {
+        requirement: String,
+        #[arg(long, default_value = "")]
+        namespace: String,
+        /// allow prereleases, like pip install --pre
+        #[arg(long)]
+        pre: bool,
+    },
+    /// Download the files of a release from a running pigi, e.g. "mypkg==1.2.0"
+    Download {
+        requirement: String,
+        /// address of pigi, defaults to EXTERNAL_URL
+        #[arg(long, env = "PIGI_INDEX_URL")]
+        index_url: Option<String>,
+        /// token sent as the basic auth password
+        #[arg(long, env = "PIGI_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+        /// directory to download into
+        #[arg(long, default_value = ".")]
+        dest: PathBuf,
+        /// only files matching this pattern, e.g. "*.whl"
+        #[arg(long)]
+        file: Option<String>,
+    },
+    /// Upload distributions to the GitHub release of their version, creating it when missing
+    Publish {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// configured package name, by default taken from the file names
+        #[arg(long)]
+        package: Option<String>,
+        #[arg(long, default_value = "")]
+        namespace: String,
+        /// prepended to the version to name the release tag
+        #[arg(long, default_value = "v")]
+        tag_prefix: String,
+        /// skip files already on the release instead of failing
+        #[arg(long)]
+        skip_existing: bool,
+        /// `sha256sum` output the files have to match, e.g. SHA256SUMS of the build
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+        /// upload to a draft release, hidden from the index until promoted with POST /debug/promote
+        #[arg(long)]
+        draft: bool,
+    },
+    /// Upload a directory of existing wheels and sdists to the GitHub releases of their versions
+    Import {
+        dir: PathBuf,
+        /// configured package name, files of other packages are skipped
+        #[arg(long)]
+        package: String,
+        #[arg(long, default_value = "")]
+        namespace: String,
+        /// prepended to the version to name the release tags
+        #[arg(long, default_value = "v")]
+        tag_prefix: String,
+        /// only list what would be uploaded
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the completion script for a shell
+    Completions { shell: Shell },
+    /// Print the simple index or a package page rendered with TEMPLATES_DIR, to check templates before deploying
+    Render {
+        /// the page of this package instead of the simple index, with --output json its JSON API document
+        #[arg(long)]
+        package: Option<String>,
+        #[arg(long, default_value = "")]
+        namespace: String,
+        /// use the metadata shared in CACHE_DIR instead of fetching it from GitHub
+        #[arg(long)]
+        cached: bool,
+    },
+    /// Write the simple index with every file into a directory, rewriting only what changed since the last export there
+    Export {
+        dest: PathBuf,
+        #[arg(long, default_value = "")]
+        namespace: String,
+        /// use the metadata shared in CACHE_DIR instead of syncing it from GitHub
+        #[arg(long)]
+        cached: bool,
+    },
+    /// Inspect the config files
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the JSON Schema of a config file, for editors and CI validation
+    Schema {
+        #[arg(value_enum, default_value = "repos")]
+        file: ConfigFile,
+    },
+    /// Print the repos config with REPOS_CONFIG_OVERLAYS merged over it and environment variables expanded, tokens redacted
+    Resolve,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConfigFile {
+    /// REPOS_CONFIG_PATH
+    Repos,
+    /// NOTIFICATIONS_CONFIG_PATH
+    Notifications,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove cached files not referenced by any current release and not downloaded within CACHE_RETENTION
+    Gc {
+        /// only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub fn main() {
+    redact::install_panic_hook();
+    dotenv().ok();
+    let cli = Cli::parse();
+    // doesn't need any config, so it works before the config is written
+    if let Some(Command::Config(ConfigCommand::Schema { file })) = cli.command {
+        let schema = match file {
+            ConfigFile::Repos => schema::repos(),
+            ConfigFile::Notifications => schema::notifications(),
+        };
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        return;
+    }
+
+    let config = Config::from_env();
+    redact::register_config(&config);
+    let main_runtime = runtime::build("pigi", config.worker_threads, &config);
+    // a dedicated runtime keeps heavy downloads from starving index requests
+    let streaming_runtime = config
+        .streaming_worker_threads
+        .map(|threads| runtime::build("pigi-streaming", Some(threads), &config));
+    let streaming = streaming_runtime
+        .as_ref()
+        .unwrap_or(&main_runtime)
+        .handle()
+        .clone();
+    main_runtime.block_on(run(cli, config, streaming));
+}
+
+async fn run(cli: Cli, config: Config, streaming: Handle) {
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            let listener = preflight::run(&config).await;
+            serve(app_state(config, streaming).await, listener).await;
+        }
+        Command::Cache(CacheCommand::Gc { dry_run }) => {
+            let app_state = app_state(config, streaming).await;
+            metadata::sync_all(&app_state, false).await;
+            cache::collect_garbage(&app_state, dry_run).await;
+        }
+        Command::Bench => bench::report().await,
+        Command::List { namespace } => {
+            let app_state = app_state(config, streaming).await;
+            inspect::list(&app_state, namespace.as_deref(), cli.output).await;
+        }
+        Command::Show { package, namespace } => {
+            let app_state = app_state(config, streaming).await;
+            inspect::show(&app_state, &namespace, &package, cli.output).await;
+        }
+        Command::Resolve {
+            requirement,
+            namespace,
+            pre,
+        } => {
+            let app_state = app_state(config, streaming).await;
+            inspect::resolve(&app_state, &namespace, &requirement, pre, cli.output).await;
+        }
+        Command::Download {
+            requirement,
+            index_url,
+            token,
+            dest,
+            file,
+        } => {
+            download::run(download::Download {
+                requirement,
+                index_url: index_url.unwrap_or(config.external_url),
+                token,
+                dest,
+                pattern: file,
+            })
+            .await
+        }
+        Command::Publish {
+            files,
+            package,
+            namespace,
+            tag_prefix,
+            skip_existing,
+            checksums,
+            draft,
+        } => {
+            let app_state = app_state(config, streaming).await;
+            let publish = publish::Publish {
+                files,
+                package,
+                namespace,
+                tag_prefix,
+                skip_existing,
+                checksums,
+                draft,
+            };
+            publish::run(&app_state, publish).await;
+        }
+        Command::Import {
+            dir,
+            package,
+            namespace,
+            tag_prefix,
+            dry_run,
+        } => {
+            let app_state = app_state(config, streaming).await;
+            let import = import::Import {
+                dir,
+                package,
+                namespace,
+                tag_prefix,
+                dry_run,
+            };
+            import::run(&app_state, import).await;
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "pigi", &mut std::io::stdout());
+        }
+        Command::Render {
+            package,
+            namespace,
+            cached,
+        } => {
+            let app_state = app_state(config, streaming).await;
+            let render_options = render::Render {
+                namespace,
+                package,
+                cached,
+            };
+            render::run(&app_state, render_options, cli.output).await;
+        }
+        Command::Export {
+            dest,
+            namespace,
+            cached,
+        } => {
+            let app_state = app_state(config, streaming).await;
+            let export = export::Export {
+                dest,
+                namespace,
+                cached,
+            };
+            export::run(&app_state, export).await;
+        }
+        Command::Config(ConfigCommand::Schema { .. }) => {
+            unreachable!("handled before loading the config")
+        }
+        Command::Config(ConfigCommand::Resolve) => {
+            let mut resolved = config::resolve_repos_config(
+                &config.repos_config_path,
+                &config.repos_config_overlays,
+            )
+            .unwrap_or_else(|error| fail(error));
+            if let Err(error) = config::ReposConfig::from_value(resolved.clone()) {
+                fail(error);
+            }
+            config::redact_secrets(&mut resolved);
+            println!("{}", serde_json::to_string_pretty(&resolved).unwrap());
+        }
+    }
+}
+
+/// Ends a command with an error message.
+fn fail(message: String) -> ! {
+    eprintln!("{}", redact::redact(&message));
+    std::process::exit(1);
+}
+
+async fn app_state(config: Config, streaming: Handle) -> Arc<AppState> {
+    let namespaces = Namespaces::from_config(&config);
+    let templates = Templates::from_config(&config);
+    let notifier = Notifier::from_config(&config);
+    let cache = ArtifactCache::from_config(&config);
+    let secrets = SecretProvider::from_config(&config);
+    let leader = Election::from_config(&config);
+    let reporter = Reporter::from_config(&config);
+    let confusion = ConfusionGuard::from_config(&config);
+    let osv = Osv::from_config(&config);
+    let quarantine = Quarantine::from_config(&config);
+    let service_accounts = ServiceAccounts::from_config(&config);
+    let trusted_publishing = TrustedPublishing::from_config(&config);
+    let lockout = Lockout::from_config(&config);
+    let signer = IndexSigner::from_config(&config);
+    let hashes = HashStore::from_config(&config);
+    let content_types = ContentTypes::from_config(&config);
+    let prefetcher = Prefetcher::from_config(&config);
+    let github = GithubShared::from_config(&config);
+    let script_hashes = security_headers::script_hashes(&config);
+    let app_state = Arc::new(AppState {
+        config,
+        namespaces,
+        templates,
+        notifier,
+        cache,
+        streaming,
+        secrets,
+        leader,
+        reporter,
+        health: Health::default(),
+        confusion,
+        osv,
+        sboms: Sboms::default(),
+        quarantine,
+        service_accounts,
+        trusted_publishing,
+        lockout,
+        signer,
+        hashes,
+        content_types,
+        prefetcher,
+        links: LinkChecker::default(),
+        asset_metadata: AssetMetadata::default(),
+        storage_usage: StorageUsage::default(),
+        github,
+        script_hashes,
+        uploads: AtomicU64::new(0),
+    });
+    metadata::load_serials(&app_state);
+    github_cache::load(&app_state);
+    let errors = secrets::refresh_all(&app_state).await;
+    app_state.health.record("secrets refresh", errors);
+    return app_state;
+}
+
+async fn serve(app_state: Arc<AppState>, listener: tokio::net::TcpListener) {
+    let config = &app_state.config;
+    let routes = Router::new()
+        .route("/", get(index))
+        .route("/simple", get(|| async { Redirect::permanent("simple/") }))
+        .route("/simple/", get(simple))
+        .route("/search", get(search::search))
+        .route("/packages/", get(browse::packages))
+        .route("/feed.xml", get(feed::feed))
+        .route("/metrics", get(metrics::metrics))
+        .route("/debug/status", get(debug::status))
+        .route("/debug/vulnerabilities", get(debug::vulnerabilities))
+        .route("/debug/filenames", get(debug::filenames))
+        .route("/debug/repositories", get(debug::repositories))
+        .route("/debug/links", get(debug::links))
+        .route(
+            "/debug/quarantine",
+            get(debug::quarantined).post(debug::approve),
+        )
+        .route("/debug/promote", post(debug::promote))
+        .route("/debug/quotas", get(quotas::usage))
+        .route(
+            "/debug/service-accounts",
+            get(service_accounts::list).post(service_accounts::create),
+        )
+        .route(
+            "/debug/service-accounts/:name",
+            delete(service_accounts::remove),
+        )
+        .route("/api/packages", get(catalog::packages))
+        .route("/api/changelog", get(changelog::changelog))
+        .route("/api/repos", get(inventory::repos))
+        .route(
+            "/api/packages/:package/metadata",
+            get(replication::package_metadata),
+        )
+        .route("/api/packages/:package/versions", get(versions::versions))
+        .route("/api/packages/:package/:version/sbom", get(sbom::sbom))
+        .route(
+            "/api/packages/:package/:version/status",
+            get(propagation::status),
+        )
+        .route(
+            "/api/packages/:package/:version/files/:filename",
+            post(upload::upload),
+        )
+        .route("/api/signing-key", get(signing::signing_key))
+        .route("/api/openapi.json", get(openapi::openapi))
+        .route("/api/docs", get(openapi::docs))
+        .route("/api/snapshot", post(snapshots::create))
+        .route("/api/prefetch", post(prefetch::create))
+        .route("/api/prefetch/:id", get(prefetch::status))
+        .route("/api/sign", get(signed_urls::sign))
+        .route("/snapshots/:id/simple/", get(snapshots::simple))
+        .route("/snapshots/:id/simple/:package/", get(snapshots::package))
+        .route(
+            "/snapshots/:id/simple/:package/:version/:filename",
+            get(snapshots::asset),
+        )
+        .route("/pypi", post(pypi::xmlrpc))
+        .route("/pypi/:package/json", get(pypi::project))
+        .route("/pypi/:package/:version/json", get(pypi::release))
+        .route(
+            "/simple/:package",
+            get(|Path((package_name,)): Path<(String,)>| async move {
+                Redirect::permanent(format!("{}/", package_name.clone()).as_str())
+            }),
+        )
+        .route("/simple/:package/", get(package))
+        .route("/simple/:package/:version/:filename", get(assets::asset))
+        .route("/artifacts/sha256/:digest", get(assets::by_hash))
+        .route("/conda/:channel/:subdir/:filename", get(conda::channel))
+        .route(
+            "/downloads/:package/:version/:filename",
+            get(assets::portal),
+        )
+        .route("/install/:script", get(install::install));
+    let embedded = get(theme::embedded);
+    let routes = match &config.static_dir {
+        Some(static_dir) => {
+            routes.nest_service("/static", ServeDir::new(static_dir).fallback(embedded))
+        }
+        None => routes.nest_service("/static", embedded),
+    };
+
+    log!("Serving under: http://0.0.0.0:{}", config.port);
+    let drain_timeout = config.drain_timeout;
+    metadata::spawn_sync_worker(app_state.clone());
+    cache::spawn_gc_worker(app_state.clone());
+    backfill::spawn_backfill_worker(app_state.clone());
+    prefetch::spawn_prefetch_worker(app_state.clone());
+    links::spawn_link_checker(app_state.clone());
+    failover::spawn_probe_worker(app_state.clone());
+    secrets::spawn_refresh_worker(app_state.clone());
+    let server = from_fn_with_state(app_state.clone(), namespace::resolve_namespace)
+        .layer(routes.with_state(app_state.clone()));
+    // outside of the namespace resolution, which rewrites the path
+    let server = from_fn(etag::etag).layer(server);
+    let server = from_fn_with_state(app_state.clone(), security_headers::apply).layer(server);
+    let server = from_fn_with_state(app_state.clone(), signing::sign).layer(server);
+    let server = from_fn_with_state(app_state.clone(), reporting::report_errors).layer(server);
+    let server = from_fn_with_state(app_state.clone(), lockout::guard).layer(server);
+    // outside of the access token check, preflight requests have no credentials
+    let server = from_fn_with_state(app_state.clone(), cors::cors).layer(server);
+    let server = from_fn_with_state(app_state, overload::shed).layer(server);
+    axum::serve(
+        listener,
+        server.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(restart::shutdown_signal(drain_timeout))
+    .await
+    .unwrap();
+}
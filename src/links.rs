@@ -0,0 +1,192 @@
+//! Checks in the background that the files the index links to can still be
+//! downloaded, when `LINK_CHECK_INTERVAL` is set: every interval a sample of
+//! `LINK_CHECK_SAMPLE` files is probed without downloading them, in turn, so
+//! every file is checked over time. Files that are gone are left out of the
+//! index, so pip doesn't pick a link that 404s, until a later check finds
+//! them again; they are listed at `/debug/links` and reported.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::github::{Asset, GithubClient, Release};
+use crate::namespace::Namespace;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::AppState;
+
+#[derive(Clone, Serialize)]
+pub struct BrokenLink {
+    namespace: String,
+    package: String,
+    version: String,
+    #[serde(skip)]
+    asset: Asset,
+    file: String,
+    /// unix timestamp of the check that found it gone
+    since: u64,
+}
+
+/// A file listed in the index, as probed.
+struct Link {
+    namespace: Arc<Namespace>,
+    package_name: String,
+    version: String,
+    asset: Asset,
+}
+
+#[derive(Default)]
+pub struct LinkChecker {
+    /// files that are gone, by asset id
+    broken: RwLock<HashMap<u64, BrokenLink>>,
+    /// where the next sample starts in the files of all namespaces
+    cursor: Mutex<usize>,
+}
+
+impl LinkChecker {
+    /// Drops the files found gone from `releases`.
+    pub fn hide_broken(&self, releases: &mut [Release]) {
+        let broken = self.broken.read().unwrap();
+        if broken.is_empty() {
+            return;
+        }
+        for release in releases {
+            release
+                .assets
+                .retain(|asset| !broken.contains_key(&asset.id));
+        }
+    }
+
+    /// Broken files, by package.
+    pub fn broken(&self) -> Vec<BrokenLink> {
+        let mut broken: Vec<BrokenLink> = self.broken.read().unwrap().values().cloned().collect();
+        broken.sort_by(|a, b| {
+            (&a.namespace, &a.package, &a.file).cmp(&(&b.namespace, &b.package, &b.file))
+        });
+        return broken;
+    }
+
+    /// The next `size` files of the index, and the broken ones to check again.
+    fn sample(&self, app_state: &AppState, size: usize) -> Vec<Link> {
+        let mut links = vec![];
+        for namespace in app_state.namespaces.iter() {
+            for (package_name, metadata) in namespace.metadata.all() {
+                for release in &metadata.releases {
+                    for asset in &release.assets {
+                        links.push(Link {
+                            namespace: namespace.clone(),
+                            package_name: package_name.clone(),
+                            version: release.version().to_string(),
+                            asset: asset.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        links.sort_by(|a, b| {
+            (&a.namespace.name, &a.package_name, a.asset.id).cmp(&(
+                &b.namespace.name,
+                &b.package_name,
+                b.asset.id,
+            ))
+        });
+        if !links.is_empty() {
+            let mut cursor = self.cursor.lock().unwrap();
+            let start = *cursor % links.len();
+            links.rotate_left(start);
+            *cursor = start + size;
+        }
+        links.truncate(size);
+        let mut sample = links;
+        for broken in self.broken.read().unwrap().values() {
+            let Some(namespace) = app_state.namespaces.by_name(&broken.namespace) else {
+                continue;
+            };
+            sample.push(Link {
+                namespace: namespace.clone(),
+                package_name: broken.package.clone(),
+                version: broken.version.clone(),
+                asset: broken.asset.clone(),
+            });
+        }
+        return sample;
+    }
+
+    /// Probes `link`, errors when it can't be probed or was newly found gone.
+    async fn check(&self, app_state: &AppState, link: &Link) -> Result<(), String> {
+        let repository = link
+            .namespace
+            .repos
+            .get(&link.package_name)
+            .ok_or("the package is no longer configured")?;
+        let client =
+            GithubClient::new(&app_state.github, link.namespace.github_token.get()).background();
+        let exists = repository
+            .asset_exists(&client, &link.asset)
+            .await
+            .map_err(|error| error.to_string())?;
+        let qualified_name = link.namespace.qualified_name(&link.package_name);
+        let mut broken = self.broken.write().unwrap();
+        if exists {
+            if broken.remove(&link.asset.id).is_some() {
+                log!(
+                    "{} of {} {} is available again",
+                    link.asset.name,
+                    qualified_name,
+                    link.version
+                );
+            }
+            return Ok(());
+        }
+        if broken.contains_key(&link.asset.id) {
+            return Ok(());
+        }
+        broken.insert(
+            link.asset.id,
+            BrokenLink {
+                namespace: link.namespace.name.clone(),
+                package: link.package_name.clone(),
+                version: link.version.clone(),
+                asset: link.asset.clone(),
+                file: link.asset.name.clone(),
+                since: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            },
+        );
+        return Err(format!(
+            "{} of {} {} is gone, it is left out of the index",
+            link.asset.name, qualified_name, link.version
+        ));
+    }
+}
+
+/// Probes a sample of the linked files every `LINK_CHECK_INTERVAL`.
+pub fn spawn_link_checker(app_state: Arc<AppState>) {
+    let Some(period) = app_state.config.link_check_interval else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let sample = app_state
+                .links
+                .sample(&app_state, app_state.config.link_check_sample);
+            let mut errors = vec![];
+            for link in &sample {
+                if let Err(error) = app_state.links.check(&app_state, link).await {
+                    let message = format!("Link check: {}", error);
+                    log!("{}", message);
+                    app_state
+                        .reporter
+                        .report(Report::task("link check", message.clone()));
+                    errors.push(message);
+                }
+            }
+            app_state.health.record("link check", errors);
+        }
+    });
+}
@@ -0,0 +1,214 @@
+//! Brute-force protection of the basic auth passwords, which are access
+//! tokens or GitHub tokens, enabled by `AUTH_FAILURE_LIMIT`. Clients, by IP
+//! address, with that many failed attempts within `AUTH_FAILURE_WINDOW` are
+//! locked out for `AUTH_LOCKOUT`: their requests with credentials are refused
+//! with 429 before reaching GitHub. Attempts fail when pigi answers 401 or
+//! GitHub rejects the token. Usernames aren't tracked, pigi ignores them and
+//! everyone can send `__token__`, and successful requests don't clear the
+//! failures, a valid token of the attacker's own would.
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use askama_axum::Response;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum_auth::{AuthBasic, AuthBasicCustom};
+
+use crate::config::Config;
+use crate::error::{ErrorCode, ErrorResponse, ERROR_CODE_HEADER};
+use crate::metrics;
+use crate::redact::log;
+use crate::AppState;
+
+/// Clients tracked before the ones without recent failures are dropped.
+const CAPACITY: usize = 100_000;
+
+#[derive(Default)]
+struct Attempts {
+    /// failed attempts within the window, oldest first
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+pub struct Lockout {
+    limit: usize,
+    window: Duration,
+    duration: Duration,
+    /// header with the client's IP address set by a reverse proxy, the peer address is used without
+    client_ip_header: Option<String>,
+    /// by `ip:<address>`
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl Lockout {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        return Some(Lockout {
+            limit: config.auth_failure_limit?,
+            window: config.auth_failure_window,
+            duration: config.auth_lockout,
+            client_ip_header: config.client_ip_header.clone(),
+            attempts: Mutex::default(),
+        });
+    }
+
+    fn client_ip(&self, request: &Request) -> Option<String> {
+        if let Some(name) = &self.client_ip_header {
+            let value = request.headers().get(name)?.to_str().ok()?;
+            // the address the proxy saw is the last one it appended
+            return Some(value.rsplit(',').next()?.trim().to_string());
+        }
+        let ConnectInfo(address) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+        return Some(address.ip().to_string());
+    }
+
+    /// Until when `key` is locked out.
+    fn locked_until(&self, key: &str) -> Option<Instant> {
+        let now = Instant::now();
+        let attempts = self.attempts.lock().unwrap();
+        return attempts.get(key)?.locked_until.filter(|until| *until > now);
+    }
+
+    fn record_failure(&self, key: &str) {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap();
+        if attempts.len() >= CAPACITY {
+            attempts.retain(|_, attempts| {
+                attempts.locked_until.is_some_and(|until| until > now)
+                    || attempts
+                        .failures
+                        .back()
+                        .is_some_and(|last| now.duration_since(*last) < self.window)
+            });
+        }
+        let entry = attempts.entry(key.to_string()).or_default();
+        while entry
+            .failures
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            entry.failures.pop_front();
+        }
+        entry.failures.push_back(now);
+        if entry.failures.len() >= self.limit {
+            entry.failures.clear();
+            entry.locked_until = Some(now + self.duration);
+            metrics::AUTH_LOCKOUTS.fetch_add(1, Ordering::Relaxed);
+            log!(
+                "Audit: locked out {} for {}s after {} failed logins",
+                key,
+                self.duration.as_secs(),
+                self.limit
+            );
+        }
+    }
+}
+
+/// Whether the response rejected the credentials, as opposed to the policy of
+/// valid ones (403) or an unrelated failure.
+fn is_rejected(status: StatusCode, headers: &HeaderMap) -> bool {
+    if status == StatusCode::UNAUTHORIZED {
+        return true;
+    }
+    let is_auth = headers
+        .get(ERROR_CODE_HEADER)
+        .is_some_and(|code| code.as_bytes() == ErrorCode::Auth.as_str().as_bytes());
+    return is_auth && status != StatusCode::FORBIDDEN;
+}
+
+/// Refuses requests with credentials of locked out clients, and counts the
+/// attempts that fail.
+pub async fn guard(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(lockout) = &app_state.lockout else {
+        return next.run(request).await;
+    };
+    let (mut parts, body) = request.into_parts();
+    let has_credentials = AuthBasic::decode_request_parts(&mut parts).is_ok();
+    let request = Request::from_parts(parts, body);
+    // requests without credentials can't guess any
+    let (true, Some(ip)) = (has_credentials, lockout.client_ip(&request)) else {
+        return next.run(request).await;
+    };
+    let key = format!("ip:{}", ip);
+    if let Some(until) = lockout.locked_until(&key) {
+        metrics::AUTH_REJECTED.fetch_add(1, Ordering::Relaxed);
+        let retry_after = until
+            .saturating_duration_since(Instant::now())
+            .as_secs()
+            .max(1);
+        return ErrorResponse::LockedOut { retry_after }.into_response();
+    }
+    let response = next.run(request).await;
+    if is_rejected(response.status(), response.headers()) {
+        metrics::AUTH_FAILURES.fetch_add(1, Ordering::Relaxed);
+        lockout.record_failure(&key);
+    }
+    return response;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockout(window: Duration) -> Lockout {
+        return Lockout {
+            limit: 3,
+            window,
+            duration: Duration::from_secs(60),
+            client_ip_header: Some("x-forwarded-for".to_string()),
+            attempts: Mutex::default(),
+        };
+    }
+
+    #[test]
+    fn locks_out_after_the_limit() {
+        let lockout = lockout(Duration::from_secs(60));
+        lockout.record_failure("ip:10.0.0.1");
+        lockout.record_failure("ip:10.0.0.1");
+        assert!(lockout.locked_until("ip:10.0.0.1").is_none());
+        lockout.record_failure("ip:10.0.0.1");
+        assert!(lockout.locked_until("ip:10.0.0.1").is_some());
+        assert!(lockout.locked_until("ip:10.0.0.2").is_none());
+    }
+
+    #[test]
+    fn forgets_failures_out_of_the_window() {
+        let lockout = lockout(Duration::from_millis(20));
+        lockout.record_failure("ip:10.0.0.1");
+        lockout.record_failure("ip:10.0.0.1");
+        std::thread::sleep(Duration::from_millis(30));
+        lockout.record_failure("ip:10.0.0.1");
+        assert!(lockout.locked_until("ip:10.0.0.1").is_none());
+    }
+
+    #[test]
+    fn reads_the_address_the_proxy_appended() {
+        let lockout = lockout(Duration::from_secs(60));
+        let request = Request::builder()
+            .header("x-forwarded-for", "1.2.3.4, 10.0.0.7")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(lockout.client_ip(&request).as_deref(), Some("10.0.0.7"));
+    }
+
+    #[test]
+    fn counts_rejected_credentials_only() {
+        let auth = HeaderMap::from_iter([(
+            axum::http::HeaderName::from_static(ERROR_CODE_HEADER),
+            axum::http::HeaderValue::from_static("auth"),
+        )]);
+        assert!(is_rejected(StatusCode::UNAUTHORIZED, &HeaderMap::new()));
+        assert!(is_rejected(StatusCode::INTERNAL_SERVER_ERROR, &auth));
+        assert!(!is_rejected(StatusCode::FORBIDDEN, &auth));
+        assert!(!is_rejected(StatusCode::NOT_FOUND, &HeaderMap::new()));
+        assert!(!is_rejected(StatusCode::OK, &HeaderMap::new()));
+    }
+}
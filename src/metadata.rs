@@ -0,0 +1,625 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::catalog::{self, Catalog};
+use crate::config::{self, Provider, Repository};
+use crate::error::ErrorResponse;
+use crate::filenames::{self, FilenameIssue};
+use crate::github::{GithubClient, Release};
+use crate::github_cache;
+use crate::metrics;
+use crate::mirror;
+use crate::namespace::Namespace;
+use crate::notify::NewRelease;
+use crate::osv::Vulnerability;
+use crate::redact::log;
+use crate::replication;
+use crate::reporting::Report;
+use crate::AppState;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PackageMetadata {
+    pub description: Option<String>,
+    pub releases: Vec<Release>,
+    /// known vulnerabilities by version, empty unless `OSV_LOOKUP` is set
+    #[serde(default)]
+    pub vulnerabilities: BTreeMap<String, Vec<Vulnerability>>,
+    /// read from the wheel of the latest release
+    #[serde(default)]
+    pub catalog: Option<Catalog>,
+    /// releases held for review, not served until approved
+    #[serde(default)]
+    pub quarantined: Vec<Release>,
+    /// distribution files with malformed or non-compliant names
+    #[serde(default)]
+    pub filename_issues: Vec<FilenameIssue>,
+    /// `owner/name` the repository was renamed or transferred to
+    #[serde(default)]
+    pub moved_to: Option<String>,
+    /// archived repositories get no new releases
+    #[serde(default)]
+    pub archived: bool,
+    /// serial of the last change of the metadata, see `Serials`
+    #[serde(default)]
+    pub serial: u64,
+}
+
+/// Serials of the packages of a namespace: like on PyPI, a counter bumped
+/// whenever the synced metadata of a package changes, so mirrors can tell
+/// what to fetch again. With `CACHE_DIR` they are kept in `serials.json` and
+/// keep growing across restarts.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Serials {
+    last_serial: u64,
+    /// serial of every package, with the digest of the metadata it was given for
+    packages: HashMap<String, (u64, String)>,
+}
+
+/// In-memory view of what GitHub knows about every configured repository,
+/// refreshed in the background by the sync worker.
+#[derive(Default)]
+pub struct MetadataStore {
+    packages: RwLock<HashMap<String, PackageMetadata>>,
+    synced_at: RwLock<HashMap<String, SystemTime>>,
+    serials: Mutex<Serials>,
+}
+
+impl MetadataStore {
+    pub fn get(&self, package_name: &String) -> Option<PackageMetadata> {
+        return self.packages.read().unwrap().get(package_name).cloned();
+    }
+
+    pub fn all(&self) -> Vec<(String, PackageMetadata)> {
+        return self
+            .packages
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, metadata)| (name.clone(), metadata.clone()))
+            .collect();
+    }
+
+    /// Forgets everything known about the package, it is fetched again on the next sync.
+    pub fn invalidate(&self, package_name: &String) {
+        self.packages.write().unwrap().remove(package_name);
+        self.synced_at.write().unwrap().remove(package_name);
+    }
+
+    /// When the package was last fetched from GitHub, or read from what the leader shared.
+    pub fn synced_at(&self, package_name: &String) -> Option<SystemTime> {
+        return self.synced_at.read().unwrap().get(package_name).copied();
+    }
+
+    /// Serves a quarantined release of the package, `None` when it has none with that version.
+    pub fn approve(&self, package_name: &String, version: &str) -> Option<Release> {
+        let mut packages = self.packages.write().unwrap();
+        let metadata = packages.get_mut(package_name)?;
+        let index = metadata
+            .quarantined
+            .iter()
+            .position(|release| release.version() == version)?;
+        let release = metadata.quarantined.remove(index);
+        metadata.releases.insert(0, release.clone());
+        let mut serials = self.serials.lock().unwrap();
+        serials.last_serial += 1;
+        metadata.serial = serials.last_serial;
+        // the digest is of the metadata before, so the next sync bumps it again
+        serials
+            .packages
+            .insert(package_name.clone(), (metadata.serial, String::new()));
+        return Some(release);
+    }
+
+    /// Serial of the latest change of any package.
+    pub fn last_serial(&self) -> u64 {
+        return self.serials.lock().unwrap().last_serial;
+    }
+
+    /// Packages whose serial is greater than `since`, with it, oldest change first.
+    pub fn changed_since(&self, since: u64) -> Vec<(String, u64)> {
+        let serials = self.serials.lock().unwrap();
+        let mut changed: Vec<(String, u64)> = serials
+            .packages
+            .iter()
+            .filter(|(_, (serial, _))| *serial > since)
+            .map(|(package_name, (serial, _))| (package_name.clone(), *serial))
+            .collect();
+        changed.sort_by_key(|(_, serial)| *serial);
+        return changed;
+    }
+
+    /// Stores synced metadata, with a new serial when it isn't the same as
+    /// when the package was given its serial.
+    fn record(&self, package_name: &str, mut metadata: PackageMetadata) {
+        metadata.serial = 0;
+        let digest = hex::encode(Sha256::digest(serde_json::to_vec(&metadata).unwrap()));
+        {
+            let mut serials = self.serials.lock().unwrap();
+            metadata.serial = match serials.packages.get(package_name) {
+                Some((serial, known)) if *known == digest => *serial,
+                _ => {
+                    serials.last_serial += 1;
+                    let serial = serials.last_serial;
+                    serials
+                        .packages
+                        .insert(package_name.to_string(), (serial, digest));
+                    serial
+                }
+            };
+        }
+        self.update(package_name, metadata);
+    }
+
+    /// Stores metadata with its serial as it is, like the leader shared it.
+    fn update(&self, package_name: &str, metadata: PackageMetadata) {
+        let mut serials = self.serials.lock().unwrap();
+        serials.last_serial = serials.last_serial.max(metadata.serial);
+        let known = serials
+            .packages
+            .entry(package_name.to_string())
+            .or_default();
+        if known.0 != metadata.serial {
+            *known = (metadata.serial, String::new());
+        }
+        drop(serials);
+        self.packages
+            .write()
+            .unwrap()
+            .insert(package_name.to_string(), metadata);
+        self.synced_at
+            .write()
+            .unwrap()
+            .insert(package_name.to_string(), SystemTime::now());
+    }
+}
+
+/// Stores metadata replicated from an upstream pigi, with the upstream's serial.
+pub fn replicate(namespace: &Namespace, package_name: &str, metadata: PackageMetadata) {
+    namespace.metadata.update(package_name, metadata);
+}
+
+pub fn spawn_sync_worker(app_state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(app_state.config.sync_interval);
+        loop {
+            interval.tick().await;
+            let mut errors = vec![];
+            if app_state.github.replication.is_enabled() {
+                // replicas don't share, the upstream did the crawling
+                errors = sync_all(&app_state, false).await;
+            } else if app_state.leader.is_leader().await {
+                errors = sync_all(&app_state, true).await;
+                if app_state.leader.is_enabled() {
+                    errors.extend(save_shared(&app_state).err());
+                }
+            } else if !load_shared(&app_state) {
+                // without a shared cache followers sync for themselves, quietly
+                errors = sync_all(&app_state, false).await;
+            }
+            app_state.health.record("metadata sync", errors);
+        }
+    });
+}
+
+/// Syncs every namespace, with `notify` new releases are announced. Returns
+/// why packages failed to sync.
+pub async fn sync_all(app_state: &AppState, notify: bool) -> Vec<String> {
+    let mut errors = vec![];
+    if let Some(quarantine) = &app_state.quarantine {
+        if let Err(error) = quarantine.reload() {
+            let message = format!("Failed to read approved versions: {}", error);
+            fail_sync(app_state, &mut errors, message);
+        }
+    }
+    for namespace in app_state.namespaces.iter() {
+        if app_state.github.replication.is_enabled() {
+            errors.extend(replication::sync_namespace(app_state, namespace).await);
+        } else {
+            errors.extend(sync_namespace(app_state, namespace, notify).await);
+        }
+    }
+    if let Err(error) = save_serials(app_state) {
+        let message = format!("Failed to save serials: {}", error);
+        fail_sync(app_state, &mut errors, message);
+    }
+    errors.extend(github_cache::save(app_state).err());
+    return errors;
+}
+
+fn serials_path(app_state: &AppState) -> Option<PathBuf> {
+    return Some(PathBuf::from(app_state.config.cache_dir.as_ref()?).join("serials.json"));
+}
+
+fn save_serials(app_state: &AppState) -> io::Result<()> {
+    let Some(path) = serials_path(app_state) else {
+        return Ok(());
+    };
+    let namespaces: HashMap<&String, &Mutex<Serials>> = app_state
+        .namespaces
+        .iter()
+        .map(|namespace| (&namespace.name, &namespace.metadata.serials))
+        .collect();
+    let temp_path = path.with_extension("json.tmp");
+    let content = serde_json::to_vec(&namespaces).map_err(io::Error::other)?;
+    fs::write(&temp_path, content)?;
+    return fs::rename(&temp_path, &path);
+}
+
+/// Restores the serials saved before a restart, so they keep growing.
+pub fn load_serials(app_state: &AppState) {
+    let Some(path) = serials_path(app_state) else {
+        return;
+    };
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return,
+        Err(error) => {
+            log!("Failed to load {}: {}", path.display(), error);
+            return;
+        }
+    };
+    let mut namespaces: HashMap<String, Serials> = match serde_json::from_slice(&content) {
+        Ok(namespaces) => namespaces,
+        Err(error) => {
+            log!("Failed to load {}: {}", path.display(), error);
+            return;
+        }
+    };
+    for namespace in app_state.namespaces.iter() {
+        if let Some(serials) = namespaces.remove(&namespace.name) {
+            *namespace.metadata.serials.lock().unwrap() = serials;
+        }
+    }
+}
+
+/// Where the leader publishes what it synced, for the other replicas.
+fn shared_path(app_state: &AppState) -> Option<PathBuf> {
+    return Some(PathBuf::from(app_state.config.cache_dir.as_ref()?).join("metadata.json"));
+}
+
+fn save_shared(app_state: &AppState) -> Result<(), String> {
+    let Some(path) = shared_path(app_state) else {
+        return Ok(());
+    };
+    let namespaces: HashMap<&String, HashMap<String, PackageMetadata>> = app_state
+        .namespaces
+        .iter()
+        .map(|namespace| {
+            (
+                &namespace.name,
+                namespace.metadata.all().into_iter().collect(),
+            )
+        })
+        .collect();
+    let temp_path = path.with_extension("json.tmp");
+    let saved = serde_json::to_vec(&namespaces)
+        .map_err(io::Error::other)
+        .and_then(|content| fs::write(&temp_path, content))
+        .and_then(|_| fs::rename(&temp_path, &path));
+    if let Err(error) = saved {
+        let message = format!("Failed to share synced metadata: {}", error);
+        log!("{}", message);
+        app_state
+            .reporter
+            .report(Report::task("metadata sync", message.clone()));
+        return Err(message);
+    }
+    return Ok(());
+}
+
+/// Replaces the metadata with what the leader synced, false when there is
+/// no shared cache to read it from.
+pub fn load_shared(app_state: &AppState) -> bool {
+    let Some(path) = shared_path(app_state) else {
+        return false;
+    };
+    let Ok(content) = fs::read(&path) else {
+        // the leader didn't finish its first sync yet
+        return true;
+    };
+    let mut namespaces: HashMap<String, HashMap<String, PackageMetadata>> =
+        match serde_json::from_slice(&content) {
+            Ok(namespaces) => namespaces,
+            Err(error) => {
+                log!("Failed to load shared metadata: {}", error);
+                return true;
+            }
+        };
+    if let Some(quarantine) = &app_state.quarantine {
+        if let Err(error) = quarantine.reload() {
+            log!("Failed to read approved versions: {}", error);
+        }
+    }
+    for namespace in app_state.namespaces.iter() {
+        for (package_name, metadata) in namespaces.remove(&namespace.name).unwrap_or_default() {
+            namespace.metadata.update(&package_name, metadata);
+        }
+    }
+    return true;
+}
+
+async fn sync_namespace(
+    app_state: &AppState,
+    namespace: &Arc<Namespace>,
+    notify: bool,
+) -> Vec<String> {
+    let mut errors = vec![];
+    let client = GithubClient::new(&app_state.github, namespace.github_token.get()).background();
+    for (package_name, repository) in namespace.repos.iter() {
+        errors.extend(
+            sync_package(
+                app_state,
+                namespace,
+                &client,
+                package_name,
+                repository,
+                notify,
+            )
+            .await,
+        );
+    }
+    return errors;
+}
+
+/// Logs and reports why the sync failed, and adds it to `errors`.
+fn fail_sync(app_state: &AppState, errors: &mut Vec<String>, message: String) {
+    log!("{}", message);
+    app_state
+        .reporter
+        .report(Report::task("metadata sync", message.clone()));
+    errors.push(message);
+}
+
+/// Fetches the package from GitHub and stores what is served of it, with
+/// `notify` new releases are announced. Returns why it failed to sync.
+pub async fn sync_package(
+    app_state: &AppState,
+    namespace: &Arc<Namespace>,
+    client: &GithubClient,
+    package_name: &String,
+    repository: &Repository,
+    notify: bool,
+) -> Vec<String> {
+    if app_state.github.replication.is_enabled() {
+        return replication::sync_package(app_state, namespace, package_name).await;
+    }
+    let mut errors = vec![];
+    let mut metadata = match fetch_metadata(client, repository).await {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            if let Some(code) = error.code() {
+                metrics::record_error(code);
+            }
+            let message = format!(
+                "Failed to sync metadata for {}: {}",
+                namespace.qualified_name(package_name),
+                error
+            );
+            fail_sync(app_state, &mut errors, message);
+            return errors;
+        }
+    };
+    let previous_location = namespace
+        .metadata
+        .get(package_name)
+        .map(|previous| (previous.moved_to, previous.archived))
+        .unwrap_or_default();
+    let is_newly_moved = metadata.moved_to != previous_location.0;
+    if let Some(moved_to) = metadata.moved_to.as_ref().filter(|_| is_newly_moved) {
+        let message = format!(
+            "{}/{} of {} moved to {}, update the repos config",
+            repository.owner,
+            repository.name,
+            namespace.qualified_name(package_name),
+            moved_to
+        );
+        log!("{}", message);
+        app_state
+            .reporter
+            .report(Report::task("metadata sync", message));
+        if app_state.config.follow_renames {
+            let updated = config::follow_rename(
+                &app_state.config.repos_config_path,
+                &namespace.name,
+                package_name,
+                moved_to,
+            );
+            match updated {
+                Ok(()) => log!(
+                    "Updated {} of {} in {}, it is used after a restart",
+                    moved_to,
+                    namespace.qualified_name(package_name),
+                    app_state.config.repos_config_path
+                ),
+                Err(error) => {
+                    let message = format!(
+                        "Failed to update the repos config for {}: {}",
+                        namespace.qualified_name(package_name),
+                        error
+                    );
+                    fail_sync(app_state, &mut errors, message);
+                }
+            }
+        }
+    }
+    if metadata.archived && !previous_location.1 {
+        log!(
+            "{}/{} of {} is archived",
+            repository.owner,
+            repository.name,
+            namespace.qualified_name(package_name)
+        );
+    }
+    app_state.hashes.fill(&mut metadata.releases);
+    app_state.links.hide_broken(&mut metadata.releases);
+    metadata.filename_issues = filenames::hide_malformed(package_name, &mut metadata.releases);
+    let previous_issues = namespace
+        .metadata
+        .get(package_name)
+        .map(|previous| previous.filename_issues)
+        .unwrap_or_default();
+    for issue in &metadata.filename_issues {
+        if !previous_issues.contains(issue) {
+            let hidden = if issue.hidden {
+                ", hidden from the index"
+            } else {
+                ""
+            };
+            log!(
+                "{} of {} {}: {}{}",
+                issue.file,
+                namespace.qualified_name(package_name),
+                issue.version,
+                issue.problem,
+                hidden
+            );
+        }
+    }
+    if let Some(osv) = &app_state.osv {
+        match osv.lookup(package_name, &metadata.releases).await {
+            Ok(vulnerabilities) => metadata.vulnerabilities = vulnerabilities,
+            Err(error) => {
+                let message = format!(
+                    "Failed to look up vulnerabilities of {}: {}",
+                    namespace.qualified_name(package_name),
+                    error
+                );
+                fail_sync(app_state, &mut errors, message);
+                // what the last lookup found still applies
+                if let Some(previous) = namespace.metadata.get(package_name) {
+                    metadata.vulnerabilities = previous.vulnerabilities;
+                }
+            }
+        }
+    }
+    if let Some(quarantine) = &app_state.quarantine {
+        let releases = std::mem::take(&mut metadata.releases);
+        match quarantine.admit(&namespace.qualified_name(package_name), releases) {
+            Ok((approved, held)) => {
+                metadata.releases = approved;
+                metadata.quarantined = held;
+            }
+            Err(error) => {
+                let message = format!(
+                    "Failed to save approved versions of {}: {}",
+                    namespace.qualified_name(package_name),
+                    error
+                );
+                fail_sync(app_state, &mut errors, message);
+                return errors;
+            }
+        }
+    }
+    if repository.provider == Provider::Pypi {
+        errors.extend(
+            mirror::cache_files(app_state, namespace, package_name, &metadata.releases).await,
+        );
+    }
+    let previous_catalog = namespace
+        .metadata
+        .get(package_name)
+        .and_then(|previous| previous.catalog);
+    match catalog::extract(
+        client,
+        repository,
+        &metadata.releases,
+        previous_catalog.clone(),
+    )
+    .await
+    {
+        Ok(catalog) => metadata.catalog = catalog,
+        Err(error) => {
+            let message = format!(
+                "Failed to read the metadata of the latest wheel of {}: {}",
+                namespace.qualified_name(package_name),
+                error
+            );
+            fail_sync(app_state, &mut errors, message);
+            metadata.catalog = previous_catalog;
+        }
+    }
+    // the first sync only establishes what is already published
+    let previous = namespace.metadata.get(package_name).filter(|_| notify);
+    if let Some(previous) = previous {
+        for release in &metadata.quarantined {
+            let is_new = !previous
+                .quarantined
+                .iter()
+                .any(|known| known.tag_name == release.tag_name);
+            if is_new {
+                log!(
+                    "Quarantined {} {} until it is approved",
+                    namespace.qualified_name(package_name),
+                    release.version()
+                );
+            }
+        }
+        // quarantined releases are announced when approved
+        for release in &metadata.releases {
+            let is_new = !previous
+                .releases
+                .iter()
+                .chain(&previous.quarantined)
+                .any(|known| known.tag_name == release.tag_name);
+            if is_new {
+                let new_release = NewRelease {
+                    package_name: namespace.qualified_name(package_name),
+                    release: release.clone(),
+                };
+                for message in app_state.notifier.notify(&new_release).await {
+                    app_state
+                        .reporter
+                        .report(Report::task("notifications", message));
+                }
+            }
+        }
+    }
+    namespace.metadata.record(package_name, metadata);
+    return errors;
+}
+
+pub async fn fetch_metadata(
+    client: &GithubClient,
+    repository: &Repository,
+) -> Result<PackageMetadata, ErrorResponse> {
+    let mut moved_to = None;
+    let mut archived = false;
+    let (description, releases) = match repository.provider {
+        Provider::Github => {
+            let info = client
+                .repository(&repository.owner, &repository.name)
+                .await?;
+            let configured = format!("{}/{}", repository.owner, repository.name);
+            if !info.full_name.is_empty() && !info.full_name.eq_ignore_ascii_case(&configured) {
+                moved_to = Some(info.full_name);
+            }
+            archived = info.archived;
+            (info.description, repository.releases(client).await?)
+        }
+        Provider::Pypi => {
+            let project = mirror::project(&repository.name).await?;
+            (project.summary, repository.served(project.releases))
+        }
+        Provider::Local | Provider::S3 | Provider::Proxy => {
+            (None, repository.releases(client).await?)
+        }
+    };
+    return Ok(PackageMetadata {
+        description,
+        releases,
+        vulnerabilities: BTreeMap::new(),
+        catalog: None,
+        quarantined: vec![],
+        filename_issues: vec![],
+        moved_to,
+        archived,
+        serial: 0,
+    });
+}
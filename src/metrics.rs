@@ -0,0 +1,136 @@
+//! Process wide counters, exposed at `/metrics` in the Prometheus text format.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use askama_axum::Response;
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::error::ErrorCode;
+
+/// Bytes received from GitHub that weren't sent to clients yet.
+pub static STREAM_BUFFERED_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Requests being handled, up to when their response starts.
+pub static REQUESTS_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+/// Requests rejected with 503 because of overload.
+pub static REQUESTS_SHED: AtomicU64 = AtomicU64::new(0);
+/// Moving average of GitHub response times, in milliseconds.
+pub static GITHUB_LATENCY_MS: AtomicU64 = AtomicU64::new(0);
+/// Calls to GitHub waiting for the scheduler.
+pub static GITHUB_REQUESTS_QUEUED: AtomicU64 = AtomicU64::new(0);
+/// Metadata calls sent a second time because GitHub was slow to respond.
+pub static GITHUB_HEDGED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Requests whose credentials were rejected.
+pub static AUTH_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// Clients locked out after too many failed logins.
+pub static AUTH_LOCKOUTS: AtomicU64 = AtomicU64::new(0);
+/// Requests refused because their client or username is locked out.
+pub static AUTH_REJECTED: AtomicU64 = AtomicU64::new(0);
+
+/// Name, help, type and value of every metric.
+const METRICS: [(&str, &str, &str, &AtomicU64); 9] = [
+    (
+        "pigi_stream_buffered_bytes",
+        "Bytes received from upstream and not yet sent to clients.",
+        "gauge",
+        &STREAM_BUFFERED_BYTES,
+    ),
+    (
+        "pigi_requests_in_flight",
+        "Requests being handled.",
+        "gauge",
+        &REQUESTS_IN_FLIGHT,
+    ),
+    (
+        "pigi_requests_shed_total",
+        "Requests rejected because of overload.",
+        "counter",
+        &REQUESTS_SHED,
+    ),
+    (
+        "pigi_github_latency_milliseconds",
+        "Moving average of GitHub response times.",
+        "gauge",
+        &GITHUB_LATENCY_MS,
+    ),
+    (
+        "pigi_github_hedged_requests_total",
+        "GitHub metadata calls sent again because the first was slow.",
+        "counter",
+        &GITHUB_HEDGED_REQUESTS,
+    ),
+    (
+        "pigi_github_requests_queued",
+        "Calls to GitHub held back to stay under its rate limits.",
+        "gauge",
+        &GITHUB_REQUESTS_QUEUED,
+    ),
+    (
+        "pigi_auth_failures_total",
+        "Requests whose basic auth credentials were rejected.",
+        "counter",
+        &AUTH_FAILURES,
+    ),
+    (
+        "pigi_auth_lockouts_total",
+        "Clients locked out after too many failed logins.",
+        "counter",
+        &AUTH_LOCKOUTS,
+    ),
+    (
+        "pigi_auth_locked_out_requests_total",
+        "Requests refused because their client or username is locked out.",
+        "counter",
+        &AUTH_REJECTED,
+    ),
+];
+
+/// Failures by class, in the order of `ErrorCode::ALL`.
+static ERRORS: [AtomicU64; ErrorCode::ALL.len()] =
+    [const { AtomicU64::new(0) }; ErrorCode::ALL.len()];
+
+pub fn record_error(code: ErrorCode) {
+    ERRORS[code as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Failures so far by class.
+pub fn errors() -> Vec<(ErrorCode, u64)> {
+    return ErrorCode::ALL
+        .iter()
+        .map(|code| (*code, ERRORS[*code as usize].load(Ordering::Relaxed)))
+        .collect();
+}
+
+/// Folds a GitHub response time into `GITHUB_LATENCY_MS`.
+pub fn record_github_latency(milliseconds: u64) {
+    let _ = GITHUB_LATENCY_MS.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+        Some((average * 7 + milliseconds) / 8)
+    });
+}
+
+pub async fn metrics() -> Response {
+    let mut body: String = METRICS
+        .iter()
+        .map(|(name, help, kind, value)| {
+            format!(
+                "# HELP {} {}\n# TYPE {} {}\n{} {}\n",
+                name,
+                help,
+                name,
+                kind,
+                name,
+                value.load(Ordering::Relaxed)
+            )
+        })
+        .collect();
+    body.push_str(
+        "# HELP pigi_errors_total Failures by class.\n# TYPE pigi_errors_total counter\n",
+    );
+    for (code, count) in errors() {
+        body.push_str(&format!(
+            "pigi_errors_total{{code=\"{}\"}} {}\n",
+            code, count
+        ));
+    }
+    return ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response();
+}
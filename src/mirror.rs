@@ -0,0 +1,270 @@
+//! Public PyPI projects served from the index, configured as repositories
+//! with `"provider": "pypi"` and optionally a version `specifier`. Releases
+//! come from the PyPI JSON API and the sync worker downloads the files of the
+//! served versions into the artifact cache, so CI without access to PyPI can
+//! install a curated set of public packages from pigi.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use pep440_rs::Version;
+use serde::Deserialize;
+
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{Asset, AssetStream, ByteStream, Release};
+use crate::namespace::Namespace;
+use crate::prefetch;
+use crate::quotas::Budget;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::AppState;
+
+pub const PYPI_URL: &str = "https://pypi.org";
+
+/// Characters of a PyPI error response kept in the error.
+const MAX_SNIPPET: usize = 500;
+
+#[derive(Deserialize)]
+struct ProjectResponse {
+    info: Info,
+    releases: HashMap<String, Vec<File>>,
+}
+
+#[derive(Deserialize)]
+struct Info {
+    summary: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    filename: String,
+    url: String,
+    size: u64,
+    digests: Digests,
+    /// UTC, without a zone designator
+    upload_time: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[derive(Deserialize)]
+struct Digests {
+    sha256: String,
+    blake2b_256: Option<String>,
+}
+
+pub struct Project {
+    pub summary: Option<String>,
+    /// newest first, like GitHub lists releases
+    pub releases: Vec<Release>,
+}
+
+fn client() -> reqwest::Client {
+    return reqwest::Client::builder()
+        .user_agent("pigi")
+        .build()
+        .unwrap();
+}
+
+/// `response` when successful, otherwise an error with the start of what PyPI said.
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, ErrorResponse> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    let snippet: String = body.chars().take(MAX_SNIPPET).collect();
+    return Err(ErrorResponse::Failure(
+        ErrorCode::from_status(status),
+        format!("PyPI responded {}: {}", status, snippet),
+    ));
+}
+
+fn asset(file: File) -> Asset {
+    let uploaded_at = format!("{}Z", file.upload_time);
+    return Asset {
+        // PyPI files have no ids, the start of the hash identifies them in the old url form
+        id: u64::from_str_radix(file.digests.sha256.get(..16).unwrap_or_default(), 16)
+            .unwrap_or_default(),
+        name: file.filename,
+        size: file.size,
+        created_at: uploaded_at.clone(),
+        updated_at: uploaded_at,
+        digest: Some(format!("sha256:{}", file.digests.sha256)),
+        blake2b_256: file.digests.blake2b_256,
+        download_url: Some(file.url),
+    };
+}
+
+/// Summary and releases of a PyPI project, yanked files left out.
+pub async fn project(name: &str) -> Result<Project, ErrorResponse> {
+    let url = format!("{}/pypi/{}/json", PYPI_URL, name);
+    let response = check(client().get(url).send().await?).await?;
+    return Ok(convert(name, response.json().await?));
+}
+
+fn convert(name: &str, project: ProjectResponse) -> Project {
+    let mut releases: Vec<Release> = project
+        .releases
+        .into_iter()
+        .filter_map(|(version, files)| {
+            let assets: Vec<Asset> = files
+                .into_iter()
+                .filter(|file| !file.yanked)
+                .map(asset)
+                .collect();
+            let published_at = assets.iter().map(|asset| asset.created_at.clone()).min()?;
+            let prerelease =
+                Version::from_str(&version).is_ok_and(|version| version.any_prerelease());
+            Some(Release {
+                id: 0,
+                html_url: format!("{}/project/{}/{}/", PYPI_URL, name, version),
+                name: Some(version.clone()),
+                tag_name: version,
+                published_at: Some(published_at),
+                prerelease,
+                draft: false,
+                assets,
+                version: None,
+            })
+        })
+        .collect();
+    releases.sort_by_cached_key(|release| Reverse(Version::from_str(release.version()).ok()));
+    return Project {
+        summary: project.info.summary,
+        releases,
+    };
+}
+
+pub async fn download(asset: &Asset) -> Result<AssetStream, ErrorResponse> {
+    let url = asset
+        .download_url
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let response = check(client().get(url).send().await?).await?;
+    return Ok(AssetStream {
+        content_length: response.content_length(),
+        stream: Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(io::Error::other)),
+        ),
+    });
+}
+
+/// Whether a file can still be downloaded, asked with a `HEAD` request.
+pub async fn exists(asset: &Asset) -> Result<bool, ErrorResponse> {
+    let url = asset
+        .download_url
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let response = client().head(url).send().await?;
+    if matches!(
+        response.status(),
+        reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+    ) {
+        return Ok(false);
+    }
+    check(response).await?;
+    return Ok(true);
+}
+
+/// Bytes `start..end` of a file.
+pub async fn download_range(
+    asset: &Asset,
+    range: Range<u64>,
+) -> Result<ByteStream<io::Error>, ErrorResponse> {
+    let url = asset
+        .download_url
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let request = client().get(url).header(
+        reqwest::header::RANGE,
+        format!("bytes={}-{}", range.start, range.end - 1),
+    );
+    let response = request.send().await?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(ErrorResponse::Failure(
+            ErrorCode::from_status(response.status()),
+            format!(
+                "PyPI doesn't support range requests, status {}",
+                response.status()
+            ),
+        ));
+    }
+    return Ok(Box::pin(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(io::Error::other)),
+    ));
+}
+
+/// Downloads the files of the synced releases of a mirrored project that
+/// aren't cached yet, those over its quotas are left out. Returns why files
+/// failed to download.
+pub async fn cache_files(
+    app_state: &AppState,
+    namespace: &Arc<Namespace>,
+    package_name: &String,
+    releases: &[Release],
+) -> Vec<String> {
+    let mut errors = vec![];
+    if !app_state.cache.is_enabled() {
+        return errors;
+    }
+    let Some(repository) = namespace.repos.get(package_name) else {
+        return errors;
+    };
+    let mut budget = Budget::synced(app_state, namespace, package_name, repository);
+    for release in releases {
+        let is_cached = |asset: &Asset| {
+            asset
+                .sha256()
+                .is_some_and(|sha256| app_state.cache.contains(sha256))
+        };
+        let mut files = release
+            .assets
+            .iter()
+            .filter(|asset| is_cached(asset))
+            .count();
+        for asset in &release.assets {
+            if is_cached(asset) {
+                continue;
+            }
+            if let Err(reason) = budget.check(&asset.name, asset.size, files + 1) {
+                log!("Not mirroring {}: {}", asset.name, reason);
+                continue;
+            }
+            match prefetch::cache_asset(app_state, namespace, package_name, asset, None).await {
+                Ok(true) => {
+                    budget.add(asset.size);
+                    files += 1;
+                    log!(
+                        "Mirrored {} of {}",
+                        asset.name,
+                        namespace.qualified_name(package_name)
+                    );
+                }
+                Ok(false) => files += 1,
+                Err(error) => {
+                    let message = format!(
+                        "Failed to mirror {} of {}: {}",
+                        asset.name,
+                        namespace.qualified_name(package_name),
+                        error
+                    );
+                    log!("{}", message);
+                    app_state
+                        .reporter
+                        .report(Report::task("metadata sync", message.clone()));
+                    errors.push(message);
+                }
+            }
+        }
+    }
+    return errors;
+}
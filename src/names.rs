@@ -0,0 +1,14 @@
+/// PEP 503 normalized form of a project name.
+pub fn normalize(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.extend(c.to_lowercase());
+        }
+    }
+    return normalized;
+}
@@ -0,0 +1,484 @@
+//! Independent indexes served by one process. Requests are routed to a
+//! namespace by their `Host` header or by a `/<namespace>` path prefix, which
+//! is stripped before the request reaches the router.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use askama_axum::Response;
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{header, Method, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect};
+use axum_auth::{AuthBasic, AuthBasicCustom};
+
+use crate::config::{
+    Config, NamespaceConfig, NamespacedConfig, ReposConfig, Repositories, Repository,
+};
+use crate::error::ErrorResponse;
+use crate::metadata::MetadataStore;
+use crate::names;
+use crate::policy::{AccessPolicy, AccessToken, ClientPolicy};
+use crate::quotas::Quota;
+use crate::redact;
+use crate::secrets::Secret;
+use crate::service_accounts::{self, Operation};
+use crate::signed_urls;
+use crate::trusted_publishing;
+use crate::AppState;
+
+/// First path segments used by pigi's own routes, which can't be namespace names.
+const RESERVED_NAMES: [&str; 15] = [
+    "simple",
+    "public",
+    "search",
+    "feed.xml",
+    "api",
+    "pypi",
+    "static",
+    "snapshots",
+    "artifacts",
+    "metrics",
+    "debug",
+    "downloads",
+    "packages",
+    "conda",
+    "install",
+];
+
+pub struct Namespace {
+    pub name: String,
+    pub repos: Repositories,
+    pub github_token: Arc<Secret>,
+    pub access_tokens: Option<Vec<AccessToken>>,
+    /// by class of access token
+    access_policies: HashMap<String, Arc<AccessPolicy>>,
+    /// reads don't need access tokens
+    pub anonymous_read: bool,
+    pub quota: Quota,
+    /// GitHub Actions OIDC tokens are accepted from the packages' repositories
+    pub trusted_publishing: bool,
+    hosts: Vec<String>,
+    pub metadata: Arc<MetadataStore>,
+    /// public packages of the namespace, served under `/public/` without access tokens
+    pub public: Option<Arc<Namespace>>,
+    /// true for the `public` view itself, it shares token and metadata with the full namespace
+    pub public_view: bool,
+}
+
+impl Namespace {
+    pub fn get_repository(&self, package_name: &String) -> Result<&Repository, ErrorResponse> {
+        return self
+            .repos
+            .get(package_name)
+            .ok_or(ErrorResponse::PageNotFound {});
+    }
+
+    /// Configured name and repository of a package, looked up by its PEP 503 normalized name.
+    pub fn find_repository(
+        &self,
+        package_name: &str,
+    ) -> Result<(&String, &Repository), ErrorResponse> {
+        let normalized = names::normalize(package_name);
+        return self
+            .repos
+            .iter()
+            .find(|(name, _)| names::normalize(name) == normalized)
+            .ok_or(ErrorResponse::PageNotFound);
+    }
+
+    fn with_public_view(mut self) -> Arc<Namespace> {
+        self.public = Some(Arc::new(Namespace {
+            name: self.name.clone(),
+            repos: self.repos.public(),
+            github_token: self.github_token.clone(),
+            access_tokens: None,
+            access_policies: HashMap::new(),
+            anonymous_read: true,
+            quota: self.quota,
+            trusted_publishing: false,
+            hosts: vec![],
+            metadata: self.metadata.clone(),
+            public: None,
+            public_view: true,
+        }));
+        return Arc::new(self);
+    }
+
+    /// Policy of the access token sent as `password`, `None` when it isn't
+    /// one of the namespace's or its class is unrestricted.
+    fn client_policy(&self, password: &str) -> Option<ClientPolicy> {
+        let class = self
+            .access_tokens
+            .iter()
+            .flatten()
+            .find(|token| token.matches(password))?
+            .class()?;
+        return Some(ClientPolicy {
+            class: class.to_string(),
+            policy: self.access_policies.get(class)?.clone(),
+        });
+    }
+
+    /// Name used to identify a package of this namespace outside of it, e.g. in notifications.
+    pub fn qualified_name(&self, package_name: &str) -> String {
+        if self.name.is_empty() {
+            return package_name.to_string();
+        }
+        return format!("{}/{}", self.name, package_name);
+    }
+}
+
+fn shared(policies: HashMap<String, AccessPolicy>) -> HashMap<String, Arc<AccessPolicy>> {
+    return policies
+        .into_iter()
+        .map(|(class, policy)| (class, Arc::new(policy)))
+        .collect();
+}
+
+pub struct Namespaces {
+    default: Arc<Namespace>,
+    named: HashMap<String, Arc<Namespace>>,
+}
+
+impl Namespaces {
+    pub fn from_config(config: &Config) -> Self {
+        let namespaced = match ReposConfig::from_config(config) {
+            ReposConfig::Single(repos) => NamespacedConfig {
+                repos,
+                default_owner: None,
+                access_tokens: None,
+                access_policies: HashMap::new(),
+                anonymous_read: false,
+                quota: Quota::default(),
+                trusted_publishing: false,
+                namespaces: HashMap::new(),
+            },
+            ReposConfig::Namespaced(namespaced) => namespaced,
+        };
+        let default_owner = namespaced.default_owner;
+        let namespaces: Vec<(String, NamespaceConfig)> = namespaced
+            .namespaces
+            .into_iter()
+            .map(|(name, mut namespace)| {
+                namespace.default_owner = namespace.default_owner.or(default_owner.clone());
+                (name, namespace)
+            })
+            .collect();
+        let default = Namespace {
+            name: String::new(),
+            repos: namespaced.repos.resolve(default_owner.as_ref()),
+            github_token: Arc::new(Secret::new(config.github_token.clone())),
+            access_tokens: namespaced.access_tokens,
+            access_policies: shared(namespaced.access_policies),
+            anonymous_read: namespaced.anonymous_read,
+            quota: namespaced.quota,
+            trusted_publishing: namespaced.trusted_publishing,
+            hosts: vec![],
+            metadata: Arc::default(),
+            public: None,
+            public_view: false,
+        }
+        .with_public_view();
+        let named: HashMap<String, Arc<Namespace>> = namespaces
+            .into_iter()
+            .map(|(name, namespace)| {
+                if name.is_empty() || name.contains('/') || RESERVED_NAMES.contains(&name.as_str())
+                {
+                    panic!("{} can't be used as a namespace name", name);
+                }
+                let namespace = Namespace {
+                    name: name.clone(),
+                    repos: namespace.repos.resolve(namespace.default_owner.as_ref()),
+                    github_token: Arc::new(Secret::new(
+                        namespace.github_token.or(config.github_token.clone()),
+                    )),
+                    access_tokens: namespace.access_tokens,
+                    access_policies: shared(namespace.access_policies),
+                    anonymous_read: namespace.anonymous_read,
+                    quota: namespace.quota,
+                    trusted_publishing: namespace.trusted_publishing,
+                    hosts: namespace.hosts,
+                    metadata: Arc::default(),
+                    public: None,
+                    public_view: false,
+                };
+                (name, namespace.with_public_view())
+            })
+            .collect();
+        let access_tokens = std::iter::once(&default)
+            .chain(named.values())
+            .flat_map(|namespace| namespace.access_tokens.iter().flatten());
+        for access_token in access_tokens {
+            redact::register(access_token.token());
+        }
+        return Namespaces { default, named };
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Namespace>> {
+        return std::iter::once(&self.default).chain(self.named.values());
+    }
+
+    /// Namespace called `name`, the empty name is the default namespace.
+    pub fn by_name(&self, name: &str) -> Option<&Arc<Namespace>> {
+        if name.is_empty() {
+            return Some(&self.default);
+        }
+        return self.named.get(name);
+    }
+
+    fn by_host(&self, host: &str) -> Option<&Arc<Namespace>> {
+        let host = host.split(':').next().unwrap_or(host);
+        return self
+            .named
+            .values()
+            .find(|namespace| namespace.hosts.iter().any(|known| known == host));
+    }
+}
+
+/// Namespace the request was routed to, with the path prefix it is served under.
+#[derive(Clone)]
+pub struct CurrentNamespace {
+    pub namespace: Arc<Namespace>,
+    pub prefix: String,
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for CurrentNamespace {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(current) = parts.extensions.get::<CurrentNamespace>() {
+            return Ok(current.clone());
+        }
+        return Ok(CurrentNamespace {
+            namespace: state.namespaces.default.clone(),
+            prefix: String::new(),
+        });
+    }
+}
+
+pub async fn resolve_namespace(
+    State(app_state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+    let current = if let Some(namespace) = app_state.namespaces.by_host(host) {
+        CurrentNamespace {
+            namespace: namespace.clone(),
+            prefix: String::new(),
+        }
+    } else {
+        let path = request.uri().path().to_string();
+        let (first, rest) = path[1..].split_once('/').unwrap_or((&path[1..], ""));
+        match app_state.namespaces.named.get(first) {
+            Some(_) if !path[1..].contains('/') => {
+                return Redirect::permanent(&format!("{}/", path)).into_response();
+            }
+            Some(namespace) => {
+                let current = CurrentNamespace {
+                    namespace: namespace.clone(),
+                    prefix: format!("/{}", first),
+                };
+                set_path(&mut request, &format!("/{}", rest));
+                current
+            }
+            None => CurrentNamespace {
+                namespace: app_state.namespaces.default.clone(),
+                prefix: String::new(),
+            },
+        }
+    };
+    let path = request.uri().path().to_string();
+    let current = match (path.strip_prefix("/public"), &current.namespace.public) {
+        (Some(""), Some(_)) => {
+            return Redirect::permanent(&format!("{}{}/", current.prefix, path)).into_response();
+        }
+        (Some(rest), Some(public)) if rest.starts_with('/') => {
+            set_path(&mut request, rest);
+            CurrentNamespace {
+                namespace: public.clone(),
+                prefix: format!("{}/public", current.prefix),
+            }
+        }
+        _ => current,
+    };
+    // the public view serves reads only, whatever the credentials
+    if current.namespace.public_view && !is_anonymous_read(request.method(), request.uri().path()) {
+        return ErrorResponse::Forbidden(format!(
+            "{} {} isn't served by the public view, it is read only",
+            request.method(),
+            request.uri().path()
+        ))
+        .into_response();
+    }
+    let signed =
+        match signed_urls::verify(&app_state.config, &current.namespace.name, request.uri()) {
+            Ok(signed) => signed,
+            Err(error) => return error.into_response(),
+        };
+    let (mut parts, body) = request.into_parts();
+    let password = match AuthBasic::decode_request_parts(&mut parts) {
+        Ok(AuthBasic((_, password))) => password,
+        Err(_) => None,
+    };
+    let mut request = Request::from_parts(parts, body);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let full_path = format!("{}{}", current.prefix, path);
+    let operation = Operation::of(&method, &path);
+    let mut account = password
+        .as_ref()
+        .zip(app_state.service_accounts.as_ref())
+        .and_then(|(password, service_accounts)| service_accounts.authenticate(password));
+    let oidc_token = password.as_ref().filter(|password| {
+        current.namespace.trusted_publishing && trusted_publishing::is_oidc_token(password)
+    });
+    if let (None, Some(token)) = (&account, oidc_token) {
+        match app_state
+            .trusted_publishing
+            .authenticate(&current.namespace, token)
+            .await
+        {
+            Ok(workflow) => account = Some(workflow),
+            Err(error) => return error.into_response(),
+        }
+    }
+    // service accounts and workflows are checked for the operation below
+    let mut authenticated = account.is_some();
+    if let Some(account) = &account {
+        if let Err(error) = account.check_request(&current.namespace, &method, &path) {
+            let response = error.into_response();
+            service_accounts::audit(
+                Some(account),
+                operation,
+                &method,
+                &full_path,
+                response.status(),
+            );
+            return response;
+        }
+        request.extensions_mut().insert(account.clone());
+    } else if let (Some(access_tokens), false) = (&current.namespace.access_tokens, signed) {
+        let anonymous = password.is_none()
+            && current.namespace.anonymous_read
+            && is_anonymous_read(&method, &path);
+        let valid = password.filter(|password| {
+            access_tokens
+                .iter()
+                .any(|token| token.matches(password))
+        });
+        match valid {
+            Some(password) => {
+                authenticated = true;
+                if let Some(policy) = current.namespace.client_policy(&password) {
+                    if let Err(error) = policy.check_operation(operation) {
+                        let response = error.into_response();
+                        service_accounts::audit(
+                            None,
+                            operation,
+                            &method,
+                            &full_path,
+                            response.status(),
+                        );
+                        return response;
+                    }
+                    request.extensions_mut().insert(policy);
+                }
+            }
+            None if anonymous => {}
+            None => return ErrorResponse::Unauthorized.into_response(),
+        }
+    }
+    if let Err(error) = check_identity(operation, authenticated) {
+        let response = error.into_response();
+        service_accounts::audit(None, operation, &method, &full_path, response.status());
+        return response;
+    }
+    request.extensions_mut().insert(current);
+    let response = next.run(request).await;
+    service_accounts::audit(
+        account.as_ref(),
+        operation,
+        &method,
+        &full_path,
+        response.status(),
+    );
+    return response;
+}
+
+/// Whether a request may be made without credentials in namespaces with
+/// `anonymous_read`: reading pages, the APIs and downloads, not metrics.
+fn is_anonymous_read(method: &Method, path: &str) -> bool {
+    return matches!(*method, Method::GET | Method::HEAD)
+        && Operation::of(method, path) == Operation::Read
+        && path != "/metrics";
+}
+
+/// Refuses anonymous writes and admin requests, also in namespaces without access tokens.
+fn check_identity(operation: Operation, authenticated: bool) -> Result<(), ErrorResponse> {
+    if operation != Operation::Read && !authenticated {
+        return Err(ErrorResponse::Unauthorized);
+    }
+    return Ok(());
+}
+
+/// Replaces the path the router sees, keeping the query.
+fn set_path(request: &mut Request, path: &str) {
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    *request.uri_mut() = Uri::try_from(path_and_query).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPLOAD: &str = "/api/snapshot";
+
+    #[test]
+    fn refuses_anonymous_writes() {
+        let operation = Operation::of(&Method::POST, UPLOAD);
+        assert!(matches!(
+            check_identity(operation, false),
+            Err(ErrorResponse::Unauthorized)
+        ));
+        assert!(check_identity(operation, true).is_ok());
+    }
+
+    #[test]
+    fn refuses_anonymous_admin_requests() {
+        let operation = Operation::of(&Method::GET, "/debug/status");
+        assert!(matches!(
+            check_identity(operation, false),
+            Err(ErrorResponse::Unauthorized)
+        ));
+        assert!(check_identity(operation, true).is_ok());
+    }
+
+    #[test]
+    fn lets_anonymous_reads_through() {
+        let operation = Operation::of(&Method::GET, "/simple/my-cli/");
+        assert!(check_identity(operation, false).is_ok());
+    }
+
+    #[test]
+    fn serves_reads_only_anonymously() {
+        assert!(is_anonymous_read(&Method::GET, "/simple/my-cli/"));
+        assert!(is_anonymous_read(&Method::HEAD, "/api/repos"));
+        assert!(!is_anonymous_read(&Method::POST, UPLOAD));
+        assert!(!is_anonymous_read(&Method::GET, "/metrics"));
+        assert!(!is_anonymous_read(&Method::GET, "/debug/status"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::{self, Config};
+use crate::github::Release;
+use crate::glob;
+use crate::redact::log;
+use crate::secrets::Secret;
+
+/// A release of a configured package that was not present during the previous sync,
+/// packages outside the default namespace are named `<namespace>/<package>`.
+pub struct NewRelease {
+    pub package_name: String,
+    pub release: Release,
+}
+
+impl NewRelease {
+    fn text(&self) -> String {
+        return format!(
+            "New release of {}: {} ({})",
+            self.package_name, self.release.tag_name, self.release.html_url
+        );
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Channel {
+    Slack {
+        webhook_url: Secret,
+    },
+    Webhook {
+        url: Secret,
+    },
+    Email {
+        smtp_host: String,
+        #[serde(default = "default_smtp_port")]
+        smtp_port: u16,
+        username: Option<String>,
+        password: Option<Secret>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+fn value(secret: &Secret) -> Result<String, String> {
+    return secret
+        .get()
+        .ok_or("the secret couldn't be fetched".to_string());
+}
+
+fn default_smtp_port() -> u16 {
+    return 587;
+}
+
+#[derive(Deserialize)]
+struct Route {
+    packages: Vec<String>,
+    notifiers: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct Notifier {
+    notifiers: HashMap<String, Channel>,
+    routes: Vec<Route>,
+    #[serde(skip)]
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn from_config(config: &Config) -> Self {
+        let Some(path) = &config.notifications_config_path else {
+            return Notifier::default();
+        };
+        return Notifier::load(path).unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json_content = fs::read_to_string(path)
+            .map_err(|error| format!("Failed to load notifications config file: {}", error))?;
+        let notifier: Notifier = serde_json::from_str(&json_content)
+            .map_err(|error| error.to_string())
+            .and_then(|mut value| config::interpolate(&mut value).map(|_| value))
+            .and_then(config::deserialize)
+            .map_err(|error| format!("failed to process notifications config file: {}", error))?;
+        for route in &notifier.routes {
+            for name in &route.notifiers {
+                if !notifier.notifiers.contains_key(name) {
+                    return Err(format!(
+                        "notifications route refers to unknown notifier {}",
+                        name
+                    ));
+                }
+            }
+        }
+        return Ok(notifier);
+    }
+
+    /// Secrets of all channels, to be fetched when they are references.
+    pub fn secrets(&self) -> Vec<&Secret> {
+        return self
+            .notifiers
+            .values()
+            .filter_map(|channel| match channel {
+                Channel::Slack { webhook_url } => Some(webhook_url),
+                Channel::Webhook { url } => Some(url),
+                Channel::Email { password, .. } => password.as_ref(),
+            })
+            .collect();
+    }
+
+    fn channels_for(&self, package_name: &str) -> Vec<(&String, &Channel)> {
+        let mut names: Vec<&String> = self
+            .routes
+            .iter()
+            .filter(|route| {
+                route
+                    .packages
+                    .iter()
+                    .any(|pattern| glob::matches(pattern, package_name))
+            })
+            .flat_map(|route| route.notifiers.iter())
+            .collect();
+        names.sort();
+        names.dedup();
+        return names
+            .into_iter()
+            .filter_map(|name| self.notifiers.get_key_value(name))
+            .collect();
+    }
+
+    /// Sends `new_release` to its channels, returns why sending failed.
+    pub async fn notify(&self, new_release: &NewRelease) -> Vec<String> {
+        let mut failures = vec![];
+        for (name, channel) in self.channels_for(&new_release.package_name) {
+            if let Err(error) = self.send(channel, new_release).await {
+                let message = format!("Failed to send notification via {}: {}", name, error);
+                log!("{}", message);
+                failures.push(message);
+            }
+        }
+        return failures;
+    }
+
+    async fn send(&self, channel: &Channel, new_release: &NewRelease) -> Result<(), String> {
+        match channel {
+            Channel::Slack { webhook_url } => {
+                let payload = json!({ "text": new_release.text() });
+                self.post(&value(webhook_url)?, &payload).await
+            }
+            Channel::Webhook { url } => {
+                let payload = json!({
+                    "package": new_release.package_name,
+                    "version": new_release.release.tag_name,
+                    "name": new_release.release.name,
+                    "url": new_release.release.html_url,
+                    "published_at": new_release.release.published_at,
+                });
+                self.post(&value(url)?, &payload).await
+            }
+            Channel::Email {
+                smtp_host,
+                smtp_port,
+                username,
+                password,
+                from,
+                to,
+            } => {
+                let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(smtp_host)
+                    .map_err(|error| error.to_string())?
+                    .port(*smtp_port);
+                if let (Some(username), Some(password)) = (username, password) {
+                    builder =
+                        builder.credentials(Credentials::new(username.clone(), value(password)?));
+                }
+                let mut message = Message::builder()
+                    .from(parse_mailbox(from)?)
+                    .subject(format!(
+                        "{} {} released",
+                        new_release.package_name, new_release.release.tag_name
+                    ));
+                for recipient in to {
+                    message = message.to(parse_mailbox(recipient)?);
+                }
+                let message = message
+                    .body(new_release.text())
+                    .map_err(|error| error.to_string())?;
+                builder
+                    .build()
+                    .send(message)
+                    .await
+                    .map(|_| ())
+                    .map_err(|error| error.to_string())
+            }
+        }
+    }
+
+    async fn post(&self, url: &str, payload: &serde_json::Value) -> Result<(), String> {
+        self.client
+            .post(url)
+            .json(payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|error| error.to_string())
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, String> {
+    return address
+        .parse::<Mailbox>()
+        .map_err(|error| format!("invalid email address {}: {}", address, error));
+}
@@ -0,0 +1,667 @@
+//! OpenAPI 3 description of the http api, kept next to the router: every new
+//! endpoint should be described here as well.
+use std::sync::Arc;
+
+use askama::Template;
+use axum::extract::State;
+use axum::response::Json;
+use serde_json::{json, Value};
+
+use crate::AppState;
+
+fn path_parameter(name: &str, description: &str) -> Value {
+    return json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": {"type": "string"},
+    });
+}
+
+fn html_response(description: &str) -> Value {
+    return json!({
+        "200": {"description": description, "content": {"text/html": {}}},
+    });
+}
+
+/// Status of a prefetch job.
+fn prefetch_job(description: &str) -> Value {
+    let file = json!({
+        "type": "object",
+        "properties": {
+            "filename": {"type": "string"},
+            "size": {"type": "integer"},
+            "state": {"type": "string", "enum": ["queued", "done", "failed"]},
+            "error": {"type": "string", "nullable": true},
+        },
+    });
+    return json!({
+        "description": description,
+        "content": {"application/json": {"schema": {
+            "type": "object",
+            "properties": {
+                "id": {"type": "string"},
+                "package": {"type": "string"},
+                "version": {"type": "string"},
+                "created_at": {"type": "integer", "description": "Unix timestamp"},
+                "state": {"type": "string", "enum": ["running", "done", "failed"]},
+                "files": {"type": "array", "items": file},
+            },
+        }}},
+    });
+}
+
+/// Downloads are typed by their file name, see `CONTENT_TYPES`.
+fn asset_content() -> Value {
+    return json!({
+        "application/zip": {},
+        "application/gzip": {},
+        "text/plain": {},
+        "application/octet-stream": {},
+    });
+}
+
+/// Answer when GitHub's rate limit is used up and nothing is cached.
+fn rate_limited() -> Value {
+    return json!({
+        "description": "GitHub's rate limit is used up, `Retry-After` says when it is restored",
+        "headers": {
+            "Retry-After": {"description": "Seconds until GitHub's rate limit is restored", "schema": {"type": "integer"}},
+        },
+        "content": {
+            "application/json": {
+                "schema": {
+                    "type": "object",
+                    "properties": {
+                        "error": {"type": "string", "example": "github_rate_limited"},
+                        "message": {"type": "string"},
+                        "retry_after": {"type": "integer"},
+                        "reset": {"type": "integer", "description": "Unix timestamp of when the rate limit is restored"},
+                    },
+                },
+            },
+        },
+    });
+}
+
+/// Simple index pages, in the format preferred in `Accept`.
+fn simple_response(description: &str) -> Value {
+    return json!({
+        "200": {
+            "description": description,
+            "content": {
+                "text/html": {},
+                "application/vnd.pypi.simple.v1+html": {},
+                "application/vnd.pypi.simple.v1+json": {},
+            },
+            "headers": {
+                "Deprecation": {"description": "When the format was deprecated, if it was", "schema": {"type": "string"}},
+                "Sunset": {"description": "When the format is going to be removed", "schema": {"type": "string"}},
+                "X-PyPI-Last-Serial": {"description": "Serial of the package, on the list of packages of the latest change", "schema": {"type": "integer"}},
+                "ETag": {"description": "Digest of the body, changes only when the page does", "schema": {"type": "string"}},
+            },
+        },
+        "304": {"description": "Unchanged since `If-None-Match`"},
+        "503": rate_limited(),
+    });
+}
+
+pub fn document(external_url: &str) -> Value {
+    let package = path_parameter("package", "Name of the package as configured in pigi");
+    let not_found = json!({"description": "Package is not configured or not synced yet"});
+    let forbidden = json!({"description": "The access policy of the token doesn't allow the file, the body says why"});
+    return json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "pigi",
+            "description": "Python package index backed by GitHub release artifacts",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{"url": external_url}],
+        "components": {
+            "securitySchemes": {
+                "githubToken": {
+                    "type": "http",
+                    "scheme": "basic",
+                    "description": "GitHub token passed as the password, the username is ignored",
+                },
+            },
+            "schemas": {
+                "File": {
+                    "type": "object",
+                    "properties": {
+                        "filename": {"type": "string"},
+                        "url": {"type": "string", "format": "uri"},
+                        "packagetype": {"type": "string", "enum": ["bdist_wheel", "sdist", "unknown"]},
+                        "size": {"type": "integer"},
+                        "upload_time_iso_8601": {"type": "string", "format": "date-time"},
+                        "digests": {"type": "object", "additionalProperties": {"type": "string"}},
+                        "yanked": {"type": "boolean"},
+                        "has_sig": {"type": "boolean", "description": "A detached `.asc` signature is published next to the file"},
+                        "provenance": {"type": "string", "format": "uri", "nullable": true, "description": "URL of the sigstore / PEP 740 attestation of the file"},
+                    },
+                },
+                "Info": {
+                    "type": "object",
+                    "properties": {
+                        "name": {"type": "string"},
+                        "version": {"type": "string", "nullable": true},
+                        "summary": {"type": "string", "nullable": true},
+                        "home_page": {"type": "string", "nullable": true},
+                        "release_url": {"type": "string", "nullable": true},
+                    },
+                },
+                "Project": {
+                    "type": "object",
+                    "properties": {
+                        "info": {"$ref": "#/components/schemas/Info"},
+                        "last_serial": {"type": "integer"},
+                        "releases": {
+                            "type": "object",
+                            "additionalProperties": {"type": "array", "items": {"$ref": "#/components/schemas/File"}},
+                        },
+                        "urls": {"type": "array", "items": {"$ref": "#/components/schemas/File"}},
+                        "vulnerabilities": {"type": "array", "items": {"$ref": "#/components/schemas/Vulnerability"}},
+                    },
+                },
+                "Vulnerability": {
+                    "type": "object",
+                    "description": "Known vulnerability of the version from OSV, listed when `OSV_LOOKUP` is set",
+                    "properties": {
+                        "id": {"type": "string"},
+                        "source": {"type": "string", "enum": ["osv"]},
+                        "link": {"type": "string", "format": "uri"},
+                        "aliases": {"type": "array", "items": {"type": "string"}},
+                        "summary": {"type": "string", "nullable": true},
+                        "fixed_in": {"type": "array", "items": {"type": "string"}},
+                    },
+                },
+            },
+        },
+        "security": [{}, {"githubToken": []}],
+        "paths": {
+            "/simple/": {
+                "get": {
+                    "summary": "PEP 503 / PEP 691 list of packages",
+                    "responses": simple_response("List of configured packages"),
+                },
+            },
+            "/simple/{package}/": {
+                "get": {
+                    "summary": "PEP 503 / PEP 691 list of files of a package",
+                    "parameters": [package],
+                    "responses": simple_response("Links to all release assets of the package"),
+                },
+            },
+            "/simple/{package}/{version}/{filename}": {
+                "get": {
+                    "summary": "Download a release asset",
+                    "parameters": [
+                        package,
+                        path_parameter("version", "Release version (or GitHub asset id)"),
+                        path_parameter("filename", "Name of the file"),
+                    ],
+                    "responses": {
+                        "200": {"description": "Content of the asset", "content": asset_content()},
+                        "304": {"description": "Unchanged since `If-Modified-Since` or `If-None-Match`"},
+                        "403": forbidden,
+                        "503": rate_limited(),
+                    },
+                },
+            },
+            "/artifacts/sha256/{digest}": {
+                "get": {
+                    "summary": "Download a release asset by the sha256 of its content",
+                    "parameters": [path_parameter("digest", "Hex encoded sha256 of the file")],
+                    "responses": {
+                        "200": {"description": "Content of the asset", "content": asset_content()},
+                        "304": {"description": "Unchanged since `If-Modified-Since` or `If-None-Match`"},
+                        "403": forbidden,
+                        "404": not_found,
+                    },
+                },
+            },
+            "/conda/{channel}/{subdir}/{file}": {
+                "get": {
+                    "summary": "Conda channel of the packages with a `conda_channel`",
+                    "description": "`repodata.json` lists the .conda and .tar.bz2 files of the synced releases in `noarch`, other files redirect to their download",
+                    "parameters": [
+                        path_parameter("channel", "The `conda_channel` of the packages"),
+                        path_parameter("subdir", "Conda platform subdir, like `noarch` or `linux-64`"),
+                        path_parameter("file", "`repodata.json` or a package file"),
+                    ],
+                    "responses": {
+                        "200": {"description": "Repodata of the subdir", "content": {"application/json": {}}},
+                        "307": {"description": "Download url of the file"},
+                        "404": not_found,
+                    },
+                },
+            },
+            "/downloads/{package}/{version}/{file}": {
+                "get": {
+                    "summary": "Download any file of a release of a package with `downloads`, like CLIs and installers",
+                    "parameters": [package, path_parameter("version", "Release version or tag"), path_parameter("file", "Name of the file")],
+                    "responses": {
+                        "200": {"description": "Content of the file, as an attachment", "content": asset_content()},
+                        "304": {"description": "Unchanged since `If-Modified-Since` or `If-None-Match`"},
+                        "403": forbidden,
+                        "404": {"description": "Package without `downloads`, or no such file"},
+                    },
+                },
+            },
+            "/install/{script}": {
+                "get": {
+                    "summary": "Install script, Homebrew formula or manifest of the CLI of a package with `downloads`",
+                    "description": "Files of the latest release for Linux and macOS, by platform read from their names",
+                    "parameters": [path_parameter("script", "`<package>.sh`, `<package>.rb` or `<package>.json`")],
+                    "responses": {
+                        "200": {"description": "Shell script, Homebrew formula or JSON manifest", "content": {"text/x-shellscript": {}, "text/x-ruby": {}, "application/json": {}}},
+                        "403": {"description": "The service account may not access the package"},
+                        "404": {"description": "Package without `downloads`, or no files for Linux or macOS"},
+                    },
+                },
+            },
+            "/metrics": {
+                "get": {
+                    "summary": "Metrics in the Prometheus text format",
+                    "security": [{}],
+                    "responses": {"200": {"description": "Metrics", "content": {"text/plain": {}}}},
+                },
+            },
+            "/debug/status": {
+                "get": {
+                    "summary": "Diagnostics of the running instance: version, config, sync state of every package, artifact cache, GitHub rate limits and background tasks",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Diagnostics", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/debug/filenames": {
+                "get": {
+                    "summary": "Distribution files with malformed or non-compliant names, by repository",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Issues of every repository with any", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/debug/quotas": {
+                "get": {
+                    "summary": "Quotas of every namespace and package, with their storage and largest files as of the last sync",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Limits and usage of namespaces and packages", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/debug/links": {
+                "get": {
+                    "summary": "Files the link check found gone, left out of the index until they are back",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Broken files by package", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/debug/repositories": {
+                "get": {
+                    "summary": "Repositories renamed, transferred or archived on GitHub since they were configured",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Every such repository with its package", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/debug/quarantine": {
+                "get": {
+                    "summary": "Every release held for review, in all namespaces",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Quarantined releases", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+                "post": {
+                    "summary": "Approve a quarantined release, it is served and announced from then on",
+                    "description": "Served when `DEBUG_TOKEN` and `QUARANTINE_PATH` are set, pass the token as the basic auth password",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "required": ["package", "version"],
+                            "properties": {
+                                "package": {"type": "string", "description": "Package name, `<namespace>/<package>` outside of the default namespace"},
+                                "version": {"type": "string"},
+                            },
+                        }}},
+                    },
+                    "responses": {
+                        "200": {"description": "Approved release", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "The version is not quarantined, or `DEBUG_TOKEN` or `QUARANTINE_PATH` is not set"},
+                    },
+                },
+            },
+            "/debug/promote": {
+                "post": {
+                    "summary": "Publish a draft release staged with `pigi publish --draft`, its files are served at once",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password. The namespace's GitHub token needs push access to see drafts",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "required": ["package", "version"],
+                            "properties": {
+                                "package": {"type": "string", "description": "Package name, `<namespace>/<package>` outside of the default namespace"},
+                                "version": {"type": "string"},
+                            },
+                        }}},
+                    },
+                    "responses": {
+                        "200": {"description": "Published release with its `url` on GitHub", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "The version has no draft release, or `DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/debug/service-accounts": {
+                "get": {
+                    "summary": "Service accounts with their packages and operations",
+                    "description": "Served when `DEBUG_TOKEN` and `SERVICE_ACCOUNTS_PATH` are set, pass the token, or one of an `admin` service account, as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Service accounts, without their tokens", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` or `SERVICE_ACCOUNTS_PATH` is not set"},
+                    },
+                },
+                "post": {
+                    "summary": "Create a service account, or replace the one with the same name and its token",
+                    "description": "Served when `DEBUG_TOKEN` and `SERVICE_ACCOUNTS_PATH` are set, pass the token, or one of an `admin` service account, as the basic auth password",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "required": ["name", "packages", "operations"],
+                            "properties": {
+                                "name": {"type": "string"},
+                                "packages": {"type": "array", "items": {"type": "string"}, "description": "Package names, `<namespace>/<package>` outside of the default namespace, `*` matches any characters"},
+                                "operations": {"type": "array", "items": {"type": "string", "enum": ["read", "publish", "admin"]}},
+                            },
+                        }}},
+                    },
+                    "responses": {
+                        "200": {"description": "The account with its `token`, which is not shown again", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` or `SERVICE_ACCOUNTS_PATH` is not set"},
+                    },
+                },
+            },
+            "/debug/service-accounts/{name}": {
+                "delete": {
+                    "summary": "Remove a service account, its token stops working",
+                    "description": "Served when `DEBUG_TOKEN` and `SERVICE_ACCOUNTS_PATH` are set, pass the token, or one of an `admin` service account, as the basic auth password",
+                    "parameters": [path_parameter("name", "Name of the service account")],
+                    "responses": {
+                        "200": {"description": "Removed", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "No such account, or `DEBUG_TOKEN` or `SERVICE_ACCOUNTS_PATH` is not set"},
+                    },
+                },
+            },
+            "/api/packages": {
+                "get": {
+                    "summary": "Every package with its latest version and the summary, license and classifiers from its wheel",
+                    "responses": {"200": {"description": "Packages", "content": {"application/json": {}}}},
+                },
+            },
+            "/api/repos": {
+                "get": {
+                    "summary": "Every package with the repository it comes from and how fresh its synced metadata is",
+                    "description": "`stale` packages weren't synced for more than two `SYNC_INTERVAL`s, or not yet",
+                    "responses": {"200": {"description": "Packages of the namespace", "content": {"application/json": {}}}},
+                },
+            },
+            "/api/changelog": {
+                "get": {
+                    "summary": "Packages whose metadata changed after a serial, oldest change first",
+                    "parameters": [{"name": "since", "in": "query", "description": "`last_serial` of the previous call, every package when not given", "schema": {"type": "integer"}}],
+                    "responses": {"200": {"description": "Changed packages with their serial and latest version, and the serial to pass next time", "content": {"application/json": {}}}},
+                },
+            },
+            "/api/packages/{package}/versions": {
+                "get": {
+                    "summary": "Versions of a package with their files, platforms, checksums and download urls",
+                    "description": "Stable manifest for tooling other than pip, `schema_version` 1",
+                    "parameters": [package],
+                    "responses": {
+                        "200": {"description": "`schema_version`, `package`, `latest` and `versions`, newest first, each with its `files`", "content": {"application/json": {}}},
+                        "403": {"description": "The service account may not access the package"},
+                        "404": not_found,
+                    },
+                },
+            },
+            "/api/packages/{package}/metadata": {
+                "get": {
+                    "summary": "The package's metadata as synced, what replicas replicate",
+                    "parameters": [package],
+                    "responses": {
+                        "200": {"description": "Releases, quarantined releases, vulnerabilities, catalog and serial", "content": {"application/json": {}}},
+                        "404": {"description": "Package is not configured or not synced yet"},
+                    },
+                },
+            },
+            "/api/sign": {
+                "get": {
+                    "summary": "Download url of a file that works without credentials until it expires",
+                    "description": "Served when `SIGNED_URL_KEY` is set, urls are valid for `SIGNED_URL_TTL` seconds",
+                    "parameters": [
+                        {"name": "package", "in": "query", "required": true, "description": "Name of the package", "schema": {"type": "string"}},
+                        {"name": "file", "in": "query", "required": true, "description": "Name of the file", "schema": {"type": "string"}},
+                    ],
+                    "responses": {
+                        "200": {"description": "The signed `url` and when it `expires`, a unix timestamp", "content": {"application/json": {}}},
+                        "403": {"description": "The access policy of the token doesn't allow the file"},
+                        "404": {"description": "Package or file doesn't exist, or `SIGNED_URL_KEY` is not set"},
+                    },
+                },
+            },
+            "/api/packages/{package}/{version}/sbom": {
+                "get": {
+                    "summary": "CycloneDX SBOM of a wheel, generated from its METADATA and RECORD",
+                    "parameters": [
+                        package,
+                        path_parameter("version", "Release version"),
+                        {"name": "file", "in": "query", "description": "Name of the wheel, the first wheel of the version by default", "schema": {"type": "string"}},
+                    ],
+                    "responses": {
+                        "200": {"description": "CycloneDX 1.5 document", "content": {"application/json": {}}},
+                        "404": {"description": "Package is not configured or the version has no such wheel"},
+                    },
+                },
+            },
+            "/api/packages/{package}/{version}/status": {
+                "get": {
+                    "summary": "Whether a published version is served yet, syncing the package first",
+                    "description": "Poll after publishing until `served` is true, the next package page lists the files then",
+                    "parameters": [package, path_parameter("version", "Release version")],
+                    "responses": {
+                        "200": {"description": "Whether the version is `published` on GitHub, `quarantined` or `served`, its served `files` and the package's `serial`", "content": {"application/json": {}}},
+                        "404": {"description": "Package is not configured"},
+                    },
+                },
+            },
+            "/api/packages/{package}/{version}/files/{filename}": {
+                "post": {
+                    "summary": "Publish a wheel or sdist, sent as the request body, to the release of the version in the package's storage",
+                    "description": "Needs the `publish` operation: an access token, a service account, or a GitHub Actions OIDC token in namespaces with `trusted_publishing`",
+                    "parameters": [
+                        package,
+                        path_parameter("version", "Release version"),
+                        path_parameter("filename", "File name of the distribution, or of its PEP 740 attestation with `.publish.attestation` appended"),
+                        {"name": "sha256", "in": "query", "schema": {"type": "string"}, "description": "Digest the file has to have"},
+                    ],
+                    "requestBody": {"content": {"application/octet-stream": {}}},
+                    "responses": {
+                        "200": {"description": "The published file's `sha256` and the `url` of its release, also when the same file was already published", "content": {"application/json": {}}},
+                        "400": {"description": "The file is invalid or over the package's quotas, an attestation doesn't attest its published distribution, or the package is mirrored from PyPI"},
+                        "401": {"description": "Missing or invalid token"},
+                        "403": {"description": "The token may not publish the package"},
+                        "404": {"description": "Package is not configured"},
+                        "409": {"description": "The file is already published with another content"},
+                        "413": {"description": "The file is over the package's `max_file_size`, or `MAX_UPLOAD_SIZE` without one"},
+                    },
+                },
+            },
+            "/debug/vulnerabilities": {
+                "get": {
+                    "summary": "Every synced version with known vulnerabilities from OSV, in all namespaces",
+                    "description": "Served when `DEBUG_TOKEN` is set, pass it as the basic auth password",
+                    "responses": {
+                        "200": {"description": "Affected versions", "content": {"application/json": {}}},
+                        "401": {"description": "Missing or wrong `DEBUG_TOKEN`"},
+                        "404": {"description": "`DEBUG_TOKEN` is not set"},
+                    },
+                },
+            },
+            "/search": {
+                "get": {
+                    "summary": "Search packages by name and description",
+                    "parameters": [{"name": "q", "in": "query", "schema": {"type": "string"}}],
+                    "responses": html_response("Matching packages"),
+                },
+            },
+            "/packages/": {
+                "get": {
+                    "summary": "Packages with their description and latest version, a page at a time",
+                    "parameters": [
+                        {"name": "page", "in": "query", "schema": {"type": "integer", "minimum": 1, "default": 1}},
+                        {"name": "per_page", "in": "query", "schema": {"type": "integer", "minimum": 1, "maximum": 500, "default": 100}},
+                        {"name": "q", "in": "query", "description": "Part of the package name", "schema": {"type": "string"}},
+                    ],
+                    "responses": html_response("A page of packages"),
+                },
+            },
+            "/feed.xml": {
+                "get": {
+                    "summary": "Atom feed of recent releases",
+                    "responses": {"200": {"description": "Atom feed", "content": {"text/xml": {}}}},
+                },
+            },
+            "/pypi/{package}/json": {
+                "get": {
+                    "summary": "PyPI JSON API view of a package",
+                    "parameters": [package],
+                    "responses": {
+                        "200": {"description": "Package with all its releases", "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Project"}}}},
+                        "404": not_found,
+                    },
+                },
+            },
+            "/pypi/{package}/{version}/json": {
+                "get": {
+                    "summary": "PyPI JSON API view of a single release",
+                    "parameters": [package, path_parameter("version", "Release version")],
+                    "responses": {
+                        "200": {"description": "Release files", "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Project"}}}},
+                        "404": not_found,
+                    },
+                },
+            },
+            "/api/signing-key": {
+                "get": {
+                    "summary": "Ed25519 public key of the `X-Pigi-Signature` header of index pages and JSON API responses",
+                    "responses": {
+                        "200": {"description": "Public key, raw base64 and PEM encoded", "content": {"application/json": {}}},
+                        "404": {"description": "`INDEX_SIGNING_KEY_PATH` is not set"},
+                    },
+                },
+            },
+            "/api/snapshot": {
+                "post": {
+                    "summary": "Freeze the latest version of every package into a snapshot index",
+                    "responses": {
+                        "200": {
+                            "description": "Created snapshot",
+                            "content": {"application/json": {"schema": {
+                                "type": "object",
+                                "properties": {
+                                    "id": {"type": "string"},
+                                    "created_at": {"type": "integer", "description": "Unix timestamp"},
+                                    "url": {"type": "string", "format": "uri", "description": "Index url of the snapshot"},
+                                    "packages": {"type": "object", "additionalProperties": {"type": "string"}},
+                                },
+                            }}},
+                        },
+                    },
+                },
+            },
+            "/api/prefetch": {
+                "post": {
+                    "summary": "Queue a download of files of a release into the artifact cache",
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "required": ["package", "version"],
+                            "properties": {
+                                "package": {"type": "string"},
+                                "version": {"type": "string"},
+                                "files": {"type": "array", "items": {"type": "string"}, "description": "Every file of the release when not given"},
+                            },
+                        }}},
+                    },
+                    "responses": {
+                        "202": prefetch_job("Queued job"),
+                        "404": {"description": "The cache is disabled, or the package, version or a file is unknown"},
+                    },
+                },
+            },
+            "/api/prefetch/{id}": {
+                "get": {
+                    "summary": "Progress of a prefetch job",
+                    "parameters": [path_parameter("id", "Job id")],
+                    "responses": {"200": prefetch_job("Job status"), "404": {"description": "Unknown job"}},
+                },
+            },
+            "/snapshots/{id}/simple/": {
+                "get": {
+                    "summary": "PEP 503 list of packages of a snapshot",
+                    "parameters": [path_parameter("id", "Snapshot id")],
+                    "responses": html_response("Packages frozen in the snapshot"),
+                },
+            },
+            "/pypi": {
+                "post": {
+                    "summary": "XML-RPC endpoint, supports `list_packages`",
+                    "requestBody": {"content": {"text/xml": {}}},
+                    "responses": {"200": {"description": "XML-RPC method response", "content": {"text/xml": {}}}},
+                },
+            },
+        },
+    });
+}
+
+pub async fn openapi(State(app_state): State<Arc<AppState>>) -> Json<Value> {
+    return Json(document(&app_state.config.external_url));
+}
+
+#[derive(Template)]
+#[template(path = "api_docs.html")]
+pub struct ApiDocs {}
+
+pub async fn docs() -> ApiDocs {
+    return ApiDocs {};
+}
@@ -0,0 +1,177 @@
+//! Known vulnerabilities of the synced versions, looked up in OSV
+//! (`https://osv.dev`) by the sync worker when `OSV_LOOKUP` is set. Internal
+//! packages sharing a name with a PyPI project get its advisories as well.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::Config;
+use crate::github::Release;
+use crate::names;
+
+/// Most queries OSV accepts in one batch.
+const MAX_BATCH: usize = 1000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Vulnerability {
+    pub id: String,
+    pub summary: Option<String>,
+    pub aliases: Vec<String>,
+    /// versions the vulnerability is fixed in
+    pub fixed_in: Vec<String>,
+    pub link: String,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+#[derive(Deserialize)]
+struct BatchResult {
+    #[serde(default)]
+    vulns: Vec<VulnerabilityRef>,
+}
+
+#[derive(Deserialize)]
+struct VulnerabilityRef {
+    id: String,
+    modified: String,
+}
+
+#[derive(Deserialize)]
+struct OsvRecord {
+    id: String,
+    summary: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    affected: Vec<Affected>,
+}
+
+#[derive(Deserialize)]
+struct Affected {
+    package: Option<AffectedPackage>,
+    #[serde(default)]
+    ranges: Vec<Range>,
+}
+
+#[derive(Deserialize)]
+struct AffectedPackage {
+    name: String,
+    ecosystem: String,
+}
+
+#[derive(Deserialize)]
+struct Range {
+    #[serde(default)]
+    events: Vec<HashMap<String, String>>,
+}
+
+pub struct Osv {
+    url: String,
+    client: reqwest::Client,
+    /// details of every vulnerability seen, with the `modified` they were fetched at
+    records: Mutex<HashMap<String, (String, Vulnerability)>>,
+}
+
+impl Osv {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        return config.osv_url.as_ref().map(|url| Osv {
+            url: url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            records: Mutex::default(),
+        });
+    }
+
+    /// Vulnerabilities of every release of the package that has any, by version.
+    pub async fn lookup(
+        &self,
+        package_name: &str,
+        releases: &[Release],
+    ) -> Result<BTreeMap<String, Vec<Vulnerability>>, String> {
+        let name = names::normalize(package_name);
+        let mut vulnerabilities = BTreeMap::new();
+        for chunk in releases.chunks(MAX_BATCH) {
+            let queries: Vec<_> = chunk
+                .iter()
+                .map(|release| {
+                    json!({
+                        "package": {"name": name, "ecosystem": "PyPI"},
+                        "version": release.version(),
+                    })
+                })
+                .collect();
+            let response: BatchResponse = self
+                .client
+                .post(format!("{}/v1/querybatch", self.url))
+                .json(&json!({ "queries": queries }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|error| error.to_string())?
+                .json()
+                .await
+                .map_err(|error| error.to_string())?;
+            for (release, result) in chunk.iter().zip(response.results) {
+                let mut found = vec![];
+                for reference in result.vulns {
+                    found.push(self.record(&name, reference).await?);
+                }
+                if !found.is_empty() {
+                    vulnerabilities.insert(release.version().to_string(), found);
+                }
+            }
+        }
+        return Ok(vulnerabilities);
+    }
+
+    /// Details of a vulnerability, fetched again only when OSV modified it.
+    async fn record(
+        &self,
+        name: &str,
+        reference: VulnerabilityRef,
+    ) -> Result<Vulnerability, String> {
+        if let Some((modified, vulnerability)) = self.records.lock().unwrap().get(&reference.id) {
+            if *modified == reference.modified {
+                return Ok(vulnerability.clone());
+            }
+        }
+        let record: OsvRecord = self
+            .client
+            .get(format!("{}/v1/vulns/{}", self.url, reference.id))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| error.to_string())?
+            .json()
+            .await
+            .map_err(|error| error.to_string())?;
+        let fixed_in = record
+            .affected
+            .iter()
+            .filter(|affected| {
+                affected.package.as_ref().is_some_and(|package| {
+                    package.ecosystem == "PyPI" && names::normalize(&package.name) == name
+                })
+            })
+            .flat_map(|affected| &affected.ranges)
+            .flat_map(|range| &range.events)
+            .filter_map(|event| event.get("fixed").cloned())
+            .collect();
+        let vulnerability = Vulnerability {
+            link: format!("https://osv.dev/vulnerability/{}", record.id),
+            id: record.id,
+            summary: record.summary,
+            aliases: record.aliases,
+            fixed_in,
+        };
+        self.records
+            .lock()
+            .unwrap()
+            .insert(reference.id, (reference.modified, vulnerability.clone()));
+        return Ok(vulnerability);
+    }
+}
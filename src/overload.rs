@@ -0,0 +1,57 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use askama_axum::Response;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+
+use crate::error::ErrorResponse;
+use crate::metrics::{GITHUB_LATENCY_MS, REQUESTS_IN_FLIGHT, REQUESTS_SHED};
+use crate::AppState;
+
+struct InFlight;
+
+impl InFlight {
+    fn start() -> (Self, u64) {
+        let in_flight = REQUESTS_IN_FLIGHT.fetch_add(1, Ordering::Relaxed) + 1;
+        return (InFlight, in_flight);
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        REQUESTS_IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Rejects requests with 503 beyond `MAX_CONCURRENT_REQUESTS`, so a stampede
+/// of CI jobs gets quick retries instead of timeouts. While GitHub responds
+/// slower than `GITHUB_LATENCY_THRESHOLD` the limit is halved.
+pub async fn shed(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (_in_flight, in_flight) = InFlight::start();
+    // metrics have to stay available, overload is what they are watched for
+    if request.uri().path().ends_with("/metrics") {
+        return next.run(request).await;
+    }
+    let config = &app_state.config;
+    if let Some(max_concurrent_requests) = config.max_concurrent_requests {
+        let github_is_slow = config.github_latency_threshold.is_some_and(|threshold| {
+            GITHUB_LATENCY_MS.load(Ordering::Relaxed) > threshold.as_millis() as u64
+        });
+        let limit = if github_is_slow {
+            (max_concurrent_requests / 2).max(1)
+        } else {
+            max_concurrent_requests
+        };
+        if in_flight > limit {
+            REQUESTS_SHED.fetch_add(1, Ordering::Relaxed);
+            return ErrorResponse::ServiceUnavailable.into_response();
+        }
+    }
+    return next.run(request).await;
+}
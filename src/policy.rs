@@ -0,0 +1,233 @@
+//! What the holders of an access token may do. Access tokens can name a
+//! class, e.g. `laptop` or `ci`, and `access_policies` restrict a class to
+//! some kinds of files, deny it prereleases or limit the operations it may
+//! perform. Requests outside of the policy are answered with a 403 saying why.
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use pep440_rs::Version;
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+use crate::companions;
+use crate::error::ErrorResponse;
+use crate::service_accounts::Operation;
+use crate::AppState;
+
+/// Entry of `access_tokens`: the token alone, or with the class of its holders.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum AccessToken {
+    Plain(String),
+    Classified { token: String, class: String },
+}
+
+impl AccessToken {
+    pub fn token(&self) -> &str {
+        return match self {
+            AccessToken::Plain(token) => token,
+            AccessToken::Classified { token, .. } => token,
+        };
+    }
+
+    /// Whether `password` is this token, compared in constant time so the
+    /// response time doesn't reveal how much of it was guessed.
+    pub fn matches(&self, password: &str) -> bool {
+        return self.token().as_bytes().ct_eq(password.as_bytes()).into();
+    }
+
+    pub fn class(&self) -> Option<&str> {
+        return match self {
+            AccessToken::Plain(_) => None,
+            AccessToken::Classified { class, .. } => Some(class),
+        };
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    Wheel,
+    Sdist,
+    /// anything else attached to a release
+    Other,
+}
+
+impl ArtifactKind {
+    /// Kind of the file, companions (`.metadata`, signatures) count as the file they belong to.
+    pub fn of(filename: &str) -> Self {
+        let filename = companions::subject_of(filename).map_or(filename, |(subject, _)| subject);
+        if filename.ends_with(".whl") {
+            return ArtifactKind::Wheel;
+        }
+        if filename.ends_with(".tar.gz") || filename.ends_with(".zip") {
+            return ArtifactKind::Sdist;
+        }
+        return ArtifactKind::Other;
+    }
+
+    fn as_str(self) -> &'static str {
+        return match self {
+            ArtifactKind::Wheel => "wheels",
+            ArtifactKind::Sdist => "sdists",
+            ArtifactKind::Other => "other files",
+        };
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessPolicy {
+    /// kinds of files allowed, all when unset
+    pub artifacts: Option<Vec<ArtifactKind>>,
+    #[serde(default = "allowed")]
+    pub prereleases: bool,
+    /// operations allowed, all when unset
+    pub operations: Option<Vec<Operation>>,
+}
+
+fn allowed() -> bool {
+    return true;
+}
+
+/// Policy of the client's access token, set on requests by the namespace middleware.
+#[derive(Clone)]
+pub struct ClientPolicy {
+    pub class: String,
+    pub policy: Arc<AccessPolicy>,
+}
+
+impl ClientPolicy {
+    /// Refuses a request performing `operation` when the policy doesn't allow it.
+    pub fn check_operation(&self, operation: Operation) -> Result<(), ErrorResponse> {
+        if let Some(operations) = &self.policy.operations {
+            if !operations.contains(&operation) {
+                return Err(ErrorResponse::Forbidden(format!(
+                    "Access tokens of class {} may not {}",
+                    self.class,
+                    operation.as_str()
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Refuses the download of `filename` of release `version` when the policy doesn't allow it.
+    pub fn check(&self, version: &str, filename: &str) -> Result<(), ErrorResponse> {
+        let kind = ArtifactKind::of(filename);
+        if let Some(artifacts) = &self.policy.artifacts {
+            if !artifacts.contains(&kind) {
+                let allowed: Vec<&str> = artifacts.iter().map(|kind| kind.as_str()).collect();
+                return Err(ErrorResponse::Forbidden(format!(
+                    "Access tokens of class {} may only download {}, {} is not allowed",
+                    self.class,
+                    allowed.join(", "),
+                    filename
+                )));
+            }
+        }
+        let prerelease = Version::from_str(version).is_ok_and(|version| version.any_prerelease());
+        if prerelease && !self.policy.prereleases {
+            return Err(ErrorResponse::Forbidden(format!(
+                "Access tokens of class {} may not download prereleases, {} is version {}",
+                self.class, filename, version
+            )));
+        }
+        return Ok(());
+    }
+}
+
+/// Policy of the request's access token, `None` when downloads aren't restricted.
+pub struct CurrentPolicy(pub Option<ClientPolicy>);
+
+impl CurrentPolicy {
+    pub fn check(&self, version: &str, filename: &str) -> Result<(), ErrorResponse> {
+        return match &self.0 {
+            Some(policy) => policy.check(version, filename),
+            None => Ok(()),
+        };
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for CurrentPolicy {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        return Ok(CurrentPolicy(
+            parts.extensions.get::<ClientPolicy>().cloned(),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::refusal;
+
+    fn policy(policy: serde_json::Value) -> ClientPolicy {
+        return ClientPolicy {
+            class: "ci".to_string(),
+            policy: Arc::new(serde_json::from_value(policy).unwrap()),
+        };
+    }
+
+    #[test]
+    fn denies_other_kinds_of_files() {
+        let policy = policy(serde_json::json!({"artifacts": ["wheel"]}));
+        assert!(policy.check("1.0", "pkg-1.0-py3-none-any.whl").is_ok());
+        assert!(policy.check("1.0", "pkg-1.0-py3-none-any.whl.asc").is_ok());
+        assert_eq!(
+            refusal(policy.check("1.0", "pkg-1.0.tar.gz")),
+            "Access tokens of class ci may only download wheels, pkg-1.0.tar.gz is not allowed"
+        );
+    }
+
+    #[test]
+    fn denies_prereleases() {
+        let policy = policy(serde_json::json!({"prereleases": false}));
+        assert!(policy.check("1.0", "pkg-1.0.tar.gz").is_ok());
+        assert_eq!(
+            refusal(policy.check("1.1rc1", "pkg-1.1rc1.tar.gz")),
+            "Access tokens of class ci may not download prereleases, pkg-1.1rc1.tar.gz is version 1.1rc1"
+        );
+    }
+
+    #[test]
+    fn denies_other_operations() {
+        let policy = policy(serde_json::json!({"operations": ["read"]}));
+        assert!(policy.check_operation(Operation::Read).is_ok());
+        assert_eq!(
+            refusal(policy.check_operation(Operation::Publish)),
+            "Access tokens of class ci may not publish"
+        );
+        assert_eq!(
+            refusal(policy.check_operation(Operation::Admin)),
+            "Access tokens of class ci may not admin"
+        );
+    }
+
+    #[test]
+    fn allows_everything_by_default() {
+        let policy = policy(serde_json::json!({}));
+        assert!(policy.check("1.1rc1", "pkg-1.1rc1.tar.gz").is_ok());
+        assert!(policy.check("1.0", "pkg-1.0.tar.gz").is_ok());
+        assert!(policy.check_operation(Operation::Publish).is_ok());
+    }
+
+    #[test]
+    fn matches_access_tokens_exactly() {
+        let token = AccessToken::Plain("release-secret".to_string());
+        assert!(token.matches("release-secret"));
+        assert!(!token.matches("release-secreT"));
+        assert!(!token.matches("release-secret2"));
+        assert!(!token.matches("release"));
+        assert!(!token.matches(""));
+    }
+}
@@ -0,0 +1,385 @@
+//! Downloads into the artifact cache ahead of a rollout: `POST /api/prefetch`
+//! queues the files of a release, a worker downloads them one after another
+//! and `GET /api/prefetch/<id>` tells how far the job got. Jobs are kept in
+//! `CACHE_DIR/prefetch.json` and resumed after a restart, files cached before
+//! it aren't downloaded again.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use askama_axum::Response;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+
+use crate::assets;
+use crate::auth::GithubToken;
+use crate::config::Config;
+use crate::error::ErrorResponse;
+use crate::filenames;
+use crate::github::{Asset, GithubClient};
+use crate::namespace::{CurrentNamespace, Namespace};
+use crate::quarantine;
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+/// finished jobs kept for their status
+const FINISHED_JOBS: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FileState {
+    Queued,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct JobFile {
+    asset: Asset,
+    state: FileState,
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Job {
+    id: String,
+    namespace: String,
+    package: String,
+    version: String,
+    created_at: u64,
+    files: Vec<JobFile>,
+    /// of the client that queued the job, jobs resumed after a restart use the namespace's
+    #[serde(skip)]
+    token: Option<String>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        return self
+            .files
+            .iter()
+            .all(|file| file.state != FileState::Queued);
+    }
+
+    fn status(&self) -> Value {
+        let state = if !self.is_finished() {
+            "running"
+        } else if self
+            .files
+            .iter()
+            .any(|file| file.state == FileState::Failed)
+        {
+            "failed"
+        } else {
+            "done"
+        };
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                json!({
+                    "filename": file.asset.name,
+                    "size": file.asset.size,
+                    "state": file.state,
+                    "error": file.error,
+                })
+            })
+            .collect();
+        return json!({
+            "id": self.id,
+            "package": self.package,
+            "version": self.version,
+            "created_at": self.created_at,
+            "state": state,
+            "files": files,
+        });
+    }
+}
+
+pub struct Prefetcher {
+    path: Option<PathBuf>,
+    jobs: Mutex<BTreeMap<String, Job>>,
+    queued: Notify,
+}
+
+impl Prefetcher {
+    pub fn from_config(config: &Config) -> Self {
+        let path = config
+            .cache_dir
+            .as_ref()
+            .map(|cache_dir| PathBuf::from(cache_dir).join("prefetch.json"));
+        let jobs = match &path {
+            Some(path) => load(path),
+            None => BTreeMap::new(),
+        };
+        return Prefetcher {
+            path,
+            jobs: Mutex::new(jobs),
+            queued: Notify::new(),
+        };
+    }
+
+    fn save(&self, jobs: &BTreeMap<String, Job>) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = serde_json::to_vec(jobs).unwrap();
+        let temp_path = path.with_extension("json.tmp");
+        return fs::write(&temp_path, content)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|error| format!("Failed to save {}: {}", path.display(), error));
+    }
+
+    fn add(&self, job: Job) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.insert(job.id.clone(), job);
+        // job ids grow with time, so the oldest finished jobs go first
+        let finished: Vec<String> = jobs
+            .values()
+            .filter(|job| job.is_finished())
+            .map(|job| job.id.clone())
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_JOBS))
+        {
+            jobs.remove(id);
+        }
+        let saved = self.save(&jobs);
+        self.queued.notify_one();
+        return saved;
+    }
+
+    /// The first queued file of the oldest unfinished job.
+    fn next(&self) -> Option<(Job, usize)> {
+        let jobs = self.jobs.lock().unwrap();
+        return jobs.values().find_map(|job| {
+            let index = job
+                .files
+                .iter()
+                .position(|file| file.state == FileState::Queued)?;
+            Some((job.clone(), index))
+        });
+    }
+
+    fn finish(&self, job_id: &str, index: usize, result: Result<(), String>) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(file) = jobs
+            .get_mut(job_id)
+            .and_then(|job| job.files.get_mut(index))
+        {
+            (file.state, file.error) = match result {
+                Ok(()) => (FileState::Done, None),
+                Err(error) => (FileState::Failed, Some(error)),
+            };
+        }
+        return self.save(&jobs);
+    }
+}
+
+fn load(path: &PathBuf) -> BTreeMap<String, Job> {
+    let jobs = match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).map_err(io::Error::other),
+        Err(error) => Err(error),
+    };
+    return match jobs {
+        Ok(jobs) => jobs,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(error) => {
+            log!("Failed to load {}: {}", path.display(), error);
+            BTreeMap::new()
+        }
+    };
+}
+
+#[derive(Deserialize)]
+pub struct PrefetchRequest {
+    package: String,
+    version: String,
+    /// every file of the release when not given
+    files: Option<Vec<String>>,
+}
+
+/// Queues the download of files of a release into the cache.
+pub async fn create(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    GithubToken(token): GithubToken,
+    account: CurrentAccount,
+    Json(request): Json<PrefetchRequest>,
+) -> Result<Response, ErrorResponse> {
+    if !app_state.cache.is_enabled() {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    let client = GithubClient::new(&app_state.github, token.clone());
+    let (configured_name, repository) = namespace.find_repository(&request.package)?;
+    account.check_package(&namespace, configured_name)?;
+    let mut releases = repository.releases(&client).await?;
+    quarantine::retain_approved(&app_state, &namespace, configured_name, &mut releases);
+    app_state.hashes.fill(&mut releases);
+    app_state.links.hide_broken(&mut releases);
+    filenames::hide_malformed(configured_name, &mut releases);
+    let release = releases
+        .into_iter()
+        .find(|release| release.version() == request.version || release.tag_name == request.version)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let assets: Vec<Asset> = match &request.files {
+        Some(files) => files
+            .iter()
+            .map(|filename| {
+                release
+                    .assets
+                    .iter()
+                    .find(|asset| asset.name == *filename)
+                    .cloned()
+                    .ok_or(ErrorResponse::PageNotFound)
+            })
+            .collect::<Result<_, _>>()?,
+        None => release.assets.clone(),
+    };
+
+    let created_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let job = Job {
+        id: format!("{:x}", created_at.as_nanos()),
+        namespace: namespace.name.clone(),
+        package: configured_name.clone(),
+        version: release.version().to_string(),
+        created_at: created_at.as_secs(),
+        files: assets
+            .into_iter()
+            .map(|asset| JobFile {
+                asset,
+                state: FileState::Queued,
+                error: None,
+            })
+            .collect(),
+        token,
+    };
+    let status = job.status();
+    log!(
+        "Queued prefetch {} of {} {}",
+        job.id,
+        namespace.qualified_name(configured_name),
+        job.version
+    );
+    app_state
+        .prefetcher
+        .add(job)
+        .map_err(|error| ErrorResponse::ServerError(Some(error)))?;
+    return Ok((StatusCode::ACCEPTED, Json(status)).into_response());
+}
+
+/// Progress of a prefetch job of the namespace.
+pub async fn status(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((id,)): Path<(String,)>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let jobs = app_state.prefetcher.jobs.lock().unwrap();
+    let job = jobs
+        .get(&id)
+        .filter(|job| job.namespace == namespace.name)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    return Ok(Json(job.status()));
+}
+
+/// Puts `asset` into the cache, false when it was there already.
+pub async fn cache_asset(
+    app_state: &AppState,
+    namespace: &Arc<Namespace>,
+    package_name: &String,
+    asset: &Asset,
+    token: Option<String>,
+) -> Result<bool, String> {
+    let sha256 = asset
+        .sha256()
+        .ok_or("the file has no digest yet, so it can't be cached")?;
+    if app_state.cache.touch(sha256).await.is_some() {
+        return Ok(false);
+    }
+    let repository = namespace
+        .repos
+        .get(package_name)
+        .ok_or("the package is no longer configured")?;
+    let token = token.or(namespace.github_token.get());
+    let client = GithubClient::new(&app_state.github, token).background();
+    let is_large = app_state
+        .config
+        .parallel_fetch_threshold
+        .is_some_and(|threshold| asset.size >= threshold);
+    if is_large {
+        let connections = app_state.config.parallel_fetch_connections;
+        let fetched = app_state
+            .cache
+            .fetch_parallel(&client, repository, asset, connections)
+            .await;
+        // a single streamed download still verifies the file and reports a mismatch
+        if fetched.is_ok() {
+            return Ok(true);
+        }
+    }
+    let stream = repository
+        .asset(&client, asset)
+        .await
+        .map_err(|error| error.to_string())?
+        .stream;
+    let stream = assets::verify(namespace, package_name, asset, sha256, stream);
+    let mut stream = app_state.cache.store(sha256, stream).await;
+    while let Some(chunk) = stream.next().await {
+        chunk.map_err(|error| error.to_string())?;
+    }
+    return Ok(true);
+}
+
+/// Downloads the queued files, one at a time.
+pub fn spawn_prefetch_worker(app_state: Arc<AppState>) {
+    if !app_state.cache.is_enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            let Some((job, index)) = app_state.prefetcher.next() else {
+                app_state.prefetcher.queued.notified().await;
+                continue;
+            };
+            let asset = &job.files[index].asset;
+            let result = match app_state.namespaces.by_name(&job.namespace) {
+                Some(namespace) => cache_asset(
+                    &app_state,
+                    namespace,
+                    &job.package,
+                    asset,
+                    job.token.clone(),
+                )
+                .await
+                .map(|_| ()),
+                None => Err("the namespace is no longer configured".to_string()),
+            };
+            let mut errors = vec![];
+            if let Err(error) = &result {
+                errors.push(format!(
+                    "Failed to prefetch {} of {} {}: {}",
+                    asset.name, job.package, job.version, error
+                ));
+            }
+            errors.extend(app_state.prefetcher.finish(&job.id, index, result).err());
+            for message in &errors {
+                log!("{}", message);
+                app_state
+                    .reporter
+                    .report(Report::task("prefetch", message.clone()));
+            }
+            app_state.health.record("prefetch", errors);
+        }
+    });
+}
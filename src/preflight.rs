@@ -0,0 +1,178 @@
+//! Checks run before serving, so a misconfiguration is reported all at once
+//! instead of as a panic from wherever it is first noticed.
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use crate::config::{Config, ReposConfig};
+use crate::error::ErrorResponse;
+use crate::github::{GithubClient, GithubShared};
+use crate::notify::Notifier;
+use crate::redact::log;
+use crate::restart;
+use crate::secrets::SecretProvider;
+use crate::templates;
+
+/// How long GitHub gets to confirm a token before it is reported unchecked.
+pub const TOKEN_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Status {
+    Ok,
+    /// doesn't prevent serving
+    Warning(String),
+    Failed(String),
+}
+
+struct Check {
+    name: String,
+    status: Status,
+}
+
+/// Runs every check and prints the report. Exits the process when a check
+/// failed, otherwise returns the bound listener.
+pub async fn run(config: &Config) -> TcpListener {
+    let mut checks = vec![];
+
+    let address = format!("0.0.0.0:{}", config.port);
+    let listener = match restart::bind(config) {
+        Ok(listener) => Some(listener),
+        Err(error) => {
+            let hint = match error.kind() {
+                std::io::ErrorKind::AddrInUse if config.reuse_port => {
+                    ", another process listens on the port without REUSE_PORT"
+                }
+                std::io::ErrorKind::AddrInUse => ", another process listens on the port",
+                std::io::ErrorKind::PermissionDenied => {
+                    ", ports below 1024 need root or CAP_NET_BIND_SERVICE"
+                }
+                _ => "",
+            };
+            checks.push(failed(
+                format!("listen on {}", address),
+                format!("{}{}", error, hint),
+            ));
+            None
+        }
+    };
+    if listener.is_some() {
+        checks.push(ok(format!("listen on {}", address)));
+    }
+
+    let repos_config = ReposConfig::load(&config.repos_config_path, &config.repos_config_overlays);
+    let mut files = vec![config.repos_config_path.clone()];
+    files.extend(config.repos_config_overlays.iter().cloned());
+    checks.push(Check {
+        name: format!("repos config {}", files.join(" + ")),
+        status: match &repos_config {
+            Ok(_) => Status::Ok,
+            Err(error) => Status::Failed(error.clone()),
+        },
+    });
+    if let Some(path) = &config.notifications_config_path {
+        checks.push(Check {
+            name: format!("notifications config {}", path),
+            status: match Notifier::load(path) {
+                Ok(_) => Status::Ok,
+                Err(error) => Status::Failed(error),
+            },
+        });
+    }
+    if let Some(path) = &config.template_context_path {
+        checks.push(Check {
+            name: format!("template context {}", path),
+            status: match templates::load_context(path) {
+                Ok(_) => Status::Ok,
+                Err(error) => Status::Failed(error),
+            },
+        });
+    }
+    for (name, dir) in [
+        ("templates directory", &config.templates_dir),
+        ("static directory", &config.static_dir),
+    ] {
+        if let Some(dir) = dir {
+            checks.push(match Path::new(dir).is_dir() {
+                true => ok(format!("{} {}", name, dir)),
+                false => failed(format!("{} {}", name, dir), "not a directory".to_string()),
+            });
+        }
+    }
+    checks.push(writable("snapshots directory", &config.snapshots_dir));
+    if let Some(cache_dir) = &config.cache_dir {
+        checks.push(writable("cache directory", cache_dir));
+    }
+
+    let mut tokens = vec![("GITHUB_TOKEN".to_string(), config.github_token.clone())];
+    if let Ok(ReposConfig::Namespaced(namespaced)) = &repos_config {
+        for (name, namespace) in &namespaced.namespaces {
+            if namespace.github_token.is_some() {
+                let token = namespace.github_token.clone();
+                tokens.push((format!("GitHub token of namespace {}", name), token));
+            }
+        }
+    }
+    let secrets = SecretProvider::from_config(config);
+    let github = GithubShared::from_config(config);
+    for (name, token) in tokens {
+        let Some(token) = token else {
+            continue;
+        };
+        let token = match secrets.resolve(&token).await {
+            Ok(token) => token,
+            Err(error) => {
+                checks.push(failed(name, format!("can't fetch the secret: {}", error)));
+                continue;
+            }
+        };
+        let client = GithubClient::new(&github, Some(token));
+        let status = match tokio::time::timeout(TOKEN_CHECK_TIMEOUT, client.rate_limit()).await {
+            Ok(Ok(_)) => Status::Ok,
+            Ok(Err(ErrorResponse::Unauthorized)) => Status::Failed("GitHub rejects it".to_string()),
+            _ => Status::Warning("can't reach GitHub to check it".to_string()),
+        };
+        checks.push(Check { name, status });
+    }
+
+    log!("Preflight checks:");
+    for check in &checks {
+        match &check.status {
+            Status::Ok => log!("  ok       {}", check.name),
+            Status::Warning(reason) => log!("  warning  {}: {}", check.name, reason),
+            Status::Failed(reason) => log!("  FAILED   {}: {}", check.name, reason),
+        }
+    }
+    let has_failed = checks
+        .iter()
+        .any(|check| matches!(check.status, Status::Failed(_)));
+    match listener {
+        Some(listener) if !has_failed => return listener,
+        _ => std::process::exit(1),
+    }
+}
+
+fn ok(name: String) -> Check {
+    return Check {
+        name,
+        status: Status::Ok,
+    };
+}
+
+fn failed(name: String, reason: String) -> Check {
+    return Check {
+        name,
+        status: Status::Failed(reason),
+    };
+}
+
+/// Whether files can be created in `dir`, which is created when missing.
+fn writable(name: &str, dir: &str) -> Check {
+    let probe = Path::new(dir).join(".pigi-preflight");
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    return match written {
+        Ok(()) => ok(format!("{} {}", name, dir)),
+        Err(error) => failed(format!("{} {}", name, dir), error.to_string()),
+    };
+}
@@ -0,0 +1,74 @@
+//! Whether a just published version is served yet, at
+//! `/api/packages/<package>/<version>/status`, for pipelines that publish and
+//! install right away. Every poll syncs the package on the spot instead of
+//! waiting for `SYNC_INTERVAL`, so the metadata, the cached GitHub responses
+//! and the serial already include the release once it reports `served`.
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::response::Json;
+use serde_json::{json, Value};
+
+use crate::auth::GithubToken;
+use crate::error::ErrorResponse;
+use crate::filenames;
+use crate::github::GithubClient;
+use crate::metadata;
+use crate::namespace::CurrentNamespace;
+use crate::quarantine;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+pub async fn status(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((package_name, version)): Path<(String, String)>,
+    GithubToken(token): GithubToken,
+    account: CurrentAccount,
+) -> Result<Json<Value>, ErrorResponse> {
+    let (configured_name, repository) = namespace.find_repository(&package_name)?;
+    account.check_package(&namespace, configured_name)?;
+
+    // followers would announce releases the leader announces again
+    let notify = app_state.leader.is_leader().await;
+    let sync_client =
+        GithubClient::new(&app_state.github, namespace.github_token.get()).background();
+    // failures are logged and reported by the sync, the package page doesn't depend on it
+    metadata::sync_package(
+        &app_state,
+        &namespace,
+        &sync_client,
+        configured_name,
+        repository,
+        notify,
+    )
+    .await;
+
+    // what the package page is built from, with the client's token
+    let client = GithubClient::new(&app_state.github, token);
+    let mut releases = repository.releases(&client).await?;
+    let published = releases.iter().any(|release| release.version() == version);
+    quarantine::retain_approved(&app_state, &namespace, configured_name, &mut releases);
+    app_state.hashes.fill(&mut releases);
+    app_state.links.hide_broken(&mut releases);
+    filenames::hide_malformed(configured_name, &mut releases);
+    let files: Vec<&String> = releases
+        .iter()
+        .filter(|release| release.version() == version)
+        .flat_map(|release| release.assets.iter().map(|asset| &asset.name))
+        .collect();
+    let metadata = namespace.metadata.get(configured_name).unwrap_or_default();
+    let quarantined = metadata
+        .quarantined
+        .iter()
+        .any(|release| release.version() == version);
+    return Ok(Json(json!({
+        "package": namespace.qualified_name(configured_name),
+        "version": version,
+        "published": published,
+        "quarantined": quarantined,
+        "served": !files.is_empty(),
+        "files": files,
+        "serial": metadata.serial,
+    })));
+}
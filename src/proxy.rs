@@ -0,0 +1,425 @@
+//! Projects of another simple index served from pigi, configured as
+//! repositories with `"provider": "proxy"` and the `index` to proxy, like an
+//! internal devpi or a cloud artifact registry. Releases are read from the
+//! project's page of the index (PEP 691 JSON, or PEP 503 HTML), files are
+//! downloaded from it when first asked for and kept in the artifact cache,
+//! so pigi can be the one index clients are configured with. The index's
+//! credentials are sent with every request to its host, clients never see them.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use futures_util::StreamExt;
+use pep440_rs::Version;
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::Repository;
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{Asset, AssetStream, ByteStream, Release};
+use crate::names;
+use crate::publish::parse_filename;
+use crate::redact::log;
+use crate::secrets::Secret;
+
+const SIMPLE_JSON: &str = "application/vnd.pypi.simple.v1+json";
+/// Characters of an index error response kept in the error.
+const MAX_SNIPPET: usize = 500;
+
+/// The index a project is proxied from.
+#[derive(Clone)]
+pub struct ProxyIndex {
+    /// base url of the simple index, project pages are under it
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<Arc<Secret>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyIndexFields {
+    url: String,
+    username: Option<String>,
+    /// literal, or a `secret:` reference
+    password: Option<String>,
+}
+
+impl From<ProxyIndexFields> for ProxyIndex {
+    fn from(fields: ProxyIndexFields) -> Self {
+        return ProxyIndex {
+            url: format!("{}/", fields.url.trim_end_matches('/')),
+            username: fields.username,
+            password: fields
+                .password
+                .map(|password| Arc::new(Secret::new(Some(password)))),
+        };
+    }
+}
+
+impl ProxyIndex {
+    /// Page of `project` on the index.
+    pub fn project_url(&self, project: &str) -> String {
+        return format!("{}{}/", self.url, names::normalize(project));
+    }
+
+    /// Request to `url`, with the index's credentials when it is on the index's host.
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        url: &str,
+    ) -> reqwest::RequestBuilder {
+        let request = client.request(method, url);
+        if host(url) != host(&self.url) || (self.username.is_none() && self.password.is_none()) {
+            return request;
+        }
+        let password = self.password.as_ref().and_then(|password| password.get());
+        return request.basic_auth(self.username.clone().unwrap_or_default(), password);
+    }
+}
+
+fn host(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    return Some(format!(
+        "{}:{:?}",
+        url.host_str()?,
+        url.port_or_known_default()
+    ));
+}
+
+/// Releases read from a project page, with the page's `ETag`.
+type Page = (Option<String>, Vec<Release>);
+
+/// What requests to proxied indexes share.
+pub struct ProxyState {
+    client: reqwest::Client,
+    /// last page of every project by its url, revalidated instead of fetched
+    /// again and served while the index is failing
+    pages: Mutex<HashMap<String, Page>>,
+}
+
+impl Default for ProxyState {
+    fn default() -> Self {
+        return ProxyState {
+            client: reqwest::Client::builder()
+                .user_agent("pigi")
+                .build()
+                .unwrap(),
+            pages: Mutex::default(),
+        };
+    }
+}
+
+fn index_of(repository: &Repository) -> Result<&ProxyIndex, ErrorResponse> {
+    return repository.index.as_ref().ok_or(ErrorResponse::Failure(
+        ErrorCode::Config,
+        format!("{} has no index to proxy", repository.name),
+    ));
+}
+
+/// `response` when successful, otherwise an error with the start of what the index said.
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, ErrorResponse> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let url = response.url().to_string();
+    let body = response.text().await.unwrap_or_default();
+    let snippet: String = body.chars().take(MAX_SNIPPET).collect();
+    return Err(ErrorResponse::Failure(
+        ErrorCode::from_status(status),
+        format!("{} responded {}: {}", url, status, snippet),
+    ));
+}
+
+#[derive(Deserialize)]
+struct JsonProject {
+    files: Vec<JsonFile>,
+}
+
+#[derive(Deserialize)]
+struct JsonFile {
+    filename: String,
+    url: String,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+    size: Option<u64>,
+    #[serde(rename = "upload-time")]
+    upload_time: Option<String>,
+    /// `false`, `true` or the reason
+    #[serde(default)]
+    yanked: serde_json::Value,
+}
+
+/// A file listed on a project page.
+struct Link {
+    filename: String,
+    url: String,
+    sha256: Option<String>,
+    size: u64,
+    uploaded_at: String,
+}
+
+fn parse_json(base: &reqwest::Url, body: &str) -> Result<Vec<Link>, String> {
+    let project: JsonProject = serde_json::from_str(body).map_err(|error| error.to_string())?;
+    return Ok(project
+        .files
+        .into_iter()
+        .filter(|file| {
+            matches!(
+                file.yanked,
+                serde_json::Value::Bool(false) | serde_json::Value::Null
+            )
+        })
+        .map(|mut file| Link {
+            url: base
+                .join(&file.url)
+                .map(|url| url.to_string())
+                .unwrap_or(file.url),
+            filename: file.filename,
+            sha256: file.hashes.remove("sha256"),
+            size: file.size.unwrap_or(0),
+            uploaded_at: file.upload_time.unwrap_or_default(),
+        })
+        .collect());
+}
+
+fn parse_html(base: &reqwest::Url, body: &str) -> Vec<Link> {
+    static ANCHOR: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#"(?is)<a\s([^>]*)>([^<]*)</a>"#).unwrap());
+    static HREF: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#"(?i)href\s*=\s*["']([^"']*)["']"#).unwrap());
+    return ANCHOR
+        .captures_iter(body)
+        .filter(|anchor| !anchor[1].to_ascii_lowercase().contains("data-yanked"))
+        .filter_map(|anchor| {
+            let href = HREF.captures(&anchor[1])?[1].replace("&amp;", "&");
+            let url = base.join(&href).ok()?;
+            let sha256 = url
+                .fragment()
+                .and_then(|fragment| fragment.strip_prefix("sha256="))
+                .map(str::to_string);
+            let mut download_url = url.clone();
+            download_url.set_fragment(None);
+            let filename = match anchor[2].trim() {
+                "" => url.path_segments()?.next_back()?.to_string(),
+                text => text.to_string(),
+            };
+            return Some(Link {
+                filename,
+                url: download_url.to_string(),
+                sha256,
+                size: 0,
+                uploaded_at: String::new(),
+            });
+        })
+        .collect();
+}
+
+fn asset(link: Link) -> Asset {
+    // files of an index have no ids, the start of their hash identifies them
+    let id_source = link
+        .sha256
+        .clone()
+        .unwrap_or_else(|| hex::encode(Sha256::digest(&link.url)));
+    return Asset {
+        id: u64::from_str_radix(id_source.get(..16).unwrap_or_default(), 16).unwrap_or_default(),
+        name: link.filename,
+        size: link.size,
+        created_at: link.uploaded_at.clone(),
+        updated_at: link.uploaded_at,
+        digest: link.sha256.map(|sha256| format!("sha256:{}", sha256)),
+        blake2b_256: None,
+        download_url: Some(link.url),
+    };
+}
+
+/// Files grouped into a release per version, newest first; files that
+/// aren't wheels or sdists of the project are left out.
+fn releases(project: &str, page_url: &str, links: Vec<Link>) -> Vec<Release> {
+    let mut by_version: HashMap<String, Vec<Asset>> = HashMap::new();
+    for link in links {
+        let Some((name, version)) = parse_filename(&link.filename) else {
+            continue;
+        };
+        if names::normalize(name) != names::normalize(project) {
+            continue;
+        }
+        by_version
+            .entry(version.to_string())
+            .or_default()
+            .push(asset(link));
+    }
+    let mut releases: Vec<Release> = by_version
+        .into_iter()
+        .map(|(version, assets)| {
+            let published_at = assets
+                .iter()
+                .map(|asset| asset.created_at.clone())
+                .filter(|created_at| !created_at.is_empty())
+                .min();
+            Release {
+                id: 0,
+                html_url: page_url.to_string(),
+                name: Some(version.clone()),
+                prerelease: Version::from_str(&version)
+                    .is_ok_and(|version| version.any_prerelease()),
+                tag_name: version,
+                published_at,
+                draft: false,
+                assets,
+                version: None,
+            }
+        })
+        .collect();
+    releases.sort_by_cached_key(|release| Reverse(Version::from_str(release.version()).ok()));
+    return releases;
+}
+
+/// Releases of the project on its index, yanked files left out. The page is
+/// revalidated with its `ETag`, and the last one is used while the index fails.
+pub async fn project(
+    proxy: &ProxyState,
+    repository: &Repository,
+) -> Result<Vec<Release>, ErrorResponse> {
+    let index = index_of(repository)?;
+    let url = index.project_url(&repository.name);
+    let known = proxy.pages.lock().unwrap().get(&url).cloned();
+    let mut request = index
+        .request(&proxy.client, reqwest::Method::GET, &url)
+        .header(
+            reqwest::header::ACCEPT,
+            format!("{}, text/html;q=0.1", SIMPLE_JSON),
+        );
+    if let Some(etag) = known.as_ref().and_then(|(etag, _)| etag.as_ref()) {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    let response = match request.send().await {
+        Ok(response) if response.status() == reqwest::StatusCode::NOT_MODIFIED => {
+            if let Some((_, releases)) = known {
+                return Ok(releases);
+            }
+            response
+        }
+        Ok(response) if response.status().is_server_error() && known.is_some() => {
+            log!(
+                "{} responded {}, serving its last page",
+                url,
+                response.status()
+            );
+            return Ok(known.unwrap().1);
+        }
+        Err(error) if known.is_some() => {
+            log!("Failed to fetch {}, serving its last page: {}", url, error);
+            return Ok(known.unwrap().1);
+        }
+        response => response?,
+    };
+    let response = check(response).await?;
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let is_json = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("json"));
+    let base = response.url().clone();
+    let body = response.text().await?;
+    let links = match is_json {
+        true => parse_json(&base, &body).map_err(|error| {
+            ErrorResponse::Failure(
+                ErrorCode::Upstream5xx,
+                format!("Can't read {}: {}", url, error),
+            )
+        })?,
+        false => parse_html(&base, &body),
+    };
+    let releases = releases(&repository.name, &url, links);
+    proxy
+        .pages
+        .lock()
+        .unwrap()
+        .insert(url, (etag, releases.clone()));
+    return Ok(releases);
+}
+
+fn download_url(asset: &Asset) -> Result<&String, ErrorResponse> {
+    return asset
+        .download_url
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound);
+}
+
+pub async fn download(
+    proxy: &ProxyState,
+    repository: &Repository,
+    asset: &Asset,
+) -> Result<AssetStream, ErrorResponse> {
+    let request =
+        index_of(repository)?.request(&proxy.client, reqwest::Method::GET, download_url(asset)?);
+    let response = check(request.send().await?).await?;
+    return Ok(AssetStream {
+        content_length: response.content_length(),
+        stream: Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(io::Error::other)),
+        ),
+    });
+}
+
+/// Whether a file can still be downloaded, asked with a `HEAD` request.
+pub async fn exists(
+    proxy: &ProxyState,
+    repository: &Repository,
+    asset: &Asset,
+) -> Result<bool, ErrorResponse> {
+    let request =
+        index_of(repository)?.request(&proxy.client, reqwest::Method::HEAD, download_url(asset)?);
+    let response = request.send().await?;
+    if matches!(
+        response.status(),
+        reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+    ) {
+        return Ok(false);
+    }
+    check(response).await?;
+    return Ok(true);
+}
+
+/// Bytes `start..end` of a file.
+pub async fn download_range(
+    proxy: &ProxyState,
+    repository: &Repository,
+    asset: &Asset,
+    range: Range<u64>,
+) -> Result<ByteStream<io::Error>, ErrorResponse> {
+    let request = index_of(repository)?
+        .request(&proxy.client, reqwest::Method::GET, download_url(asset)?)
+        .header(
+            reqwest::header::RANGE,
+            format!("bytes={}-{}", range.start, range.end - 1),
+        );
+    let response = request.send().await?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(ErrorResponse::Failure(
+            ErrorCode::from_status(response.status()),
+            format!(
+                "the index doesn't support range requests, status {}",
+                response.status()
+            ),
+        ));
+    }
+    return Ok(Box::pin(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(io::Error::other)),
+    ));
+}
@@ -0,0 +1,431 @@
+//! `pigi publish`: uploads distributions as assets of the GitHub release of
+//! their version, which is how pigi serves them afterwards. Files are
+//! validated before anything is sent to GitHub: their names carry a PEP 440
+//! version, the metadata inside agrees with the name, and their sha256 is the
+//! one of the `--checksums` file. PEP 740 attestations (`.publish.attestation`)
+//! are published next to their distribution, once checked to be about it.
+//! Published files are never replaced.
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::Engine;
+use pep440_rs::Version;
+use serde_json::Value;
+
+use crate::cache::file_sha256;
+use crate::catalog;
+use crate::companions;
+use crate::config::{Provider, Repository};
+use crate::github::{GithubClient, Release};
+use crate::names;
+use crate::namespace::Namespace;
+use crate::quotas;
+use crate::{fail, AppState};
+
+pub struct Publish {
+    pub files: Vec<PathBuf>,
+    /// configured package name, taken from the file names when not given
+    pub package: Option<String>,
+    pub namespace: String,
+    /// prepended to the version to name the release tag
+    pub tag_prefix: String,
+    /// skip files already on the release instead of failing
+    pub skip_existing: bool,
+    /// `sha256sum` output the files have to match
+    pub checksums: Option<PathBuf>,
+    /// upload to a draft release, hidden until promoted
+    pub draft: bool,
+}
+
+/// Project name and version of a wheel or sdist file name.
+pub fn parse_filename(filename: &str) -> Option<(&str, &str)> {
+    if let Some(stem) = filename.strip_suffix(".whl") {
+        let mut parts = stem.split('-');
+        return Some((parts.next()?, parts.next()?));
+    }
+    let stem = [".tar.gz", ".zip"]
+        .iter()
+        .find_map(|extension| filename.strip_suffix(extension))?;
+    return stem.rsplit_once('-');
+}
+
+/// Digests by file name of `sha256sum` output.
+fn read_checksums(path: &Path) -> Result<HashMap<String, String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    let mut checksums = HashMap::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((digest, filename)) = line.split_once(char::is_whitespace) else {
+            return Err(format!("Can't parse {}: {}", path.display(), line));
+        };
+        // `*` marks files hashed in binary mode
+        let filename = filename.trim_start().trim_start_matches('*');
+        let filename = Path::new(filename).file_name().unwrap_or_default();
+        checksums.insert(
+            filename.to_string_lossy().to_string(),
+            digest.to_ascii_lowercase(),
+        );
+    }
+    return Ok(checksums);
+}
+
+/// `PKG-INFO` at the top of a zip sdist.
+fn pkg_info(sdist: Vec<u8>) -> Result<String, String> {
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(sdist)).map_err(|error| error.to_string())?;
+    let name = archive
+        .file_names()
+        .filter_map(Result::ok)
+        .find(|name| {
+            name.split_once('/')
+                .is_some_and(|(_, file)| file == "PKG-INFO")
+        })
+        .map(|name| name.to_string())
+        .ok_or("the sdist has no PKG-INFO")?;
+    let mut content = String::new();
+    archive
+        .by_name(&name)
+        .map_err(|error| error.to_string())?
+        .read_to_string(&mut content)
+        .map_err(|error| error.to_string())?;
+    return Ok(content);
+}
+
+/// Core metadata of a wheel or zip sdist, `.tar.gz` sdists aren't read.
+async fn core_metadata(path: &Path, filename: &str) -> Result<Option<String>, String> {
+    if !filename.ends_with(".whl") && !filename.ends_with(".zip") {
+        return Ok(None);
+    }
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    let metadata = match filename.ends_with(".whl") {
+        true => catalog::dist_info(content, &["METADATA"]).map(|mut files| files.remove(0)),
+        false => pkg_info(content),
+    };
+    return metadata
+        .map(Some)
+        .map_err(|error| format!("Can't read the metadata of {}: {}", filename, error));
+}
+
+/// Project name and version of a file to publish, refused when the version
+/// isn't PEP 440, the metadata declares another name or version, or its
+/// sha256 isn't the one in `checksums`.
+pub async fn validate(
+    path: &Path,
+    checksums: Option<&HashMap<String, String>>,
+) -> Result<(String, String), String> {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let Some((name, version)) = parse_filename(&filename) else {
+        return Err(format!("{} isn't a wheel or sdist", path.display()));
+    };
+    let parsed_version = Version::from_str(version)
+        .map_err(|error| format!("{} has an invalid version {}: {}", filename, version, error))?;
+    if let Some(checksums) = checksums {
+        let Some(expected) = checksums.get(filename.as_ref()) else {
+            return Err(format!("{} isn't listed in the checksums", filename));
+        };
+        let sha256 = file_sha256(path)
+            .await
+            .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+        if &sha256 != expected {
+            return Err(format!(
+                "{} has sha256 {}, the checksums say {}",
+                filename, sha256, expected
+            ));
+        }
+    }
+    if let Some(metadata) = core_metadata(path, &filename).await? {
+        let headers = catalog::metadata_headers(&metadata);
+        let declared_name = catalog::header_values(&headers, "name").next();
+        if declared_name.map(|declared| names::normalize(declared)) != Some(names::normalize(name))
+        {
+            return Err(format!(
+                "{} declares the name {} in its metadata",
+                filename,
+                declared_name.map_or("<none>", String::as_str)
+            ));
+        }
+        let declared_version = catalog::header_values(&headers, "version").next();
+        let declared = declared_version.and_then(|declared| Version::from_str(declared).ok());
+        if declared.as_ref() != Some(&parsed_version) {
+            return Err(format!(
+                "{} declares the version {} in its metadata",
+                filename,
+                declared_version.map_or("<none>", String::as_str)
+            ));
+        }
+    }
+    return Ok((name.to_string(), version.to_string()));
+}
+
+/// Checks a PEP 740 attestation is about its distribution, one of
+/// `distributions`, as it is now. Its signature is left to the verifiers of
+/// the index's clients, to whom it is served as is.
+async fn validate_attestation(path: &Path, distributions: &[&PathBuf]) -> Result<(), String> {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let subject = filename.trim_end_matches(companions::PEP740_SUFFIX);
+    let Some(distribution) = distributions
+        .iter()
+        .find(|distribution| distribution.file_name().unwrap_or_default() == subject)
+    else {
+        return Err(format!("{} is published without {}", filename, subject));
+    };
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    let sha256 = file_sha256(distribution)
+        .await
+        .map_err(|error| format!("Can't read {}: {}", distribution.display(), error))?;
+    return check_attestation(&filename, &content, &sha256);
+}
+
+/// Checks the PEP 740 attestation `filename`, of `content`, attests its
+/// distribution with the digest `sha256`.
+pub fn check_attestation(filename: &str, content: &[u8], sha256: &str) -> Result<(), String> {
+    let subject = filename.trim_end_matches(companions::PEP740_SUFFIX);
+    let attestation: Value = serde_json::from_slice(content)
+        .map_err(|error| format!("{} isn't valid JSON: {}", filename, error))?;
+    if attestation["version"] != 1 {
+        return Err(format!("{} isn't a version 1 attestation", filename));
+    }
+    let statement = attestation["envelope"]["statement"]
+        .as_str()
+        .and_then(|statement| {
+            base64::engine::general_purpose::STANDARD
+                .decode(statement)
+                .ok()
+        })
+        .and_then(|statement| serde_json::from_slice::<Value>(&statement).ok())
+        .ok_or(format!("{} has no readable statement", filename))?;
+    let covered = statement["subject"].as_array().is_some_and(|subjects| {
+        subjects.iter().any(|statement_subject| {
+            statement_subject["name"] == subject && statement_subject["digest"]["sha256"] == sha256
+        })
+    });
+    if !covered {
+        return Err(format!(
+            "{} doesn't attest {} with sha256 {}",
+            filename, subject, sha256
+        ));
+    }
+    return Ok(());
+}
+
+/// Creates the release of the files' version when missing and uploads the
+/// files to it. Files already on the release fail the upload, or are skipped
+/// with `--skip-existing`, files over the package's quotas fail it.
+pub async fn run(app_state: &AppState, publish: Publish) {
+    let checksums = publish
+        .checksums
+        .as_ref()
+        .map(|path| read_checksums(path).unwrap_or_else(|error| fail(error)));
+    let (attestations, distributions): (Vec<&PathBuf>, Vec<&PathBuf>) = publish
+        .files
+        .iter()
+        .partition(|path| path.to_string_lossy().ends_with(companions::PEP740_SUFFIX));
+    let mut names = BTreeSet::new();
+    let mut versions = BTreeSet::new();
+    for path in &distributions {
+        let (name, version) = validate(path, checksums.as_ref())
+            .await
+            .unwrap_or_else(|error| fail(error));
+        names.insert(names::normalize(&name));
+        versions.insert(version);
+    }
+    for path in attestations {
+        validate_attestation(path, &distributions)
+            .await
+            .unwrap_or_else(|error| fail(error));
+    }
+    let version = match versions.len() {
+        0 => fail("No files to publish".to_string()),
+        1 => versions.pop_first().unwrap(),
+        _ => fail(format!(
+            "The files are of several versions: {}",
+            versions.into_iter().collect::<Vec<_>>().join(", ")
+        )),
+    };
+    let package_name = match (&publish.package, names.len()) {
+        (Some(package), _) => package.clone(),
+        (None, 1) => names.pop_first().unwrap(),
+        (None, _) => fail(format!(
+            "The files are of several packages: {}, pick one with --package",
+            names.into_iter().collect::<Vec<_>>().join(", ")
+        )),
+    };
+
+    let (namespace, configured_name, repository, client) =
+        target(app_state, &publish.namespace, &package_name);
+    let tag = format!("{}{}", publish.tag_prefix, version);
+    quotas::check_upload(
+        app_state,
+        &client,
+        namespace,
+        configured_name,
+        repository,
+        &tag,
+        &publish.files,
+    )
+    .await
+    .unwrap_or_else(|error| fail(error));
+    let release = upload(
+        &client,
+        repository,
+        &tag,
+        &version,
+        &publish.files,
+        publish.skip_existing,
+        publish.draft,
+    )
+    .await
+    .unwrap_or_else(|error| fail(error));
+    if publish.draft {
+        println!(
+            "Staged {} {} in draft {}, promote it with POST /debug/promote",
+            namespace.qualified_name(configured_name),
+            version,
+            release.html_url
+        );
+        return;
+    }
+    println!(
+        "Published {} {} to {}",
+        namespace.qualified_name(configured_name),
+        version,
+        release.html_url
+    );
+}
+
+/// The configured package files of `package_name` are published to, with a
+/// client using the namespace's token.
+pub fn target<'a>(
+    app_state: &'a AppState,
+    namespace: &str,
+    package_name: &str,
+) -> (&'a Arc<Namespace>, &'a String, &'a Repository, GithubClient) {
+    let Some(namespace) = app_state.namespaces.by_name(namespace) else {
+        fail(format!("No namespace {} is configured", namespace));
+    };
+    let Ok((configured_name, repository)) = namespace.find_repository(package_name) else {
+        fail(format!(
+            "{} is not configured",
+            namespace.qualified_name(package_name)
+        ));
+    };
+    if matches!(repository.provider, Provider::Pypi | Provider::Proxy) {
+        fail(format!(
+            "{} is mirrored from {}, it can't be published to",
+            namespace.qualified_name(configured_name),
+            repository.url(&app_state.github.storage)
+        ));
+    }
+    if matches!(repository.provider, Provider::Local | Provider::S3) {
+        fail(format!(
+            "{} is kept in {}, publish its files with the upload endpoint",
+            namespace.qualified_name(configured_name),
+            repository.url(&app_state.github.storage)
+        ));
+    }
+    let Some(token) = namespace.github_token.get() else {
+        fail("Publishing needs a GitHub token, set GITHUB_TOKEN".to_string());
+    };
+    return (
+        namespace,
+        configured_name,
+        repository,
+        GithubClient::new(&app_state.github, Some(token)),
+    );
+}
+
+/// Creates the release `tag` of `version` when missing and uploads `files`
+/// to it, the release as it was before the upload is returned. Files already
+/// on the release are skipped with `skip_existing`, otherwise nothing is
+/// uploaded. A `draft` release is staged, a published one of the tag is left
+/// alone.
+pub async fn upload(
+    client: &GithubClient,
+    repository: &Repository,
+    tag: &String,
+    version: &str,
+    files: &[PathBuf],
+    skip_existing: bool,
+    draft: bool,
+) -> Result<Release, String> {
+    let (owner, repo) = (&repository.owner, &repository.name);
+    let published = client
+        .release_by_tag(owner, repo, tag)
+        .await
+        .map_err(|error| format!("Can't look up release {}: {}", tag, error))?;
+    let existing = match (draft, published) {
+        (false, published) => published,
+        (true, Some(_)) => return Err(format!("{} is already published", tag)),
+        // drafts have no tag yet, they are only listed
+        (true, None) => client
+            .releases(owner, repo)
+            .await
+            .map_err(|error| format!("Can't look up release {}: {}", tag, error))?
+            .into_iter()
+            .find(|release| release.draft && release.tag_name == *tag),
+    };
+    let release = match existing {
+        Some(release) => release,
+        None => {
+            let prerelease = Version::from_str(version)
+                .map(|version| version.any_prerelease())
+                .unwrap_or(false);
+            let kind = if draft { "draft release" } else { "release" };
+            println!("Creating {} {} of {}/{}", kind, tag, owner, repo);
+            client
+                .create_release(owner, repo, tag, prerelease, draft)
+                .await
+                .map_err(|error| format!("Can't create release {}: {}", tag, error))?
+        }
+    };
+
+    let is_published = |path: &PathBuf| {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        release.assets.iter().any(|asset| asset.name == filename)
+    };
+    let existing: Vec<String> = files
+        .iter()
+        .filter(|path| is_published(path))
+        .map(|path| {
+            path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    if !existing.is_empty() && !skip_existing {
+        return Err(format!(
+            "{} already published to {}, pass --skip-existing to skip",
+            existing.join(", "),
+            tag
+        ));
+    }
+    for path in files {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        if is_published(path) {
+            println!("{} is already published", filename);
+            continue;
+        }
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+        let length = file
+            .metadata()
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        client
+            .upload_asset(owner, repo, release.id, &filename, file.into(), length)
+            .await
+            .map_err(|error| format!("Can't upload {}: {}", filename, error))?;
+        println!("Uploaded {} ({} bytes)", filename, length);
+    }
+    return Ok(release);
+}
@@ -0,0 +1,253 @@
+//! Compatibility with the legacy PyPI JSON API and the `list_packages` XML-RPC call,
+//! for tooling that predates the simple repository API.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use askama_axum::Response;
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Json};
+use serde_json::{json, Value};
+
+use crate::companions::{self, CompanionKind};
+use crate::error::ErrorResponse;
+use crate::github::{latest_release, Asset, Release};
+use crate::metadata::PackageMetadata;
+use crate::namespace::{CurrentNamespace, Namespace};
+use crate::simple_api;
+use crate::storage::StorageSettings;
+use crate::AppState;
+
+fn package_metadata(
+    package_name: &String,
+    namespace: &Namespace,
+) -> Result<PackageMetadata, ErrorResponse> {
+    namespace.get_repository(package_name)?;
+    return namespace
+        .metadata
+        .get(package_name)
+        .ok_or(ErrorResponse::PageNotFound);
+}
+
+pub fn package_type(filename: &str) -> &'static str {
+    if filename.ends_with(".whl") {
+        return "bdist_wheel";
+    }
+    if filename.ends_with(".tar.gz") || filename.ends_with(".zip") {
+        return "sdist";
+    }
+    return "unknown";
+}
+
+fn asset_url(base_url: &str, package_name: &str, release: &Release, asset: &Asset) -> String {
+    return format!(
+        "{}/simple/{}/{}/{}",
+        base_url,
+        package_name,
+        release.version(),
+        asset.name
+    );
+}
+
+/// Files of a release, each with `has_sig` and `provenance` pointing to its
+/// signature and attestation companions. Companions stay in the list too.
+fn files_json(base_url: &str, package_name: &str, release: &Release) -> Vec<Value> {
+    let assets = &release.assets;
+    let mut sorted: Vec<&Asset> = assets.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    return sorted
+        .into_iter()
+        .map(|asset| {
+            let mut file = file_json(base_url, package_name, release, asset);
+            let signature = companions::find(assets, &asset.name, CompanionKind::Signature);
+            let attestation = companions::find(assets, &asset.name, CompanionKind::Attestation);
+            file["has_sig"] = json!(signature.is_some());
+            file["provenance"] = json!(attestation.map(|attestation| asset_url(
+                base_url,
+                package_name,
+                release,
+                attestation
+            )));
+            file
+        })
+        .collect();
+}
+
+fn file_json(base_url: &str, package_name: &str, release: &Release, asset: &Asset) -> Value {
+    let mut digests = serde_json::Map::new();
+    if let Some(sha256) = asset.sha256() {
+        digests.insert("sha256".to_string(), json!(sha256));
+    }
+    if let Some(blake2b_256) = &asset.blake2b_256 {
+        digests.insert("blake2b_256".to_string(), json!(blake2b_256));
+    }
+    return json!({
+        "filename": asset.name,
+        "url": asset_url(base_url, package_name, release, asset),
+        "packagetype": package_type(&asset.name),
+        "size": asset.size,
+        "upload_time_iso_8601": asset.created_at,
+        "digests": digests,
+        "yanked": false,
+    });
+}
+
+fn info_json(
+    namespace: &Namespace,
+    package_name: &str,
+    metadata: &PackageMetadata,
+    release: Option<&Release>,
+    storage: &StorageSettings,
+) -> Value {
+    let repository = namespace.repos.get(&package_name.to_string());
+    let home_page = repository.map(|repository| repository.url(storage));
+    return json!({
+        "name": package_name,
+        "version": release.map(|release| release.version()),
+        "summary": metadata.description,
+        "home_page": home_page,
+        "project_urls": {"Homepage": home_page},
+        "release_url": release.map(|release| release.html_url.clone()),
+    });
+}
+
+/// Known vulnerabilities of the release, in the format of PyPI's JSON API.
+fn vulnerabilities_json(metadata: &PackageMetadata, release: Option<&Release>) -> Vec<Value> {
+    let Some(vulnerabilities) =
+        release.and_then(|release| metadata.vulnerabilities.get(release.version()))
+    else {
+        return vec![];
+    };
+    return vulnerabilities
+        .iter()
+        .map(|vulnerability| {
+            json!({
+                "id": vulnerability.id,
+                "source": "osv",
+                "link": vulnerability.link,
+                "aliases": vulnerability.aliases,
+                "summary": vulnerability.summary,
+                "fixed_in": vulnerability.fixed_in,
+            })
+        })
+        .collect();
+}
+
+pub async fn project(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+    Path((package_name,)): Path<(String,)>,
+) -> Result<Response, ErrorResponse> {
+    let base_url = format!("{}{}", app_state.config.external_url, prefix);
+    let metadata = package_metadata(&package_name, &namespace)?;
+    let document = project_json(
+        &base_url,
+        &namespace,
+        &package_name,
+        &metadata,
+        &app_state.github.storage,
+    );
+    return Ok(simple_api::with_serial(
+        Json(document).into_response(),
+        metadata.serial,
+    ));
+}
+
+/// Document served at `/pypi/<package>/json`.
+pub fn project_json(
+    base_url: &str,
+    namespace: &Namespace,
+    package_name: &str,
+    metadata: &PackageMetadata,
+    storage: &StorageSettings,
+) -> Value {
+    let latest = latest_release(&metadata.releases);
+    let releases: BTreeMap<&str, Vec<Value>> = metadata
+        .releases
+        .iter()
+        .map(|release| {
+            let files = files_json(base_url, package_name, release);
+            (release.version(), files)
+        })
+        .collect();
+    let urls: Vec<Value> = latest
+        .map(|release| files_json(base_url, package_name, release))
+        .unwrap_or_default();
+    return json!({
+        "info": info_json(namespace, package_name, metadata, latest, storage),
+        "last_serial": metadata.serial,
+        "releases": releases,
+        "urls": urls,
+        "vulnerabilities": vulnerabilities_json(metadata, latest),
+    });
+}
+
+pub async fn release(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+    Path((package_name, version)): Path<(String, String)>,
+) -> Result<Response, ErrorResponse> {
+    let base_url = format!("{}{}", app_state.config.external_url, prefix);
+    let metadata = package_metadata(&package_name, &namespace)?;
+    let release = metadata
+        .releases
+        .iter()
+        .find(|release| release.version() == version)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let urls = files_json(&base_url, &package_name, release);
+    let document = json!({
+        "info": info_json(
+            &namespace,
+            &package_name,
+            &metadata,
+            Some(release),
+            &app_state.github.storage,
+        ),
+        "last_serial": metadata.serial,
+        "urls": urls,
+        "vulnerabilities": vulnerabilities_json(&metadata, Some(release)),
+    });
+    return Ok(simple_api::with_serial(
+        Json(document).into_response(),
+        metadata.serial,
+    ));
+}
+
+/// Minimal XML-RPC endpoint, only `list_packages` is supported.
+pub async fn xmlrpc(
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    body: String,
+) -> Response {
+    let method_name = body
+        .split_once("<methodName>")
+        .and_then(|(_, rest)| rest.split_once("</methodName>"))
+        .map(|(method_name, _)| method_name.trim());
+    let body = match method_name {
+        Some("list_packages") => {
+            let packages = namespace.repos.all();
+            let values: String = packages
+                .iter()
+                .map(|package| format!("<value><string>{}</string></value>", xml_escape(package)))
+                .collect();
+            format!(
+                "<?xml version='1.0'?>\n<methodResponse><params><param><value><array><data>{}</data></array></value></param></params></methodResponse>",
+                values
+            )
+        }
+        _ => format!(
+            "<?xml version='1.0'?>\n<methodResponse><fault><value><struct>\
+             <member><name>faultCode</name><value><int>-32601</int></value></member>\
+             <member><name>faultString</name><value><string>method {} is not supported</string></value></member>\
+             </struct></value></fault></methodResponse>",
+            xml_escape(method_name.unwrap_or_default())
+        ),
+    };
+    return ([(header::CONTENT_TYPE, "text/xml")], body).into_response();
+}
+
+fn xml_escape(value: &str) -> String {
+    return value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+}
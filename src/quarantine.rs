@@ -0,0 +1,121 @@
+//! Review gate for new versions, enabled by `QUARANTINE_PATH`. Versions the
+//! sync finds after the first sync of a package are held back from every page
+//! and download until approved with `POST /debug/quarantine`. Approvals are
+//! kept in the file, so they survive restarts and replicas sharing it agree.
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::config::Config;
+use crate::github::Release;
+use crate::namespace::Namespace;
+use crate::AppState;
+
+type Approvals = HashMap<String, BTreeSet<String>>;
+
+pub struct Quarantine {
+    path: PathBuf,
+    /// approved versions by qualified package name
+    approved: RwLock<Approvals>,
+}
+
+impl Quarantine {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let quarantine = Quarantine {
+            path: PathBuf::from(config.quarantine_path.as_ref()?),
+            approved: RwLock::default(),
+        };
+        if let Err(error) = quarantine.reload() {
+            panic!("Can't read {}: {}", quarantine.path.display(), error);
+        }
+        return Some(quarantine);
+    }
+
+    /// Reads the approvals again, other replicas may have added some.
+    pub fn reload(&self) -> Result<(), String> {
+        let approved = match fs::read(&self.path) {
+            Ok(content) => serde_json::from_slice(&content).map_err(|error| error.to_string())?,
+            // nothing was synced yet
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Approvals::new(),
+            Err(error) => return Err(error.to_string()),
+        };
+        *self.approved.write().unwrap() = approved;
+        return Ok(());
+    }
+
+    fn save(&self, approved: &Approvals) -> Result<(), String> {
+        let temp_path = self.path.with_extension("tmp");
+        return serde_json::to_vec_pretty(approved)
+            .map_err(io::Error::other)
+            .and_then(|content| fs::write(&temp_path, content))
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|error| error.to_string());
+    }
+
+    /// Splits the releases of a package into the approved ones and the ones
+    /// held for review. Everything published before the first sync of the
+    /// package is approved.
+    pub fn admit(
+        &self,
+        package: &str,
+        releases: Vec<Release>,
+    ) -> Result<(Vec<Release>, Vec<Release>), String> {
+        let mut approved = self.approved.write().unwrap();
+        if let Some(versions) = approved.get(package) {
+            return Ok(releases
+                .into_iter()
+                .partition(|release| versions.contains(release.version())));
+        }
+        let mut updated = approved.clone();
+        updated.insert(
+            package.to_string(),
+            releases
+                .iter()
+                .map(|release| release.version().to_string())
+                .collect(),
+        );
+        self.save(&updated)?;
+        *approved = updated;
+        return Ok((releases, vec![]));
+    }
+
+    pub fn is_approved(&self, package: &str, version: &str) -> bool {
+        // packages are gated only once their first sync established what is published
+        return self
+            .approved
+            .read()
+            .unwrap()
+            .get(package)
+            .is_none_or(|versions| versions.contains(version));
+    }
+
+    pub fn approve(&self, package: &str, version: &str) -> Result<(), String> {
+        // keeps what other replicas approved meanwhile
+        self.reload()?;
+        let mut approved = self.approved.write().unwrap();
+        let mut updated = approved.clone();
+        updated
+            .entry(package.to_string())
+            .or_default()
+            .insert(version.to_string());
+        self.save(&updated)?;
+        *approved = updated;
+        return Ok(());
+    }
+}
+
+/// Drops the releases not approved yet from what GitHub returned for a package,
+/// for the pages and downloads served without the synced metadata.
+pub fn retain_approved(
+    app_state: &AppState,
+    namespace: &Namespace,
+    package_name: &str,
+    releases: &mut Vec<Release>,
+) {
+    if let Some(quarantine) = &app_state.quarantine {
+        let package = namespace.qualified_name(package_name);
+        releases.retain(|release| quarantine.is_approved(&package, release.version()));
+    }
+}
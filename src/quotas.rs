@@ -0,0 +1,399 @@
+//! Limits on what packages and namespaces hold, set with `quota` in the
+//! repos config: `max_file_size` and `max_storage` in bytes, and
+//! `max_files_per_version`. The file limits of a namespace apply to its
+//! packages without their own, its `max_storage` to all of them together.
+//! Storage is the size of the release assets on GitHub or in local or S3
+//! storage, and for projects mirrored from PyPI or proxied of their files in
+//! the artifact cache. `pigi publish`, `pigi import` and uploads refuse files
+//! over the limits before uploading anything, mirroring leaves them out of the
+//! cache, and `/debug/quotas` shows the usage. The storage of the other
+//! packages of a namespace is counted again every `USAGE_TTL` only, and
+//! uploads add to it meanwhile.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::response::Json;
+use axum_auth::AuthBasic;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{Provider, Repository};
+use crate::debug;
+use crate::error::ErrorResponse;
+use crate::github::{GithubClient, Release};
+use crate::namespace::Namespace;
+use crate::storage;
+use crate::AppState;
+
+/// How long the storage of a package is reused for the `max_storage` of its
+/// namespace before its releases are listed again.
+const USAGE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    /// bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_files_per_version: Option<usize>,
+    /// bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_storage: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_empty(&self) -> bool {
+        return self.max_file_size.is_none()
+            && self.max_files_per_version.is_none()
+            && self.max_storage.is_none();
+    }
+
+    /// Limits of a package of `namespace`, its own file limits or the namespace's.
+    pub fn of(namespace: &Namespace, repository: &Repository) -> Quota {
+        return Quota {
+            max_file_size: repository
+                .quota
+                .max_file_size
+                .or(namespace.quota.max_file_size),
+            max_files_per_version: repository
+                .quota
+                .max_files_per_version
+                .or(namespace.quota.max_files_per_version),
+            max_storage: repository.quota.max_storage,
+        };
+    }
+}
+
+/// Bytes stored for `releases` of `repository`.
+pub fn stored(app_state: &AppState, repository: &Repository, releases: &[Release]) -> u64 {
+    let assets = releases.iter().flat_map(|release| &release.assets);
+    return match repository.provider {
+        Provider::Github | Provider::Local | Provider::S3 => assets.map(|asset| asset.size).sum(),
+        Provider::Pypi | Provider::Proxy => assets
+            .filter(|asset| {
+                asset
+                    .sha256()
+                    .is_some_and(|sha256| app_state.cache.contains(sha256))
+            })
+            .map(|asset| asset.size)
+            .sum(),
+    };
+}
+
+/// Bytes stored for the synced releases of `package_name`, quarantined ones included.
+fn package_storage(app_state: &AppState, namespace: &Namespace, package_name: &String) -> u64 {
+    let (Some(repository), Some(metadata)) = (
+        namespace.repos.get(package_name),
+        namespace.metadata.get(package_name),
+    ) else {
+        return 0;
+    };
+    return stored(app_state, repository, &metadata.releases)
+        + stored(app_state, repository, &metadata.quarantined);
+}
+
+/// What is left of the quotas of a package, file by file.
+pub struct Budget {
+    package: String,
+    quota: Quota,
+    namespace_max_storage: Option<u64>,
+    package_storage: u64,
+    namespace_storage: u64,
+}
+
+impl Budget {
+    pub fn new(
+        namespace: &Namespace,
+        package_name: &str,
+        repository: &Repository,
+        package_storage: u64,
+        namespace_storage: u64,
+    ) -> Self {
+        return Budget {
+            package: namespace.qualified_name(package_name),
+            quota: Quota::of(namespace, repository),
+            namespace_max_storage: namespace.quota.max_storage,
+            package_storage,
+            namespace_storage,
+        };
+    }
+
+    /// Budget of `package_name` as of the last sync.
+    pub fn synced(
+        app_state: &AppState,
+        namespace: &Namespace,
+        package_name: &String,
+        repository: &Repository,
+    ) -> Self {
+        let namespace_storage = namespace
+            .repos
+            .iter()
+            .map(|(name, _)| package_storage(app_state, namespace, name))
+            .sum();
+        return Budget::new(
+            namespace,
+            package_name,
+            repository,
+            package_storage(app_state, namespace, package_name),
+            namespace_storage,
+        );
+    }
+
+    /// Why `filename` of `size` bytes can't be stored as file number `files`
+    /// of its version.
+    pub fn check(&self, filename: &str, size: u64, files: usize) -> Result<(), String> {
+        if let Some(max) = self.quota.max_file_size.filter(|max| size > *max) {
+            return Err(format!(
+                "{} is {} bytes, over the max_file_size of {}: {} bytes",
+                filename, size, self.package, max
+            ));
+        }
+        if let Some(max) = self.quota.max_files_per_version.filter(|max| files > *max) {
+            return Err(format!(
+                "{} would be file {} of its version, over the max_files_per_version of {}: {}",
+                filename, files, self.package, max
+            ));
+        }
+        if let Some(max) = self
+            .quota
+            .max_storage
+            .filter(|max| self.package_storage + size > *max)
+        {
+            return Err(format!(
+                "{} would bring {} to {} bytes, over its max_storage: {} bytes",
+                filename,
+                self.package,
+                self.package_storage + size,
+                max
+            ));
+        }
+        if let Some(max) = self
+            .namespace_max_storage
+            .filter(|max| self.namespace_storage + size > *max)
+        {
+            return Err(format!(
+                "{} would bring the namespace of {} to {} bytes, over its max_storage: {} bytes",
+                filename,
+                self.package,
+                self.namespace_storage + size,
+                max
+            ));
+        }
+        return Ok(());
+    }
+
+    pub fn add(&mut self, size: u64) {
+        self.package_storage += size;
+        self.namespace_storage += size;
+    }
+}
+
+/// Releases stored for `repository`, GitHub drafts included.
+async fn fetch_releases(
+    client: &GithubClient,
+    repository: &Repository,
+) -> Result<Vec<Release>, ErrorResponse> {
+    return match storage::of(repository, client) {
+        Some(storage) => storage.releases().await,
+        None => repository.releases(client).await,
+    };
+}
+
+/// Bytes stored by package, as counted for the `max_storage` of namespaces.
+#[derive(Default)]
+pub struct StorageUsage {
+    /// by qualified package name, with when they were counted
+    entries: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+impl StorageUsage {
+    fn get(&self, package: &str) -> Option<u64> {
+        return self
+            .entries
+            .lock()
+            .unwrap()
+            .get(package)
+            .filter(|(_, counted_at)| counted_at.elapsed() < USAGE_TTL)
+            .map(|(bytes, _)| *bytes);
+    }
+
+    fn set(&self, package: String, bytes: u64) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(package, (bytes, Instant::now()));
+    }
+
+    /// Counts `size` bytes uploaded to `package_name` in the storage of its namespace.
+    pub fn record_upload(&self, namespace: &Namespace, package_name: &str, size: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((bytes, _)) = entries.get_mut(&namespace.qualified_name(package_name)) {
+            *bytes += size;
+        }
+    }
+}
+
+/// Bytes stored for `package_name`, as counted within `USAGE_TTL`.
+async fn cached_storage(
+    app_state: &AppState,
+    client: &GithubClient,
+    namespace: &Namespace,
+    package_name: &str,
+    repository: &Repository,
+) -> Result<u64, String> {
+    let package = namespace.qualified_name(package_name);
+    if let Some(bytes) = app_state.storage_usage.get(&package) {
+        return Ok(bytes);
+    }
+    let releases = fetch_releases(client, repository).await.map_err(|error| {
+        format!(
+            "Can't look up the releases of {} to check quotas: {}",
+            package, error
+        )
+    })?;
+    let bytes = stored(app_state, repository, &releases);
+    app_state.storage_usage.set(package, bytes);
+    return Ok(bytes);
+}
+
+/// Refuses uploading `files` to the release `tag` of `package_name` when
+/// they don't fit its quotas. Files already on the release are left out, they
+/// aren't uploaded again.
+pub async fn check_upload(
+    app_state: &AppState,
+    client: &GithubClient,
+    namespace: &Namespace,
+    package_name: &str,
+    repository: &Repository,
+    tag: &String,
+    files: &[PathBuf],
+) -> Result<(), String> {
+    if Quota::of(namespace, repository).is_empty() && namespace.quota.max_storage.is_none() {
+        return Ok(());
+    }
+    let releases = fetch_releases(client, repository)
+        .await
+        .map_err(|error| format!("Can't look up the releases to check quotas: {}", error))?;
+    let package_storage = stored(app_state, repository, &releases);
+    let mut namespace_storage = package_storage;
+    if namespace.quota.max_storage.is_some() {
+        app_state
+            .storage_usage
+            .set(namespace.qualified_name(package_name), package_storage);
+        for (name, other) in namespace.repos.iter() {
+            if name == package_name {
+                continue;
+            }
+            namespace_storage += cached_storage(app_state, client, namespace, name, other).await?;
+        }
+    }
+    let existing: Vec<&String> = releases
+        .iter()
+        .filter(|release| release.tag_name == *tag)
+        .flat_map(|release| release.assets.iter().map(|asset| &asset.name))
+        .collect();
+    let mut budget = Budget::new(
+        namespace,
+        package_name,
+        repository,
+        package_storage,
+        namespace_storage,
+    );
+    let mut count = existing.len();
+    for path in files {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        if existing.iter().any(|name| **name == filename) {
+            continue;
+        }
+        let size = tokio::fs::metadata(path)
+            .await
+            .map_err(|error| format!("Can't read {}: {}", path.display(), error))?
+            .len();
+        count += 1;
+        budget.check(&filename, size, count)?;
+        budget.add(size);
+    }
+    return Ok(());
+}
+
+/// Largest file and most files of a version of `releases`.
+fn largest(releases: &[Release]) -> (u64, usize) {
+    let largest_file = releases
+        .iter()
+        .flat_map(|release| &release.assets)
+        .map(|asset| asset.size)
+        .max()
+        .unwrap_or(0);
+    let most_files = releases
+        .iter()
+        .map(|release| release.assets.len())
+        .max()
+        .unwrap_or(0);
+    return (largest_file, most_files);
+}
+
+/// Quotas of every namespace and package with what they use, as of the last sync.
+pub async fn usage(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    debug::authorize(&app_state, auth)?;
+    let mut namespaces = vec![];
+    let mut packages = vec![];
+    for namespace in app_state.namespaces.iter() {
+        let mut namespace_storage = 0;
+        for package_name in namespace.repos.all() {
+            let Some(repository) = namespace.repos.get(&package_name) else {
+                continue;
+            };
+            let storage = package_storage(&app_state, namespace, &package_name);
+            namespace_storage += storage;
+            let metadata = namespace.metadata.get(&package_name).unwrap_or_default();
+            let releases: Vec<Release> = metadata
+                .releases
+                .into_iter()
+                .chain(metadata.quarantined)
+                .collect();
+            let (largest_file, most_files) = largest(&releases);
+            let quota = Quota::of(namespace, repository);
+            let mut over = vec![];
+            if quota.max_file_size.is_some_and(|max| largest_file > max) {
+                over.push("max_file_size");
+            }
+            if quota
+                .max_files_per_version
+                .is_some_and(|max| most_files > max)
+            {
+                over.push("max_files_per_version");
+            }
+            if quota.max_storage.is_some_and(|max| storage > max) {
+                over.push("max_storage");
+            }
+            packages.push(json!({
+                "package": namespace.qualified_name(&package_name),
+                "quota": quota,
+                "storage": storage,
+                "largest_file": largest_file,
+                "most_files_per_version": most_files,
+                "over": over,
+            }));
+        }
+        namespaces.push(json!({
+            "namespace": namespace.name,
+            "quota": namespace.quota,
+            "storage": namespace_storage,
+            "over_max_storage": namespace
+                .quota
+                .max_storage
+                .is_some_and(|max| namespace_storage > max),
+        }));
+    }
+    return Ok(Json(json!({
+        "namespaces": namespaces,
+        "packages": packages,
+    })));
+}
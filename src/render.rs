@@ -0,0 +1,94 @@
+//! `pigi render`: prints the pages the index would serve, rendered with the
+//! templates of `TEMPLATES_DIR`, so template changes can be checked before
+//! deploying them.
+use crate::config::Repository;
+use crate::metadata::{self, PackageMetadata};
+use crate::namespace::Namespace;
+use crate::templates::Page;
+use crate::{fail, inspect, names, pypi, AppState, Output, PackageTemplate, Simple};
+
+pub struct Render {
+    pub namespace: String,
+    /// the package page instead of the simple index
+    pub package: Option<String>,
+    /// the metadata the leader shared in `CACHE_DIR` instead of fetching it from GitHub
+    pub cached: bool,
+}
+
+pub fn render<T: Page>(app_state: &AppState, page: T) -> String {
+    return app_state
+        .templates
+        .render_to_string(page)
+        .unwrap_or_else(|error| fail(format!("Can't render {}: {}", T::NAME, error)));
+}
+
+async fn package_metadata(
+    app_state: &AppState,
+    namespace: &Namespace,
+    (configured_name, repository): (&String, &Repository),
+    cached: bool,
+) -> PackageMetadata {
+    if !cached {
+        return inspect::fetch(app_state, namespace, repository)
+            .await
+            .unwrap_or_else(|error| {
+                fail(format!(
+                    "Can't fetch {}/{} from GitHub: {}",
+                    repository.owner, repository.name, error
+                ))
+            });
+    }
+    if !metadata::load_shared(app_state) {
+        fail("--cached reads the metadata shared in CACHE_DIR, set it".to_string());
+    }
+    return namespace.metadata.get(configured_name).unwrap_or_else(|| {
+        fail(format!(
+            "{} isn't in the metadata shared in CACHE_DIR, the leader didn't sync it yet",
+            namespace.qualified_name(configured_name)
+        ))
+    });
+}
+
+/// Prints the simple index of the namespace, or the page of a package; with
+/// json output the package's `/pypi/<package>/json` document instead.
+pub async fn run(app_state: &AppState, render_options: Render, output: Output) {
+    let namespace = inspect::namespace(app_state, &render_options.namespace);
+    let Some(package_name) = &render_options.package else {
+        if output == Output::Json {
+            fail("Only packages have a json document, pick one with --package".to_string());
+        }
+        println!("{}", render(app_state, Simple::from_namespace(namespace)));
+        return;
+    };
+    let Ok(package) = namespace.find_repository(package_name) else {
+        fail(format!(
+            "{} is not configured",
+            namespace.qualified_name(package_name)
+        ));
+    };
+    let (configured_name, repository) = package;
+    let metadata = package_metadata(app_state, namespace, package, render_options.cached).await;
+    if output == Output::Json {
+        let prefix = match namespace.name.as_str() {
+            "" => String::new(),
+            name => format!("/{}", name),
+        };
+        let base_url = format!("{}{}", app_state.config.external_url, prefix);
+        let document = pypi::project_json(
+            &base_url,
+            namespace,
+            configured_name,
+            &metadata,
+            &app_state.github.storage,
+        );
+        println!("{}", serde_json::to_string_pretty(&document).unwrap());
+        return;
+    }
+    let page = PackageTemplate::new(
+        repository,
+        names::normalize(configured_name),
+        &metadata.releases,
+        &metadata.vulnerabilities,
+    );
+    println!("{}", render(app_state, page));
+}
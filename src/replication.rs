@@ -0,0 +1,368 @@
+//! Replication from another pigi: with `UPSTREAM_URL` set, pigi syncs from
+//! the upstream instance instead of GitHub and PyPI, for replicas in other
+//! regions or zones without access to them. Every sync asks the upstream's
+//! `/api/changelog` what changed since the last serial it replicated and
+//! fetches only those packages, from `/api/packages/<package>/metadata`, with
+//! the upstream's serials. Files are downloaded from the upstream when asked
+//! for, those of the packages matching `UPSTREAM_ARTIFACTS` are downloaded
+//! into the cache at every sync. Namespaces are replicated from the
+//! namespace of the same name upstream.
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, RwLock};
+
+use axum::extract::Path;
+use axum::response::Json;
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::config::{Config, Repository};
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{Asset, AssetStream, ByteStream, Release};
+use crate::glob;
+use crate::metadata::{self, PackageMetadata};
+use crate::mirror;
+use crate::names;
+use crate::namespace::{CurrentNamespace, Namespace};
+use crate::redact::log;
+use crate::reporting::Report;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+struct Upstream {
+    url: String,
+    token: Option<String>,
+    /// packages whose files are downloaded at every sync
+    artifacts: Vec<String>,
+    client: reqwest::Client,
+}
+
+/// What is replicated from the upstream, pigi syncs from GitHub without `UPSTREAM_URL`.
+pub struct Replication {
+    upstream: Option<Upstream>,
+    /// upstream serial replicated up to, by namespace
+    serials: Mutex<BTreeMap<String, u64>>,
+    /// replicated releases by repository, then by package, as several
+    /// packages can be released from one repository
+    releases: RwLock<BTreeMap<String, BTreeMap<String, Vec<Release>>>>,
+}
+
+impl Replication {
+    pub fn from_config(config: &Config) -> Self {
+        let upstream = config.upstream_url.as_ref().map(|url| Upstream {
+            url: url.clone(),
+            token: config.upstream_token.clone(),
+            artifacts: config.upstream_artifacts.clone(),
+            client: reqwest::Client::new(),
+        });
+        return Replication {
+            upstream,
+            serials: Mutex::default(),
+            releases: RwLock::default(),
+        };
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return self.upstream.is_some();
+    }
+
+    fn upstream(&self) -> Result<&Upstream, ErrorResponse> {
+        return self.upstream.as_ref().ok_or(ErrorResponse::PageNotFound);
+    }
+
+    /// Replicated releases of `repository`, `None` when not replicating.
+    pub fn releases(&self, repository: &Repository) -> Option<Vec<Release>> {
+        self.upstream.as_ref()?;
+        return Some(merged_releases(
+            self.releases
+                .read()
+                .unwrap()
+                .get(&repository_key(repository))
+                .into_iter()
+                .flat_map(|packages| packages.values()),
+        ));
+    }
+
+    /// Content of a replicated file, from the upstream.
+    pub async fn download(&self, asset: &Asset) -> Result<AssetStream, ErrorResponse> {
+        let request = self
+            .upstream()?
+            .request(reqwest::Method::GET, asset_url(asset)?);
+        let response = check(request.send().await?).await?;
+        return Ok(AssetStream {
+            content_length: response.content_length(),
+            stream: Box::pin(
+                response
+                    .bytes_stream()
+                    .map(|chunk| chunk.map_err(io::Error::other)),
+            ),
+        });
+    }
+
+    /// Bytes `range` of a replicated file.
+    pub async fn download_range(
+        &self,
+        asset: &Asset,
+        range: Range<u64>,
+    ) -> Result<ByteStream<io::Error>, ErrorResponse> {
+        let request = self
+            .upstream()?
+            .request(reqwest::Method::GET, asset_url(asset)?)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            );
+        let response = request.send().await?;
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(ErrorResponse::Failure(
+                ErrorCode::from_status(response.status()),
+                format!(
+                    "upstream pigi didn't answer the range request, status {}",
+                    response.status()
+                ),
+            ));
+        }
+        return Ok(Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(io::Error::other)),
+        ));
+    }
+
+    /// Whether a replicated file can still be downloaded from the upstream.
+    pub async fn exists(&self, asset: &Asset) -> Result<bool, ErrorResponse> {
+        let request = self
+            .upstream()?
+            .request(reqwest::Method::HEAD, asset_url(asset)?);
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check(response).await?;
+        return Ok(true);
+    }
+}
+
+fn repository_key(repository: &Repository) -> String {
+    return format!("{}/{}", repository.owner, repository.name);
+}
+
+/// Releases of the packages of one repository, the assets of a tag released
+/// by several packages in one release.
+fn merged_releases<'a>(packages: impl Iterator<Item = &'a Vec<Release>>) -> Vec<Release> {
+    let mut releases: Vec<Release> = vec![];
+    for package_releases in packages {
+        for release in package_releases {
+            match releases
+                .iter_mut()
+                .find(|known| known.tag_name == release.tag_name)
+            {
+                Some(known) => {
+                    for asset in &release.assets {
+                        if !known.assets.iter().any(|other| other.id == asset.id) {
+                            known.assets.push(asset.clone());
+                        }
+                    }
+                }
+                None => releases.push(release.clone()),
+            }
+        }
+    }
+    return releases;
+}
+
+/// Upstream url of `asset`, set when it was replicated.
+fn asset_url(asset: &Asset) -> Result<&String, ErrorResponse> {
+    return asset
+        .download_url
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound);
+}
+
+impl Upstream {
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, url);
+        return match &self.token {
+            Some(token) => request.basic_auth("pigi", Some(token)),
+            None => request,
+        };
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: String) -> Result<T, ErrorResponse> {
+        let response = check(self.request(reqwest::Method::GET, &url).send().await?).await?;
+        return response.json().await.map_err(|error| {
+            ErrorResponse::Failure(
+                ErrorCode::Upstream5xx,
+                format!("Can't read {}: {}", url, error),
+            )
+        });
+    }
+
+    /// Base url of `namespace` upstream.
+    fn namespace_url(&self, namespace: &Namespace) -> String {
+        if namespace.name.is_empty() {
+            return self.url.clone();
+        }
+        return format!("{}/{}", self.url, namespace.name);
+    }
+}
+
+/// `response` when successful, otherwise an error with the upstream's status.
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, ErrorResponse> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    return Err(ErrorResponse::Failure(
+        ErrorCode::from_status(status),
+        format!("upstream pigi answered {} for {}", status, response.url()),
+    ));
+}
+
+#[derive(Deserialize)]
+struct Changelog {
+    last_serial: u64,
+    changes: Vec<Change>,
+}
+
+#[derive(Deserialize)]
+struct Change {
+    name: String,
+}
+
+/// Fetches `package_name` from the upstream and stores it with the upstream's serial.
+pub async fn sync_package(
+    app_state: &AppState,
+    namespace: &Arc<Namespace>,
+    package_name: &String,
+) -> Vec<String> {
+    let mut errors = vec![];
+    let replication = &app_state.github.replication;
+    let Ok(upstream) = replication.upstream() else {
+        return errors;
+    };
+    let Some(repository) = namespace.repos.get(package_name) else {
+        return errors;
+    };
+    let base = upstream.namespace_url(namespace);
+    let url = format!("{}/api/packages/{}/metadata", base, package_name);
+    let mut package: PackageMetadata = match upstream.get_json(url).await {
+        Ok(package) => package,
+        Err(error) => {
+            let message = format!(
+                "Failed to replicate {}: {}",
+                namespace.qualified_name(package_name),
+                error
+            );
+            log!("{}", message);
+            app_state
+                .reporter
+                .report(Report::task("metadata sync", message.clone()));
+            errors.push(message);
+            return errors;
+        }
+    };
+    let normalized = names::normalize(package_name);
+    for release in package
+        .releases
+        .iter_mut()
+        .chain(package.quarantined.iter_mut())
+    {
+        let version = release.version().to_string();
+        for asset in release.assets.iter_mut() {
+            asset.download_url = Some(format!(
+                "{}/simple/{}/{}/{}",
+                base, normalized, version, asset.name
+            ));
+        }
+    }
+    let qualified_name = namespace.qualified_name(package_name);
+    let releases = package.releases.clone();
+    replication
+        .releases
+        .write()
+        .unwrap()
+        .entry(repository_key(repository))
+        .or_default()
+        .insert(qualified_name.clone(), releases.clone());
+    metadata::replicate(namespace, package_name, package);
+    if upstream
+        .artifacts
+        .iter()
+        .any(|pattern| glob::matches(pattern, &qualified_name))
+    {
+        errors.extend(mirror::cache_files(app_state, namespace, package_name, &releases).await);
+    }
+    return errors;
+}
+
+/// Fetches the packages of `namespace` that changed upstream since the last
+/// sync. The serial replicated up to only moves once all of them synced, the
+/// failed ones are tried again on the next sync.
+pub async fn sync_namespace(app_state: &AppState, namespace: &Arc<Namespace>) -> Vec<String> {
+    let mut errors = vec![];
+    let replication = &app_state.github.replication;
+    let Ok(upstream) = replication.upstream() else {
+        return errors;
+    };
+    let since = replication
+        .serials
+        .lock()
+        .unwrap()
+        .get(&namespace.name)
+        .copied()
+        .unwrap_or(0);
+    let url = format!(
+        "{}/api/changelog?since={}",
+        upstream.namespace_url(namespace),
+        since
+    );
+    let changelog: Changelog = match upstream.get_json(url).await {
+        Ok(changelog) => changelog,
+        Err(error) => {
+            let message = format!(
+                "Failed to read the upstream changelog of {}: {}",
+                upstream.namespace_url(namespace),
+                error
+            );
+            log!("{}", message);
+            app_state
+                .reporter
+                .report(Report::task("metadata sync", message.clone()));
+            errors.push(message);
+            return errors;
+        }
+    };
+    for change in changelog.changes {
+        // upstream packages that aren't configured here aren't replicated
+        let Ok((configured_name, _)) = namespace.find_repository(&change.name) else {
+            continue;
+        };
+        errors.extend(sync_package(app_state, namespace, configured_name).await);
+    }
+    if errors.is_empty() {
+        replication
+            .serials
+            .lock()
+            .unwrap()
+            .insert(namespace.name.clone(), changelog.last_serial);
+    }
+    return errors;
+}
+
+/// `GET /api/packages/<package>/metadata`: the package as synced, what replicas replicate.
+pub async fn package_metadata(
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path(package_name): Path<String>,
+    account: CurrentAccount,
+) -> Result<Json<PackageMetadata>, ErrorResponse> {
+    let (configured_name, _) = namespace.find_repository(&package_name)?;
+    account.check_package(&namespace, configured_name)?;
+    let metadata = namespace
+        .metadata
+        .get(configured_name)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    return Ok(Json(metadata));
+}
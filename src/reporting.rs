@@ -0,0 +1,299 @@
+//! Reports server errors and failing background tasks to Sentry (`SENTRY_DSN`)
+//! and/or as json to a webhook (`ERROR_WEBHOOK_URL`). Tokens are redacted
+//! from everything that is sent. With `PRIVACY_MODE` client addresses are
+//! replaced by keyed hashes, which still tell whether two reports came from
+//! the same client.
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use askama_axum::Response;
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::error::ERROR_CODE_HEADER;
+use crate::redact::{log, redact, redact_value};
+use crate::AppState;
+
+/// Longest part of an error response body included in a report.
+const MAX_BODY: usize = 4096;
+
+/// Headers in which proxies pass on the address of the client.
+const CLIENT_ADDRESS_HEADERS: [&str; 5] = [
+    "x-forwarded-for",
+    "x-real-ip",
+    "forwarded",
+    "true-client-ip",
+    "cf-connecting-ip",
+];
+
+struct SentryDsn {
+    store_url: String,
+    public_key: String,
+}
+
+impl SentryDsn {
+    /// Parses `https://<public key>@<host>/<project id>`.
+    fn parse(dsn: &str) -> Option<Self> {
+        let (scheme, rest) = dsn.split_once("://")?;
+        let (public_key, rest) = rest.split_once('@')?;
+        let (host, project_id) = rest.rsplit_once('/')?;
+        return Some(SentryDsn {
+            store_url: format!("{}://{}/api/{}/store/", scheme, host, project_id),
+            public_key: public_key.split(':').next()?.to_string(),
+        });
+    }
+}
+
+pub struct Report {
+    pub message: String,
+    /// `request` or the name of the background task
+    pub source: String,
+    pub request: Option<Value>,
+    /// `ErrorCode` of a failed request
+    pub code: Option<String>,
+}
+
+impl Report {
+    pub fn task(task: &str, message: String) -> Self {
+        return Report {
+            message,
+            source: task.to_string(),
+            request: None,
+            code: None,
+        };
+    }
+}
+
+pub struct Reporter {
+    sentry: Option<Arc<SentryDsn>>,
+    webhook_url: Option<String>,
+    client: reqwest::Client,
+    /// key client addresses are hashed with, in `PRIVACY_MODE`
+    privacy_hash_key: Option<Vec<u8>>,
+}
+
+impl Reporter {
+    pub fn from_config(config: &Config) -> Self {
+        let sentry = config.sentry_dsn.as_ref().map(|dsn| {
+            Arc::new(SentryDsn::parse(dsn).expect("cannot parse SENTRY_DSN env variable"))
+        });
+        let privacy_hash_key = config.privacy_mode.then(|| match &config.privacy_hash_key {
+            Some(key) => key.as_bytes().to_vec(),
+            // hashes only match within this process
+            None => event_id().into_bytes(),
+        });
+        return Reporter {
+            sentry,
+            webhook_url: config.error_webhook_url.clone(),
+            client: reqwest::Client::new(),
+            privacy_hash_key,
+        };
+    }
+
+    /// Value of a request header as it is reported.
+    fn header_value(&self, name: &HeaderName, value: &HeaderValue) -> String {
+        let value = value.to_str().unwrap_or_default();
+        return match (name, &self.privacy_hash_key) {
+            (&header::AUTHORIZATION | &header::COOKIE, _) => "[redacted]".to_string(),
+            (name, Some(key)) if CLIENT_ADDRESS_HEADERS.contains(&name.as_str()) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+                mac.update(value.as_bytes());
+                format!("hmac:{}", &hex::encode(mac.finalize().into_bytes())[..16])
+            }
+            _ => value.to_string(),
+        };
+    }
+
+    fn is_enabled(&self) -> bool {
+        return self.sentry.is_some() || self.webhook_url.is_some();
+    }
+
+    /// Sends `report` in the background, failures to send are only logged.
+    pub fn report(&self, report: Report) {
+        if !self.is_enabled() {
+            return;
+        }
+        let message = redact(&report.message);
+        let request = report.request.map(|mut request| {
+            redact_value(&mut request);
+            request
+        });
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+
+        if let Some(sentry) = self.sentry.clone() {
+            let event = json!({
+                "event_id": event_id(),
+                "timestamp": timestamp,
+                "level": "error",
+                "platform": "other",
+                "logger": report.source,
+                "server_name": std::env::var("HOSTNAME").ok(),
+                "release": env!("CARGO_PKG_VERSION"),
+                "message": {"formatted": message},
+                "request": request,
+                "tags": {"source": report.source, "error_code": report.code},
+            });
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                let auth = format!(
+                    "Sentry sentry_version=7, sentry_key={}, sentry_client=pigi/{}",
+                    sentry.public_key,
+                    env!("CARGO_PKG_VERSION")
+                );
+                let sent = client
+                    .post(&sentry.store_url)
+                    .header("X-Sentry-Auth", auth)
+                    .json(&event)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(error) = sent {
+                    log!("Failed to report error to Sentry: {}", error);
+                }
+            });
+        }
+        if let Some(webhook_url) = self.webhook_url.clone() {
+            let payload = json!({
+                "message": message,
+                "source": report.source,
+                "timestamp": timestamp,
+                "request": request,
+                "code": report.code,
+            });
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                let sent = client
+                    .post(&webhook_url)
+                    .json(&payload)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(error) = sent {
+                    log!("Failed to report error to the webhook: {}", error);
+                }
+            });
+        }
+    }
+}
+
+/// Random 32 hex digits, from the randomly keyed hasher of the standard library.
+fn event_id() -> String {
+    let random = || RandomState::new().hash_one(()) as u128;
+    return format!("{:032x}", random() << 64 | random());
+}
+
+/// The response body, and a copy of it to report when it is known to be at
+/// most `MAX_BODY` long. Longer bodies or bodies of unknown size are streamed
+/// to the client as they are, without a copy.
+async fn copy_body(body: Body) -> (Body, Option<axum::body::Bytes>) {
+    if body.size_hint().exact().is_none_or(|size| size > MAX_BODY as u64) {
+        return (body, None);
+    }
+    return match axum::body::to_bytes(body, MAX_BODY).await {
+        Ok(bytes) => (Body::from(bytes.clone()), Some(bytes)),
+        Err(error) => (Body::empty(), Some(error.to_string().into())),
+    };
+}
+
+/// Reports responses with a 5xx status, with the method, url and headers of
+/// the request and the response body when it is short.
+pub async fn report_errors(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !app_state.reporter.is_enabled() {
+        return next.run(request).await;
+    }
+    let headers: serde_json::Map<String, Value> = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = app_state.reporter.header_value(name, value);
+            (name.to_string(), json!(value))
+        })
+        .collect();
+    let context = json!({
+        "method": request.method().as_str(),
+        "url": request.uri().to_string(),
+        "headers": headers,
+    });
+    let response = next.run(request).await;
+    if !response.status().is_server_error() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let (body, copy) = copy_body(body).await;
+    let url = context["url"].as_str().unwrap_or_default();
+    let message = match copy {
+        Some(copy) => format!(
+            "{} responded {}: {}",
+            url,
+            parts.status,
+            String::from_utf8_lossy(&copy)
+        ),
+        None => format!("{} responded {}", url, parts.status),
+    };
+    app_state.reporter.report(Report {
+        message,
+        source: "request".to_string(),
+        request: Some(context),
+        code: parts
+            .headers
+            .get(ERROR_CODE_HEADER)
+            .and_then(|code| code.to_str().ok())
+            .map(str::to_string),
+    });
+    return Response::from_parts(parts, body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(body: Body) -> Vec<u8> {
+        return tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(axum::body::to_bytes(body, usize::MAX))
+            .unwrap()
+            .to_vec();
+    }
+
+    #[test]
+    fn copies_short_bodies() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (body, copy) = runtime.block_on(copy_body(Body::from("upstream failed")));
+        assert_eq!(copy.as_deref(), Some(&b"upstream failed"[..]));
+        assert_eq!(received(body), b"upstream failed");
+    }
+
+    #[test]
+    fn passes_long_bodies_on_whole() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let long = "x".repeat(MAX_BODY + 1);
+        let (body, copy) = runtime.block_on(copy_body(Body::from(long.clone())));
+        assert!(copy.is_none());
+        assert_eq!(received(body), long.as_bytes());
+
+        let chunks = futures_util::stream::iter([Ok::<_, std::io::Error>("a"), Ok("b")]);
+        let (body, copy) = runtime.block_on(copy_body(Body::from_stream(chunks)));
+        assert!(copy.is_none());
+        assert_eq!(received(body), b"ab");
+    }
+}
@@ -0,0 +1,42 @@
+//! Hot restarts: with `REUSE_PORT` a new pigi process binds the port while
+//! the old one still listens, then the old one is stopped with SIGTERM and
+//! finishes in-flight downloads before exiting.
+use std::io;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpSocket};
+
+use crate::config::Config;
+use crate::redact::log;
+
+pub fn bind(config: &Config) -> io::Result<TcpListener> {
+    let socket = TcpSocket::new_v4()?;
+    socket.set_reuseaddr(true)?;
+    if config.reuse_port {
+        socket.set_reuseport(true)?;
+    }
+    // connections accepted from the socket inherit it
+    socket.set_nodelay(config.tcp_nodelay)?;
+    socket.bind(([0, 0, 0, 0], config.port).into())?;
+    return socket.listen(1024);
+}
+
+/// Resolves on SIGTERM or ctrl-c, which stops accepting connections. The
+/// process exits once in-flight requests are done, or after `drain_timeout`.
+pub async fn shutdown_signal(drain_timeout: Duration) {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("cannot listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+    log!(
+        "Shutting down, draining in-flight requests for up to {}s",
+        drain_timeout.as_secs()
+    );
+    tokio::spawn(async move {
+        tokio::time::sleep(drain_timeout).await;
+        log!("In-flight requests didn't finish in time, exiting");
+        std::process::exit(0);
+    });
+}
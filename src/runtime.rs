@@ -0,0 +1,19 @@
+use tokio::runtime::{Builder, Runtime};
+
+use crate::config::Config;
+
+/// Multi-threaded runtime sized by `worker_threads` and `MAX_BLOCKING_THREADS`,
+/// tokio's defaults apply to what isn't configured.
+pub fn build(name: &str, worker_threads: Option<usize>, config: &Config) -> Runtime {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all().thread_name(name);
+    if let Some(worker_threads) = worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    if let Some(max_blocking_threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+    return builder
+        .build()
+        .unwrap_or_else(|error| panic!("cannot start the {} runtime: {}", name, error));
+}
@@ -0,0 +1,221 @@
+//! CycloneDX SBOM of a wheel at `/api/packages/<package>/<version>/sbom`,
+//! built from its `METADATA` and `RECORD` when first asked for, so security
+//! tooling can inventory packages without downloading and unpacking them.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, Query, State};
+use axum::response::Json;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::auth::GithubToken;
+use crate::catalog::{self, header_values};
+use crate::error::ErrorResponse;
+use crate::github::{Asset, GithubClient};
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::AppState;
+
+/// Documents kept in memory, the oldest are dropped beyond this.
+const MAX_CACHED: usize = 256;
+
+/// Generated documents by the id of their wheel.
+#[derive(Default)]
+pub struct Sboms {
+    documents: Mutex<HashMap<u64, (u64, Value)>>,
+    generated: Mutex<u64>,
+}
+
+impl Sboms {
+    fn get(&self, asset_id: u64) -> Option<Value> {
+        let documents = self.documents.lock().unwrap();
+        return documents
+            .get(&asset_id)
+            .map(|(_, document)| document.clone());
+    }
+
+    fn insert(&self, asset_id: u64, document: Value) {
+        let mut generated = self.generated.lock().unwrap();
+        *generated += 1;
+        let mut documents = self.documents.lock().unwrap();
+        if documents.len() >= MAX_CACHED {
+            let oldest = documents
+                .iter()
+                .min_by_key(|(_, (generation, _))| *generation)
+                .map(|(asset_id, _)| *asset_id);
+            if let Some(oldest) = oldest {
+                documents.remove(&oldest);
+            }
+        }
+        documents.insert(asset_id, (*generated, document));
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SbomQuery {
+    /// name of the wheel, the first wheel of the release by default
+    file: Option<String>,
+}
+
+fn purl(name: &str, version: Option<&str>) -> String {
+    let name = names::normalize(name);
+    return match version {
+        Some(version) => format!("pkg:pypi/{}@{}", name, version),
+        None => format!("pkg:pypi/{}", name),
+    };
+}
+
+/// Hex sha256 of a `RECORD` hash like `sha256=<urlsafe base64>`.
+fn record_sha256(hash: &str) -> Option<String> {
+    let encoded = hash.strip_prefix("sha256=")?;
+    let digest = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(encoded)
+        .ok()?;
+    return Some(hex::encode(digest));
+}
+
+/// Name of the project a `Requires-Dist` value depends on.
+fn requirement_name(requirement: &str) -> &str {
+    let end = requirement
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "._-".contains(c)))
+        .unwrap_or(requirement.len());
+    return &requirement[..end];
+}
+
+fn document(wheel: &Asset, metadata: &str, record: &str) -> Value {
+    let headers = catalog::metadata_headers(metadata);
+    let header = |name| header_values(&headers, name).next().cloned();
+    let name = header("name").unwrap_or_default();
+    let version = header("version").unwrap_or_default();
+    let bom_ref = purl(&name, Some(&version));
+    let licenses: Vec<Value> = catalog::license(&headers)
+        .map(|license| match header("license-expression") {
+            Some(expression) => json!({"expression": expression}),
+            None => json!({"license": {"name": license}}),
+        })
+        .into_iter()
+        .collect();
+    let hashes: Vec<Value> = wheel
+        .sha256()
+        .map(|sha256| json!({"alg": "SHA-256", "content": sha256}))
+        .into_iter()
+        .collect();
+
+    let files = record.lines().filter_map(|line| {
+        // paths may contain commas, the hash and size never do
+        let mut fields = line.rsplitn(3, ',');
+        let (_size, hash, path) = (fields.next()?, fields.next()?, fields.next()?);
+        let path = path.trim_matches('"');
+        let hashes: Vec<Value> = record_sha256(hash)
+            .map(|sha256| json!({"alg": "SHA-256", "content": sha256}))
+            .into_iter()
+            .collect();
+        return Some(json!({
+            "type": "file",
+            "bom-ref": format!("{}#{}", bom_ref, path),
+            "name": path,
+            "hashes": hashes,
+        }));
+    });
+    // a project can be required more than once, with different markers
+    let mut seen = HashSet::new();
+    let requirements: Vec<(String, bool)> = header_values(&headers, "requires-dist")
+        .map(|requirement| {
+            let optional = requirement.contains("extra ==") || requirement.contains("extra==");
+            (names::normalize(requirement_name(requirement)), optional)
+        })
+        .filter(|(name, _)| seen.insert(name.clone()))
+        .collect();
+    let dependencies = requirements.iter().map(|(name, optional)| {
+        let scope = if *optional { "optional" } else { "required" };
+        json!({
+            "type": "library",
+            "bom-ref": purl(name, None),
+            "name": name,
+            "purl": purl(name, None),
+            "scope": scope,
+        })
+    });
+    let components: Vec<Value> = files.chain(dependencies).collect();
+    let depends_on: Vec<String> = requirements
+        .iter()
+        .map(|(name, _)| purl(name, None))
+        .collect();
+
+    return json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "tools": {
+                "components": [{"type": "application", "name": "pigi", "version": env!("CARGO_PKG_VERSION")}],
+            },
+            "component": {
+                "type": "library",
+                "bom-ref": bom_ref,
+                "name": name,
+                "version": version,
+                "purl": bom_ref,
+                "description": header("summary"),
+                "licenses": licenses,
+                "hashes": hashes,
+                "properties": [{"name": "pigi:filename", "value": wheel.name}],
+            },
+        },
+        "components": components,
+        "dependencies": [{"ref": bom_ref, "dependsOn": depends_on}],
+    });
+}
+
+pub async fn sbom(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((package_name, version)): Path<(String, String)>,
+    Query(SbomQuery { file }): Query<SbomQuery>,
+    GithubToken(token): GithubToken,
+) -> Result<Json<Value>, ErrorResponse> {
+    let (configured_name, repository) = namespace.find_repository(&package_name)?;
+    let metadata = namespace
+        .metadata
+        .get(configured_name)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let release = metadata
+        .releases
+        .iter()
+        .find(|release| release.version() == version)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let wheel = release
+        .assets
+        .iter()
+        .filter(|asset| asset.name.ends_with(".whl"))
+        .find(|asset| file.as_ref().is_none_or(|file| asset.name == *file))
+        .ok_or(ErrorResponse::PageNotFound)?;
+    if let Some(document) = app_state.sboms.get(wheel.id) {
+        return Ok(Json(document));
+    }
+
+    let cached = match wheel.sha256() {
+        Some(sha256) => app_state.cache.touch(sha256).await,
+        None => None,
+    };
+    let content = match cached {
+        Some(path) => tokio::fs::read(path).await?,
+        None => {
+            let client = GithubClient::new(&app_state.github, token);
+            catalog::download_wheel(&client, repository, wheel)
+                .await
+                .map_err(|error| ErrorResponse::ServerError(Some(error)))?
+                .ok_or(ErrorResponse::ServerError(Some(format!(
+                    "{} is too large to generate its SBOM",
+                    wheel.name
+                ))))?
+        }
+    };
+    let files = catalog::dist_info(content, &["METADATA", "RECORD"])
+        .map_err(|error| ErrorResponse::ServerError(Some(format!("{}: {}", wheel.name, error))))?;
+    let document = document(wheel, &files[0], &files[1]);
+    app_state.sboms.insert(wheel.id, document.clone());
+    return Ok(Json(document));
+}
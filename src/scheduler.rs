@@ -0,0 +1,211 @@
+//! Paces the calls to GitHub to stay under its secondary rate limits: at most
+//! `GITHUB_MAX_CONCURRENT` calls in flight and `GITHUB_POINTS_PER_MINUTE`
+//! points spent in any minute, a read costing one point and a write five, as
+//! GitHub counts them. Background jobs (sync, link checks, hash backfill,
+//! prefetch) get half of the calls in flight and stop short of the last fifth
+//! of the points, so requests of clients don't wait behind them. When GitHub
+//! still answers with a secondary rate limit every call waits as long as it
+//! asks for.
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::config::Config;
+use crate::metrics;
+
+pub const MAX_CONCURRENT: usize = 20;
+pub const POINTS_PER_MINUTE: u64 = 900;
+
+const MINUTE: Duration = Duration::from_secs(60);
+/// How long GitHub wants clients to wait after a secondary rate limit without `Retry-After`.
+const DEFAULT_PAUSE: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy)]
+pub enum Priority {
+    /// on behalf of a client waiting for the response
+    Interactive,
+    Background,
+}
+
+#[derive(Default)]
+struct Window {
+    /// points spent in the last minute, oldest first
+    spent: VecDeque<(Instant, u64)>,
+    /// calls wait until then after a secondary rate limit
+    paused_until: Option<Instant>,
+}
+
+pub struct Scheduler {
+    in_flight: Semaphore,
+    background: Semaphore,
+    points_per_minute: u64,
+    window: Mutex<Window>,
+}
+
+impl Scheduler {
+    pub fn from_config(config: &Config) -> Self {
+        return Scheduler::new(config.github_max_concurrent, config.github_points_per_minute);
+    }
+
+    fn new(max_concurrent: usize, points_per_minute: u64) -> Self {
+        return Scheduler {
+            in_flight: Semaphore::new(max_concurrent.max(1)),
+            background: Semaphore::new((max_concurrent / 2).max(1)),
+            points_per_minute,
+            window: Mutex::default(),
+        };
+    }
+
+    /// Spends `points`, or how long to wait before trying again.
+    fn spend(&self, priority: Priority, points: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        if let Some(paused_until) = window.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            window.paused_until = None;
+        }
+        while window
+            .spent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= MINUTE)
+        {
+            window.spent.pop_front();
+        }
+        let budget = match priority {
+            Priority::Interactive => self.points_per_minute,
+            Priority::Background => self.points_per_minute * 4 / 5,
+        };
+        let spent: u64 = window.spent.iter().map(|(_, points)| points).sum();
+        match window.spent.front() {
+            Some((oldest, _)) if spent + points > budget => {
+                return Err(MINUTE - now.duration_since(*oldest));
+            }
+            _ => {}
+        }
+        window.spent.push_back((now, points));
+        return Ok(());
+    }
+
+    /// Waits until a call costing `points` can be sent.
+    pub async fn acquire(&self, priority: Priority, points: u64) -> Permit<'_> {
+        let queued = Queued::new();
+        let background = match priority {
+            Priority::Interactive => None,
+            Priority::Background => Some(self.background.acquire().await.unwrap()),
+        };
+        let in_flight = self.in_flight.acquire().await.unwrap();
+        while let Err(wait) = self.spend(priority, points) {
+            tokio::time::sleep(wait).await;
+        }
+        drop(queued);
+        return Permit {
+            _in_flight: in_flight,
+            _background: background,
+        };
+    }
+
+    /// Holds back every call for `duration`.
+    pub fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut window = self.window.lock().unwrap();
+        window.paused_until = Some(
+            window
+                .paused_until
+                .map_or(until, |paused| paused.max(until)),
+        );
+    }
+
+    /// Points spent in the last minute and calls that can still be sent at once, for `/debug/status`.
+    pub fn status(&self) -> Value {
+        let now = Instant::now();
+        let window = self.window.lock().unwrap();
+        let spent: u64 = window
+            .spent
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) < MINUTE)
+            .map(|(_, points)| points)
+            .sum();
+        return json!({
+            "points_per_minute": self.points_per_minute,
+            "points_spent": spent,
+            "available": self.in_flight.available_permits(),
+            "paused_for": window
+                .paused_until
+                .map(|until| until.saturating_duration_since(now).as_secs()),
+        });
+    }
+}
+
+/// Allows a call to GitHub while it is held.
+pub struct Permit<'a> {
+    _in_flight: SemaphorePermit<'a>,
+    _background: Option<SemaphorePermit<'a>>,
+}
+
+/// Counts a call in `GITHUB_REQUESTS_QUEUED` while it waits, also when the
+/// wait is given up.
+struct Queued;
+
+impl Queued {
+    fn new() -> Self {
+        metrics::GITHUB_REQUESTS_QUEUED.fetch_add(1, Ordering::Relaxed);
+        return Queued;
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        metrics::GITHUB_REQUESTS_QUEUED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How long to wait when `response` is a secondary rate limit.
+pub fn secondary_limit(response: &reqwest::Response) -> Option<Duration> {
+    let status = response.status();
+    if status != reqwest::StatusCode::FORBIDDEN && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+    {
+        return None;
+    }
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+        .map(Duration::from_secs);
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Some(retry_after.unwrap_or(DEFAULT_PAUSE));
+    }
+    // other 403s are missing permissions, or the primary rate limit running out
+    return retry_after;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_calls_leave_points_to_clients() {
+        let scheduler = Scheduler::new(2, 10);
+        for _ in 0..8 {
+            assert!(scheduler.spend(Priority::Background, 1).is_ok());
+        }
+        assert!(scheduler.spend(Priority::Background, 1).is_err());
+        assert!(scheduler.spend(Priority::Interactive, 2).is_ok());
+        assert!(scheduler.spend(Priority::Interactive, 1).is_err());
+    }
+
+    #[test]
+    fn pauses_hold_every_call_back() {
+        let scheduler = Scheduler::new(2, 10);
+        scheduler.pause(Duration::from_secs(30));
+        scheduler.pause(Duration::from_secs(5));
+        let wait = scheduler.spend(Priority::Interactive, 1).unwrap_err();
+        assert!(wait > Duration::from_secs(25));
+    }
+}
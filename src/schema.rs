@@ -0,0 +1,243 @@
+//! JSON Schema of the config files, printed by `pigi config schema` for
+//! editors and CI validation. Kept next to the structs they describe in
+//! `config.rs` and `notify.rs`: every new key should be described here as well.
+use serde_json::{json, Value};
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+fn secret(description: &str) -> Value {
+    return json!({
+        "type": "string",
+        "description": format!("{}, or `secret:<name>[#<key>]` to fetch it from SECRETS_PROVIDER", description),
+    });
+}
+
+pub fn repos() -> Value {
+    let owner = json!({"type": "string", "description": "GitHub user or organization owning the repositories without an owner"});
+    let renames = json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "required": ["pattern", "replacement"],
+            "properties": {
+                "pattern": {"type": "string", "description": "Regular expression matched against asset names"},
+                "replacement": {"type": "string", "description": "Replaces the matched part, `$1` or `${name}` insert capture groups and `{version}` the release's version"},
+            },
+        },
+        "description": "Rules giving assets with non-standard names a standard file name, the first matching rule applies",
+    });
+    let quota = json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "max_file_size": {"type": "integer", "minimum": 0, "description": "Bytes a file may have"},
+            "max_files_per_version": {"type": "integer", "minimum": 0, "description": "Files a version may have"},
+            "max_storage": {"type": "integer", "minimum": 0, "description": "Bytes all files may have together"},
+        },
+        "description": "Limits enforced by pigi publish, pigi import and mirroring",
+    });
+    let repository = json!({
+        "oneOf": [
+            {"type": "string", "description": "Name of the repository, owned by default_owner"},
+            {
+                "type": "object",
+                "required": ["name"],
+                "properties": {
+                    "name": {"type": "string", "description": "Name of the repository, or of the PyPI project"},
+                    "owner": {"type": "string", "description": "Owner of the repository, defaults to default_owner"},
+                    "provider": {
+                        "enum": ["github", "pypi", "local", "s3", "proxy"],
+                        "default": "github",
+                        "description": "Where releases come from, files of PyPI projects are mirrored into the cache; local and s3 packages keep the files published with the upload endpoint in STORAGE_DIR or S3_BUCKET; proxy projects are read from `index`",
+                    },
+                    "index": {
+                        "type": "object",
+                        "required": ["url"],
+                        "additionalProperties": false,
+                        "description": "Simple index a project with the proxy provider is served from",
+                        "properties": {
+                            "url": {"type": "string", "description": "Base url of the index, like `https://devpi.internal/root/prod/+simple/`"},
+                            "username": {"type": "string"},
+                            "password": {"type": "string", "description": "Sent with basic auth to the index's host, can be a `secret:` reference"},
+                        },
+                    },
+                    "specifier": {"type": "string", "description": "PEP 440 specifiers of the versions served, like `>=2.31,<3`"},
+                    "exclude_versions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Exact versions not served",
+                    },
+                    "block_pattern": {"type": "string", "description": "Versions matching this pattern are not served, with `*` and `?` wildcards"},
+                    "renames": renames,
+                    "latest_only": {
+                        "type": "boolean",
+                        "default": false,
+                        "description": "Serve only the newest release that isn't a prerelease",
+                    },
+                    "quota": {"$ref": "#/$defs/quota"},
+                    "conda_channel": {
+                        "type": "string",
+                        "description": "Conda channel the .conda and .tar.bz2 files of the releases are listed in, at /conda/<channel>/",
+                    },
+                    "downloads": {
+                        "type": "boolean",
+                        "default": false,
+                        "description": "Serve every file of the releases, not only Python distributions, at /downloads/<package>/<version>/<file>",
+                    },
+                    "links": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["label", "url"],
+                            "properties": {
+                                "label": {"type": "string"},
+                                "url": {"type": "string"},
+                            },
+                        },
+                        "description": "Links shown on the package page, like its documentation or support channel",
+                    },
+                    "asset_patterns": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only assets matching one of these patterns, with `*` and `?` wildcards, are files of the package",
+                    },
+                    "version_from_tag": {"type": "string", "description": "Regular expression taking the version out of release tags, its `version` group, first group or whole match; by default a leading `v` is stripped"},
+                    "visibility": {
+                        "enum": ["public", "private"],
+                        "default": "private",
+                        "description": "Public packages are also served under /public/ without access tokens",
+                    },
+                },
+            },
+        ],
+    });
+    let access_tokens = json!({
+        "type": "array",
+        "items": {"type": "string"},
+        "description": "Tokens clients must send as the basic auth password, except under /public/",
+    });
+    let trusted_publishing = json!({
+        "type": "boolean",
+        "default": false,
+        "description": "Accept GitHub Actions OIDC tokens to publish the packages of the workflow's repository",
+    });
+    let repos = json!({
+        "type": "object",
+        "description": "Repositories by the package name they are served as",
+        "additionalProperties": {"$ref": "#/$defs/repository"},
+    });
+    return json!({
+        "$schema": DRAFT,
+        "title": "pigi repos config",
+        "description": "Read from REPOS_CONFIG_PATH",
+        "$defs": {
+            "quota": quota,
+            "repository": repository,
+            "repos": repos,
+            "namespace": {
+                "type": "object",
+                "required": ["repos"],
+                "additionalProperties": false,
+                "properties": {
+                    "repos": {"$ref": "#/$defs/repos"},
+                    "default_owner": owner,
+                    "github_token": secret("Token used for the repositories of the namespace instead of GITHUB_TOKEN"),
+                    "hosts": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Hosts serving the namespace at their root, next to /<namespace>/simple/",
+                    },
+                    "access_tokens": access_tokens,
+                    "quota": {"$ref": "#/$defs/quota"},
+                    "trusted_publishing": trusted_publishing,
+                },
+            },
+        },
+        "oneOf": [
+            {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "repos": {"$ref": "#/$defs/repos"},
+                    "default_owner": owner,
+                    "access_tokens": access_tokens,
+                    "quota": {"$ref": "#/$defs/quota"},
+                    "trusted_publishing": trusted_publishing,
+                    "namespaces": {
+                        "type": "object",
+                        "additionalProperties": {"$ref": "#/$defs/namespace"},
+                    },
+                },
+            },
+            {"$ref": "#/$defs/repos"},
+        ],
+    });
+}
+
+pub fn notifications() -> Value {
+    return json!({
+        "$schema": DRAFT,
+        "title": "pigi notifications config",
+        "description": "Read from NOTIFICATIONS_CONFIG_PATH",
+        "type": "object",
+        "required": ["notifiers", "routes"],
+        "properties": {
+            "notifiers": {
+                "type": "object",
+                "description": "Channels by the name routes refer to them with",
+                "additionalProperties": {
+                    "oneOf": [
+                        {
+                            "type": "object",
+                            "required": ["type", "webhook_url"],
+                            "properties": {
+                                "type": {"const": "slack"},
+                                "webhook_url": secret("Slack incoming webhook"),
+                            },
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "url"],
+                            "properties": {
+                                "type": {"const": "webhook"},
+                                "url": secret("URL the release is posted to as json"),
+                            },
+                        },
+                        {
+                            "type": "object",
+                            "required": ["type", "smtp_host", "from", "to"],
+                            "properties": {
+                                "type": {"const": "email"},
+                                "smtp_host": {"type": "string"},
+                                "smtp_port": {"type": "integer", "minimum": 0, "maximum": 65535, "default": 587},
+                                "username": {"type": "string"},
+                                "password": secret("SMTP password"),
+                                "from": {"type": "string"},
+                                "to": {"type": "array", "items": {"type": "string"}},
+                            },
+                        },
+                    ],
+                },
+            },
+            "routes": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["packages", "notifiers"],
+                    "properties": {
+                        "packages": {
+                            "type": "array",
+                            "items": {"type": "string"},
+                            "description": "Package names, `*` matches any characters",
+                        },
+                        "notifiers": {
+                            "type": "array",
+                            "items": {"type": "string"},
+                            "description": "Names of the notifiers new releases of the packages are sent to",
+                        },
+                    },
+                },
+            },
+        },
+    });
+}
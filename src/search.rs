@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use askama::Template;
+use askama_axum::Response;
+use axum::extract::{Query, State};
+use serde::{Deserialize, Serialize};
+
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::templates::{Page, TemplateContext};
+use crate::theme::Theme;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    name: String,
+    normalized_name: String,
+    description: Option<String>,
+    /// from the latest wheel's metadata
+    summary: Option<String>,
+    license: Option<String>,
+    classifiers: Vec<String>,
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "search.html")]
+pub struct SearchTemplate {
+    query: String,
+    results: Vec<SearchResult>,
+    context: TemplateContext,
+    theme: Theme,
+}
+
+impl Page for SearchTemplate {
+    const NAME: &'static str = "search.html";
+
+    fn context(&mut self) -> &mut TemplateContext {
+        return &mut self.context;
+    }
+
+    fn theme(&mut self) -> &mut Theme {
+        return &mut self.theme;
+    }
+}
+
+pub async fn search(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Query(SearchQuery { q }): Query<SearchQuery>,
+) -> Response {
+    let query = q.unwrap_or_default().trim().to_string();
+    let needle = query.to_lowercase();
+    let mut results: Vec<SearchResult> = namespace
+        .repos
+        .all()
+        .into_iter()
+        .map(|name| {
+            let metadata = namespace.metadata.get(&name).unwrap_or_default();
+            let catalog = metadata.catalog.unwrap_or_default();
+            SearchResult {
+                normalized_name: names::normalize(&name),
+                name,
+                description: metadata.description,
+                summary: catalog.summary,
+                license: catalog.license,
+                classifiers: catalog.classifiers,
+            }
+        })
+        .filter(|result| {
+            result.name.to_lowercase().contains(&needle)
+                || [&result.description, &result.summary]
+                    .into_iter()
+                    .flatten()
+                    .any(|text| text.to_lowercase().contains(&needle))
+        })
+        .collect();
+    results.sort_by(|a, b| a.normalized_name.cmp(&b.normalized_name));
+    return app_state.templates.render(SearchTemplate {
+        query,
+        results,
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    });
+}
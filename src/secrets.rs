@@ -0,0 +1,218 @@
+//! Secrets that can be kept out of env variables and config files. A value
+//! `secret:<name>` (or `secret:<name>#<key>` for a field of a structured
+//! secret) is fetched from the provider selected with `SECRETS_PROVIDER`:
+//! `vault` (HashiCorp Vault, KV engine) or `aws-secrets-manager`.
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::aws::AwsCredentials;
+use crate::config::Config;
+use crate::redact::{self, log};
+use crate::reporting::Report;
+use crate::AppState;
+
+const PREFIX: &str = "secret:";
+
+/// Config value that is either given literally or a reference to a secret,
+/// resolved by `SecretProvider::refresh`.
+pub struct Secret {
+    reference: Option<String>,
+    value: RwLock<Option<String>>,
+}
+
+impl Secret {
+    pub fn new(value: Option<String>) -> Self {
+        if let Some(value) = value.as_ref().filter(|value| !value.starts_with(PREFIX)) {
+            redact::register(value);
+        }
+        return match value {
+            Some(value) if value.starts_with(PREFIX) => Secret {
+                reference: Some(value),
+                value: RwLock::new(None),
+            },
+            value => Secret {
+                reference: None,
+                value: RwLock::new(value),
+            },
+        };
+    }
+
+    /// Current value, `None` when not set or the secret couldn't be fetched yet.
+    pub fn get(&self) -> Option<String> {
+        return self.value.read().unwrap().clone();
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        return Ok(Secret::new(Some(String::deserialize(deserializer)?)));
+    }
+}
+
+pub enum SecretProvider {
+    None,
+    Vault { address: String, token: String },
+    AwsSecretsManager(AwsCredentials),
+}
+
+pub fn env(name: &str) -> String {
+    return std::env::var(name).unwrap_or_else(|_| panic!("{} env variable is not set", name));
+}
+
+impl SecretProvider {
+    /// Provider named by `SECRETS_PROVIDER`, configured with the env variables
+    /// its own tooling uses.
+    pub fn from_config(config: &Config) -> Self {
+        return match config.secrets_provider.as_deref() {
+            None => SecretProvider::None,
+            Some("vault") => {
+                let token = env("VAULT_TOKEN");
+                redact::register(&token);
+                SecretProvider::Vault {
+                    address: env("VAULT_ADDR").trim_end_matches('/').to_string(),
+                    token,
+                }
+            }
+            Some("aws-secrets-manager") => {
+                SecretProvider::AwsSecretsManager(AwsCredentials::from_env())
+            }
+            Some(other) => panic!("unknown SECRETS_PROVIDER {}", other),
+        };
+    }
+
+    /// `value` itself, or the secret it refers to.
+    pub async fn resolve(&self, value: &str) -> Result<String, String> {
+        let Some(reference) = value.strip_prefix(PREFIX) else {
+            return Ok(value.to_string());
+        };
+        let (name, key) = match reference.split_once('#') {
+            Some((name, key)) => (name, Some(key)),
+            None => (reference, None),
+        };
+        let client = reqwest::Client::new();
+        let secret = match self {
+            SecretProvider::None => {
+                return Err(format!(
+                    "{} refers to a secret, but SECRETS_PROVIDER is not set",
+                    value
+                ))
+            }
+            SecretProvider::Vault { address, token } => {
+                let response = client
+                    .get(format!("{}/v1/{}", address, name))
+                    .header("X-Vault-Token", token)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|error| error.to_string())?;
+                let body: Value = response.json().await.map_err(|error| error.to_string())?;
+                // KV version 2 nests the secret one level deeper
+                match body["data"].get("data") {
+                    Some(data) if data.is_object() => data.clone(),
+                    _ => body["data"].clone(),
+                }
+            }
+            SecretProvider::AwsSecretsManager(credentials) => {
+                let body = json!({ "SecretId": name }).to_string();
+                let host = format!("secretsmanager.{}.amazonaws.com", credentials.region);
+                let request = credentials
+                    .sign(
+                        client.post(format!("https://{}/", host)),
+                        "secretsmanager",
+                        "POST",
+                        &host,
+                        "/",
+                        vec![
+                            ("content-type", "application/x-amz-json-1.1".to_string()),
+                            ("x-amz-target", "secretsmanager.GetSecretValue".to_string()),
+                        ],
+                        &hex::encode(Sha256::digest(&body)),
+                    )
+                    .body(body);
+                let response = request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|error| error.to_string())?;
+                let body: Value = response.json().await.map_err(|error| error.to_string())?;
+                let secret_string = body["SecretString"]
+                    .as_str()
+                    .ok_or(format!("secret {} has no SecretString", name))?;
+                match key {
+                    Some(_) => serde_json::from_str(secret_string).map_err(|error| {
+                        format!("secret {} isn't a json object: {}", name, error)
+                    })?,
+                    None => Value::String(secret_string.to_string()),
+                }
+            }
+        };
+        let value = match key {
+            Some(key) => &secret[key],
+            None => &secret,
+        };
+        return value.as_str().map(|value| value.to_string()).ok_or(format!(
+            "secret {} has no string {}",
+            name,
+            key.unwrap_or("value")
+        ));
+    }
+
+    /// Fetches the current value of `secret`, when it is a reference.
+    pub async fn refresh(&self, secret: &Secret) -> Result<(), String> {
+        let Some(reference) = &secret.reference else {
+            return Ok(());
+        };
+        let value = self.resolve(reference).await?;
+        redact::register(&value);
+        *secret.value.write().unwrap() = Some(value);
+        return Ok(());
+    }
+}
+
+/// Fetches every secret referenced by the configuration, returns why fetching failed.
+pub async fn refresh_all(app_state: &AppState) -> Vec<String> {
+    let mut errors = vec![];
+    let index_passwords: Vec<Arc<Secret>> = app_state
+        .namespaces
+        .iter()
+        .flat_map(|namespace| namespace.repos.iter())
+        .filter_map(|(_, repository)| repository.index.as_ref()?.password.clone())
+        .collect();
+    let secrets = app_state
+        .namespaces
+        .iter()
+        .map(|namespace| namespace.github_token.as_ref())
+        .chain(index_passwords.iter().map(Arc::as_ref))
+        .chain(app_state.notifier.secrets());
+    for secret in secrets {
+        if let Err(error) = app_state.secrets.refresh(secret).await {
+            let message = format!("Failed to fetch secret: {}", error);
+            log!("{}", message);
+            app_state
+                .reporter
+                .report(Report::task("secrets refresh", message.clone()));
+            errors.push(message);
+        }
+    }
+    return errors;
+}
+
+/// Refetches secrets every `SECRETS_REFRESH_INTERVAL`, so rotated tokens are
+/// picked up without a restart.
+pub fn spawn_refresh_worker(app_state: Arc<AppState>) {
+    if matches!(app_state.secrets, SecretProvider::None) {
+        return;
+    }
+    tokio::spawn(async move {
+        let period = app_state.config.secrets_refresh_interval;
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            let errors = refresh_all(&app_state).await;
+            app_state.health.record("secrets refresh", errors);
+        }
+    });
+}
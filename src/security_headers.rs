@@ -0,0 +1,179 @@
+//! Security headers of the HTML pages: a `Content-Security-Policy` allowing
+//! only pigi's own resources (and the Swagger UI assets on `/api/docs`),
+//! `X-Content-Type-Options`, `Referrer-Policy`, `X-Frame-Options`,
+//! `Cross-Origin-Opener-Policy` and `Permissions-Policy`. Inline scripts of
+//! the built-in templates and of those of `TEMPLATES_DIR` are allowed by
+//! their hash, taken from the templates at startup rather than from the
+//! pages, so a script injected into a page isn't. `SECURITY_HEADERS=false`
+//! turns them off.
+use std::fs;
+use std::sync::Arc;
+
+use askama_axum::Response;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::error::ErrorResponse;
+use crate::redact::log;
+use crate::AppState;
+
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'self'; style-src 'self'; \
+    img-src 'self' data: https:; object-src 'none'; base-uri 'self'; \
+    form-action 'self'; frame-ancestors 'none'";
+pub const DEFAULT_REFERRER_POLICY: &str = "same-origin";
+/// Where `/api/docs` loads the Swagger UI from.
+const SWAGGER_UI_ORIGIN: &str = "https://unpkg.com";
+
+/// Built-in templates with inline scripts.
+const TEMPLATES: [&str; 3] = [
+    include_str!("../templates/api_docs.html"),
+    include_str!("../templates/packages.html"),
+    include_str!("../templates/simple.html"),
+];
+
+/// Hashes the inline scripts of the built-in templates and of the templates
+/// of `TEMPLATES_DIR`. Scripts are hashed as written, those with template
+/// expressions in them are rendered differently and not allowed.
+pub fn script_hashes(config: &Config) -> Vec<String> {
+    let mut hashes: Vec<String> = TEMPLATES
+        .iter()
+        .flat_map(|template| inline_script_hashes(template))
+        .collect();
+    if let Some(templates_dir) = &config.templates_dir {
+        match fs::read_dir(templates_dir) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    if let Ok(template) = fs::read_to_string(entry.path()) {
+                        hashes.extend(inline_script_hashes(&template));
+                    }
+                }
+            }
+            Err(error) => log!("Can't read {} for its scripts: {}", templates_dir, error),
+        }
+    }
+    hashes.sort();
+    hashes.dedup();
+    return hashes;
+}
+
+/// `'sha256-...'` sources of the inline scripts of `html`.
+fn inline_script_hashes(html: &str) -> Vec<String> {
+    let mut hashes = vec![];
+    let mut rest = html;
+    while let Some(start) = rest.find("<script") {
+        rest = &rest[start..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..tag_end];
+        rest = &rest[tag_end + 1..];
+        let Some(end) = rest.find("</script>") else {
+            break;
+        };
+        if !tag.contains("src=") {
+            let digest = Sha256::digest(&rest[..end]);
+            hashes.push(format!(
+                "'sha256-{}'",
+                base64::engine::general_purpose::STANDARD.encode(digest)
+            ));
+        }
+        rest = &rest[end..];
+    }
+    return hashes;
+}
+
+/// `policy` with `hashes` added to its `script-src`, unless it allows every
+/// inline script anyway, which hashes would turn off.
+fn with_script_hashes(policy: &str, hashes: &[String]) -> String {
+    if hashes.is_empty() {
+        return policy.to_string();
+    }
+    return policy
+        .split(';')
+        .map(|directive| {
+            let directive = directive.trim();
+            if directive.starts_with("script-src ") && !directive.contains("'unsafe-inline'") {
+                return format!("{} {}", directive, hashes.join(" "));
+            }
+            directive.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+}
+
+/// `policy` with the Swagger UI origin added to its `script-src` and `style-src`.
+fn with_swagger_ui(policy: &str) -> String {
+    return policy
+        .split(';')
+        .map(|directive| {
+            let directive = directive.trim();
+            if directive.starts_with("script-src ") || directive.starts_with("style-src ") {
+                return format!("{} {}", directive, SWAGGER_UI_ORIGIN);
+            }
+            directive.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+}
+
+/// Adds the security headers to HTML responses.
+pub async fn apply(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // `/api/docs` of any namespace or public view
+    let is_api_docs = request.uri().path().ends_with("/api/docs");
+    let response = next.run(request).await;
+    let config = &app_state.config;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if !config.security_headers || !is_html {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(error) => {
+            log!("Failed to read the page to add security headers: {}", error);
+            return ErrorResponse::ServerError(None).into_response();
+        }
+    };
+    let mut headers = vec![
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        (header::REFERRER_POLICY, config.referrer_policy.clone()),
+        (header::X_FRAME_OPTIONS, "DENY".to_string()),
+        (
+            HeaderName::from_static("cross-origin-opener-policy"),
+            "same-origin".to_string(),
+        ),
+        (
+            HeaderName::from_static("permissions-policy"),
+            "camera=(), microphone=(), geolocation=(), payment=()".to_string(),
+        ),
+    ];
+    if !config.content_security_policy.is_empty() {
+        let mut policy =
+            with_script_hashes(&config.content_security_policy, &app_state.script_hashes);
+        if is_api_docs {
+            policy = with_swagger_ui(&policy);
+        }
+        headers.push((header::CONTENT_SECURITY_POLICY, policy));
+    }
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            parts.headers.entry(name).or_insert(value);
+        }
+    }
+    return Response::from_parts(parts, Body::from(body));
+}
@@ -0,0 +1,349 @@
+//! Identities of automation, enabled by `SERVICE_ACCOUNTS_PATH`. A service
+//! account has a name, the packages it may access and the operations it may
+//! perform: `read` pages and downloads, `publish` with the write endpoints of
+//! the API, `admin` the `/debug` endpoints. Accounts are managed with
+//! `/debug/service-accounts` and kept in the file, only the sha256 of their
+//! tokens is stored. Requests with a service account's token as the basic
+//! auth password are checked against it, and its writes and admin requests
+//! are logged with its name.
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Path, State};
+use axum::http::request::Parts;
+use axum::http::{Method, StatusCode};
+use axum::response::Json;
+use axum_auth::AuthBasic;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::debug;
+use crate::error::ErrorResponse;
+use crate::glob;
+use crate::namespace::Namespace;
+use crate::redact::log;
+use crate::AppState;
+
+/// Prefix of service account tokens, tells them apart from GitHub tokens.
+const TOKEN_PREFIX: &str = "pigi_sa_";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Read,
+    Publish,
+    Admin,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        return match self {
+            Operation::Read => "read",
+            Operation::Publish => "publish",
+            Operation::Admin => "admin",
+        };
+    }
+
+    /// Operation performed by a request to `path`, as routed within its namespace.
+    pub fn of(method: &Method, path: &str) -> Self {
+        if path.starts_with("/debug/") {
+            return Operation::Admin;
+        }
+        if method == Method::POST && path.starts_with("/api/") {
+            return Operation::Publish;
+        }
+        return Operation::Read;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ServiceAccount {
+    pub name: String,
+    /// qualified package names (`team-a/alpha`), `*` and `?` match any characters
+    pub packages: Vec<String>,
+    pub operations: Vec<Operation>,
+    token_sha256: String,
+    /// unix timestamp
+    created_at: u64,
+}
+
+impl ServiceAccount {
+    /// Account of a CI workflow authenticated by trusted publishing, never stored.
+    pub fn workflow(name: String, packages: Vec<String>) -> Self {
+        return ServiceAccount {
+            name,
+            packages,
+            operations: vec![Operation::Read, Operation::Publish],
+            token_sha256: String::new(),
+            created_at: now(),
+        };
+    }
+
+    pub fn allows(&self, operation: Operation) -> bool {
+        return self.operations.contains(&operation);
+    }
+
+    /// Refuses access to `package_name` of `namespace` when it isn't one of the account's packages.
+    pub fn check_package(
+        &self,
+        namespace: &Namespace,
+        package_name: &str,
+    ) -> Result<(), ErrorResponse> {
+        let qualified_name = namespace.qualified_name(package_name);
+        if self
+            .packages
+            .iter()
+            .any(|pattern| glob::matches(pattern, &qualified_name))
+        {
+            return Ok(());
+        }
+        return Err(ErrorResponse::Forbidden(format!(
+            "Service account {} may not access {}",
+            self.name, qualified_name
+        )));
+    }
+
+    /// Refuses a request the account may not make, checking the package
+    /// named in the path, the one of request bodies is checked by their handlers.
+    pub fn check_request(
+        &self,
+        namespace: &Namespace,
+        method: &Method,
+        path: &str,
+    ) -> Result<(), ErrorResponse> {
+        let operation = Operation::of(method, path);
+        if !self.allows(operation) {
+            return Err(ErrorResponse::Forbidden(format!(
+                "Service account {} may not {}",
+                self.name,
+                operation.as_str()
+            )));
+        }
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let package_name = match segments.as_slice() {
+            ["simple", package_name, ..]
+            | ["pypi", package_name, ..]
+            | ["downloads", package_name, ..]
+            | ["api", "packages", package_name, ..]
+            | ["snapshots", _, "simple", package_name, ..] => package_name,
+            _ => return Ok(()),
+        };
+        // unknown packages are left to the handler to answer with 404
+        if let Ok((configured_name, _)) = namespace.find_repository(package_name) {
+            return self.check_package(namespace, configured_name);
+        }
+        return Ok(());
+    }
+}
+
+fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+}
+
+fn token_sha256(token: &str) -> String {
+    return hex::encode(Sha256::digest(token));
+}
+
+fn new_token() -> io::Result<String> {
+    let mut random = [0u8; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut random)?;
+    return Ok(format!("{}{}", TOKEN_PREFIX, hex::encode(random)));
+}
+
+pub struct ServiceAccounts {
+    path: PathBuf,
+    accounts: RwLock<Vec<ServiceAccount>>,
+}
+
+impl ServiceAccounts {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let path = PathBuf::from(config.service_accounts_path.as_ref()?);
+        let accounts = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .unwrap_or_else(|error| panic!("Can't read {}: {}", path.display(), error)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => vec![],
+            Err(error) => panic!("Can't read {}: {}", path.display(), error),
+        };
+        return Some(ServiceAccounts {
+            path,
+            accounts: RwLock::new(accounts),
+        });
+    }
+
+    fn save(&self, accounts: &[ServiceAccount]) -> Result<(), String> {
+        let temp_path = self.path.with_extension("tmp");
+        return serde_json::to_vec_pretty(accounts)
+            .map_err(io::Error::other)
+            .and_then(|content| fs::write(&temp_path, content))
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|error| error.to_string());
+    }
+
+    /// Account whose token is `password`.
+    pub fn authenticate(&self, password: &str) -> Option<ServiceAccount> {
+        if !password.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        let token_sha256 = token_sha256(password);
+        return self
+            .accounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|account| account.token_sha256 == token_sha256)
+            .cloned();
+    }
+}
+
+/// Service account the request was authenticated as.
+pub struct CurrentAccount(pub Option<ServiceAccount>);
+
+impl CurrentAccount {
+    pub fn check_package(
+        &self,
+        namespace: &Namespace,
+        package_name: &str,
+    ) -> Result<(), ErrorResponse> {
+        return match &self.0 {
+            Some(account) => account.check_package(namespace, package_name),
+            None => Ok(()),
+        };
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for CurrentAccount {
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        return Ok(CurrentAccount(
+            parts.extensions.get::<ServiceAccount>().cloned(),
+        ));
+    }
+}
+
+/// Logs every request of a service account, and writes and admin requests of
+/// anyone else, with who made them.
+pub fn audit(
+    account: Option<&ServiceAccount>,
+    operation: Operation,
+    method: &Method,
+    path: &str,
+    status: StatusCode,
+) {
+    if account.is_none() && operation == Operation::Read {
+        return;
+    }
+    let actor = match account {
+        Some(account) => format!("service account {}", account.name),
+        None => "user".to_string(),
+    };
+    log!("Audit: {} {} {} {}", actor, method, path, status.as_u16());
+}
+
+fn summary(account: &ServiceAccount) -> Value {
+    return json!({
+        "name": account.name,
+        "packages": account.packages,
+        "operations": account.operations,
+        "created_at": account.created_at,
+    });
+}
+
+fn service_accounts(app_state: &AppState) -> Result<&ServiceAccounts, ErrorResponse> {
+    return app_state
+        .service_accounts
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound);
+}
+
+pub async fn list(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+) -> Result<Json<Value>, ErrorResponse> {
+    debug::authorize(&app_state, auth)?;
+    let accounts = service_accounts(&app_state)?.accounts.read().unwrap();
+    return Ok(Json(json!(accounts
+        .iter()
+        .map(summary)
+        .collect::<Vec<_>>())));
+}
+
+#[derive(Deserialize)]
+pub struct NewServiceAccount {
+    name: String,
+    packages: Vec<String>,
+    operations: Vec<Operation>,
+}
+
+/// Creates an account, or replaces the one with the same name and its token.
+/// The token is only ever shown in the response.
+pub async fn create(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+    Json(new): Json<NewServiceAccount>,
+) -> Result<Json<Value>, ErrorResponse> {
+    debug::authorize(&app_state, auth)?;
+    let service_accounts = service_accounts(&app_state)?;
+    let token = new_token().map_err(|error| {
+        ErrorResponse::ServerError(Some(format!("Can't create a token: {}", error)))
+    })?;
+    let account = ServiceAccount {
+        name: new.name,
+        packages: new.packages,
+        operations: new.operations,
+        token_sha256: token_sha256(&token),
+        created_at: now(),
+    };
+    let mut accounts = service_accounts.accounts.write().unwrap();
+    let mut updated: Vec<ServiceAccount> = accounts
+        .iter()
+        .filter(|existing| existing.name != account.name)
+        .cloned()
+        .collect();
+    updated.push(account.clone());
+    service_accounts.save(&updated).map_err(|error| {
+        ErrorResponse::ServerError(Some(format!("Can't save the service account: {}", error)))
+    })?;
+    *accounts = updated;
+    log!("Created service account {}", account.name);
+    let mut response = summary(&account);
+    response["token"] = json!(token);
+    return Ok(Json(response));
+}
+
+pub async fn remove(
+    State(app_state): State<Arc<AppState>>,
+    auth: Option<AuthBasic>,
+    Path((name,)): Path<(String,)>,
+) -> Result<Json<Value>, ErrorResponse> {
+    debug::authorize(&app_state, auth)?;
+    let service_accounts = service_accounts(&app_state)?;
+    let mut accounts = service_accounts.accounts.write().unwrap();
+    if !accounts.iter().any(|account| account.name == name) {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    let updated: Vec<ServiceAccount> = accounts
+        .iter()
+        .filter(|account| account.name != name)
+        .cloned()
+        .collect();
+    service_accounts.save(&updated).map_err(|error| {
+        ErrorResponse::ServerError(Some(format!("Can't save the service accounts: {}", error)))
+    })?;
+    *accounts = updated;
+    log!("Removed service account {}", name);
+    return Ok(Json(json!({"removed": name})));
+}
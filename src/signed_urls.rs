@@ -0,0 +1,191 @@
+//! Download urls that work without credentials until they expire, for
+//! systems that shouldn't get an access token, e.g. a build farm. Clients of
+//! the index get one from `/api/sign`, the url carries its expiry and an
+//! HMAC-SHA256 signature made with `SIGNED_URL_KEY` of the namespace, path
+//! and expiry, and is let through the access token check of the namespace.
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Query, State};
+use axum::http::Uri;
+use axum::response::Json;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::auth::GithubToken;
+use crate::config::Config;
+use crate::error::ErrorResponse;
+use crate::filenames;
+use crate::github::GithubClient;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::policy::CurrentPolicy;
+use crate::quarantine;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct SignQuery {
+    package: String,
+    /// name of the file, looked up in every release of the package
+    file: String,
+}
+
+fn mac(key: &str, namespace: &str, path: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(format!("{}\n{}\n{}", namespace, path, expires).as_bytes());
+    return mac;
+}
+
+fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+}
+
+/// A signed url to download `file` of `package`, valid for `SIGNED_URL_TTL`.
+/// The access policy of the caller is checked now, not on download.
+pub async fn sign(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+    GithubToken(token): GithubToken,
+    policy: CurrentPolicy,
+    account: CurrentAccount,
+    Query(SignQuery { package, file }): Query<SignQuery>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let Some(key) = &app_state.config.signed_url_key else {
+        return Err(ErrorResponse::PageNotFound);
+    };
+    let client = GithubClient::new(&app_state.github, token);
+    let (configured_name, repository) = namespace.find_repository(&package)?;
+    account.check_package(&namespace, configured_name)?;
+    let mut releases = repository.releases(&client).await?;
+    quarantine::retain_approved(&app_state, &namespace, configured_name, &mut releases);
+    app_state.links.hide_broken(&mut releases);
+    filenames::hide_malformed(configured_name, &mut releases);
+    let release = releases
+        .iter()
+        .find(|release| release.assets.iter().any(|asset| asset.name == file))
+        .ok_or(ErrorResponse::PageNotFound)?;
+    policy.check(release.version(), &file)?;
+
+    let path = format!(
+        "/simple/{}/{}/{}",
+        names::normalize(configured_name),
+        release.version(),
+        file
+    );
+    let expires = now() + app_state.config.signed_url_ttl.as_secs();
+    let signature = hex::encode(
+        mac(key, &namespace.name, &path, expires)
+            .finalize()
+            .into_bytes(),
+    );
+    return Ok(Json(json!({
+        "url": format!(
+            "{}{}{}?expires={}&signature={}",
+            app_state.config.external_url, prefix, path, expires, signature
+        ),
+        "expires": expires,
+    })));
+}
+
+/// Whether `uri`, as routed within `namespace`, is a signed url. Signed urls
+/// that expired or whose signature doesn't match are refused.
+pub fn verify(config: &Config, namespace: &str, uri: &Uri) -> Result<bool, ErrorResponse> {
+    let Some(key) = &config.signed_url_key else {
+        return Ok(false);
+    };
+    return verify_with_key(key, namespace, uri);
+}
+
+fn verify_with_key(key: &str, namespace: &str, uri: &Uri) -> Result<bool, ErrorResponse> {
+    let parameter = |name: &str| {
+        uri.query()?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+    };
+    let Some(signature) = parameter("signature") else {
+        return Ok(false);
+    };
+    let expires = parameter("expires")
+        .and_then(|expires| expires.parse::<u64>().ok())
+        .ok_or(ErrorResponse::Forbidden(
+            "Signed url without a valid expiry".to_string(),
+        ))?;
+    let signature = hex::decode(signature).unwrap_or_default();
+    if mac(key, namespace, uri.path(), expires)
+        .verify_slice(&signature)
+        .is_err()
+    {
+        return Err(ErrorResponse::Forbidden(
+            "Invalid signature of the signed url".to_string(),
+        ));
+    }
+    if expires < now() {
+        return Err(ErrorResponse::Forbidden("Signed url expired".to_string()));
+    }
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::refusal;
+
+    const KEY: &str = "signed-url-test-key";
+    const PATH: &str = "/simple/my-cli/1.0.0/my_cli-1.0.0-py3-none-any.whl";
+
+    fn signed(path: &str, expires: u64) -> Uri {
+        let signature = hex::encode(mac(KEY, "team", PATH, expires).finalize().into_bytes());
+        return format!("{}?expires={}&signature={}", path, expires, signature)
+            .parse()
+            .unwrap();
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let uri = signed(PATH, now() + 60);
+        assert!(verify_with_key(KEY, "team", &uri).is_ok_and(|signed| signed));
+    }
+
+    #[test]
+    fn ignores_unsigned_urls() {
+        let uri: Uri = PATH.parse().unwrap();
+        assert!(verify_with_key(KEY, "team", &uri).is_ok_and(|signed| !signed));
+    }
+
+    #[test]
+    fn refuses_an_expired_url() {
+        let uri = signed(PATH, now() - 1);
+        assert_eq!(
+            refusal(verify_with_key(KEY, "team", &uri)),
+            "Signed url expired"
+        );
+    }
+
+    #[test]
+    fn refuses_a_tampered_url() {
+        let expires = now() + 60;
+        let other_file = signed("/simple/my-cli/1.0.0/my_cli-1.0.0.tar.gz", expires);
+        let later: Uri = signed(PATH, expires)
+            .to_string()
+            .replace(&expires.to_string(), &(expires + 3600).to_string())
+            .parse()
+            .unwrap();
+        for uri in [other_file, later] {
+            assert_eq!(
+                refusal(verify_with_key(KEY, "team", &uri)),
+                "Invalid signature of the signed url"
+            );
+        }
+        let uri = signed(PATH, expires);
+        assert_eq!(
+            refusal(verify_with_key(KEY, "other-team", &uri)),
+            "Invalid signature of the signed url"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Ed25519 signatures of the index pages and JSON API responses, enabled by
+//! `INDEX_SIGNING_KEY_PATH`. The signature of the exact body is sent in the
+//! `X-Pigi-Signature` header and the public key is served at
+//! `/api/signing-key`, so mirrors and clients can check that what they got was
+//! produced by this index and not altered on the way.
+use std::sync::Arc;
+
+use askama_axum::Response;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json};
+use base64::Engine;
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, EncodePublicKey};
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::error::ErrorResponse;
+use crate::redact::log;
+use crate::AppState;
+
+pub const SIGNATURE_HEADER: &str = "x-pigi-signature";
+
+pub struct IndexSigner {
+    key: SigningKey,
+    /// hex sha256 of the public key, lets clients tell rotated keys apart
+    key_id: String,
+}
+
+impl IndexSigner {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let path = config.index_signing_key_path.as_ref()?;
+        let pem = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| panic!("Can't read {}: {}", path, error));
+        let key = SigningKey::from_pkcs8_pem(&pem)
+            .unwrap_or_else(|error| panic!("{} is not an Ed25519 PKCS#8 key: {}", path, error));
+        let key_id = hex::encode(Sha256::digest(key.verifying_key().as_bytes()));
+        return Some(IndexSigner { key, key_id });
+    }
+
+    fn signature(&self, body: &[u8]) -> String {
+        let signature = self.key.sign(body);
+        let signature = base64::engine::general_purpose::STANDARD.encode(signature.to_bytes());
+        return format!("keyid=\"{}\", sig=\"{}\"", self.key_id, signature);
+    }
+}
+
+/// Index pages, of namespaces and snapshots too, and JSON API documents.
+pub fn is_signed(path: &str) -> bool {
+    return (path.contains("/simple/") && path.ends_with('/'))
+        || (path.contains("/pypi/") && path.ends_with("/json"));
+}
+
+/// Adds the signature of the body to successful responses of signed urls.
+pub async fn sign(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(signer) = &app_state.signer else {
+        return next.run(request).await;
+    };
+    let signed = is_signed(request.uri().path());
+    let response = next.run(request).await;
+    if !signed || response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(error) => {
+            log!("Failed to read the response to sign: {}", error);
+            return ErrorResponse::ServerError(None).into_response();
+        }
+    };
+    let signature = HeaderValue::from_str(&signer.signature(&body)).unwrap();
+    parts.headers.insert(SIGNATURE_HEADER, signature);
+    return Response::from_parts(parts, Body::from(body));
+}
+
+/// Public key the responses are signed with.
+pub async fn signing_key(
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let signer = app_state
+        .signer
+        .as_ref()
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let public_key = signer.key.verifying_key();
+    return Ok(Json(json!({
+        "algorithm": "ed25519",
+        "keyid": signer.key_id,
+        "public_key": base64::engine::general_purpose::STANDARD.encode(public_key.as_bytes()),
+        "public_key_pem": public_key.to_public_key_pem(LineEnding::LF).ok(),
+        "header": SIGNATURE_HEADER,
+    })));
+}
@@ -0,0 +1,313 @@
+//! Formats of the simple index: the HTML pages and their JSON form (PEP 691,
+//! with the PEP 700 additions of API version 1.1). The format is negotiated
+//! from `Accept`, quality factors included, and formats scheduled for removal
+//! are served with `Deprecation` and `Sunset` headers.
+use std::time::{Duration, UNIX_EPOCH};
+
+use askama_axum::Response;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::IntoResponse;
+use serde_json::{json, Value};
+
+use crate::companions::{self, CompanionKind};
+use crate::config::Config;
+use crate::github::{version_key, Release};
+use crate::names;
+
+pub const JSON_V1: &str = "application/vnd.pypi.simple.v1+json";
+/// Clients only check the major version, 1.1 adds `versions`, `size` and `upload-time`.
+pub const API_VERSION: &str = "1.1";
+pub const LAST_SERIAL_HEADER: &str = "x-pypi-last-serial";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    JsonV1,
+}
+
+/// Media types of every format with the content type they are answered with,
+/// in the order they are preferred when the client accepts several equally.
+const MEDIA_TYPES: [(&str, Format, &str); 5] = [
+    ("text/html", Format::Html, "text/html; charset=utf-8"),
+    (
+        "application/vnd.pypi.simple.v1+html",
+        Format::Html,
+        "application/vnd.pypi.simple.v1+html",
+    ),
+    (
+        "application/vnd.pypi.simple.latest+html",
+        Format::Html,
+        "application/vnd.pypi.simple.v1+html",
+    ),
+    (JSON_V1, Format::JsonV1, JSON_V1),
+    (
+        "application/vnd.pypi.simple.latest+json",
+        Format::JsonV1,
+        JSON_V1,
+    ),
+];
+
+pub struct Negotiated {
+    pub format: Format,
+    content_type: &'static str,
+}
+
+struct MediaRange<'a> {
+    media_type: &'a str,
+    quality: f32,
+}
+
+fn media_ranges(headers: &HeaderMap) -> Vec<MediaRange<'_>> {
+    return headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut parameters = range.split(';');
+            let media_type = parameters.next()?.trim();
+            let quality = parameters
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!media_type.is_empty()).then_some(MediaRange {
+                media_type,
+                quality,
+            })
+        })
+        .collect();
+}
+
+/// Quality of the most specific range matching `media_type`, `None` when none does.
+fn quality(ranges: &[MediaRange], media_type: &str) -> Option<f32> {
+    let (kind, _) = media_type.split_once('/')?;
+    return ranges
+        .iter()
+        .filter_map(|range| {
+            let specificity = match range.media_type.split_once('/')? {
+                ("*", "*") => 0,
+                (range_kind, "*") if range_kind.eq_ignore_ascii_case(kind) => 1,
+                _ if range.media_type.eq_ignore_ascii_case(media_type) => 2,
+                _ => return None,
+            };
+            Some((specificity, range.quality))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, quality)| quality);
+}
+
+/// The format the client prefers, HTML when it accepts none of them.
+pub fn negotiate(headers: &HeaderMap) -> Negotiated {
+    let ranges = media_ranges(headers);
+    let mut best = (0.0, Format::Html, MEDIA_TYPES[0].2);
+    for (media_type, format, content_type) in MEDIA_TYPES {
+        let quality = quality(&ranges, media_type).unwrap_or(0.0);
+        if quality > best.0 {
+            best = (quality, format, content_type);
+        }
+    }
+    return Negotiated {
+        format: best.1,
+        content_type: best.2,
+    };
+}
+
+/// When the format was deprecated and when it is going to be removed, as unix timestamps.
+fn lifecycle(config: &Config, format: Format) -> (Option<u64>, Option<u64>) {
+    return match format {
+        Format::Html => (
+            config.simple_html_deprecated_at,
+            config.simple_html_sunset_at,
+        ),
+        Format::JsonV1 => (None, None),
+    };
+}
+
+/// Sets the headers every simple index response carries.
+pub fn finish(config: &Config, negotiated: Negotiated, mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(negotiated.content_type),
+    );
+    // caches have to keep the formats apart
+    headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+    let (deprecated_at, sunset_at) = lifecycle(config, negotiated.format);
+    if let Some(deprecated_at) = deprecated_at {
+        // RFC 9745 structured date
+        let value = format!("@{}", deprecated_at);
+        headers.insert("deprecation", HeaderValue::from_str(&value).unwrap());
+    }
+    if let Some(sunset_at) = sunset_at {
+        let value = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(sunset_at));
+        headers.insert("sunset", HeaderValue::from_str(&value).unwrap());
+    }
+    return response;
+}
+
+/// Serial of the package, or on the project list of the latest change, as PyPI sends it.
+pub fn with_serial(mut response: Response, serial: u64) -> Response {
+    response
+        .headers_mut()
+        .insert(LAST_SERIAL_HEADER, HeaderValue::from(serial));
+    return response;
+}
+
+fn json_response(document: Value) -> Response {
+    return ([(header::CONTENT_TYPE, JSON_V1)], document.to_string()).into_response();
+}
+
+/// Every project of the namespace, with names as configured.
+pub fn index(names: Vec<String>) -> Response {
+    let projects: Vec<Value> = names.iter().map(|name| json!({"name": name})).collect();
+    return json_response(json!({
+        "meta": {"api-version": API_VERSION},
+        "projects": projects,
+    }));
+}
+
+/// Files of a project, with urls relative to its page like the HTML form.
+pub fn project(project_name: &str, releases: &[Release]) -> Response {
+    let mut assets: Vec<_> = releases
+        .iter()
+        .flat_map(|release| release.assets.iter().map(move |asset| (release, asset)))
+        .collect();
+    assets.sort_by_cached_key(|(release, asset)| (version_key(release.version()), &asset.name));
+    let files: Vec<Value> = assets
+        .into_iter()
+        .map(|(release, asset)| {
+            let mut hashes = serde_json::Map::new();
+            if let Some(sha256) = asset.sha256() {
+                hashes.insert("sha256".to_string(), json!(sha256));
+            }
+            if let Some(blake2b_256) = &asset.blake2b_256 {
+                hashes.insert("blake2b_256".to_string(), json!(blake2b_256));
+            }
+            let has_sig =
+                companions::find(&release.assets, &asset.name, CompanionKind::Signature).is_some();
+            // PEP 740, relative to the page like `url`
+            let provenance = companions::pep740_attestation(&release.assets, &asset.name)
+                .map(|_| format!("{}/{}.provenance", release.version(), asset.name));
+            let mut file = json!({
+                "filename": asset.name,
+                "url": format!("{}/{}", release.version(), asset.name),
+                "hashes": hashes,
+                "gpg-sig": has_sig,
+                "yanked": false,
+                "size": asset.size,
+                "provenance": provenance,
+            });
+            if !asset.created_at.is_empty() {
+                file["upload-time"] = json!(asset.created_at);
+            }
+            // no core metadata is served, so neither `core-metadata` (PEP 714)
+            // nor the older `dist-info-metadata` key is set
+            file
+        })
+        .collect();
+    let versions = sorted_versions(releases.iter().map(|release| release.version()));
+    return json_response(json!({
+        "meta": {"api-version": API_VERSION},
+        // unlike the index, project pages must use the normalized name
+        "name": names::normalize(project_name),
+        "files": files,
+        "versions": versions,
+    }));
+}
+
+/// `versions` in `version_key` order, each once: releases tagged `1.0` and
+/// `v1.0` are the same version.
+fn sorted_versions<'a>(versions: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut versions: Vec<&str> = versions.collect();
+    versions.sort_by_cached_key(|version| version_key(version));
+    versions.dedup();
+    return versions;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        return headers;
+    }
+
+    fn negotiated(value: &str) -> (bool, &'static str) {
+        let negotiated = negotiate(&accept(value));
+        return (negotiated.format == Format::JsonV1, negotiated.content_type);
+    }
+
+    #[test]
+    fn quality_picks_the_most_specific_range() {
+        let headers = accept("*/*;q=0.1, text/*;q=0.5, text/html;q=0.8");
+        let ranges = media_ranges(&headers);
+        assert_eq!(quality(&ranges, "text/html"), Some(0.8));
+        assert_eq!(quality(&ranges, "text/plain"), Some(0.5));
+        assert_eq!(quality(&ranges, JSON_V1), Some(0.1));
+        let headers = accept("text/html");
+        assert_eq!(quality(&media_ranges(&headers), JSON_V1), None);
+    }
+
+    #[test]
+    fn json_is_served_when_asked_for() {
+        assert_eq!(negotiated(JSON_V1), (true, JSON_V1));
+        assert_eq!(
+            negotiated("application/vnd.pypi.simple.latest+json"),
+            (true, JSON_V1)
+        );
+    }
+
+    #[test]
+    fn higher_quality_wins() {
+        let value = "application/vnd.pypi.simple.v1+json;q=0.9, text/html;q=0.2";
+        assert_eq!(negotiated(value), (true, JSON_V1));
+        let value = "application/vnd.pypi.simple.v1+json;q=0.2, text/html;q=0.9";
+        assert_eq!(negotiated(value), (false, "text/html; charset=utf-8"));
+    }
+
+    #[test]
+    fn ties_prefer_html() {
+        let value = "application/vnd.pypi.simple.v1+json, text/html";
+        assert_eq!(negotiated(value), (false, "text/html; charset=utf-8"));
+        assert_eq!(negotiated("*/*"), (false, "text/html; charset=utf-8"));
+    }
+
+    #[test]
+    fn wildcards_match_their_type() {
+        let value = "application/*, text/html;q=0.5";
+        assert_eq!(
+            negotiated(value),
+            (false, "application/vnd.pypi.simple.v1+html")
+        );
+        let value = "application/*;q=0.5, application/vnd.pypi.simple.v1+json";
+        assert_eq!(negotiated(value), (true, JSON_V1));
+    }
+
+    #[test]
+    fn zero_quality_excludes_a_format() {
+        let value = "text/html;q=0, application/vnd.pypi.simple.v1+json;q=0.1";
+        assert_eq!(negotiated(value), (true, JSON_V1));
+        let value = "*/*, application/vnd.pypi.simple.v1+json;q=0";
+        assert_eq!(negotiated(value), (false, "text/html; charset=utf-8"));
+    }
+
+    #[test]
+    fn html_is_the_fallback() {
+        assert!(negotiate(&HeaderMap::new()).format == Format::Html);
+        assert_eq!(negotiated("image/png"), (false, "text/html; charset=utf-8"));
+        assert!(!negotiated(&JSON_V1.replace("v1", "v2")).0);
+        let value = "text/html;q=0, application/vnd.pypi.simple.v1+json;q=0";
+        assert_eq!(negotiated(value), (false, "text/html; charset=utf-8"));
+    }
+
+    #[test]
+    fn versions_are_sorted_once_each() {
+        let versions = ["1.10.0", "1.2.0", "nightly", "1.2.0", "1.0rc1", "1.0"];
+        assert_eq!(
+            sorted_versions(versions.into_iter()),
+            ["1.0rc1", "1.0", "1.2.0", "1.10.0", "nightly"]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Frozen views of the index: a snapshot pins the latest version of every
+//! package at the time it was taken and serves them as a simple index of its
+//! own, for reproducible builds.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use askama_axum::Response;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json, Redirect};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{latest_release, version_key};
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::templates::TemplateContext;
+use crate::theme::Theme;
+use crate::{AppState, PackageFile, PackageTemplate, Simple, SimpleProject};
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    id: String,
+    namespace: String,
+    created_at: u64,
+    packages: BTreeMap<String, SnapshotPackage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotPackage {
+    owner: String,
+    version: String,
+    files: Vec<SnapshotFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    id: u64,
+    name: String,
+    sha256: Option<String>,
+    has_sig: bool,
+    #[serde(default)]
+    has_provenance: bool,
+}
+
+fn snapshot_path(app_state: &AppState, id: &str) -> Result<PathBuf, ErrorResponse> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    return Ok(PathBuf::from(&app_state.config.snapshots_dir).join(format!("{}.json", id)));
+}
+
+fn load(
+    app_state: &AppState,
+    current: &CurrentNamespace,
+    id: &str,
+) -> Result<Snapshot, ErrorResponse> {
+    let content = fs::read_to_string(snapshot_path(app_state, id)?)
+        .map_err(|_| ErrorResponse::PageNotFound)?;
+    let mut snapshot: Snapshot = serde_json::from_str(&content).map_err(|_| {
+        ErrorResponse::Failure(ErrorCode::Cache, format!("Snapshot {} is corrupted", id))
+    })?;
+    if snapshot.namespace != current.namespace.name {
+        return Err(ErrorResponse::PageNotFound);
+    }
+    if current.namespace.public_view {
+        // a snapshot of the full namespace mustn't reveal its private packages
+        let namespace = &current.namespace;
+        snapshot
+            .packages
+            .retain(|package_name, _| namespace.find_repository(package_name).is_ok());
+    }
+    return Ok(snapshot);
+}
+
+/// Captures the latest synced version of every package of the namespace.
+pub async fn create(
+    State(app_state): State<Arc<AppState>>,
+    current: CurrentNamespace,
+) -> Result<Response, ErrorResponse> {
+    let namespace = &current.namespace;
+    let packages: BTreeMap<String, SnapshotPackage> = namespace
+        .repos
+        .iter()
+        .filter_map(|(package_name, repository)| {
+            let metadata = namespace.metadata.get(package_name)?;
+            let release = latest_release(&metadata.releases)?;
+            let files = PackageFile::from_release(release)
+                .map(|file| SnapshotFile {
+                    id: file.id,
+                    name: file.name,
+                    sha256: file.sha256,
+                    has_sig: file.has_sig,
+                    has_provenance: file.has_provenance,
+                })
+                .collect();
+            let package = SnapshotPackage {
+                owner: repository.owner.clone(),
+                version: release.version().to_string(),
+                files,
+            };
+            Some((names::normalize(package_name), package))
+        })
+        .collect();
+    let created_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let snapshot = Snapshot {
+        id: format!("{:x}", created_at.as_nanos()),
+        namespace: namespace.name.clone(),
+        created_at: created_at.as_secs(),
+        packages,
+    };
+
+    let path = snapshot_path(&app_state, &snapshot.id)?;
+    let content = serde_json::to_string_pretty(&snapshot).unwrap();
+    fs::create_dir_all(&app_state.config.snapshots_dir)
+        .and_then(|_| fs::write(path, content))
+        .map_err(|error| {
+            ErrorResponse::Failure(
+                ErrorCode::Cache,
+                format!("Failed to store snapshot: {}", error),
+            )
+        })?;
+
+    let versions: BTreeMap<&String, &String> = snapshot
+        .packages
+        .iter()
+        .map(|(name, package)| (name, &package.version))
+        .collect();
+    return Ok(Json(json!({
+        "id": snapshot.id,
+        "created_at": snapshot.created_at,
+        "url": format!(
+            "{}{}/snapshots/{}/simple/",
+            app_state.config.external_url, current.prefix, snapshot.id
+        ),
+        "packages": versions,
+    }))
+    .into_response());
+}
+
+pub async fn simple(
+    State(app_state): State<Arc<AppState>>,
+    current: CurrentNamespace,
+    Path((id,)): Path<(String,)>,
+) -> Result<Response, ErrorResponse> {
+    let snapshot = load(&app_state, &current, &id)?;
+    let repos = snapshot
+        .packages
+        .into_keys()
+        .map(|name| SimpleProject {
+            normalized_name: name.clone(),
+            name,
+        })
+        .collect();
+    return Ok(app_state.templates.render(Simple {
+        repos,
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    }));
+}
+
+pub async fn package(
+    State(app_state): State<Arc<AppState>>,
+    current: CurrentNamespace,
+    Path((id, package_name)): Path<(String, String)>,
+) -> Result<Response, ErrorResponse> {
+    let mut snapshot = load(&app_state, &current, &id)?;
+    let package = snapshot
+        .packages
+        .remove(&package_name)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    let mut assets: Vec<PackageFile> = package
+        .files
+        .into_iter()
+        .map(|file| PackageFile {
+            id: file.id,
+            version: package.version.clone(),
+            name: file.name,
+            sha256: file.sha256,
+            has_sig: file.has_sig,
+            has_provenance: file.has_provenance,
+            vulnerabilities: vec![],
+        })
+        .collect();
+    assets.sort_by_cached_key(|file| (version_key(&file.version), file.name.clone()));
+    // links of packages no longer configured are gone with their config
+    let links = current
+        .namespace
+        .find_repository(&package_name)
+        .map(|(_, repository)| repository.links.clone())
+        .unwrap_or_default();
+    return Ok(app_state.templates.render(PackageTemplate {
+        github_org: package.owner,
+        package_name,
+        assets,
+        links,
+        context: TemplateContext::new(),
+        theme: Theme::default(),
+    }));
+}
+
+/// Files of a snapshot are served by the regular download route.
+pub async fn asset(
+    State(app_state): State<Arc<AppState>>,
+    current: CurrentNamespace,
+    Path((id, package_name, version, filename)): Path<(String, String, String, String)>,
+) -> Result<Response, ErrorResponse> {
+    let snapshot = load(&app_state, &current, &id)?;
+    let package = snapshot
+        .packages
+        .get(&package_name)
+        .filter(|package| package.version == version)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    return Ok(Redirect::temporary(&format!(
+        "{}/simple/{}/{}/{}",
+        current.prefix, package_name, package.version, filename
+    ))
+    .into_response());
+}
@@ -0,0 +1,586 @@
+//! Where the files of a package are stored, picked by its `provider`: the
+//! releases of a GitHub repository, or for packages only published with the
+//! upload endpoint a directory under `STORAGE_DIR` (`local`) or the bucket
+//! `S3_BUCKET` (`s3`). Local and S3 storage keep the files of a release under
+//! `<owner>/<name>/<tag>/` and the releases in `<owner>/<name>/releases.json`,
+//! with the same fields as GitHub's so the rest of pigi doesn't tell them apart.
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use axum::async_trait;
+use axum::body::Bytes;
+use futures_util::StreamExt;
+use pep440_rs::Version;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Mutex;
+
+use crate::aws::{self, AwsCredentials};
+use crate::cache::file_sha256;
+use crate::config::{Config, Provider, Repository};
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::{Asset, AssetStream, ByteStream, GithubClient, Release};
+use crate::publish;
+
+const MANIFEST: &str = "releases.json";
+/// Bytes read from a stored file at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// Where local and S3 storage keep files, neither is available without its
+/// variable. S3 storage is signed with the env variables of the AWS tooling.
+pub struct StorageSettings {
+    dir: Option<PathBuf>,
+    s3: Option<S3Settings>,
+    /// serializes updates of manifests, so concurrent uploads don't lose releases
+    manifests: Mutex<()>,
+    client: reqwest::Client,
+}
+
+struct S3Settings {
+    bucket: String,
+    endpoint: String,
+    credentials: AwsCredentials,
+}
+
+impl StorageSettings {
+    pub fn from_config(config: &Config) -> Self {
+        let s3 = config.s3_bucket.as_ref().map(|bucket| {
+            let credentials = AwsCredentials::from_env();
+            let endpoint = config
+                .s3_endpoint
+                .clone()
+                .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", credentials.region));
+            S3Settings {
+                bucket: bucket.clone(),
+                endpoint,
+                credentials,
+            }
+        });
+        return StorageSettings {
+            dir: config.storage_dir.as_ref().map(PathBuf::from),
+            s3,
+            manifests: Mutex::new(()),
+            client: reqwest::Client::new(),
+        };
+    }
+}
+
+fn not_configured(variable: &str) -> ErrorResponse {
+    return ErrorResponse::Failure(
+        ErrorCode::Config,
+        format!("Storage isn't configured, set {}", variable),
+    );
+}
+
+/// Files of the releases of a package.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// All releases, drafts included.
+    async fn releases(&self) -> Result<Vec<Release>, ErrorResponse>;
+
+    /// Adds the file at `path` to the release `tag` of `version`, created
+    /// when missing. Returns the release as it was before.
+    async fn store(&self, tag: &str, version: &str, path: &Path) -> Result<Release, ErrorResponse>;
+
+    async fn download(&self, asset: &Asset) -> Result<AssetStream, ErrorResponse>;
+
+    /// Bytes `range` of `asset`.
+    async fn download_range(
+        &self,
+        asset: &Asset,
+        range: Range<u64>,
+    ) -> Result<ByteStream<io::Error>, ErrorResponse>;
+
+    /// Whether `asset` can still be downloaded, without downloading it.
+    async fn exists(&self, asset: &Asset) -> Result<bool, ErrorResponse>;
+}
+
+/// Storage of `repository`, `None` for mirrored PyPI projects and proxied
+/// ones, which are only read.
+pub fn of<'a>(
+    repository: &'a Repository,
+    client: &'a GithubClient,
+) -> Option<Box<dyn Storage + 'a>> {
+    let prefix = format!("{}/{}", repository.owner, repository.name);
+    let settings = &client.shared().storage;
+    return match repository.provider {
+        Provider::Github => Some(Box::new(GithubStorage { client, repository })),
+        Provider::Pypi | Provider::Proxy => None,
+        Provider::Local => Some(Box::new(Manifested {
+            objects: LocalObjects { settings },
+            prefix,
+            manifests: &settings.manifests,
+        })),
+        Provider::S3 => Some(Box::new(Manifested {
+            objects: S3Objects { settings },
+            prefix,
+            manifests: &settings.manifests,
+        })),
+    };
+}
+
+/// Where the files of a local or S3 package are, like `s3://bucket/owner/name`.
+pub fn location(repository: &Repository, settings: &StorageSettings) -> String {
+    let prefix = format!("{}/{}", repository.owner, repository.name);
+    return match repository.provider {
+        Provider::S3 => S3Objects { settings }.url(&prefix),
+        _ => LocalObjects { settings }.url(&prefix),
+    };
+}
+
+struct GithubStorage<'a> {
+    client: &'a GithubClient,
+    repository: &'a Repository,
+}
+
+#[async_trait]
+impl Storage for GithubStorage<'_> {
+    async fn releases(&self) -> Result<Vec<Release>, ErrorResponse> {
+        let Repository { owner, name, .. } = self.repository;
+        return self.client.releases(owner, name).await;
+    }
+
+    async fn store(&self, tag: &str, version: &str, path: &Path) -> Result<Release, ErrorResponse> {
+        let files = [path.to_path_buf()];
+        return publish::upload(
+            self.client,
+            self.repository,
+            &tag.to_string(),
+            version,
+            &files,
+            false,
+            false,
+        )
+        .await
+        .map_err(|error| ErrorResponse::Failure(ErrorCode::Upstream5xx, error));
+    }
+
+    async fn download(&self, asset: &Asset) -> Result<AssetStream, ErrorResponse> {
+        let Repository { owner, name, .. } = self.repository;
+        return self.client.asset(owner, name, &asset.id.to_string()).await;
+    }
+
+    async fn download_range(
+        &self,
+        asset: &Asset,
+        range: Range<u64>,
+    ) -> Result<ByteStream<io::Error>, ErrorResponse> {
+        let Repository { owner, name, .. } = self.repository;
+        return self
+            .client
+            .asset_range(owner, name, &asset.id.to_string(), range)
+            .await;
+    }
+
+    async fn exists(&self, asset: &Asset) -> Result<bool, ErrorResponse> {
+        let Repository { owner, name, .. } = self.repository;
+        return self.client.asset_exists(owner, name, asset.id).await;
+    }
+}
+
+/// Objects by key, what local and S3 storage keep manifests and files in.
+#[async_trait]
+trait Objects: Send + Sync {
+    /// Bytes `range` of the object `key`, all of them when not set, `None` when missing.
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<Option<AssetStream>, ErrorResponse>;
+
+    async fn put(&self, key: &str, content: Bytes) -> Result<(), ErrorResponse>;
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), ErrorResponse>;
+
+    async fn exists(&self, key: &str) -> Result<bool, ErrorResponse>;
+
+    fn url(&self, key: &str) -> String;
+}
+
+/// Releases listed in a manifest, with their files beside it.
+struct Manifested<'a, O: Objects> {
+    objects: O,
+    /// `<owner>/<name>`
+    prefix: String,
+    manifests: &'a Mutex<()>,
+}
+
+impl<O: Objects> Manifested<'_, O> {
+    fn manifest_key(&self) -> String {
+        return format!("{}/{}", self.prefix, MANIFEST);
+    }
+
+    /// Key of `asset`, kept in its `download_url`.
+    fn key<'a>(&self, asset: &'a Asset) -> Result<&'a str, ErrorResponse> {
+        return asset
+            .download_url
+            .as_deref()
+            .ok_or(ErrorResponse::PageNotFound);
+    }
+}
+
+#[async_trait]
+impl<O: Objects> Storage for Manifested<'_, O> {
+    async fn releases(&self) -> Result<Vec<Release>, ErrorResponse> {
+        let Some(manifest) = self.objects.get(&self.manifest_key(), None).await? else {
+            return Ok(vec![]);
+        };
+        let mut content = vec![];
+        let mut stream = manifest.stream;
+        while let Some(chunk) = stream.next().await {
+            content.extend_from_slice(&chunk?);
+        }
+        return serde_json::from_slice(&content).map_err(|error| {
+            ErrorResponse::Failure(
+                ErrorCode::Cache,
+                format!("Can't read {}: {}", self.manifest_key(), error),
+            )
+        });
+    }
+
+    async fn store(&self, tag: &str, version: &str, path: &Path) -> Result<Release, ErrorResponse> {
+        let _guard = self.manifests.lock().await;
+        let mut releases = self.releases().await?;
+        let index = match releases.iter().position(|release| release.tag_name == tag) {
+            Some(index) => index,
+            None => {
+                let prerelease = Version::from_str(version)
+                    .map(|version| version.any_prerelease())
+                    .unwrap_or(false);
+                releases.push(Release {
+                    id: releases.iter().map(|release| release.id).max().unwrap_or(0) + 1,
+                    tag_name: tag.to_string(),
+                    name: Some(version.to_string()),
+                    html_url: self.objects.url(&format!("{}/{}/", self.prefix, tag)),
+                    published_at: Some(timestamp()),
+                    prerelease,
+                    draft: false,
+                    assets: vec![],
+                    version: None,
+                });
+                releases.len() - 1
+            }
+        };
+        let before = releases[index].clone();
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        if before.assets.iter().any(|asset| asset.name == filename) {
+            return Err(ErrorResponse::BadRequest(format!(
+                "{} is already published to {}",
+                filename, tag
+            )));
+        }
+        let key = format!("{}/{}/{}", self.prefix, tag, filename);
+        self.objects.put_file(&key, path).await?;
+        let id = releases
+            .iter()
+            .flat_map(|release| &release.assets)
+            .map(|asset| asset.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let now = timestamp();
+        releases[index].assets.push(Asset {
+            id,
+            name: filename.to_string(),
+            size: tokio::fs::metadata(path).await?.len(),
+            created_at: now.clone(),
+            updated_at: now,
+            digest: Some(format!("sha256:{}", file_sha256(path).await?)),
+            blake2b_256: None,
+            download_url: Some(key),
+        });
+        let manifest = serde_json::to_vec(&releases)
+            .map_err(|error| ErrorResponse::Failure(ErrorCode::Internal, error.to_string()))?;
+        self.objects
+            .put(&self.manifest_key(), Bytes::from(manifest))
+            .await?;
+        return Ok(before);
+    }
+
+    async fn download(&self, asset: &Asset) -> Result<AssetStream, ErrorResponse> {
+        return self
+            .objects
+            .get(self.key(asset)?, None)
+            .await?
+            .ok_or(ErrorResponse::PageNotFound);
+    }
+
+    async fn download_range(
+        &self,
+        asset: &Asset,
+        range: Range<u64>,
+    ) -> Result<ByteStream<io::Error>, ErrorResponse> {
+        return Ok(self
+            .objects
+            .get(self.key(asset)?, Some(range))
+            .await?
+            .ok_or(ErrorResponse::PageNotFound)?
+            .stream);
+    }
+
+    async fn exists(&self, asset: &Asset) -> Result<bool, ErrorResponse> {
+        return self.objects.exists(self.key(asset)?).await;
+    }
+}
+
+/// Now as a timestamp of the GitHub API, like `2024-01-02T03:04:05Z`.
+fn timestamp() -> String {
+    let (date, time) = aws::utc_now();
+    return format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        &date[..4],
+        &date[4..6],
+        &date[6..],
+        &time[..2],
+        &time[2..4],
+        &time[4..]
+    );
+}
+
+/// Files under `STORAGE_DIR`.
+struct LocalObjects<'a> {
+    settings: &'a StorageSettings,
+}
+
+impl LocalObjects<'_> {
+    fn path(&self, key: &str) -> Result<PathBuf, ErrorResponse> {
+        let dir = self
+            .settings
+            .dir
+            .as_ref()
+            .ok_or_else(|| not_configured("STORAGE_DIR"))?;
+        // keys are made of configured names and checked file names, never `..`
+        return Ok(dir.join(key));
+    }
+}
+
+#[async_trait]
+impl Objects for LocalObjects<'_> {
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<Option<AssetStream>, ErrorResponse> {
+        let mut file = match tokio::fs::File::open(self.path(key)?).await {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let size = file.metadata().await?.len();
+        let range = range.unwrap_or(0..size);
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        let remaining = range.end.min(size).saturating_sub(range.start);
+        let stream =
+            futures_util::stream::unfold((file, remaining), |(mut file, remaining)| async move {
+                if remaining == 0 {
+                    return None;
+                }
+                let mut buffer = vec![0; CHUNK.min(remaining as usize)];
+                return match file.read(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        Some((Ok(Bytes::from(buffer)), (file, remaining - read as u64)))
+                    }
+                    Err(error) => Some((Err(error), (file, 0))),
+                };
+            });
+        return Ok(Some(AssetStream {
+            content_length: Some(remaining),
+            stream: Box::pin(stream),
+        }));
+    }
+
+    async fn put(&self, key: &str, content: Bytes) -> Result<(), ErrorResponse> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // written aside and renamed, readers never see a partial manifest
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, content).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+        return Ok(());
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), ErrorResponse> {
+        let target = self.path(key)?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(path, &target).await?;
+        return Ok(());
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, ErrorResponse> {
+        return Ok(tokio::fs::try_exists(self.path(key)?).await?);
+    }
+
+    fn url(&self, key: &str) -> String {
+        return match self.path(key) {
+            Ok(path) => format!("file://{}", path.display()),
+            Err(_) => key.to_string(),
+        };
+    }
+}
+
+/// Objects of `S3_BUCKET`, addressed path style so S3 compatible services work too.
+struct S3Objects<'a> {
+    settings: &'a StorageSettings,
+}
+
+impl S3Objects<'_> {
+    fn settings(&self) -> Result<&S3Settings, ErrorResponse> {
+        return self
+            .settings
+            .s3
+            .as_ref()
+            .ok_or_else(|| not_configured("S3_BUCKET"));
+    }
+
+    /// Request `method` of the object `key`, signed.
+    fn request(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        headers: Vec<(&str, String)>,
+    ) -> Result<reqwest::RequestBuilder, ErrorResponse> {
+        let settings = self.settings()?;
+        let path = format!(
+            "/{}/{}",
+            aws::uri_encode(&settings.bucket),
+            key.split('/')
+                .map(aws::uri_encode)
+                .collect::<Vec<_>>()
+                .join("/")
+        );
+        let url = format!("{}{}", settings.endpoint, path);
+        let host = reqwest::Url::parse(&url)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                return Some(match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host,
+                });
+            })
+            .ok_or_else(|| {
+                ErrorResponse::Failure(ErrorCode::Config, format!("Invalid S3_ENDPOINT {}", url))
+            })?;
+        let mut headers = headers;
+        headers.push(("x-amz-content-sha256", "UNSIGNED-PAYLOAD".to_string()));
+        return Ok(settings.credentials.sign(
+            self.settings.client.request(method.clone(), &url),
+            "s3",
+            method.as_str(),
+            &host,
+            &path,
+            headers,
+            "UNSIGNED-PAYLOAD",
+        ));
+    }
+}
+
+/// `response` when successful, otherwise an error with S3's message.
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, ErrorResponse> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    return Err(ErrorResponse::Failure(
+        ErrorCode::from_status(status),
+        format!("S3 answered {}: {}", status, body),
+    ));
+}
+
+#[async_trait]
+impl Objects for S3Objects<'_> {
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<Option<AssetStream>, ErrorResponse> {
+        let mut request = self.request(reqwest::Method::GET, key, vec![])?;
+        if let Some(range) = &range {
+            request = request.header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            );
+        }
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check(response).await?;
+        return Ok(Some(AssetStream {
+            content_length: response.content_length(),
+            stream: Box::pin(
+                response
+                    .bytes_stream()
+                    .map(|chunk| chunk.map_err(io::Error::other)),
+            ),
+        }));
+    }
+
+    async fn put(&self, key: &str, content: Bytes) -> Result<(), ErrorResponse> {
+        check(
+            self.request(reqwest::Method::PUT, key, vec![])?
+                .body(content)
+                .send()
+                .await?,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), ErrorResponse> {
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let stream = futures_util::stream::unfold(file, |mut file| async move {
+            let mut buffer = vec![0; CHUNK];
+            return match file.read(&mut buffer).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    buffer.truncate(read);
+                    Some((Ok::<_, io::Error>(Bytes::from(buffer)), file))
+                }
+                Err(error) => Some((Err(error), file)),
+            };
+        });
+        // S3 needs the length up front, the body isn't sent chunked
+        let request = self.request(
+            reqwest::Method::PUT,
+            key,
+            vec![("content-length", size.to_string())],
+        )?;
+        check(
+            request
+                .body(reqwest::Body::wrap_stream(stream))
+                .send()
+                .await?,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, ErrorResponse> {
+        let response = self
+            .request(reqwest::Method::HEAD, key, vec![])?
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check(response).await?;
+        return Ok(true);
+    }
+
+    fn url(&self, key: &str) -> String {
+        return match self.settings() {
+            Ok(settings) => format!("s3://{}/{}", settings.bucket, key),
+            Err(_) => key.to_string(),
+        };
+    }
+}
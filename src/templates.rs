@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use askama::Template;
+use askama_axum::Response;
+use axum::response::{Html, IntoResponse};
+use minijinja::{path_loader, Environment, ErrorKind};
+use serde::Serialize;
+
+use crate::config::{self, Config};
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::redact::log;
+use crate::theme::Theme;
+
+/// Page rendered with the built-in askama template unless a template with the
+/// same name is present in `TEMPLATES_DIR`.
+pub trait Page: Template + Serialize + IntoResponse {
+    const NAME: &'static str;
+
+    /// Where the deployment's template context is put before the page is rendered.
+    fn context(&mut self) -> &mut TemplateContext;
+
+    /// Where the brand and stylesheet of the deployment are put before the page is rendered.
+    fn theme(&mut self) -> &mut Theme;
+}
+
+/// Values of `TEMPLATE_CONTEXT_PATH`, available to every page as `context`:
+/// the built-in templates show `banner` and `footer`, overrides can use any key.
+pub type TemplateContext = BTreeMap<String, String>;
+
+pub struct Templates {
+    overrides: Option<Environment<'static>>,
+    context: TemplateContext,
+    theme: Theme,
+}
+
+impl Templates {
+    pub fn from_config(config: &Config) -> Self {
+        let overrides = config.templates_dir.as_ref().map(|templates_dir| {
+            let mut env = Environment::new();
+            env.set_loader(path_loader(templates_dir));
+            env
+        });
+        let context = match &config.template_context_path {
+            Some(path) => load_context(path).unwrap_or_else(|error| panic!("{}", error)),
+            None => TemplateContext::new(),
+        };
+        return Templates {
+            overrides,
+            context,
+            theme: Theme::from_config(config),
+        };
+    }
+
+    pub fn render<T: Page>(&self, page: T) -> Response {
+        return match self.render_to_string(page) {
+            Ok(body) => Html(body).into_response(),
+            Err(error) => render_error(T::NAME, error),
+        };
+    }
+
+    /// Body of the page, also used by `pigi render` to check templates without serving them.
+    pub fn render_to_string<T: Page>(&self, mut page: T) -> Result<String, String> {
+        page.context().clone_from(&self.context);
+        page.theme().clone_from(&self.theme);
+        if let Some(env) = &self.overrides {
+            match env.get_template(T::NAME) {
+                Ok(template) => return template.render(&page).map_err(|error| error.to_string()),
+                Err(error) if error.kind() == ErrorKind::TemplateNotFound => {}
+                Err(error) => return Err(error.to_string()),
+            }
+        }
+        return page.render().map_err(|error| error.to_string());
+    }
+}
+
+/// Reads the template context file, a json object of strings.
+pub fn load_context(path: &str) -> Result<TemplateContext, String> {
+    let json_content = fs::read_to_string(path)
+        .map_err(|error| format!("Failed to load template context file: {}", error))?;
+    return serde_json::from_str(&json_content)
+        .map_err(|error| error.to_string())
+        .and_then(|mut value| config::interpolate(&mut value).map(|_| value))
+        .and_then(config::deserialize)
+        .map_err(|error| format!("failed to process template context file: {}", error));
+}
+
+fn render_error(name: &str, error: String) -> Response {
+    log!("Failed to render template {}: {}", name, error);
+    return ErrorResponse::Failure(
+        ErrorCode::Config,
+        format!("Failed to render template {}", name),
+    )
+    .into_response();
+}
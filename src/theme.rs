@@ -0,0 +1,55 @@
+//! Look of the built-in pages: the brand name and logo shown on every page,
+//! and the stylesheet served under `/static/`, with colors as CSS variables
+//! and a dark variant. The stylesheet is built into the binary, files of
+//! `STATIC_DIR` with the same name take its place.
+use askama_axum::Response;
+use axum::http::{header, StatusCode, Uri};
+use axum::response::IntoResponse;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::ErrorResponse;
+
+/// Files served under `/static/` when `STATIC_DIR` doesn't have them.
+const EMBEDDED: &[(&str, &str, &[u8])] = &[(
+    "pigi.css",
+    "text/css; charset=utf-8",
+    include_bytes!("../static/pigi.css"),
+)];
+
+#[derive(Serialize, Clone, Default)]
+pub struct Theme {
+    pub brand: String,
+    pub logo_url: Option<String>,
+    pub home_url: String,
+    pub stylesheet_url: String,
+}
+
+impl Theme {
+    pub fn from_config(config: &Config) -> Self {
+        return Theme {
+            brand: config.brand_name.clone(),
+            logo_url: config.brand_logo_url.clone(),
+            home_url: format!("{}/", config.external_url),
+            stylesheet_url: format!("{}/static/pigi.css", config.external_url),
+        };
+    }
+}
+
+/// A file built into the binary, by its path under `/static/`.
+pub async fn embedded(uri: Uri) -> Result<Response, ErrorResponse> {
+    let path = uri.path().trim_start_matches('/');
+    let (_, content_type, content) = EMBEDDED
+        .iter()
+        .find(|(name, _, _)| *name == path)
+        .ok_or(ErrorResponse::PageNotFound)?;
+    return Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, *content_type),
+            (header::CACHE_CONTROL, "public, max-age=3600"),
+        ],
+        *content,
+    )
+        .into_response());
+}
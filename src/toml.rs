@@ -0,0 +1,580 @@
+//! Reads the part of TOML a repos config needs into json, so config files can
+//! be written as TOML: tables and arrays of tables, dotted and quoted keys,
+//! inline tables, arrays, strings of all four kinds, integers, floats and
+//! booleans. Dates and times are refused, the config has no use for them.
+use serde_json::{Map, Number, Value};
+
+/// `text` as json, or what is wrong with it and on which line.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        position: 0,
+        line: 1,
+    };
+    return parser
+        .document()
+        .map_err(|error| format!("line {}: {}", parser.line, error));
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.position).copied();
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        return self.chars.get(self.position + offset).copied();
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        return Some(c);
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        return prefix
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| self.peek_at(offset) == Some(c));
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        return match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            Some('\n') => Err(format!("expected `{}`, not the end of the line", expected)),
+            Some(c) => Err(format!("expected `{}`, not `{}`", expected, c)),
+            None => Err(format!("expected `{}`, not the end of the file", expected)),
+        };
+    }
+
+    /// Skips spaces and tabs.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    /// Skips spaces, comments and line ends, as allowed between array values.
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.next();
+                }
+                Some('#') => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while self.peek().is_some_and(|c| c != '\n') {
+            self.next();
+        }
+    }
+
+    /// Ends a line: only a comment may follow what it holds.
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        if self.starts_with("\r\n") {
+            self.next();
+        }
+        return match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(format!("unexpected `{}` after a value", c)),
+        };
+    }
+
+    fn document(&mut self) -> Result<Value, String> {
+        let mut root = Map::new();
+        let mut table: Vec<String> = vec![];
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(Value::Object(root)),
+                Some('[') if self.peek_at(1) == Some('[') => {
+                    self.position += 2;
+                    let path = self.header("]]")?;
+                    let (name, parents) = path.split_last().unwrap();
+                    let parent = table_mut(&mut root, parents)?;
+                    let tables = parent
+                        .entry(name.clone())
+                        .or_insert_with(|| Value::Array(vec![]));
+                    let Value::Array(tables) = tables else {
+                        return Err(format!("{} isn't an array of tables", path.join(".")));
+                    };
+                    tables.push(Value::Object(Map::new()));
+                    table = path;
+                    self.end_of_line()?;
+                }
+                Some('[') => {
+                    self.position += 1;
+                    let path = self.header("]")?;
+                    table_mut(&mut root, &path)?;
+                    table = path;
+                    self.end_of_line()?;
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.skip_spaces();
+                    self.expect('=')?;
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    insert(table_mut(&mut root, &table)?, &key, value)?;
+                    self.end_of_line()?;
+                }
+            }
+        }
+    }
+
+    /// The key of a table header, up to its closing brackets.
+    fn header(&mut self, close: &str) -> Result<Vec<String>, String> {
+        self.skip_spaces();
+        let key = self.key()?;
+        self.skip_spaces();
+        for c in close.chars() {
+            self.expect(c)?;
+        }
+        return Ok(key);
+    }
+
+    /// A dotted key, as its parts.
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = vec![self.simple_key()?];
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+            self.next();
+            self.skip_spaces();
+            parts.push(self.simple_key()?);
+        }
+    }
+
+    fn simple_key(&mut self) -> Result<String, String> {
+        return match self.peek() {
+            Some('"') => {
+                self.next();
+                self.basic_string()
+            }
+            Some('\'') => {
+                self.next();
+                self.literal_string()
+            }
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+                {
+                    key.push(c);
+                    self.next();
+                }
+                match self.peek() {
+                    _ if !key.is_empty() => Ok(key),
+                    Some(c) if c != '\n' => Err(format!("expected a key, not `{}`", c)),
+                    _ => Err("expected a key".to_string()),
+                }
+            }
+        };
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.starts_with("\"\"\"") {
+            self.position += 3;
+            return self.multiline_string('"').map(Value::String);
+        }
+        if self.starts_with("'''") {
+            self.position += 3;
+            return self.multiline_string('\'').map(Value::String);
+        }
+        return match self.peek() {
+            Some('"') => {
+                self.next();
+                self.basic_string().map(Value::String)
+            }
+            Some('\'') => {
+                self.next();
+                self.literal_string().map(Value::String)
+            }
+            Some('[') => {
+                self.next();
+                self.array()
+            }
+            Some('{') => {
+                self.next();
+                self.inline_table()
+            }
+            _ => self.scalar(),
+        };
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        let mut values = vec![];
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                Some(']') => {}
+                Some(c) => return Err(format!("expected `,` or `]`, not `{}`", c)),
+                None => return Err("expected `]`, not the end of the file".to_string()),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, String> {
+        let mut table = Map::new();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Value::Object(table));
+        }
+        loop {
+            self.skip_spaces();
+            let key = self.key()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            insert(&mut table, &key, value)?;
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => {}
+                Some('}') => {
+                    self.next();
+                    return Ok(Value::Object(table));
+                }
+                Some(c) if c != '\n' => return Err(format!("expected `,` or `}}`, not `{}`", c)),
+                _ => return Err("an inline table has to end on its line".to_string()),
+            }
+            self.next();
+        }
+    }
+
+    /// A `"` string, after its opening quote.
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return Err("unterminated string".to_string()),
+                Some(c) => c,
+            };
+            self.next();
+            match c {
+                '"' => return Ok(string),
+                '\\' => string.push(self.escape()?),
+                c => string.push(c),
+            }
+        }
+    }
+
+    /// A `'` string, after its opening quote: no escapes.
+    fn literal_string(&mut self) -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return Err("unterminated string".to_string()),
+                Some(c) => c,
+            };
+            self.next();
+            match c {
+                '\'' => return Ok(string),
+                c => string.push(c),
+            }
+        }
+    }
+
+    /// A `"""` or `'''` string, after its opening quotes. A line end right
+    /// after them isn't part of the string, and in `"""` strings a backslash
+    /// at the end of a line drops the line end and the blanks after it.
+    fn multiline_string(&mut self, quote: char) -> Result<String, String> {
+        if self.starts_with("\r\n") {
+            self.next();
+        }
+        if self.peek() == Some('\n') {
+            self.next();
+        }
+        let close: String = [quote; 3].iter().collect();
+        let mut string = String::new();
+        loop {
+            if self.starts_with(&close) {
+                self.position += 3;
+                // up to two more quotes belong to the string
+                for _ in 0..2 {
+                    if self.peek() == Some(quote) {
+                        string.push(quote);
+                        self.next();
+                    }
+                }
+                return Ok(string);
+            }
+            match self.next() {
+                None => return Err("unterminated string".to_string()),
+                Some('\\') if quote == '"' => {
+                    let rest = self.position;
+                    self.skip_spaces();
+                    if matches!(self.peek(), Some('\r' | '\n')) {
+                        while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                            self.next();
+                        }
+                    } else {
+                        self.position = rest;
+                        string.push(self.escape()?);
+                    }
+                }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// The character an escape sequence stands for, after its backslash.
+    fn escape(&mut self) -> Result<char, String> {
+        let length = match self.next() {
+            Some('b') => return Ok('\u{8}'),
+            Some('t') => return Ok('\t'),
+            Some('n') => return Ok('\n'),
+            Some('f') => return Ok('\u{c}'),
+            Some('r') => return Ok('\r'),
+            Some('"') => return Ok('"'),
+            Some('\\') => return Ok('\\'),
+            Some('u') => 4,
+            Some('U') => 8,
+            Some(c) => return Err(format!("invalid escape `\\{}`", c)),
+            None => return Err("unterminated string".to_string()),
+        };
+        let digits: String = (0..length).filter_map(|_| self.next()).collect();
+        return u32::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|_| digits.len() == length)
+            .and_then(char::from_u32)
+            .ok_or(format!("invalid unicode escape `{}`", digits));
+    }
+
+    /// A boolean or a number.
+    fn scalar(&mut self) -> Result<Value, String> {
+        let mut token = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n' | ',' | ']' | '}' | '#'))
+        {
+            token.push(c);
+            self.next();
+        }
+        return match token.as_str() {
+            "" => match self.peek() {
+                Some(c) if c != '\n' => Err(format!("expected a value, not `{}`", c)),
+                _ => Err("expected a value".to_string()),
+            },
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            token => number(token),
+        };
+    }
+}
+
+fn number(token: &str) -> Result<Value, String> {
+    let invalid = || format!("invalid value `{}`", token);
+    let bytes = token.as_bytes();
+    let is_date = bytes.len() > 4 && bytes[..4].iter().all(u8::is_ascii_digit) && bytes[4] == b'-';
+    let is_time = bytes.len() > 2 && bytes[..2].iter().all(u8::is_ascii_digit) && bytes[2] == b':';
+    if is_date || is_time {
+        return Err(format!("dates and times aren't supported: `{}`", token));
+    }
+    if token.starts_with('_') || token.ends_with('_') || token.contains("__") {
+        return Err(invalid());
+    }
+    let digits = token.replace('_', "");
+    let radix = [("0x", 16), ("0o", 8), ("0b", 2)]
+        .into_iter()
+        .find_map(|(prefix, radix)| Some((digits.strip_prefix(prefix)?, radix)));
+    if let Some((digits, radix)) = radix {
+        return i64::from_str_radix(digits, radix)
+            .ok()
+            .filter(|_| !digits.starts_with(['+', '-']))
+            .map(Value::from)
+            .ok_or_else(invalid);
+    }
+    let unsigned = digits.trim_start_matches(['+', '-']);
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // leading zeros aren't allowed, `0.5` and `0e1` are
+    if unsigned.len() > 1
+        && unsigned.starts_with('0')
+        && unsigned[1..].starts_with(|c: char| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    if !unsigned.contains(['.', 'e', 'E']) {
+        return digits.parse::<i64>().map(Value::from).map_err(|_| invalid());
+    }
+    if unsigned.contains(".e") || unsigned.contains(".E") || unsigned.ends_with('.') {
+        return Err(invalid());
+    }
+    return digits
+        .parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+        .ok_or_else(invalid);
+}
+
+/// The table `path` leads to from `root`, created when missing. Arrays of
+/// tables lead to their last table, as in TOML the headers after `[[x]]`
+/// refer to the table it added.
+fn table_mut<'a>(root: &'a mut Map<String, Value>, path: &[String]) -> Result<&'a mut Map<String, Value>, String> {
+    let mut table = root;
+    for (depth, key) in path.iter().enumerate() {
+        let value = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        let value = match value {
+            Value::Array(values) => values.last_mut(),
+            value => Some(value),
+        };
+        table = match value {
+            Some(Value::Object(object)) => object,
+            _ => {
+                return Err(format!(
+                    "{} is already set to a value that isn't a table",
+                    path[..=depth].join(".")
+                ))
+            }
+        };
+    }
+    return Ok(table);
+}
+
+/// Sets the dotted `key` of `table`, refusing keys that are already set.
+fn insert(table: &mut Map<String, Value>, key: &[String], value: Value) -> Result<(), String> {
+    let (name, parents) = key.split_last().unwrap();
+    let table = table_mut(table, parents)?;
+    if table.contains_key(name) {
+        return Err(format!("{} is set twice", key.join(".")));
+    }
+    table.insert(name.clone(), value);
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_a_repos_config() {
+        let text = r#"
+# shared by every environment
+default_owner = "${GITHUB_ORG}"
+access_tokens = [
+    "ci-token", # the pipeline's
+    { token = 'r\aw', operations = ["read"] },
+]
+
+[repos.my-cli]
+name = "my-cli"
+visibility = "public"
+quota.max_file_size = 1_048_576
+
+[repos."odd.name"]
+provider = 'local'
+renames = {"old-name" = "new-name"}
+"#;
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "default_owner": "${GITHUB_ORG}",
+                "access_tokens": [
+                    "ci-token",
+                    {"token": "r\\aw", "operations": ["read"]},
+                ],
+                "repos": {
+                    "my-cli": {
+                        "name": "my-cli",
+                        "visibility": "public",
+                        "quota": {"max_file_size": 1048576},
+                    },
+                    "odd.name": {"provider": "local", "renames": {"old-name": "new-name"}},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn parses_arrays_of_tables() {
+        let text = "[[links]]\nname = \"docs\"\n[links.extra]\nx = 1\n[[links]]\nname = \"home\"\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({"links": [{"name": "docs", "extra": {"x": 1}}, {"name": "home"}]})
+        );
+    }
+
+    #[test]
+    fn parses_strings() {
+        let text = concat!(
+            "basic = \"tab\\there \\\"quoted\\\" \\u00e9\"\n",
+            "literal = 'C:\\dir'\n",
+            "multiline = \"\"\"\nfirst \\\n    second\nthird\"\"\"\n",
+            "raw = '''\n'quoted' \\n'''\n",
+        );
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "basic": "tab\there \"quoted\" é",
+                "literal": "C:\\dir",
+                "multiline": "first second\nthird",
+                "raw": "'quoted' \\n",
+            })
+        );
+    }
+
+    #[test]
+    fn parses_numbers_and_booleans() {
+        let text = "a = -42\nb = 0x1f\nc = 1.5e3\nd = +0.25\ne = true\nf = false\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({"a": -42, "b": 31, "c": 1500.0, "d": 0.25, "e": true, "f": false})
+        );
+    }
+
+    #[test]
+    fn refuses_invalid_documents() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(error("a = 1\na = 2\n"), "line 2: a is set twice");
+        assert_eq!(error("a = 1\n[a]\n"), "line 2: a is already set to a value that isn't a table");
+        assert_eq!(error("a = \"open\nb = 1\n"), "line 1: unterminated string");
+        assert_eq!(error("a = 1 2\n"), "line 1: unexpected `2` after a value");
+        assert_eq!(error("a =\n"), "line 1: expected a value");
+        assert_eq!(error("a = 007\n"), "line 1: invalid value `007`");
+        assert_eq!(
+            error("a = 2024-01-01\n"),
+            "line 1: dates and times aren't supported: `2024-01-01`"
+        );
+        assert_eq!(error("a = [1, 2\n"), "line 2: expected `]`, not the end of the file");
+        assert_eq!(error("a = { b = 1\n}\n"), "line 1: an inline table has to end on its line");
+    }
+}
@@ -0,0 +1,335 @@
+//! Trusted publishing: GitHub Actions OIDC tokens accepted as the basic auth
+//! password in namespaces with `trusted_publishing`, so CI publishes without
+//! a long-lived secret. A token signed by `TRUSTED_PUBLISHING_ISSUER` for
+//! `TRUSTED_PUBLISHING_AUDIENCE` authenticates its workflow as a service
+//! account that may read and publish the packages configured with the
+//! repository of its `repository` claim, and nothing else.
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::config::{Config, Provider};
+use crate::error::ErrorResponse;
+use crate::namespace::Namespace;
+use crate::redact::log;
+use crate::service_accounts::ServiceAccount;
+
+pub const GITHUB_ACTIONS_ISSUER: &str = "https://token.actions.githubusercontent.com";
+pub const DEFAULT_AUDIENCE: &str = "pigi";
+/// How long after fetching the issuer's keys an unknown key id fetches them again.
+const KEYS_REFRESH: Duration = Duration::from_secs(60);
+/// Clock difference tolerated with the issuer.
+const LEEWAY: u64 = 60;
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    aud: Audience,
+    exp: u64,
+    nbf: Option<u64>,
+    /// `owner/name` of the repository the workflow runs in
+    repository: String,
+    /// `owner/name/.github/workflows/release.yml@refs/tags/v1.2.0`
+    job_workflow_ref: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct Key {
+    kid: String,
+    n: String,
+    e: String,
+}
+
+#[derive(Deserialize)]
+struct Keys {
+    keys: Vec<Key>,
+}
+
+/// Whether `password` looks like a JWT rather than an access token.
+pub fn is_oidc_token(password: &str) -> bool {
+    return password.starts_with("eyJ") && password.split('.').count() == 3;
+}
+
+fn decode(segment: &str) -> Result<Vec<u8>, String> {
+    return base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .map_err(|error| error.to_string());
+}
+
+fn decode_json<T: DeserializeOwned>(segment: &str) -> Result<T, String> {
+    return serde_json::from_slice(&decode(segment)?).map_err(|error| error.to_string());
+}
+
+/// Whether `signature` is `key`'s RS256 signature of `message`.
+fn verify_signature(key: &Key, message: &[u8], signature: &[u8]) -> Result<bool, String> {
+    let rsa = Rsa::from_public_components(
+        BigNum::from_slice(&decode(&key.n)?).map_err(|error| error.to_string())?,
+        BigNum::from_slice(&decode(&key.e)?).map_err(|error| error.to_string())?,
+    )
+    .map_err(|error| error.to_string())?;
+    let key = PKey::from_rsa(rsa).map_err(|error| error.to_string())?;
+    let mut verifier =
+        Verifier::new(MessageDigest::sha256(), &key).map_err(|error| error.to_string())?;
+    verifier
+        .update(message)
+        .map_err(|error| error.to_string())?;
+    return verifier
+        .verify(signature)
+        .map_err(|error| error.to_string());
+}
+
+pub struct TrustedPublishing {
+    issuer: String,
+    audience: String,
+    client: reqwest::Client,
+    /// signing keys of the issuer, as last fetched
+    keys: RwLock<Vec<Key>>,
+    fetched_at: Mutex<Option<Instant>>,
+}
+
+impl TrustedPublishing {
+    pub fn from_config(config: &Config) -> Self {
+        return TrustedPublishing {
+            issuer: config.trusted_publishing_issuer.clone(),
+            audience: config.trusted_publishing_audience.clone(),
+            client: reqwest::Client::new(),
+            keys: RwLock::default(),
+            fetched_at: Mutex::default(),
+        };
+    }
+
+    /// The issuer's key `kid`, its keys are fetched again when it's unknown,
+    /// as the issuer rotates them.
+    async fn key(&self, kid: &str) -> Result<Key, String> {
+        let find = |keys: &Vec<Key>| keys.iter().find(|key| key.kid == kid).cloned();
+        if let Some(key) = find(&self.keys.read().unwrap()) {
+            return Ok(key);
+        }
+        let fetched_at = *self.fetched_at.lock().unwrap();
+        if fetched_at.is_some_and(|at| at.elapsed() < KEYS_REFRESH) {
+            return Err(format!("unknown signing key {}", kid));
+        }
+        let url = format!("{}/.well-known/jwks", self.issuer.trim_end_matches('/'));
+        let keys: Keys = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("can't fetch {}: {}", url, error))?
+            .json()
+            .await
+            .map_err(|error| format!("can't read {}: {}", url, error))?;
+        // failed fetches are retried right away, only read keys are kept a while
+        *self.fetched_at.lock().unwrap() = Some(Instant::now());
+        let key = find(&keys.keys);
+        *self.keys.write().unwrap() = keys.keys;
+        return key.ok_or(format!("unknown signing key {}", kid));
+    }
+
+    /// Claims of `token`, once its signature, issuer, audience and validity are checked.
+    async fn verify(&self, token: &str) -> Result<Claims, String> {
+        let segments: Vec<&str> = token.split('.').collect();
+        let [header, claims, signature] = segments[..] else {
+            return Err("not a JWT".to_string());
+        };
+        let parsed: Header = decode_json(header)?;
+        if parsed.alg != "RS256" {
+            return Err(format!("unsupported algorithm {}", parsed.alg));
+        }
+        let key = self.key(parsed.kid.as_deref().unwrap_or_default()).await?;
+        let message = format!("{}.{}", header, claims);
+        if !verify_signature(&key, message.as_bytes(), &decode(signature)?)? {
+            return Err("invalid signature".to_string());
+        }
+        let claims: Claims = decode_json(claims)?;
+        if claims.iss != self.issuer {
+            return Err(format!("issued by {}", claims.iss));
+        }
+        let audiences = match &claims.aud {
+            Audience::One(audience) => std::slice::from_ref(audience),
+            Audience::Many(audiences) => audiences.as_slice(),
+        };
+        if !audiences.contains(&self.audience) {
+            return Err(format!("not issued for {}", self.audience));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if claims.exp + LEEWAY < now {
+            return Err("expired".to_string());
+        }
+        if claims.nbf.is_some_and(|nbf| nbf > now + LEEWAY) {
+            return Err("not valid yet".to_string());
+        }
+        return Ok(claims);
+    }
+
+    /// Service account of the workflow `token` was issued to, allowed the
+    /// packages of `namespace` configured with the workflow's repository.
+    pub async fn authenticate(
+        &self,
+        namespace: &Namespace,
+        token: &str,
+    ) -> Result<ServiceAccount, ErrorResponse> {
+        let claims = self.verify(token).await.map_err(|error| {
+            log!("Refused a trusted publishing token: {}", error);
+            ErrorResponse::Unauthorized
+        })?;
+        let packages: Vec<String> = namespace
+            .repos
+            .iter()
+            .filter(|(_, repository)| {
+                repository.provider == Provider::Github
+                    && format!("{}/{}", repository.owner, repository.name)
+                        .eq_ignore_ascii_case(&claims.repository)
+            })
+            .map(|(package_name, _)| namespace.qualified_name(package_name))
+            .collect();
+        if packages.is_empty() {
+            return Err(ErrorResponse::Forbidden(format!(
+                "No package is published from {}",
+                claims.repository
+            )));
+        }
+        let name = claims.job_workflow_ref.unwrap_or(claims.repository);
+        return Ok(ServiceAccount::workflow(
+            format!("github:{}", name),
+            packages,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::sign::Signer;
+    use serde_json::json;
+
+    use super::*;
+
+    const ISSUER: &str = "https://issuer.example";
+
+    fn encode(bytes: &[u8]) -> String {
+        return base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    }
+
+    fn now() -> u64 {
+        return SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+    }
+
+    /// An issuer knowing its own key, and a function signing claims with it.
+    fn issuer() -> (TrustedPublishing, impl Fn(serde_json::Value) -> String) {
+        let rsa = Rsa::generate(2048).unwrap();
+        let key = Key {
+            kid: "test".to_string(),
+            n: encode(&rsa.n().to_vec()),
+            e: encode(&rsa.e().to_vec()),
+        };
+        let trusted_publishing = TrustedPublishing {
+            issuer: ISSUER.to_string(),
+            audience: DEFAULT_AUDIENCE.to_string(),
+            client: reqwest::Client::new(),
+            keys: RwLock::new(vec![key]),
+            fetched_at: Mutex::new(Some(Instant::now())),
+        };
+        let private_key = PKey::from_rsa(rsa).unwrap();
+        let sign = move |claims: serde_json::Value| {
+            let header = encode(
+                json!({"alg": "RS256", "kid": "test"})
+                    .to_string()
+                    .as_bytes(),
+            );
+            let claims = encode(claims.to_string().as_bytes());
+            let message = format!("{}.{}", header, claims);
+            let mut signer = Signer::new(MessageDigest::sha256(), &private_key).unwrap();
+            signer.update(message.as_bytes()).unwrap();
+            return format!("{}.{}", message, encode(&signer.sign_to_vec().unwrap()));
+        };
+        return (trusted_publishing, sign);
+    }
+
+    fn verify(trusted_publishing: &TrustedPublishing, token: &str) -> Result<Claims, String> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        return runtime.block_on(trusted_publishing.verify(token));
+    }
+
+    fn claims() -> serde_json::Value {
+        return json!({
+            "iss": ISSUER,
+            "aud": DEFAULT_AUDIENCE,
+            "exp": now() + 300,
+            "repository": "acme/tool",
+        });
+    }
+
+    #[test]
+    fn accepts_a_valid_token() {
+        let (trusted_publishing, sign) = issuer();
+        let claims = verify(&trusted_publishing, &sign(claims())).unwrap();
+        assert_eq!(claims.repository, "acme/tool");
+    }
+
+    #[test]
+    fn refuses_another_issuer() {
+        let (trusted_publishing, sign) = issuer();
+        let mut claims = claims();
+        claims["iss"] = json!("https://other.example");
+        let error = verify(&trusted_publishing, &sign(claims)).err().unwrap();
+        assert_eq!(error, "issued by https://other.example");
+    }
+
+    #[test]
+    fn refuses_another_audience() {
+        let (trusted_publishing, sign) = issuer();
+        let mut claims = claims();
+        claims["aud"] = json!(["sigstore", "other"]);
+        let error = verify(&trusted_publishing, &sign(claims)).err().unwrap();
+        assert_eq!(error, "not issued for pigi");
+    }
+
+    #[test]
+    fn refuses_an_expired_token() {
+        let (trusted_publishing, sign) = issuer();
+        let mut claims = claims();
+        claims["exp"] = json!(now() - LEEWAY - 1);
+        let error = verify(&trusted_publishing, &sign(claims)).err().unwrap();
+        assert_eq!(error, "expired");
+    }
+
+    #[test]
+    fn refuses_a_forged_signature() {
+        let (trusted_publishing, _) = issuer();
+        let (_, forger) = issuer();
+        let error = verify(&trusted_publishing, &forger(claims()))
+            .err()
+            .unwrap();
+        assert_eq!(error, "invalid signature");
+    }
+}
@@ -0,0 +1,304 @@
+//! `POST /api/packages/<package>/<version>/files/<filename>`: publishes a
+//! distribution sent as the request body, for CI pipelines that shouldn't hold
+//! a GitHub token. The file is checked like `pigi publish` checks it, against
+//! the `sha256` query parameter when given, and stored in the release
+//! `v<version>` of the package's storage: uploaded to GitHub with the
+//! namespace's token, or written to local or S3 storage. Published files are
+//! never replaced: sending one again answers as if it was uploaded when the
+//! content is the same, so retried uploads succeed, and 409 otherwise. A PEP
+//! 740 attestation, `<filename>.publish.attestation`, is uploaded after its
+//! distribution and stored next to it once checked to attest it.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap};
+use axum::response::Json;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+
+use crate::cache::file_sha256;
+use crate::companions;
+use crate::config::Provider;
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::github::GithubClient;
+use crate::metadata;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::publish::{self, parse_filename};
+use crate::quotas::{self, Quota};
+use crate::redact::log;
+use crate::service_accounts::CurrentAccount;
+use crate::storage;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    sha256: Option<String>,
+}
+
+/// Writes `body` to `path`, refusing it once it gets over `limit` bytes.
+async fn receive(path: &PathBuf, body: Body, limit: u64) -> Result<(), ErrorResponse> {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let failed = |error: String| {
+        ErrorResponse::BadRequest(format!("Can't receive {}: {}", filename, error))
+    };
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|error| failed(error.to_string()))?;
+    let mut stream = body.into_data_stream();
+    let mut received = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|error| failed(error.to_string()))?;
+        received += chunk.len() as u64;
+        if received > limit {
+            return Err(too_large(&filename, limit));
+        }
+        file.write_all(&chunk)
+            .await
+            .map_err(|error| failed(error.to_string()))?;
+    }
+    return file.flush().await.map_err(|error| failed(error.to_string()));
+}
+
+fn too_large(filename: &str, limit: u64) -> ErrorResponse {
+    return ErrorResponse::TooLarge(format!(
+        "{} is over the upload limit of {} bytes",
+        filename, limit
+    ));
+}
+
+/// Whether `filename` names a file of its own, nothing a path could be built
+/// from to get out of the upload's directory.
+fn is_plain_name(filename: &str) -> bool {
+    return !filename.is_empty()
+        && !filename.contains(['/', '\\', '\0'])
+        && !filename.contains("..");
+}
+
+pub async fn upload(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, .. }: CurrentNamespace,
+    Path((package_name, version, filename)): Path<(String, String, String)>,
+    Query(query): Query<UploadQuery>,
+    account: CurrentAccount,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Value>, ErrorResponse> {
+    if !is_plain_name(&filename) {
+        return Err(ErrorResponse::BadRequest(format!(
+            "{:?} isn't a valid file name",
+            filename
+        )));
+    }
+    let (configured_name, repository) = namespace.find_repository(&package_name)?;
+    account.check_package(&namespace, configured_name)?;
+    let package = namespace.qualified_name(configured_name);
+    if app_state.github.replication.is_enabled() {
+        return Err(ErrorResponse::BadRequest(format!(
+            "{} is replicated, publish it to the upstream pigi",
+            package
+        )));
+    }
+    let subject = filename.strip_suffix(companions::PEP740_SUFFIX);
+    match parse_filename(subject.unwrap_or(&filename)) {
+        Some((name, file_version))
+            if names::normalize(name) == names::normalize(configured_name)
+                && file_version == version => {}
+        _ => {
+            return Err(ErrorResponse::BadRequest(format!(
+                "{} isn't a wheel or sdist of {} {}",
+                filename, package, version
+            )))
+        }
+    }
+    let token = namespace.github_token.get();
+    if repository.provider == Provider::Github && token.is_none() {
+        return Err(ErrorResponse::Failure(
+            ErrorCode::Config,
+            "Publishing needs a GitHub token, set GITHUB_TOKEN".to_string(),
+        ));
+    }
+    let limit = Quota::of(&namespace, repository)
+        .max_file_size
+        .unwrap_or(app_state.config.max_upload_size);
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return Err(too_large(&filename, limit));
+    }
+    let client = GithubClient::new(&app_state.github, token);
+    let Some(storage) = storage::of(repository, &client) else {
+        return Err(ErrorResponse::BadRequest(format!(
+            "{} is mirrored from {}, it can't be published to",
+            package,
+            repository.url(&app_state.github.storage)
+        )));
+    };
+
+    let dir = std::env::temp_dir().join(format!(
+        "pigi-upload-{}-{}",
+        std::process::id(),
+        app_state.uploads.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(&filename);
+    let tag = format!("v{}", version);
+    // the directory is removed whatever the outcome
+    let published: Result<(String, String, bool), ErrorResponse> = async {
+        receive(&path, body, limit).await?;
+        let checksums = query
+            .sha256
+            .map(|sha256| HashMap::from([(filename.clone(), sha256)]));
+        if subject.is_none() {
+            publish::validate(&path, checksums.as_ref())
+                .await
+                .map_err(ErrorResponse::BadRequest)?;
+        }
+        let sha256 = file_sha256(&path).await?;
+        let releases = storage.releases().await?;
+        if let Some(subject) = subject {
+            if let Some(expected) = checksums
+                .as_ref()
+                .and_then(|checksums| checksums.get(&filename))
+            {
+                if &sha256 != expected {
+                    return Err(ErrorResponse::BadRequest(format!(
+                        "{} has sha256 {}, not {}",
+                        filename, sha256, expected
+                    )));
+                }
+            }
+            let distribution = releases
+                .iter()
+                .filter(|release| release.tag_name == tag)
+                .flat_map(|release| &release.assets)
+                .find(|asset| asset.name == subject)
+                .ok_or(ErrorResponse::BadRequest(format!(
+                    "{} isn't published to {}, upload it before its attestation",
+                    subject, tag
+                )))?;
+            let Some(subject_sha256) = distribution.sha256() else {
+                return Err(ErrorResponse::BadRequest(format!(
+                    "The sha256 of {} isn't known, its attestation can't be checked",
+                    subject
+                )));
+            };
+            let content = tokio::fs::read(&path).await?;
+            publish::check_attestation(&filename, &content, subject_sha256)
+                .map_err(ErrorResponse::BadRequest)?;
+        }
+        let published = releases
+            .iter()
+            .filter(|release| release.tag_name == tag)
+            .find_map(|release| {
+                let asset = release.assets.iter().find(|asset| asset.name == filename)?;
+                return Some((release, asset));
+            });
+        if let Some((release, asset)) = published {
+            if asset.sha256() == Some(sha256.as_str()) {
+                return Ok((release.html_url.clone(), sha256, false));
+            }
+            return Err(ErrorResponse::Conflict(format!(
+                "{} is already published to {} with another content",
+                filename, tag
+            )));
+        }
+        let files = [path.clone()];
+        quotas::check_upload(
+            &app_state,
+            &client,
+            &namespace,
+            configured_name,
+            repository,
+            &tag,
+            &files,
+        )
+        .await
+        .map_err(ErrorResponse::BadRequest)?;
+        let size = tokio::fs::metadata(&path).await?.len();
+        let release = storage.store(&tag, &version, &path).await?;
+        app_state
+            .storage_usage
+            .record_upload(&namespace, configured_name, size);
+        return Ok((release.html_url, sha256, true));
+    }
+    .await;
+    if let Err(error) = tokio::fs::remove_dir_all(&dir).await {
+        log!("Failed to remove {}: {}", dir.display(), error);
+    }
+    let (url, sha256, stored) = published?;
+    if stored {
+        log!("Published {} of {} {}", filename, package, version);
+        let notify = app_state.leader.is_leader().await;
+        let sync_client =
+            GithubClient::new(&app_state.github, namespace.github_token.get()).background();
+        metadata::sync_package(
+            &app_state,
+            &namespace,
+            &sync_client,
+            configured_name,
+            repository,
+            notify,
+        )
+        .await;
+    } else {
+        log!(
+            "{} of {} {} is already published, skipped",
+            filename,
+            package,
+            version
+        );
+    }
+    return Ok(Json(json!({
+        "package": package,
+        "version": version,
+        "filename": filename,
+        "sha256": sha256,
+        "url": url,
+    })));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_names() {
+        assert!(is_plain_name("pigi-1.0.tar.gz"));
+        assert!(is_plain_name("pigi-1.0-py3-none-any.whl.publish.attestation"));
+        assert!(!is_plain_name(""));
+        assert!(!is_plain_name("../pigi-1.0.tar.gz"));
+        assert!(!is_plain_name("dist/pigi-1.0.tar.gz"));
+        assert!(!is_plain_name("dist\\pigi-1.0.tar.gz"));
+        assert!(!is_plain_name(".."));
+    }
+
+    #[test]
+    fn receive_stops_over_limit() {
+        let dir = std::env::temp_dir().join(format!("pigi-upload-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pigi-1.0.tar.gz");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        if let Err(error) = runtime.block_on(receive(&path, Body::from("0123456789"), 10)) {
+            panic!("refused with {}", error);
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+        match runtime.block_on(receive(&path, Body::from("0123456789"), 9)) {
+            Err(ErrorResponse::TooLarge(message)) => {
+                assert_eq!(message, "pigi-1.0.tar.gz is over the upload limit of 9 bytes")
+            }
+            Err(error) => panic!("refused with {}", error),
+            Ok(()) => panic!("received"),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
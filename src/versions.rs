@@ -0,0 +1,109 @@
+//! `/api/packages/<package>/versions`: the versions of a package with their
+//! files, platforms, checksums and download urls, for tooling other than pip
+//! resolving artifacts, like the version listings of Terraform provider
+//! registries. The document has a `schema_version`, fields are only added to
+//! it. Platforms use Go's names, like Terraform: `linux`, `darwin` and
+//! `windows`, `amd64`, `arm64` and `386`; files without any, sdists and pure
+//! Python wheels say, run everywhere.
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::response::Json;
+use serde_json::{json, Value};
+
+use crate::assets;
+use crate::auth::GithubToken;
+use crate::error::ErrorResponse;
+use crate::github::{latest_release, Asset, GithubClient, Release};
+use crate::install;
+use crate::names;
+use crate::namespace::CurrentNamespace;
+use crate::pypi;
+use crate::service_accounts::CurrentAccount;
+use crate::AppState;
+
+const SCHEMA_VERSION: u64 = 1;
+
+/// Platforms of a file: of the platform tag of wheels, of the name of other files.
+fn platforms(filename: &str) -> Vec<Value> {
+    let source = match filename.strip_suffix(".whl") {
+        Some(stem) => stem.rsplit('-').next().unwrap_or_default(),
+        None => filename,
+    };
+    return install::platforms(source)
+        .into_iter()
+        .map(|(os, arch)| {
+            let arch = match arch {
+                "x86_64" => "amd64",
+                "aarch64" => "arm64",
+                _ => "386",
+            };
+            json!({"os": os, "arch": arch})
+        })
+        .collect();
+}
+
+fn file_json(files_url: &str, release: &Release, asset: &Asset) -> Value {
+    return json!({
+        "filename": asset.name,
+        "url": format!("{}/{}/{}", files_url, release.version(), asset.name),
+        "packagetype": pypi::package_type(&asset.name),
+        "size": asset.size,
+        "sha256": asset.sha256(),
+        "platforms": platforms(&asset.name),
+        "upload_time": asset.created_at,
+    });
+}
+
+/// Versions of a package, newest first. Packages with `downloads` list every
+/// file of their releases with `/downloads/` urls, others the files of the index.
+pub async fn versions(
+    State(app_state): State<Arc<AppState>>,
+    CurrentNamespace { namespace, prefix }: CurrentNamespace,
+    Path(package_name): Path<String>,
+    GithubToken(token): GithubToken,
+    account: CurrentAccount,
+) -> Result<Json<Value>, ErrorResponse> {
+    let (configured_name, repository) = namespace.find_repository(&package_name)?;
+    account.check_package(&namespace, configured_name)?;
+    let base_url = format!("{}{}", app_state.config.external_url, prefix);
+    let normalized = names::normalize(configured_name);
+    let (releases, files_url) = if repository.downloads {
+        let client = GithubClient::new(&app_state.github, token);
+        let releases =
+            assets::downloadable(&app_state, &client, &namespace, configured_name, repository)
+                .await?;
+        (releases, format!("{}/downloads/{}", base_url, normalized))
+    } else {
+        let metadata = namespace
+            .metadata
+            .get(configured_name)
+            .ok_or(ErrorResponse::PageNotFound)?;
+        (
+            metadata.releases,
+            format!("{}/simple/{}", base_url, normalized),
+        )
+    };
+    let versions: Vec<Value> = releases
+        .iter()
+        .map(|release| {
+            json!({
+                "version": release.version(),
+                "tag": release.tag_name,
+                "prerelease": release.prerelease,
+                "published_at": release.published_at,
+                "files": release
+                    .assets
+                    .iter()
+                    .map(|asset| file_json(&files_url, release, asset))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    return Ok(Json(json!({
+        "schema_version": SCHEMA_VERSION,
+        "package": configured_name,
+        "latest": latest_release(&releases).map(|release| release.version()),
+        "versions": versions,
+    })));
+}
@@ -0,0 +1,23 @@
+class {{ class_name }} < Formula
+  desc "{{ description }}"
+  homepage "{{ homepage }}"
+  version "{{ version }}"
+{%- for (os, files) in systems %}
+
+  on_{{ os }} do
+  {%- for file in files %}
+    on_{{ file.cpu }} do
+      url "{{ file.url }}"
+      {%- if !file.sha256.is_empty() %}
+      sha256 "{{ file.sha256 }}"
+      {%- endif %}
+    end
+  {%- endfor %}
+  end
+{%- endfor %}
+
+  def install
+    binary = Dir["**/{{ binary }}"].first || Dir["*"].first
+    bin.install binary => "{{ binary }}"
+  end
+end
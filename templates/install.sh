@@ -0,0 +1,63 @@
+#!/bin/sh
+# Installs {{ package_name }} {{ version }} from {{ base_url }}
+# PIGI_INSTALL_DIR: where {{ binary }} goes, ~/.local/bin by default
+# PIGI_TOKEN: access token of the index, when it needs one
+set -eu
+
+install_dir="${PIGI_INSTALL_DIR:-$HOME/.local/bin}"
+os=$(uname -s | tr '[:upper:]' '[:lower:]')
+arch=$(uname -m)
+case "$arch" in
+    x86_64 | amd64) arch=x86_64 ;;
+    aarch64 | arm64) arch=aarch64 ;;
+esac
+
+case "$os-$arch" in
+{%- for file in files %}
+    {{ file.platform }}) file='{{ file.name }}' url='{{ file.url }}' sha256='{{ file.sha256 }}' ;;
+{%- endfor %}
+    *)
+        echo "{{ package_name }} {{ version }} has no build for $os-$arch" >&2
+        exit 1
+        ;;
+esac
+
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+if command -v curl >/dev/null 2>&1; then
+    curl -fsSL ${PIGI_TOKEN:+-u "pigi:$PIGI_TOKEN"} -o "$tmp/$file" "$url"
+else
+    wget -q ${PIGI_TOKEN:+--user pigi --password "$PIGI_TOKEN"} -O "$tmp/$file" "$url"
+fi
+
+if [ -n "$sha256" ]; then
+    if command -v sha256sum >/dev/null 2>&1; then
+        actual=$(sha256sum "$tmp/$file" | cut -d ' ' -f 1)
+    else
+        actual=$(shasum -a 256 "$tmp/$file" | cut -d ' ' -f 1)
+    fi
+    if [ "$actual" != "$sha256" ]; then
+        echo "Checksum of $file doesn't match, not installing it" >&2
+        exit 1
+    fi
+fi
+
+mkdir "$tmp/out"
+case "$file" in
+    *.tar.gz | *.tgz) tar -xzf "$tmp/$file" -C "$tmp/out" ;;
+    *.tar.xz) tar -xJf "$tmp/$file" -C "$tmp/out" ;;
+    *.zip) unzip -q "$tmp/$file" -d "$tmp/out" ;;
+    *) mv "$tmp/$file" "$tmp/out/{{ binary }}" ;;
+esac
+binary=$(find "$tmp/out" -type f -name '{{ binary }}' | head -n 1)
+if [ -z "$binary" ] && [ "$(find "$tmp/out" -type f | wc -l)" -eq 1 ]; then
+    binary=$(find "$tmp/out" -type f)
+fi
+if [ -z "$binary" ]; then
+    echo "$file has no {{ binary }} executable" >&2
+    exit 1
+fi
+mkdir -p "$install_dir"
+cp "$binary" "$install_dir/{{ binary }}"
+chmod 755 "$install_dir/{{ binary }}"
+echo "Installed {{ package_name }} {{ version }} to $install_dir/{{ binary }}"